@@ -6,7 +6,7 @@ pub async fn main() {
     pretty_env_logger::init();
 
     let addr = "127.0.0.1:2121";
-    let server = libunftp::Server::new_with_fs_root(std::env::temp_dir());
+    let server = libunftp::Server::new_with_fs_root(std::env::temp_dir()).build().unwrap();
 
     info!("Starting ftp server on {}", addr);
     server.listen(addr).await;
@@ -68,12 +68,14 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
             libunftp::storage::cloud_storage::CloudStorage::new(&bucket_name, service_account_key.clone())
         }))
         .ftps(ftps_certs_file, ftps_certs_password)
+        .build()?
         .listen(BIND_ADDRESS)
         .await;
     } else {
         libunftp::Server::new(Box::new(move || {
             libunftp::storage::cloud_storage::CloudStorage::new(&bucket_name, service_account_key.clone())
         }))
+        .build()?
         .listen(BIND_ADDRESS)
         .await;
     }
@@ -10,7 +10,9 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
     let authenticator = jsonfile::JsonFileAuthenticator::new(String::from("credentials.json"))?;
 
     let addr = "127.0.0.1:2121";
-    let server = libunftp::Server::new_with_fs_root(std::env::temp_dir()).authenticator(Arc::new(authenticator));
+    let server = libunftp::Server::new_with_fs_root(std::env::temp_dir())
+        .authenticator(Arc::new(authenticator))
+        .build()?;
 
     info!("Starting ftp server on {}", addr);
     let mut runtime = tokio::runtime::Builder::new().build().unwrap();
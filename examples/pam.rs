@@ -11,7 +11,10 @@ pub fn main() {
     info!("Starting ftp server on {}", addr);
     let authenticator = pam::PAMAuthenticator::new("hello");
 
-    let server = libunftp::Server::new_with_fs_root(std::env::temp_dir()).authenticator(Arc::new(authenticator));
+    let server = libunftp::Server::new_with_fs_root(std::env::temp_dir())
+        .authenticator(Arc::new(authenticator))
+        .build()
+        .unwrap();
 
     let mut runtime = tokio::runtime::Builder::new().build().unwrap();
     runtime.block_on(server.listen(addr));
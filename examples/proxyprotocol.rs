@@ -7,6 +7,8 @@ pub async fn main() {
     let addr = "127.0.0.1:2121";
     let server = libunftp::Server::new_with_fs_root(std::env::temp_dir())
         .proxy_protocol_mode("10.0.0.1", 2121)
+        .unwrap()
+        .build()
         .unwrap();
 
     info!("Starting ftp server with proxy protocol on {}", addr);
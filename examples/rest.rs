@@ -20,7 +20,9 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
         .build()?;
 
     let addr = "127.0.0.1:2121";
-    let server = libunftp::Server::new_with_fs_root(std::env::temp_dir()).authenticator(Arc::new(authenticator));
+    let server = libunftp::Server::new_with_fs_root(std::env::temp_dir())
+        .authenticator(Arc::new(authenticator))
+        .build()?;
 
     info!("Starting ftp server on {}", addr);
     let mut runtime = Builder::new().build()?;
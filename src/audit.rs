@@ -0,0 +1,459 @@
+//! Contains the audit log that records every command a client sends, and the sinks it can be
+//! written to.
+//!
+//! Enable it with [`ServerBuilder::audit_sink`]. A sink is anything that implements
+//! [`AuditSink`]. This module provides sinks that write plain text lines ([`TextSink`]), JSON
+//! lines ([`JsonLinesSink`]) or syslog messages ([`SyslogSink`]).
+//!
+//! [`ServerBuilder::audit_sink`]: ../struct.ServerBuilder.html#method.audit_sink
+//! [`AuditSink`]: trait.AuditSink.html
+//! [`TextSink`]: struct.TextSink.html
+//! [`JsonLinesSink`]: struct.JsonLinesSink.html
+//! [`SyslogSink`]: struct.SyslogSink.html
+
+use crate::metrics::command_label;
+use crate::server::{Command, Reply};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use slog::{o, warn, Drain, Logger};
+use std::io::{self, Write};
+use std::net::{IpAddr, ToSocketAddrs, UdpSocket};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// A command that was received from a client.
+#[derive(Clone, Debug)]
+pub struct AuditEvent {
+    /// When the command was handled.
+    pub time: SystemTime,
+    /// The ID of the session, as found in the logs.
+    pub session_id: String,
+    /// The name the client gave with `USER`, if it did so already.
+    pub username: Option<String>,
+    /// The IP address of the client.
+    pub peer_ip: IpAddr,
+    /// The name of the command, for instance `RETR`.
+    pub command: String,
+    /// The arguments of the command, as the client gave them. The secrets passed with `PASS`,
+    /// `ACCT` and `ADAT` and the commands protected with `MIC` or `ENC` are redacted.
+    pub arguments: String,
+    /// The code of the reply to the command. For commands that are answered later, like
+    /// transfers and logins, this is the code of the first reply sent afterwards. It is `None`
+    /// if no reply was sent before the next command or the end of the session.
+    pub reply_code: Option<u32>,
+}
+
+/// Receives the events of the audit log.
+pub trait AuditSink: Send + Sync {
+    /// Records the event. Sinks that fail to do so should report this themselves, since the
+    /// server carries on regardless.
+    fn record(&self, event: &AuditEvent);
+}
+
+// Records the commands of a single session. A command that is not answered right away stays
+// pending until the next reply, the next command or the end of the session.
+pub(crate) struct AuditTrail {
+    sink: Arc<dyn AuditSink>,
+    session_id: String,
+    peer_ip: IpAddr,
+    pending: Option<AuditEvent>,
+}
+
+impl AuditTrail {
+    pub fn new(sink: Arc<dyn AuditSink>, session_id: String, peer_ip: IpAddr) -> Self {
+        AuditTrail {
+            sink,
+            session_id,
+            peer_ip,
+            pending: None,
+        }
+    }
+
+    // Records the command once it was handled, given the reply the handler came up with.
+    pub fn command(&mut self, cmd: &Command, username: Option<String>, reply: &Reply) {
+        self.flush();
+        self.pending = Some(AuditEvent {
+            time: SystemTime::now(),
+            session_id: self.session_id.clone(),
+            username,
+            peer_ip: self.peer_ip,
+            command: command_label(cmd).to_uppercase(),
+            arguments: cmd.arguments(),
+            reply_code: None,
+        });
+        self.reply(reply);
+    }
+
+    // Completes the pending command with the code of a reply.
+    pub fn reply(&mut self, reply: &Reply) {
+        let code = match reply {
+            Reply::CodeAndMsg { code, .. } | Reply::MultiLine { code, .. } => u32::from(*code),
+            Reply::None => return,
+        };
+        if let Some(mut event) = self.pending.take() {
+            event.reply_code = Some(code);
+            self.sink.record(&event);
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(event) = self.pending.take() {
+            self.sink.record(&event);
+        }
+    }
+}
+
+impl Drop for AuditTrail {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn text_line(event: &AuditEvent) -> String {
+    format!(
+        "{} session={} user={} peer={} command={} reply={} {}",
+        timestamp(event.time),
+        event.session_id,
+        event.username.as_deref().unwrap_or("-"),
+        event.peer_ip,
+        event.command,
+        event.reply_code.map(|code| code.to_string()).unwrap_or_else(|| "-".to_string()),
+        event.arguments
+    )
+}
+
+fn json_line(event: &AuditEvent) -> String {
+    serde_json::json!({
+        "time": timestamp(event.time),
+        "session_id": event.session_id,
+        "username": event.username,
+        "peer_ip": event.peer_ip.to_string(),
+        "command": event.command,
+        "arguments": event.arguments,
+        "reply_code": event.reply_code,
+    })
+    .to_string()
+}
+
+fn open_append(path: &Path) -> io::Result<std::fs::File> {
+    std::fs::OpenOptions::new().create(true).append(true).open(path)
+}
+
+type Output = Box<dyn FnMut(&str) -> io::Result<()> + Send>;
+
+// How many messages may wait for the thread of a sink before new ones are dropped.
+const QUEUE_CAPACITY: usize = 1024;
+
+enum WorkerState {
+    Idle(Output),
+    Running(mpsc::SyncSender<String>),
+}
+
+// Hands the messages of a sink to a thread of its own, so that the sessions don't wait for disks
+// or syslog daemons. The thread is started with the first message, once the logger that it
+// reports failures to is known. When the sink falls behind, messages are dropped rather than
+// queued without limit.
+struct Worker {
+    name: &'static str,
+    logger: Logger,
+    capacity: usize,
+    state: Mutex<Option<WorkerState>>,
+    // The number of messages dropped since the last one that was queued.
+    dropped: AtomicU64,
+}
+
+impl Worker {
+    fn new(name: &'static str, output: Output) -> Self {
+        Worker {
+            name,
+            logger: Logger::root(slog_stdlog::StdLog.fuse(), o!()),
+            capacity: QUEUE_CAPACITY,
+            state: Mutex::new(Some(WorkerState::Idle(output))),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    fn send(&self, message: String) {
+        let mut state = self.state.lock().unwrap();
+        let tx = match state.take() {
+            Some(WorkerState::Idle(mut output)) => {
+                let (tx, rx) = mpsc::sync_channel::<String>(self.capacity);
+                let (name, logger) = (self.name, self.logger.clone());
+                std::thread::spawn(move || {
+                    for message in rx {
+                        if let Err(err) = output(&message) {
+                            warn!(logger, "Could not write to the audit log"; "sink" => name, "error" => %err);
+                        }
+                    }
+                });
+                tx
+            }
+            Some(WorkerState::Running(tx)) => tx,
+            // The thread is gone, which only happens if it panicked.
+            None => return,
+        };
+        match tx.try_send(message) {
+            Ok(()) => {
+                let dropped = self.dropped.swap(0, Ordering::Relaxed);
+                if dropped > 0 {
+                    warn!(self.logger, "Dropped {} audit records because the sink fell behind", dropped; "sink" => self.name);
+                }
+            }
+            Err(TrySendError::Full(_)) => {
+                if self.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                    warn!(self.logger, "The audit sink falls behind, dropping records"; "sink" => self.name);
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => return,
+        }
+        *state = Some(WorkerState::Running(tx));
+    }
+}
+
+fn line_output<W: Write + Send + 'static>(mut writer: W) -> Output {
+    Box::new(move |line| {
+        writeln!(writer, "{}", line)?;
+        writer.flush()
+    })
+}
+
+/// An [`AuditSink`] that writes an event per line in plain text.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::audit::TextSink;
+/// use libunftp::Server;
+///
+/// let server = Server::new_with_fs_root("/tmp").audit_sink(TextSink::new(std::io::stdout()));
+/// ```
+///
+/// [`AuditSink`]: trait.AuditSink.html
+pub struct TextSink(Worker);
+
+impl TextSink {
+    /// Creates a sink that writes to the given writer. The writing happens on a thread of its
+    /// own.
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        TextSink(Worker::new("text", line_output(writer)))
+    }
+
+    /// Creates a sink that appends to the file at the given path, creating it if needed.
+    pub fn file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(open_append(path.as_ref())?))
+    }
+
+    /// Sets the logger that failures to write are reported to. By default they go to the `log`
+    /// crate.
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.0.logger = logger;
+        self
+    }
+}
+
+impl AuditSink for TextSink {
+    fn record(&self, event: &AuditEvent) {
+        self.0.send(text_line(event));
+    }
+}
+
+/// An [`AuditSink`] that writes an event per line as a JSON object.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::audit::JsonLinesSink;
+/// use libunftp::Server;
+///
+/// let sink = JsonLinesSink::file(std::env::temp_dir().join("audit.jsonl")).unwrap();
+/// let server = Server::new_with_fs_root("/tmp").audit_sink(sink);
+/// ```
+///
+/// [`AuditSink`]: trait.AuditSink.html
+pub struct JsonLinesSink(Worker);
+
+impl JsonLinesSink {
+    /// Creates a sink that writes to the given writer. The writing happens on a thread of its
+    /// own.
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        JsonLinesSink(Worker::new("json", line_output(writer)))
+    }
+
+    /// Creates a sink that appends to the file at the given path, creating it if needed.
+    pub fn file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(open_append(path.as_ref())?))
+    }
+
+    /// Sets the logger that failures to write are reported to. By default they go to the `log`
+    /// crate.
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.0.logger = logger;
+        self
+    }
+}
+
+impl AuditSink for JsonLinesSink {
+    fn record(&self, event: &AuditEvent) {
+        self.0.send(json_line(event));
+    }
+}
+
+enum SyslogSocket {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixDatagram),
+}
+
+impl SyslogSocket {
+    fn send(&self, message: &str) -> io::Result<usize> {
+        match self {
+            SyslogSocket::Udp(socket) => socket.send(message.as_bytes()),
+            #[cfg(unix)]
+            SyslogSocket::Unix(socket) => socket.send(message.as_bytes()),
+        }
+    }
+}
+
+/// An [`AuditSink`] that sends every event as an RFC 3164 syslog message with the `authpriv`
+/// facility and the `info` severity.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::audit::SyslogSink;
+/// use libunftp::Server;
+///
+/// let sink = SyslogSink::udp("127.0.0.1:514").unwrap();
+/// let server = Server::new_with_fs_root("/tmp").audit_sink(sink);
+/// ```
+///
+/// [`AuditSink`]: trait.AuditSink.html
+pub struct SyslogSink {
+    worker: Worker,
+    tag: String,
+}
+
+// authpriv (10) * 8 + info (6)
+const SYSLOG_PRIORITY: u32 = 86;
+
+impl SyslogSink {
+    /// Creates a sink that sends the messages over UDP to the syslog server at the given address.
+    pub fn udp<A: ToSocketAddrs>(server: A) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(server)?;
+        Ok(Self::with_socket(SyslogSocket::Udp(socket)))
+    }
+
+    /// Creates a sink that sends the messages to the local syslog daemon through the Unix socket
+    /// at the given path, usually `/dev/log`.
+    #[cfg(unix)]
+    pub fn unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Self::with_socket(SyslogSocket::Unix(socket)))
+    }
+
+    fn with_socket(socket: SyslogSocket) -> Self {
+        SyslogSink {
+            worker: Worker::new("syslog", Box::new(move |message| socket.send(message).map(|_| ()))),
+            tag: "libunftp".to_string(),
+        }
+    }
+
+    /// Sets the tag that identifies the messages, `libunftp` by default.
+    pub fn tag<T: Into<String>>(mut self, tag: T) -> Self {
+        self.tag = tag.into();
+        self
+    }
+
+    /// Sets the logger that failures to send are reported to. By default they go to the `log`
+    /// crate.
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.worker.logger = logger;
+        self
+    }
+}
+
+impl AuditSink for SyslogSink {
+    fn record(&self, event: &AuditEvent) {
+        let time = DateTime::<Utc>::from(event.time).format("%b %e %H:%M:%S");
+        self.worker.send(format!("<{}>{} {}: {}", SYSLOG_PRIORITY, time, self.tag, text_line(event)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{json_line, text_line, AuditEvent, Worker};
+    use std::sync::atomic::Ordering;
+    use std::sync::mpsc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn event() -> AuditEvent {
+        AuditEvent {
+            time: UNIX_EPOCH + Duration::from_millis(1_588_597_509_250),
+            session_id: "s1".to_string(),
+            username: Some("alice".to_string()),
+            peer_ip: "127.0.0.1".parse().unwrap(),
+            command: "RETR".to_string(),
+            arguments: "a \"b\".txt".to_string(),
+            reply_code: Some(150),
+        }
+    }
+
+    #[test]
+    fn text_format() {
+        assert_eq!(
+            text_line(&event()),
+            "2020-05-04T13:05:09.250Z session=s1 user=alice peer=127.0.0.1 command=RETR reply=150 a \"b\".txt"
+        );
+    }
+
+    #[test]
+    fn json_format() {
+        let mut event = event();
+        event.username = None;
+        event.reply_code = None;
+        assert_eq!(
+            json_line(&event),
+            r#"{"arguments":"a \"b\".txt","command":"RETR","peer_ip":"127.0.0.1","reply_code":null,"session_id":"s1","time":"2020-05-04T13:05:09.250Z","username":null}"#
+        );
+    }
+
+    #[test]
+    fn drops_messages_when_the_sink_falls_behind() {
+        let (started_tx, started_rx) = mpsc::channel();
+        let (go_tx, go_rx) = mpsc::channel::<()>();
+        let (written_tx, written_rx) = mpsc::channel();
+        let mut worker = Worker::new(
+            "test",
+            Box::new(move |message| {
+                started_tx.send(()).unwrap();
+                go_rx.recv().unwrap();
+                written_tx.send(message.to_string()).unwrap();
+                Ok(())
+            }),
+        );
+        worker.capacity = 2;
+
+        // The first message keeps the thread busy, the next two wait and the last two are dropped.
+        worker.send("1".to_string());
+        started_rx.recv().unwrap();
+        for message in &["2", "3", "4", "5"] {
+            worker.send(message.to_string());
+        }
+        assert_eq!(worker.dropped.load(Ordering::Relaxed), 2);
+
+        for _ in 0..3 {
+            go_tx.send(()).unwrap();
+        }
+        let written: Vec<String> = written_rx.iter().take(3).collect();
+        assert_eq!(written, vec!["1", "2", "3"]);
+    }
+}
@@ -20,9 +20,18 @@ use async_trait::async_trait;
 ///
 pub struct AnonymousAuthenticator;
 
+// Tells if the user name is one that RFC 1635 reserves for anonymous FTP.
+pub(crate) fn is_anonymous_user(username: &str) -> bool {
+    username.eq_ignore_ascii_case("anonymous") || username.eq_ignore_ascii_case("ftp")
+}
+
 #[async_trait]
 impl Authenticator<DefaultUser> for AnonymousAuthenticator {
     async fn authenticate(&self, _username: &str, _password: &str) -> Result<DefaultUser, Box<dyn std::error::Error + Send + Sync>> {
         Ok(DefaultUser {})
     }
+
+    async fn authenticate_principal(&self, _username: &str, _principal: &str) -> Result<DefaultUser, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(DefaultUser {})
+    }
 }
@@ -14,6 +14,13 @@ where
 {
     /// Authenticate the given user with the given password.
     async fn authenticate(&self, username: &str, password: &str) -> Result<U, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Authenticate the given user without a password, because a security context, like the one
+    /// of `AUTH GSSAPI`, already authenticated the client as `principal`. The default
+    /// implementation refuses, after which the client is asked for a password as usual.
+    async fn authenticate_principal(&self, _username: &str, _principal: &str) -> Result<U, Box<dyn std::error::Error + Send + Sync>> {
+        Err("logging in without a password is not supported".into())
+    }
 }
 
 #[derive(Debug)]
@@ -27,8 +34,12 @@ impl fmt::Display for BadPasswordError {
 
 impl Error for BadPasswordError {}
 
+/// The error that authenticators return when the user name is not known to them, as opposed to
+/// when the password is wrong. [`GuestFallbackAuthenticator`] lets such users in as guests.
+///
+/// [`GuestFallbackAuthenticator`]: struct.GuestFallbackAuthenticator.html
 #[derive(Debug)]
-pub(crate) struct UnknownUsernameError;
+pub struct UnknownUsernameError;
 
 impl fmt::Display for UnknownUsernameError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -0,0 +1,103 @@
+//! This module provides an authenticator that lets unknown users in as guests
+
+use crate::auth::*;
+use async_trait::async_trait;
+
+/// [`Authenticator`] that wraps another one and lets users that it doesn't know in as a guest,
+/// instead of refusing them. This eases moving from FTP servers that do the same. Users that are
+/// known but give the wrong password are still refused.
+///
+/// The guest is made by the given function from the name the client gave. Give the guest user the
+/// restrictions that fit, for instance lower bandwidth limits through [`UserDetail`].
+///
+/// The wrapped authenticator tells that it doesn't know a user by returning an
+/// [`UnknownUsernameError`].
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::auth::{AnonymousAuthenticator, DefaultUser, GuestFallbackAuthenticator};
+///
+/// let authenticator = GuestFallbackAuthenticator::new(AnonymousAuthenticator, |_username: &str| DefaultUser);
+/// ```
+///
+/// [`Authenticator`]: trait.Authenticator.html
+/// [`UserDetail`]: trait.UserDetail.html
+/// [`UnknownUsernameError`]: struct.UnknownUsernameError.html
+pub struct GuestFallbackAuthenticator<A, F> {
+    inner: A,
+    guest: F,
+}
+
+impl<A, F> GuestFallbackAuthenticator<A, F> {
+    /// Creates an authenticator that asks `inner` first and makes a guest with `guest` for users
+    /// that `inner` doesn't know.
+    pub fn new(inner: A, guest: F) -> Self {
+        GuestFallbackAuthenticator { inner, guest }
+    }
+}
+
+#[async_trait]
+impl<A, F, U> Authenticator<U> for GuestFallbackAuthenticator<A, F>
+where
+    A: Authenticator<U>,
+    F: Fn(&str) -> U + Send + Sync,
+    U: UserDetail + 'static,
+{
+    async fn authenticate(&self, username: &str, password: &str) -> Result<U, Box<dyn std::error::Error + Send + Sync>> {
+        match self.inner.authenticate(username, password).await {
+            Err(err) if err.is::<UnknownUsernameError>() => Ok((self.guest)(username)),
+            result => result,
+        }
+    }
+
+    async fn authenticate_principal(&self, username: &str, principal: &str) -> Result<U, Box<dyn std::error::Error + Send + Sync>> {
+        self.inner.authenticate_principal(username, principal).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GuestFallbackAuthenticator;
+    use crate::auth::{Authenticator, BadPasswordError, UnknownUsernameError, UserDetail};
+    use async_trait::async_trait;
+    use std::fmt;
+    use tokio::runtime::Runtime;
+
+    #[derive(Debug, PartialEq)]
+    enum User {
+        Known,
+        Guest(String),
+    }
+
+    impl UserDetail for User {}
+
+    impl fmt::Display for User {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:?}", self)
+        }
+    }
+
+    // Knows alice, with password secret.
+    struct Alice;
+
+    #[async_trait]
+    impl Authenticator<User> for Alice {
+        async fn authenticate(&self, username: &str, password: &str) -> Result<User, Box<dyn std::error::Error + Send + Sync>> {
+            match (username, password) {
+                ("alice", "secret") => Ok(User::Known),
+                ("alice", _) => Err(Box::new(BadPasswordError)),
+                _ => Err(Box::new(UnknownUsernameError)),
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_users_become_guests() {
+        let authenticator = GuestFallbackAuthenticator::new(Alice, |username: &str| User::Guest(username.to_string()));
+        let mut rt = Runtime::new().unwrap();
+        assert_eq!(rt.block_on(authenticator.authenticate("alice", "secret")).unwrap(), User::Known);
+        assert!(rt.block_on(authenticator.authenticate("alice", "wrong")).is_err());
+        assert_eq!(rt.block_on(authenticator.authenticate("bob", "any")).unwrap(), User::Guest("bob".to_string()));
+    }
+}
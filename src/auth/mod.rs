@@ -41,13 +41,18 @@ pub mod anonymous;
 pub use anonymous::AnonymousAuthenticator;
 
 pub(crate) mod authenticator;
-pub use authenticator::Authenticator;
 #[allow(unused_imports)]
-pub(crate) use authenticator::{BadPasswordError, UnknownUsernameError};
+pub(crate) use authenticator::BadPasswordError;
+pub use authenticator::{Authenticator, UnknownUsernameError};
+
+pub mod guest;
+pub use guest::GuestFallbackAuthenticator;
 
 mod user;
 pub use user::{DefaultUser, UserDetail};
 
+pub mod testsuite;
+
 #[cfg(feature = "pam_auth")]
 pub mod pam;
 
@@ -0,0 +1,175 @@
+//! Contains tests that every [`Authenticator`] should pass, for the authors of authenticators to
+//! run against their own implementation.
+//!
+//! The tests are given the name and password of an enabled account that the authenticator knows
+//! and check how it answers for those and for wrong ones. Each test panics, like a failing
+//! assertion does, when the authenticator doesn't behave the way the login flow expects. [`run`]
+//! runs all of them.
+//!
+//! # Example
+//!
+//! ```rust
+//! use async_trait::async_trait;
+//! use libunftp::auth::{testsuite, Authenticator, DefaultUser, UnknownUsernameError};
+//!
+//! struct Single;
+//!
+//! #[async_trait]
+//! impl Authenticator<DefaultUser> for Single {
+//!     async fn authenticate(&self, username: &str, password: &str) -> Result<DefaultUser, Box<dyn std::error::Error + Send + Sync>> {
+//!         match (username, password) {
+//!             ("alice", "s3cr3t") => Ok(DefaultUser),
+//!             ("alice", _) => Err("bad password".into()),
+//!             _ => Err(Box::new(UnknownUsernameError)),
+//!         }
+//!     }
+//! }
+//!
+//! let mut rt = tokio::runtime::Runtime::new().unwrap();
+//! rt.block_on(testsuite::run(&Single, "alice", "s3cr3t"));
+//! rt.block_on(testsuite::unknown_user_is_reported(&Single));
+//! ```
+//!
+//! [`Authenticator`]: ../trait.Authenticator.html
+//! [`run`]: fn.run.html
+
+use super::{Authenticator, UnknownUsernameError, UserDetail};
+
+use futures::future::join_all;
+use std::time::Duration;
+
+/// How long the tests wait for an answer of the authenticator. The server waits for it without a
+/// limit of its own, so a client whose login hangs in the authenticator waits along.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+// The number of logins that `concurrent_logins` runs at the same time.
+const CONCURRENT_LOGINS: usize = 16;
+
+type AuthResult<U> = Result<U, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Runs all tests of the suite except [`unknown_user_is_reported`], which not every
+/// authenticator needs to pass.
+///
+/// [`unknown_user_is_reported`]: fn.unknown_user_is_reported.html
+pub async fn run<A, U>(authenticator: &A, username: &str, password: &str)
+where
+    A: Authenticator<U>,
+    U: UserDetail,
+{
+    valid_credentials(authenticator, username, password).await;
+    wrong_password(authenticator, username, password).await;
+    unknown_user(authenticator, password).await;
+    concurrent_logins(authenticator, username, password).await;
+}
+
+/// Checks that the right password lets the user in, with an enabled account.
+pub async fn valid_credentials<A, U>(authenticator: &A, username: &str, password: &str)
+where
+    A: Authenticator<U>,
+    U: UserDetail,
+{
+    match authenticate(authenticator, username, password).await {
+        Ok(user) => assert!(user.account_enabled(), "The account of {} should be enabled", username),
+        Err(err) => panic!("Expected {} to log in, but got: {}", username, err),
+    }
+}
+
+/// Checks that a wrong or empty password keeps the user out.
+pub async fn wrong_password<A, U>(authenticator: &A, username: &str, password: &str)
+where
+    A: Authenticator<U>,
+    U: UserDetail,
+{
+    for wrong in [format!("{}x", password), String::new()] {
+        assert!(
+            authenticate(authenticator, username, &wrong).await.is_err(),
+            "Expected {} not to log in with the password {:?}",
+            username,
+            wrong
+        );
+    }
+}
+
+/// Checks that a user that the authenticator doesn't know is kept out, whatever the password.
+pub async fn unknown_user<A, U>(authenticator: &A, password: &str)
+where
+    A: Authenticator<U>,
+    U: UserDetail,
+{
+    let username = unknown_username();
+    assert!(
+        authenticate(authenticator, &username, password).await.is_err(),
+        "Expected the unknown user {} not to log in",
+        username
+    );
+}
+
+/// Checks that the authenticator tells users it doesn't know with an [`UnknownUsernameError`],
+/// which [`GuestFallbackAuthenticator`] needs to let them in as guests.
+///
+/// [`UnknownUsernameError`]: ../struct.UnknownUsernameError.html
+/// [`GuestFallbackAuthenticator`]: ../struct.GuestFallbackAuthenticator.html
+pub async fn unknown_user_is_reported<A, U>(authenticator: &A)
+where
+    A: Authenticator<U>,
+    U: UserDetail,
+{
+    let username = unknown_username();
+    match authenticate(authenticator, &username, "password").await {
+        Ok(_) => panic!("Expected the unknown user {} not to log in", username),
+        Err(err) => assert!(err.is::<UnknownUsernameError>(), "Expected an UnknownUsernameError, but got: {}", err),
+    }
+}
+
+/// Checks that logins that run at the same time, as they do for different sessions, each get the
+/// right answer.
+pub async fn concurrent_logins<A, U>(authenticator: &A, username: &str, password: &str)
+where
+    A: Authenticator<U>,
+    U: UserDetail,
+{
+    let wrong = format!("{}x", password);
+    let logins = (0..CONCURRENT_LOGINS).map(|i| {
+        let password = if i % 2 == 0 { password } else { wrong.as_str() };
+        authenticate(authenticator, username, password)
+    });
+    for (i, result) in join_all(logins).await.into_iter().enumerate() {
+        assert_eq!(
+            result.is_ok(),
+            i % 2 == 0,
+            "Login {} of {} at the same time got the wrong answer",
+            i + 1,
+            CONCURRENT_LOGINS
+        );
+    }
+}
+
+// Asks the authenticator, failing the test when it takes longer than `TIMEOUT`.
+async fn authenticate<A, U>(authenticator: &A, username: &str, password: &str) -> AuthResult<U>
+where
+    A: Authenticator<U>,
+    U: UserDetail,
+{
+    match tokio::time::timeout(TIMEOUT, authenticator.authenticate(username, password)).await {
+        Ok(result) => result,
+        Err(_) => panic!("The authenticator did not answer for {} within {:?}", username, TIMEOUT),
+    }
+}
+
+fn unknown_username() -> String {
+    format!("libunftp-testsuite-{}", std::process::id())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::{AnonymousAuthenticator, DefaultUser};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    #[should_panic(expected = "not to log in with the password")]
+    fn catches_authenticators_that_let_everyone_in() {
+        Runtime::new()
+            .unwrap()
+            .block_on(super::run::<_, DefaultUser>(&AnonymousAuthenticator, "alice", "s3cr3t"));
+    }
+}
@@ -16,6 +16,24 @@ pub trait UserDetail: Send + Sync + Display + Debug {
     fn account_enabled(&self) -> bool {
         true
     }
+
+    /// The maximum number of bytes per second this subject may upload in a session. Returning
+    /// `None`, as this default implementation does, applies the limit configured on the server.
+    fn upload_bandwidth_limit(&self) -> Option<u64> {
+        None
+    }
+
+    /// The maximum number of bytes per second this subject may download in a session. Returning
+    /// `None`, as this default implementation does, applies the limit configured on the server.
+    fn download_bandwidth_limit(&self) -> Option<u64> {
+        None
+    }
+
+    /// Tells if this subject may not overwrite existing files. Returning `None`, as this default
+    /// implementation does, applies the setting of the server.
+    fn no_clobber(&self) -> Option<bool> {
+        None
+    }
 }
 
 /// DefaultUser is a default implementation of the `UserDetail` trait that doesn't hold any user
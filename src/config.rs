@@ -0,0 +1,585 @@
+//! Contains the [`ServerConfig`] struct that allows setting up a [`Server`] from a TOML or YAML
+//! configuration file instead of calling its builder methods one by one.
+//!
+//! # Example
+//!
+//! ```rust
+//! use libunftp::config::ServerConfig;
+//! use libunftp::storage::filesystem::Filesystem;
+//! use libunftp::Server;
+//!
+//! let config = ServerConfig::from_toml(r#"
+//!     address = "127.0.0.1:2121"
+//!     passive_ports = "50000-51000"
+//!     idle_session_timeout = 120
+//!
+//!     [storage]
+//!     backend = "filesystem"
+//!     root = "/srv/ftp"
+//! "#).unwrap();
+//!
+//! let server: Server<Filesystem, _> = Server::from_config(&config).unwrap().build().unwrap();
+//! ```
+//!
+//! [`ServerConfig`]: struct.ServerConfig.html
+//! [`Server`]: ../struct.Server.html
+
+use crate::storage::filesystem::Filesystem;
+use crate::PartialUploads;
+
+use serde::{de, Deserialize, Deserializer};
+use std::error::Error;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// The address the server listens on if none is configured.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:2121";
+
+/// The settings of a [`Server`](../struct.Server.html). Settings that are left out keep the
+/// defaults of the server.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ServerConfig {
+    /// The address to listen on for control connections, `127.0.0.1:2121` by default.
+    #[serde(default = "default_address")]
+    pub address: String,
+    /// The greeting sent to clients after connecting.
+    pub greeting: Option<String>,
+    /// The message shown to users after logging in.
+    pub login_message: Option<String>,
+    /// The reply to `SYST`, `UNIX Type: L8` by default.
+    pub system_type: Option<String>,
+    /// The line that identifies the server software in the replies to `STAT` and `HELP`. An
+    /// empty line leaves it out.
+    pub identification: Option<String>,
+    /// The range of ports to listen on for passive data connections, written as `start-end`. Like
+    /// the range passed to `ServerBuilder::passive_ports` the end is exclusive.
+    #[serde(default, deserialize_with = "deserialize_port_range")]
+    pub passive_ports: Option<Range<u16>>,
+    /// Enables FTPS. Needs the `ftps` feature.
+    pub ftps: Option<FtpsConfig>,
+    /// Enables PROXY protocol mode. Needs the `proxy_protocol` feature.
+    pub proxy_protocol: Option<ProxyProtocolConfig>,
+    /// The number of seconds after which idle sessions are closed.
+    pub idle_session_timeout: Option<u64>,
+    /// The number of seconds after which transfers that don't move any data are aborted, 0 to
+    /// never abort them.
+    pub stalled_transfer_timeout: Option<u64>,
+    /// The number of seconds after which calls to the storage backend fail.
+    pub storage_timeout: Option<u64>,
+    /// The number of seconds that sessions remember the metadata of files for `SIZE`, `MDTM`
+    /// and `MLST`.
+    pub metadata_cache_ttl: Option<u64>,
+    /// The number of seconds to wait for sessions to end when shutting down.
+    pub shutdown_grace_period: Option<u64>,
+    /// The maximum number of concurrent control connections.
+    pub max_connections: Option<usize>,
+    /// The maximum number of bytes per second transferred by all sessions together.
+    pub bandwidth_limit: Option<u64>,
+    /// The maximum number of bytes per second uploaded by a single session.
+    pub upload_bandwidth_limit: Option<u64>,
+    /// The maximum number of bytes per second downloaded by a single session.
+    pub download_bandwidth_limit: Option<u64>,
+    /// The networks, in CIDR notation, clients may connect from.
+    #[serde(default)]
+    pub allow_ips: Vec<String>,
+    /// The networks, in CIDR notation, clients may not connect from.
+    #[serde(default)]
+    pub deny_ips: Vec<String>,
+    /// The commands that are refused, like `DELE` and `RMD`.
+    #[serde(default)]
+    pub disabled_commands: Vec<String>,
+    /// Hides files and directories whose names start with a dot from clients.
+    #[serde(default)]
+    pub hide_dotfiles: bool,
+    /// Glob patterns of the paths that are hidden from clients.
+    #[serde(default)]
+    pub hidden_paths: Vec<String>,
+    /// Refuses uploads that would overwrite existing files.
+    #[serde(default)]
+    pub no_clobber: bool,
+    /// What happens to the files of interrupted uploads: `keep`, `delete` or `rename`.
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub partial_uploads: Option<PartialUploads>,
+    /// Runs the server as a drop box, where clients can upload files but not see or download them.
+    #[serde(default)]
+    pub drop_box: bool,
+    /// Enables the collection of prometheus metrics. Needs the `metrics` feature.
+    #[serde(default)]
+    pub metrics: bool,
+    /// The storage backend to use.
+    pub storage: StorageConfig,
+}
+
+/// The FTPS settings of a [`ServerConfig`](struct.ServerConfig.html).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FtpsConfig {
+    /// The path to the PKCS#12 file holding the certificate and private key.
+    pub certs_file: PathBuf,
+    /// The password of the certificate file.
+    pub certs_password: String,
+    /// The mechanisms that clients may ask for with `AUTH`, `TLS` and `SSL` by default.
+    #[serde(default)]
+    pub auth_mechanisms: Vec<String>,
+}
+
+/// The PROXY protocol settings of a [`ServerConfig`](struct.ServerConfig.html).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ProxyProtocolConfig {
+    /// The IP address reported to clients for passive data connections.
+    pub external_ip: String,
+    /// The port on which the proxy accepts control connections.
+    pub external_control_port: u16,
+}
+
+/// Selects and configures the storage backend of a [`ServerConfig`](struct.ServerConfig.html).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum StorageConfig {
+    /// Stores files on the local filesystem.
+    Filesystem {
+        /// The directory that is the root of the FTP server.
+        root: PathBuf,
+    },
+    /// Stores files in a Google Cloud Storage bucket.
+    CloudStorage {
+        /// The name of the bucket.
+        bucket: String,
+        /// The path to the service account key JSON file that grants access to the bucket.
+        service_account_key: PathBuf,
+    },
+}
+
+/// Implemented by storage backends that can be set up from a [`StorageConfig`], which allows
+/// creating a [`Server`] with `Server::from_config`.
+///
+/// [`StorageConfig`]: enum.StorageConfig.html
+/// [`Server`]: ../struct.Server.html
+pub trait FromStorageConfig: Sized {
+    /// Returns the function that creates the backend for every session, or an error if the
+    /// configuration is meant for another backend.
+    fn factory(config: &StorageConfig) -> Result<Box<dyn (Fn() -> Self) + Send + Sync>, Box<dyn Error>>;
+}
+
+impl FromStorageConfig for Filesystem {
+    fn factory(config: &StorageConfig) -> Result<Box<dyn (Fn() -> Self) + Send + Sync>, Box<dyn Error>> {
+        match config {
+            StorageConfig::Filesystem { root } => {
+                let root = root.clone();
+                Ok(Box::new(move || Filesystem::new(root.clone())))
+            }
+            other => Err(format!("a filesystem server cannot use the storage configuration {:?}", other).into()),
+        }
+    }
+}
+
+#[cfg(feature = "cloud_storage")]
+impl FromStorageConfig for crate::storage::cloud_storage::CloudStorage {
+    fn factory(config: &StorageConfig) -> Result<Box<dyn (Fn() -> Self) + Send + Sync>, Box<dyn Error>> {
+        match config {
+            StorageConfig::CloudStorage { bucket, service_account_key } => {
+                let bucket = bucket.clone();
+                let key: yup_oauth2::ServiceAccountKey = serde_json::from_slice(&std::fs::read(service_account_key)?)?;
+                Ok(Box::new(move || crate::storage::cloud_storage::CloudStorage::new(&bucket, key.clone())))
+            }
+            other => Err(format!("a cloud storage server cannot use the storage configuration {:?}", other).into()),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Parses the configuration from a TOML document.
+    pub fn from_toml(toml: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    /// Parses the configuration from a YAML document.
+    pub fn from_yaml(yaml: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+
+    /// Reads the configuration from the given file. Files with a `.yaml` or `.yml` extension are
+    /// parsed as YAML, all others as TOML.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => Self::from_yaml(&contents),
+            _ => Self::from_toml(&contents),
+        }
+    }
+
+    /// Builds the configuration from environment variables only. See [`env_overlay`] for the
+    /// variables that are read. The storage backend must be configured through either
+    /// `LIBUNFTP_FS_ROOT` or `LIBUNFTP_BUCKET_NAME` and `LIBUNFTP_SERVICE_ACCOUNT_KEY`.
+    ///
+    /// [`env_overlay`]: #method.env_overlay
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Overrides the settings for which an environment variable is set. This allows container
+    /// deployments to tweak a configuration file that is baked into an image. Every setting has a
+    /// variable:
+    ///
+    /// - `LIBUNFTP_ADDRESS`
+    /// - `LIBUNFTP_GREETING` and `LIBUNFTP_LOGIN_MESSAGE`
+    /// - `LIBUNFTP_SYSTEM_TYPE` and `LIBUNFTP_IDENTIFICATION`
+    /// - `LIBUNFTP_PASSIVE_PORTS`, written as `start-end`
+    /// - `LIBUNFTP_CERTS_FILE` and `LIBUNFTP_CERTS_PASSWORD`
+    /// - `LIBUNFTP_FTPS_AUTH_MECHANISMS`, as a comma separated list, if FTPS is configured
+    /// - `LIBUNFTP_PROXY_EXTERNAL_IP` and `LIBUNFTP_PROXY_EXTERNAL_CONTROL_PORT`
+    /// - `LIBUNFTP_IDLE_SESSION_TIMEOUT`, `LIBUNFTP_STALLED_TRANSFER_TIMEOUT`,
+    ///   `LIBUNFTP_STORAGE_TIMEOUT`, `LIBUNFTP_METADATA_CACHE_TTL` and
+    ///   `LIBUNFTP_SHUTDOWN_GRACE_PERIOD`, in seconds
+    /// - `LIBUNFTP_MAX_CONNECTIONS`
+    /// - `LIBUNFTP_BANDWIDTH_LIMIT`, `LIBUNFTP_UPLOAD_BANDWIDTH_LIMIT` and
+    ///   `LIBUNFTP_DOWNLOAD_BANDWIDTH_LIMIT`, in bytes per second
+    /// - `LIBUNFTP_ALLOW_IPS` and `LIBUNFTP_DENY_IPS`, as comma separated lists
+    /// - `LIBUNFTP_DISABLED_COMMANDS` and `LIBUNFTP_HIDDEN_PATHS`, as comma separated lists
+    /// - `LIBUNFTP_HIDE_DOTFILES`, `LIBUNFTP_NO_CLOBBER`, `LIBUNFTP_DROP_BOX` and
+    ///   `LIBUNFTP_METRICS`, `true` or `false`
+    /// - `LIBUNFTP_PARTIAL_UPLOADS`, `keep`, `delete` or `rename`
+    /// - `LIBUNFTP_FS_ROOT`, or `LIBUNFTP_BUCKET_NAME` and `LIBUNFTP_SERVICE_ACCOUNT_KEY`. Either
+    ///   of the latter two is enough to change a cloud storage configuration.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::config::ServerConfig;
+    ///
+    /// std::env::set_var("LIBUNFTP_MAX_CONNECTIONS", "50");
+    /// let config = ServerConfig::from_yaml("storage: {backend: filesystem, root: /srv/ftp}").unwrap();
+    /// let config = config.env_overlay().unwrap();
+    /// assert_eq!(config.max_connections, Some(50));
+    /// ```
+    pub fn env_overlay(self) -> Result<Self, Box<dyn Error>> {
+        self.overlay(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars<F: Fn(&str) -> Option<String>>(var: F) -> Result<Self, Box<dyn Error>> {
+        let storage = match (var("LIBUNFTP_FS_ROOT"), var("LIBUNFTP_BUCKET_NAME"), var("LIBUNFTP_SERVICE_ACCOUNT_KEY")) {
+            (Some(root), None, None) => StorageConfig::Filesystem { root: root.into() },
+            (None, Some(bucket), Some(key)) => StorageConfig::CloudStorage {
+                bucket,
+                service_account_key: key.into(),
+            },
+            _ => return Err("set either LIBUNFTP_FS_ROOT or LIBUNFTP_BUCKET_NAME and LIBUNFTP_SERVICE_ACCOUNT_KEY".into()),
+        };
+        let config = ServerConfig {
+            address: default_address(),
+            greeting: None,
+            login_message: None,
+            system_type: None,
+            identification: None,
+            passive_ports: None,
+            ftps: None,
+            proxy_protocol: None,
+            idle_session_timeout: None,
+            stalled_transfer_timeout: None,
+            storage_timeout: None,
+            metadata_cache_ttl: None,
+            shutdown_grace_period: None,
+            max_connections: None,
+            bandwidth_limit: None,
+            upload_bandwidth_limit: None,
+            download_bandwidth_limit: None,
+            allow_ips: vec![],
+            deny_ips: vec![],
+            disabled_commands: vec![],
+            hide_dotfiles: false,
+            hidden_paths: vec![],
+            no_clobber: false,
+            partial_uploads: None,
+            drop_box: false,
+            metrics: false,
+            storage,
+        };
+        config.overlay(var)
+    }
+
+    fn overlay<F: Fn(&str) -> Option<String>>(mut self, var: F) -> Result<Self, Box<dyn Error>> {
+        if let Some(address) = var("LIBUNFTP_ADDRESS") {
+            self.address = address;
+        }
+        if let Some(greeting) = var("LIBUNFTP_GREETING") {
+            self.greeting = Some(greeting);
+        }
+        if let Some(message) = var("LIBUNFTP_LOGIN_MESSAGE") {
+            self.login_message = Some(message);
+        }
+        if let Some(system_type) = var("LIBUNFTP_SYSTEM_TYPE") {
+            self.system_type = Some(system_type);
+        }
+        if let Some(identification) = var("LIBUNFTP_IDENTIFICATION") {
+            self.identification = Some(identification);
+        }
+        if let Some(range) = var("LIBUNFTP_PASSIVE_PORTS") {
+            self.passive_ports = Some(parse_port_range(&range).map_err(|e| format!("LIBUNFTP_PASSIVE_PORTS: {}", e))?);
+        }
+        match (var("LIBUNFTP_CERTS_FILE"), var("LIBUNFTP_CERTS_PASSWORD"), self.ftps.take()) {
+            (Some(certs_file), Some(certs_password), ftps) => {
+                self.ftps = Some(FtpsConfig {
+                    certs_file: certs_file.into(),
+                    certs_password,
+                    auth_mechanisms: ftps.map(|ftps| ftps.auth_mechanisms).unwrap_or_default(),
+                })
+            }
+            (Some(certs_file), None, Some(ftps)) => {
+                self.ftps = Some(FtpsConfig {
+                    certs_file: certs_file.into(),
+                    ..ftps
+                })
+            }
+            (None, Some(certs_password), Some(ftps)) => self.ftps = Some(FtpsConfig { certs_password, ..ftps }),
+            (None, None, ftps) => self.ftps = ftps,
+            _ => return Err("LIBUNFTP_CERTS_FILE and LIBUNFTP_CERTS_PASSWORD must be set together".into()),
+        }
+        if let Some(mechanisms) = var("LIBUNFTP_FTPS_AUTH_MECHANISMS") {
+            match &mut self.ftps {
+                Some(ftps) => ftps.auth_mechanisms = split_list(&mechanisms),
+                None => return Err("LIBUNFTP_FTPS_AUTH_MECHANISMS needs LIBUNFTP_CERTS_FILE and LIBUNFTP_CERTS_PASSWORD".into()),
+            }
+        }
+        match (
+            var("LIBUNFTP_PROXY_EXTERNAL_IP"),
+            parse_var(&var, "LIBUNFTP_PROXY_EXTERNAL_CONTROL_PORT")?,
+            self.proxy_protocol.take(),
+        ) {
+            (Some(external_ip), Some(external_control_port), _) => {
+                self.proxy_protocol = Some(ProxyProtocolConfig {
+                    external_ip,
+                    external_control_port,
+                })
+            }
+            (Some(external_ip), None, Some(proxy)) => self.proxy_protocol = Some(ProxyProtocolConfig { external_ip, ..proxy }),
+            (None, Some(external_control_port), Some(proxy)) => {
+                self.proxy_protocol = Some(ProxyProtocolConfig {
+                    external_control_port,
+                    ..proxy
+                })
+            }
+            (None, None, proxy) => self.proxy_protocol = proxy,
+            _ => return Err("LIBUNFTP_PROXY_EXTERNAL_IP and LIBUNFTP_PROXY_EXTERNAL_CONTROL_PORT must be set together".into()),
+        }
+        self.idle_session_timeout = parse_var(&var, "LIBUNFTP_IDLE_SESSION_TIMEOUT")?.or(self.idle_session_timeout);
+        self.stalled_transfer_timeout = parse_var(&var, "LIBUNFTP_STALLED_TRANSFER_TIMEOUT")?.or(self.stalled_transfer_timeout);
+        self.storage_timeout = parse_var(&var, "LIBUNFTP_STORAGE_TIMEOUT")?.or(self.storage_timeout);
+        self.metadata_cache_ttl = parse_var(&var, "LIBUNFTP_METADATA_CACHE_TTL")?.or(self.metadata_cache_ttl);
+        self.shutdown_grace_period = parse_var(&var, "LIBUNFTP_SHUTDOWN_GRACE_PERIOD")?.or(self.shutdown_grace_period);
+        self.max_connections = parse_var(&var, "LIBUNFTP_MAX_CONNECTIONS")?.or(self.max_connections);
+        self.bandwidth_limit = parse_var(&var, "LIBUNFTP_BANDWIDTH_LIMIT")?.or(self.bandwidth_limit);
+        self.upload_bandwidth_limit = parse_var(&var, "LIBUNFTP_UPLOAD_BANDWIDTH_LIMIT")?.or(self.upload_bandwidth_limit);
+        self.download_bandwidth_limit = parse_var(&var, "LIBUNFTP_DOWNLOAD_BANDWIDTH_LIMIT")?.or(self.download_bandwidth_limit);
+        if let Some(networks) = var("LIBUNFTP_ALLOW_IPS") {
+            self.allow_ips = split_list(&networks);
+        }
+        if let Some(networks) = var("LIBUNFTP_DENY_IPS") {
+            self.deny_ips = split_list(&networks);
+        }
+        if let Some(commands) = var("LIBUNFTP_DISABLED_COMMANDS") {
+            self.disabled_commands = split_list(&commands);
+        }
+        if let Some(globs) = var("LIBUNFTP_HIDDEN_PATHS") {
+            self.hidden_paths = split_list(&globs);
+        }
+        self.hide_dotfiles = parse_var(&var, "LIBUNFTP_HIDE_DOTFILES")?.unwrap_or(self.hide_dotfiles);
+        self.no_clobber = parse_var(&var, "LIBUNFTP_NO_CLOBBER")?.unwrap_or(self.no_clobber);
+        self.partial_uploads = parse_var(&var, "LIBUNFTP_PARTIAL_UPLOADS")?.or(self.partial_uploads);
+        self.drop_box = parse_var(&var, "LIBUNFTP_DROP_BOX")?.unwrap_or(self.drop_box);
+        self.metrics = parse_var(&var, "LIBUNFTP_METRICS")?.unwrap_or(self.metrics);
+        match (
+            var("LIBUNFTP_FS_ROOT"),
+            var("LIBUNFTP_BUCKET_NAME"),
+            var("LIBUNFTP_SERVICE_ACCOUNT_KEY"),
+            self.storage,
+        ) {
+            (Some(root), None, None, _) => self.storage = StorageConfig::Filesystem { root: root.into() },
+            (None, Some(bucket), Some(key), _) => {
+                self.storage = StorageConfig::CloudStorage {
+                    bucket,
+                    service_account_key: key.into(),
+                }
+            }
+            (None, Some(bucket), None, StorageConfig::CloudStorage { service_account_key, .. }) => {
+                self.storage = StorageConfig::CloudStorage { bucket, service_account_key }
+            }
+            (None, None, Some(key), StorageConfig::CloudStorage { bucket, .. }) => {
+                self.storage = StorageConfig::CloudStorage {
+                    bucket,
+                    service_account_key: key.into(),
+                }
+            }
+            (None, None, None, storage) => self.storage = storage,
+            _ => return Err("set either LIBUNFTP_FS_ROOT or LIBUNFTP_BUCKET_NAME and LIBUNFTP_SERVICE_ACCOUNT_KEY".into()),
+        }
+        Ok(self)
+    }
+}
+
+// Parses the value of the given environment variable, if it is set.
+fn parse_var<F, T>(var: F, name: &str) -> Result<Option<T>, Box<dyn Error>>
+where
+    F: Fn(&str) -> Option<String>,
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match var(name) {
+        Some(value) => Ok(Some(value.trim().parse().map_err(|e| format!("{}: invalid value '{}': {}", name, value, e))?)),
+        None => Ok(None),
+    }
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect()
+}
+
+fn default_address() -> String {
+    DEFAULT_ADDRESS.to_string()
+}
+
+/// Parses a port range written as `start-end`.
+pub(crate) fn parse_port_range(range: &str) -> Result<Range<u16>, String> {
+    let invalid = || format!("invalid port range '{}', expected 'start-end'", range);
+    let mut ports = range.splitn(2, '-').map(|port| port.trim().parse::<u16>());
+    match (ports.next(), ports.next()) {
+        (Some(Ok(start)), Some(Ok(end))) if start < end => Ok(start..end),
+        _ => Err(invalid()),
+    }
+}
+
+fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value: Option<String> = Option::deserialize(deserializer)?;
+    value.map(|value| value.trim().parse().map_err(de::Error::custom)).transpose()
+}
+
+fn deserialize_port_range<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Range<u16>>, D::Error> {
+    let range: Option<String> = Option::deserialize(deserializer)?;
+    range.map(|range| parse_port_range(&range).map_err(de::Error::custom)).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn toml_and_yaml() {
+        let toml = ServerConfig::from_toml(
+            r#"
+            passive_ports = "50000-51000"
+            max_connections = 10
+            partial_uploads = "delete"
+            deny_ips = ["10.0.0.0/8"]
+
+            [ftps]
+            certs_file = "/etc/unftp/certs.pfx"
+            certs_password = "secret"
+
+            [storage]
+            backend = "filesystem"
+            root = "/srv/ftp"
+            "#,
+        )
+        .unwrap();
+        let yaml = ServerConfig::from_yaml(
+            r#"
+            passive_ports: 50000-51000
+            max_connections: 10
+            partial_uploads: delete
+            deny_ips: ["10.0.0.0/8"]
+            ftps:
+              certs_file: /etc/unftp/certs.pfx
+              certs_password: secret
+            storage:
+              backend: filesystem
+              root: /srv/ftp
+            "#,
+        )
+        .unwrap();
+        assert_eq!(toml, yaml);
+        assert_eq!(toml.address, DEFAULT_ADDRESS);
+        assert_eq!(toml.passive_ports, Some(50000..51000));
+        assert_eq!(toml.max_connections, Some(10));
+        assert_eq!(toml.idle_session_timeout, None);
+        assert_eq!(toml.partial_uploads, Some(PartialUploads::Delete));
+        assert_eq!(toml.storage, StorageConfig::Filesystem { root: "/srv/ftp".into() });
+    }
+
+    #[test]
+    fn env_overlay() {
+        let vars: std::collections::HashMap<&str, &str> = vec![
+            ("LIBUNFTP_ADDRESS", "0.0.0.0:21"),
+            ("LIBUNFTP_PASSIVE_PORTS", "30000-30100"),
+            ("LIBUNFTP_CERTS_PASSWORD", "rotated"),
+            ("LIBUNFTP_DENY_IPS", "10.0.0.0/8, 192.168.0.0/16"),
+            ("LIBUNFTP_METRICS", "true"),
+            ("LIBUNFTP_PARTIAL_UPLOADS", "rename"),
+            ("LIBUNFTP_FTPS_AUTH_MECHANISMS", "TLS"),
+            ("LIBUNFTP_BUCKET_NAME", "archive"),
+        ]
+        .into_iter()
+        .collect();
+        let var = |name: &str| vars.get(name).map(|value| value.to_string());
+        let config = ServerConfig::from_toml(
+            r#"
+            max_connections = 10
+            ftps = { certs_file = "/etc/unftp/certs.pfx", certs_password = "secret" }
+            storage = { backend = "cloud_storage", bucket = "uploads", service_account_key = "/etc/unftp/key.json" }
+            "#,
+        )
+        .unwrap()
+        .overlay(var)
+        .unwrap();
+        assert_eq!(config.address, "0.0.0.0:21");
+        assert_eq!(config.passive_ports, Some(30000..30100));
+        assert_eq!(config.max_connections, Some(10));
+        assert_eq!(
+            config.ftps,
+            Some(FtpsConfig {
+                certs_file: "/etc/unftp/certs.pfx".into(),
+                certs_password: "rotated".into(),
+                auth_mechanisms: vec!["TLS".to_string()]
+            })
+        );
+        assert_eq!(
+            config.storage,
+            StorageConfig::CloudStorage {
+                bucket: "archive".into(),
+                service_account_key: "/etc/unftp/key.json".into()
+            }
+        );
+        assert_eq!(config.deny_ips, vec!["10.0.0.0/8", "192.168.0.0/16"]);
+        assert!(config.metrics);
+        assert_eq!(config.partial_uploads, Some(PartialUploads::Rename));
+    }
+
+    #[test]
+    fn from_vars() {
+        assert!(ServerConfig::from_vars(|_| None).is_err());
+        assert!(ServerConfig::from_vars(|name| if name == "LIBUNFTP_CERTS_FILE" { Some("/certs.pfx".into()) } else { None }).is_err());
+
+        let config = ServerConfig::from_vars(|name| match name {
+            "LIBUNFTP_FS_ROOT" => Some("/srv/ftp".into()),
+            "LIBUNFTP_MAX_CONNECTIONS" => Some("many".into()),
+            _ => None,
+        });
+        assert!(config.unwrap_err().to_string().starts_with("LIBUNFTP_MAX_CONNECTIONS"));
+
+        let config = ServerConfig::from_vars(|name| if name == "LIBUNFTP_FS_ROOT" { Some("/srv/ftp".into()) } else { None }).unwrap();
+        assert_eq!(config.address, DEFAULT_ADDRESS);
+        assert_eq!(config.storage, StorageConfig::Filesystem { root: "/srv/ftp".into() });
+    }
+
+    #[test]
+    fn invalid_port_range() {
+        assert!(parse_port_range("50000").is_err());
+        assert!(parse_port_range("51000-50000").is_err());
+        assert!(parse_port_range("a-b").is_err());
+        assert_eq!(parse_port_range("50000 - 51000"), Ok(50000..51000));
+    }
+}
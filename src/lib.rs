@@ -14,17 +14,48 @@
 //!  let ftp_home = std::env::temp_dir();
 //!  let server = libunftp::Server::new_with_fs_root(ftp_home)
 //!    .greeting("Welcome to my FTP server")
-//!    .passive_ports(50000..65535);
+//!    .passive_ports(50000..65535)
+//!    .build()
+//!    .unwrap();
 //!
 //!  server.listen("127.0.0.1:2121");
 //! ```
+//!
+//! # Features
+//!
+//! Parts of the server that pull in large dependencies can be left out with cargo features. These
+//! are on by default:
+//!
+//! - `ftps`: FTPS, with `AUTH TLS` and `PROT P`.
+//! - `metrics`: the collection of prometheus metrics.
+//! - `proxy_protocol`: PROXY protocol mode, for running behind a proxy such as haproxy.
+//!
+//! Turn off the default features to build a minimal server with just the filesystem backend and
+//! the anonymous authenticator. The other storage backends and authenticators, the HTTP endpoint,
+//! notifications and configuration files are off by default and come with features of their own,
+//! as does `gssapi`, for GSSAPI authentication with `AUTH GSSAPI` as RFC 2228 describes. So are
+//! `tracing`, which traces sessions, commands and transfers as spans, and `otlp`, which exports
+//! those spans to an OpenTelemetry collector.
 
+pub mod audit;
 pub mod auth;
+#[cfg(feature = "config")]
+pub mod config;
 pub(crate) mod metrics;
+pub mod notification;
+#[cfg(feature = "otlp")]
+pub mod otlp;
 pub(crate) mod server;
 pub mod storage;
 
-pub use crate::server::ftpserver::Server;
+pub use crate::server::ftpserver::{Server, ServerBuilder};
+pub use crate::server::{
+    BackendStatus, CommandContext, ConfigError, ControlChanError, ControlChanErrorKind, CustomCommandHandler, DosListFormatter, FilenamePolicy, HealthCheck,
+    HealthStatus, ListFormatter, Middleware, Next, PartialUploads, PathMapper, Reply, ReplyBuilder, ReplyCatalog, ReplyCode, ReplyFilter, Request,
+    ServerHandle, SessionInfo, SessionObserver, SocketOptions, TransferInfo, UnixListFormatter,
+};
+#[cfg(feature = "gssapi")]
+pub use crate::server::{GssapiContext, GssapiResult};
 
 #[cfg(any(feature = "rest_auth", feature = "pam_auth"))]
 #[macro_use]
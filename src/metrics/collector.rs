@@ -0,0 +1,300 @@
+//! Contains the `Metrics` struct that gathers the metrics of a server in a prometheus `Registry`.
+
+use super::command_label;
+use crate::server::{Command, ControlChanErrorKind, Event, InternalMsg, Reply, ReplyCode};
+
+use lazy_static::*;
+use prometheus::{exponential_buckets, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry};
+#[cfg(feature = "http_endpoint")]
+use prometheus::{Encoder, TextEncoder};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+lazy_static! {
+    // The metrics in the default registry, shared by all servers that don't bring their own.
+    static ref DEFAULT_METRICS: Arc<Metrics> = Arc::new(Metrics::new(prometheus::default_registry(), "", HashMap::new()).unwrap());
+}
+
+/// The metrics of a server, registered in a prometheus `Registry`.
+pub struct Metrics {
+    // Kept to serve its contents from the HTTP endpoint.
+    #[cfg(feature = "http_endpoint")]
+    registry: Registry,
+    auth_failures: IntCounter,
+    logins_total: IntCounterVec,
+    idle_timeouts_total: IntCounter,
+    sessions: IntGauge,
+    data_connections: IntGauge,
+    reserved_passive_ports: IntGauge,
+    passive_port_reservation_failures: IntCounter,
+    backend_write_bytes: IntCounter,
+    backend_read_bytes: IntCounter,
+    backend_write_files: IntCounter,
+    backend_read_files: IntCounter,
+    command_total: IntCounterVec,
+    command_duration: HistogramVec,
+    reply_total: IntCounterVec,
+    transferred_bytes: IntCounterVec,
+    transfer_progress_bytes: IntCounterVec,
+    transfer_duration: HistogramVec,
+    transfer_size: HistogramVec,
+    error_total: IntCounterVec,
+}
+
+// Creates the metrics and registers them, giving their names the prefix and adding the constant
+// labels.
+struct Factory<'a> {
+    registry: &'a Registry,
+    prefix: &'a str,
+    const_labels: HashMap<String, String>,
+}
+
+impl<'a> Factory<'a> {
+    fn opts(&self, name: &str, help: &str) -> Opts {
+        Opts::new(name, help).namespace(self.prefix).const_labels(self.const_labels.clone())
+    }
+
+    fn histogram_opts(&self, name: &str, help: &str) -> HistogramOpts {
+        HistogramOpts::new(name, help).namespace(self.prefix).const_labels(self.const_labels.clone())
+    }
+
+    fn counter(&self, name: &str, help: &str) -> prometheus::Result<IntCounter> {
+        let counter = IntCounter::with_opts(self.opts(name, help))?;
+        self.registry.register(Box::new(counter.clone()))?;
+        Ok(counter)
+    }
+
+    fn counter_vec(&self, name: &str, help: &str, labels: &[&str]) -> prometheus::Result<IntCounterVec> {
+        let counter = IntCounterVec::new(self.opts(name, help), labels)?;
+        self.registry.register(Box::new(counter.clone()))?;
+        Ok(counter)
+    }
+
+    fn gauge(&self, name: &str, help: &str) -> prometheus::Result<IntGauge> {
+        let gauge = IntGauge::with_opts(self.opts(name, help))?;
+        self.registry.register(Box::new(gauge.clone()))?;
+        Ok(gauge)
+    }
+
+    fn histogram_vec(&self, opts: HistogramOpts, labels: &[&str]) -> prometheus::Result<HistogramVec> {
+        let histogram = HistogramVec::new(opts, labels)?;
+        self.registry.register(Box::new(histogram.clone()))?;
+        Ok(histogram)
+    }
+}
+
+impl Metrics {
+    /// Creates the metrics and registers them in the given registry. The names of the metrics
+    /// get the prefix, if it isn't empty, and the constant labels are added to all of them.
+    pub fn new(registry: &Registry, prefix: &str, const_labels: HashMap<String, String>) -> prometheus::Result<Self> {
+        let f = Factory {
+            registry,
+            prefix,
+            const_labels,
+        };
+        Ok(Metrics {
+            #[cfg(feature = "http_endpoint")]
+            registry: registry.clone(),
+            auth_failures: f.counter("ftp_auth_failures", "Total number of authentication failures.")?,
+            logins_total: f.counter_vec("ftp_logins_total", "Total number of login attempts.", &["result", "user_type"])?,
+            idle_timeouts_total: f.counter(
+                "ftp_idle_timeouts_total",
+                "Total number of sessions closed because they were idle for too long.",
+            )?,
+            sessions: f.gauge("ftp_sessions_total", "Total number of FTP sessions.")?,
+            data_connections: f.gauge("ftp_data_connections", "Number of open data connections.")?,
+            reserved_passive_ports: f.gauge(
+                "ftp_passive_ports_reserved",
+                "Number of passive ports reserved by the proxy protocol switchboard.",
+            )?,
+            passive_port_reservation_failures: f.counter(
+                "ftp_passive_port_reservation_failures_total",
+                "Total number of passive mode requests refused because no passive port could be reserved.",
+            )?,
+            backend_write_bytes: f.counter("ftp_backend_write_bytes", "Total number of bytes written to the backend.")?,
+            backend_read_bytes: f.counter("ftp_backend_read_bytes", "Total number of bytes retrieved from the backend.")?,
+            backend_write_files: f.counter("ftp_backend_write_files", "Total number of files written to the backend.")?,
+            backend_read_files: f.counter("ftp_backend_read_files", "Total number of files retrieved from the backend.")?,
+            command_total: f.counter_vec("ftp_command_total", "Total number of commands received.", &["command"])?,
+            command_duration: f.histogram_vec(f.histogram_opts("ftp_command_duration_seconds", "Time spent handling commands."), &["command"])?,
+            reply_total: f.counter_vec("ftp_reply_total", "Total number of reply codes server sent to clients.", &["range"])?,
+            transferred_bytes: f.counter_vec(
+                "ftp_transferred_bytes",
+                "Total number of bytes moved over data connections by file transfers.",
+                &["direction", "result"],
+            )?,
+            transfer_progress_bytes: f.counter_vec(
+                "ftp_transfer_progress_bytes",
+                "Total number of bytes moved over data connections by file transfers, counted while they are in progress.",
+                &["direction"],
+            )?,
+            transfer_duration: f.histogram_vec(
+                f.histogram_opts("ftp_transfer_duration_seconds", "Duration of successful file transfers.")
+                    .buckets(exponential_buckets(0.01, 4.0, 10)?),
+                &["direction"],
+            )?,
+            transfer_size: f.histogram_vec(
+                f.histogram_opts("ftp_transfer_size_bytes", "Size of successfully transferred files.")
+                    .buckets(exponential_buckets(1024.0, 4.0, 12)?),
+                &["direction"],
+            )?,
+            error_total: f.counter_vec("ftp_error_total", "Total number of errors encountered.", &["type"])?,
+        })
+    }
+
+    /// Returns the metrics in the default registry.
+    pub fn default_registry() -> Arc<Self> {
+        DEFAULT_METRICS.clone()
+    }
+
+    /// Returns all metrics in the registry, including those that were not registered by us, in the
+    /// prometheus text format.
+    #[cfg(feature = "http_endpoint")]
+    pub fn encode(&self) -> prometheus::Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Add a metric for an event.
+    pub fn add_event_metric(&self, event: &Event) {
+        match event {
+            Event::Command(cmd) => {
+                self.add_command_metric(cmd);
+            }
+            Event::InternalMsg(msg) => match msg {
+                InternalMsg::SendData { bytes } => {
+                    self.backend_read_bytes.inc_by(*bytes);
+                    self.backend_read_files.inc();
+                }
+                InternalMsg::WrittenData { bytes } => {
+                    self.backend_write_bytes.inc_by(*bytes);
+                    self.backend_write_files.inc();
+                }
+                InternalMsg::TransferProgress { direction, moved, .. } => {
+                    self.transfer_progress_bytes.with_label_values(&[direction]).inc_by(*moved as i64);
+                }
+                _ => {}
+            },
+        }
+    }
+
+    /// Increase the metrics gauge for client sessions
+    pub fn inc_session(&self) {
+        self.sessions.inc();
+    }
+
+    /// Decrease the metrics gauge for client sessions
+    pub fn dec_session(&self) {
+        self.sessions.dec();
+    }
+
+    /// Increase the metrics gauge for open data connections
+    pub fn inc_data_connection(&self) {
+        self.data_connections.inc();
+    }
+
+    /// Decrease the metrics gauge for open data connections
+    pub fn dec_data_connection(&self) {
+        self.data_connections.dec();
+    }
+
+    /// Set the number of passive ports reserved by the proxy protocol switchboard.
+    pub fn set_reserved_passive_ports(&self, reserved: usize) {
+        self.reserved_passive_ports.set(reserved as i64);
+    }
+
+    /// Increase the counter for passive mode requests that got no port.
+    pub fn inc_passive_port_reservation_failure(&self) {
+        self.passive_port_reservation_failures.inc();
+    }
+
+    /// Add a metric for a login attempt. Attempts of anonymous and named users are counted apart.
+    pub fn add_login_metric(&self, success: bool, anonymous: bool) {
+        let result = if success { "success" } else { "failure" };
+        let user_type = if anonymous { "anonymous" } else { "named" };
+        self.logins_total.with_label_values(&[result, user_type]).inc();
+        if !success {
+            self.auth_failures.inc();
+        }
+    }
+
+    /// Increase the counter for sessions closed by the idle session timeout.
+    pub fn inc_idle_timeout(&self) {
+        self.idle_timeouts_total.inc();
+    }
+
+    /// Add the bytes moved by a file transfer. The direction is either `upload` or `download` and the
+    /// result tells how the transfer ended: `success`, `client_error` when the data connection
+    /// failed, `backend_error` when the storage backend failed, `stalled` or `aborted`.
+    pub fn add_transferred_bytes_metric(&self, direction: &str, result: &str, bytes: u64) {
+        self.transferred_bytes.with_label_values(&[direction, result]).inc_by(bytes as i64);
+    }
+
+    /// Add the duration and size of a successful file transfer to the histograms for the given
+    /// direction, `upload` or `download`.
+    pub fn add_transfer_histogram_metrics(&self, direction: &str, duration: Duration, bytes: u64) {
+        self.transfer_duration.with_label_values(&[direction]).observe(duration.as_secs_f64());
+        self.transfer_size.with_label_values(&[direction]).observe(bytes as f64);
+    }
+
+    /// Add a metric for an FTP server error.
+    pub fn add_error_metric(&self, error: &ControlChanErrorKind) {
+        let error_str = error.to_string();
+        let label = error_str.split_whitespace().next().unwrap_or("unknown").to_lowercase();
+        self.error_total.with_label_values(&[&label]).inc();
+    }
+
+    fn add_command_metric(&self, cmd: &Command) {
+        self.command_total.with_label_values(&[&command_label(cmd)]).inc();
+    }
+
+    /// Add the time it took to handle a command.
+    pub fn add_command_duration_metric(&self, cmd: &Command, duration: Duration) {
+        self.command_duration.with_label_values(&[&command_label(cmd)]).observe(duration.as_secs_f64());
+    }
+
+    /// Add a metric for a reply.
+    pub fn add_reply_metric(&self, reply: &Reply) {
+        match *reply {
+            Reply::None => {}
+            Reply::CodeAndMsg { code, .. } => self.add_replycode_metric(code),
+            Reply::MultiLine { code, .. } => self.add_replycode_metric(code),
+        }
+    }
+
+    fn add_replycode_metric(&self, code: ReplyCode) {
+        let range = format!("{}xx", u32::from(code) / 100 % 10);
+        self.reply_total.with_label_values(&[&range]).inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+    use prometheus::Registry;
+    use std::collections::HashMap;
+
+    #[test]
+    fn prefix_and_const_labels() {
+        let registry = Registry::new();
+        let mut labels = HashMap::new();
+        labels.insert("instance".to_string(), "ftp1".to_string());
+        let metrics = Metrics::new(&registry, "myapp", labels).unwrap();
+        metrics.inc_idle_timeout();
+
+        let family = registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "myapp_ftp_idle_timeouts_total")
+            .unwrap();
+        let metric = &family.get_metric()[0];
+        assert_eq!(metric.get_label()[0].get_name(), "instance");
+        assert_eq!(metric.get_label()[0].get_value(), "ftp1");
+        assert_eq!(metric.get_counter().get_value() as i64, 1);
+
+        // The same metrics cannot be registered twice.
+        assert!(Metrics::new(&registry, "myapp", HashMap::new()).is_err());
+    }
+}
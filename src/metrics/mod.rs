@@ -0,0 +1,23 @@
+//! Contains the `Metrics` struct with the `add...metric` methods that are used for gathering metrics.
+//!
+//! The metrics are collected with prometheus when the `metrics` feature is on. Without it a
+//! `Metrics` that does nothing takes its place, so that the rest of the server doesn't need to
+//! know which one it has.
+
+#[cfg(feature = "metrics")]
+mod collector;
+#[cfg(not(feature = "metrics"))]
+mod noop;
+
+#[cfg(feature = "metrics")]
+pub use collector::Metrics;
+#[cfg(not(feature = "metrics"))]
+pub use noop::Metrics;
+
+use crate::server::Command;
+
+// The name of the command as used in labels.
+pub(crate) fn command_label(cmd: &Command) -> String {
+    let cmd_str = cmd.to_string();
+    cmd_str.split_whitespace().next().unwrap_or("unknown").to_lowercase()
+}
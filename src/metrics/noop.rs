@@ -0,0 +1,61 @@
+//! Contains the `Metrics` struct that is used when the `metrics` feature is off. There is no way to
+//! enable metrics then, so servers never have one and its methods are never called.
+
+use crate::server::{Command, ControlChanErrorKind, Event, Reply};
+
+use std::time::Duration;
+
+/// The metrics of a server, which aren't collected without the `metrics` feature.
+#[allow(dead_code)]
+pub struct Metrics;
+
+#[allow(unused_variables)]
+impl Metrics {
+    /// Add a metric for an event.
+    pub fn add_event_metric(&self, event: &Event) {}
+
+    /// Increase the metrics gauge for client sessions
+    pub fn inc_session(&self) {}
+
+    /// Decrease the metrics gauge for client sessions
+    pub fn dec_session(&self) {}
+
+    /// Increase the metrics gauge for open data connections
+    pub fn inc_data_connection(&self) {}
+
+    /// Decrease the metrics gauge for open data connections
+    pub fn dec_data_connection(&self) {}
+
+    /// Set the number of passive ports reserved by the proxy protocol switchboard.
+    pub fn set_reserved_passive_ports(&self, reserved: usize) {}
+
+    /// Increase the counter for passive mode requests that got no port.
+    pub fn inc_passive_port_reservation_failure(&self) {}
+
+    /// Add a metric for a login attempt.
+    pub fn add_login_metric(&self, success: bool, anonymous: bool) {}
+
+    /// Increase the counter for sessions closed by the idle session timeout.
+    pub fn inc_idle_timeout(&self) {}
+
+    /// Add the bytes moved by a file transfer.
+    pub fn add_transferred_bytes_metric(&self, direction: &str, result: &str, bytes: u64) {}
+
+    /// Add the duration and size of a successful file transfer.
+    pub fn add_transfer_histogram_metrics(&self, direction: &str, duration: Duration, bytes: u64) {}
+
+    /// Add a metric for an FTP server error.
+    pub fn add_error_metric(&self, error: &ControlChanErrorKind) {}
+
+    /// Add the time it took to handle a command.
+    pub fn add_command_duration_metric(&self, cmd: &Command, duration: Duration) {}
+
+    /// Add a metric for a reply.
+    pub fn add_reply_metric(&self, reply: &Reply) {}
+
+    /// Returns the metrics in the prometheus text format, of which there are none.
+    #[cfg(feature = "http_endpoint")]
+    pub fn encode(&self) -> std::io::Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
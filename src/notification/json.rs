@@ -0,0 +1,64 @@
+//! The JSON representation of the file events, shared by the notifiers that send them elsewhere.
+
+use super::{FileEvent, FileEventKind};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::json;
+
+// The name of the kind of event as used in the payloads.
+pub(crate) fn event_name(kind: &FileEventKind) -> &'static str {
+    match kind {
+        FileEventKind::Uploaded { .. } => "uploaded",
+        FileEventKind::Downloaded { .. } => "downloaded",
+        FileEventKind::Deleted => "deleted",
+        FileEventKind::Renamed { .. } => "renamed",
+        FileEventKind::DirectoryCreated => "directory_created",
+        FileEventKind::DirectoryRemoved => "directory_removed",
+    }
+}
+
+pub(crate) fn payload(event: &FileEvent) -> String {
+    let mut payload = json!({
+        "event": event_name(&event.kind),
+        "path": event.path.to_string_lossy(),
+        "username": event.username,
+        "session_id": event.session_id,
+        "time": DateTime::<Utc>::from(event.time).to_rfc3339_opts(SecondsFormat::Millis, true),
+    });
+    match &event.kind {
+        FileEventKind::Uploaded { bytes } | FileEventKind::Downloaded { bytes } => payload["bytes"] = json!(bytes),
+        FileEventKind::Renamed { from } => payload["from"] = json!(from.to_string_lossy()),
+        _ => {}
+    }
+    payload.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::payload;
+    use crate::notification::{FileEvent, FileEventKind};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn json_payload() {
+        let event = FileEvent {
+            kind: FileEventKind::Renamed { from: "/a.txt".into() },
+            path: "/b.txt".into(),
+            username: Some("alice".to_string()),
+            session_id: "s1".to_string(),
+            time: UNIX_EPOCH + Duration::from_millis(1_588_597_509_250),
+        };
+        let payload: serde_json::Value = serde_json::from_str(&payload(&event)).unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "event": "renamed",
+                "path": "/b.txt",
+                "from": "/a.txt",
+                "username": "alice",
+                "session_id": "s1",
+                "time": "2020-05-04T13:05:09.250Z",
+            })
+        );
+    }
+}
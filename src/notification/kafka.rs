@@ -0,0 +1,124 @@
+//! A [`FileEventListener`] that produces the events as JSON messages to a Kafka topic.
+//!
+//! The messages are keyed by the path of the file, so that the events of a file end up in the same
+//! partition. They are produced one after the other, in the order they happened.
+//!
+//! [`FileEventListener`]: ../trait.FileEventListener.html
+
+use super::json::payload;
+use super::{FileEvent, FileEventListener};
+
+use rdkafka::config::ClientConfig;
+use rdkafka::error::KafkaResult;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use slog::{o, warn, Drain, Logger};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+// How many events may wait to be produced before new ones are dropped.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Produces the file events to a Kafka topic.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::notification::kafka::KafkaNotifier;
+/// use libunftp::Server;
+///
+/// let kafka = KafkaNotifier::new("localhost:9092", "ftp-events").unwrap().build();
+/// let server = Server::new_with_fs_root("/tmp").notify(kafka);
+/// ```
+pub struct KafkaNotifier {
+    kafka: Kafka,
+    // The queue of the task that produces the events in order. The task is started with the first
+    // event, since the notifier may be created outside of the runtime.
+    queue: Mutex<Option<mpsc::Sender<FileEvent>>>,
+}
+
+/// Sets up a [`KafkaNotifier`], as returned by [`KafkaNotifier::new`].
+///
+/// [`KafkaNotifier`]: struct.KafkaNotifier.html
+/// [`KafkaNotifier::new`]: struct.KafkaNotifier.html#method.new
+pub struct KafkaNotifierBuilder(Kafka);
+
+#[derive(Clone)]
+struct Kafka {
+    producer: FutureProducer,
+    topic: String,
+    logger: Logger,
+}
+
+impl KafkaNotifier {
+    /// Starts setting up a notifier that connects to the given comma separated list of brokers.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<T: Into<String>>(brokers: &str, topic: T) -> KafkaResult<KafkaNotifierBuilder> {
+        Self::with_config(ClientConfig::new().set("bootstrap.servers", brokers), topic)
+    }
+
+    /// Starts setting up a notifier with a producer configured by the given configuration, for
+    /// instance to set up authentication or compression.
+    pub fn with_config<T: Into<String>>(config: &ClientConfig, topic: T) -> KafkaResult<KafkaNotifierBuilder> {
+        Ok(KafkaNotifierBuilder(Kafka {
+            producer: config.create()?,
+            topic: topic.into(),
+            logger: Logger::root(slog_stdlog::StdLog.fuse(), o!()),
+        }))
+    }
+}
+
+impl KafkaNotifierBuilder {
+    /// Sets the logger that failed deliveries are reported to. By default they go to the `log`
+    /// crate.
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.0.logger = logger;
+        self
+    }
+
+    /// Creates the notifier.
+    pub fn build(self) -> KafkaNotifier {
+        KafkaNotifier {
+            kafka: self.0,
+            queue: Mutex::new(None),
+        }
+    }
+}
+
+impl FileEventListener for KafkaNotifier {
+    fn receive(&self, event: FileEvent) {
+        let mut queue = self.queue.lock().unwrap();
+        let tx = queue.get_or_insert_with(|| {
+            let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+            tokio::spawn(self.kafka.clone().produce(rx));
+            tx
+        });
+        match tx.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(event)) => warn!(
+                self.kafka.logger,
+                "Dropping event for {}: too many events waiting for Kafka topic {}",
+                event.path.display(),
+                self.kafka.topic
+            ),
+            // The task is gone, which only happens if it panicked.
+            Err(TrySendError::Closed(_)) => *queue = None,
+        }
+    }
+}
+
+impl Kafka {
+    // Produces the events one by one, so that a later event never overtakes an earlier one.
+    async fn produce(self, mut rx: mpsc::Receiver<FileEvent>) {
+        while let Some(event) = rx.recv().await {
+            let key = event.path.to_string_lossy();
+            let body = payload(&event);
+            let record = FutureRecord::to(&self.topic).key(key.as_ref()).payload(&body);
+            // Don't wait for room in the queue of the producer. Its delivery timeout bounds how
+            // long a message may be retried.
+            if let Err((err, _)) = self.producer.send(record, Duration::from_secs(0)).await {
+                warn!(self.logger, "Could not produce event to Kafka topic {}: {}", self.topic, err);
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Contains the events the server emits when clients change or retrieve files, so that
+//! applications can react to them without polling the storage.
+//!
+//! Register a listener with [`ServerBuilder::notify`]. A listener is anything that implements
+//! [`FileEventListener`], including the sending half of a tokio unbounded channel:
+//!
+//! ```rust
+//! use libunftp::notification::FileEvent;
+//! use libunftp::Server;
+//! use tokio::sync::mpsc;
+//!
+//! let (tx, rx) = mpsc::unbounded_channel::<FileEvent>();
+//! let server = Server::new_with_fs_root("/tmp").notify(tx);
+//! // Receive the events as they happen with rx.recv().await
+//! ```
+//!
+//! [`ServerBuilder::notify`]: ../struct.ServerBuilder.html#method.notify
+//! [`FileEventListener`]: trait.FileEventListener.html
+
+#[cfg(any(feature = "webhook", feature = "pubsub", feature = "kafka"))]
+mod json;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "pubsub")]
+pub mod pubsub;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// What happened to a file or directory.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileEventKind {
+    /// A file was uploaded completely with `STOR`.
+    Uploaded {
+        /// The number of bytes written.
+        bytes: u64,
+    },
+    /// A file was downloaded completely with `RETR`.
+    Downloaded {
+        /// The number of bytes sent.
+        bytes: u64,
+    },
+    /// A file was deleted with `DELE`.
+    Deleted,
+    /// A file or directory was renamed with `RNFR` and `RNTO`. The event's path is the new name.
+    Renamed {
+        /// The old name.
+        from: PathBuf,
+    },
+    /// A directory was created with `MKD`.
+    DirectoryCreated,
+    /// A directory was removed with `RMD`.
+    DirectoryRemoved,
+}
+
+/// An operation that completed successfully.
+#[derive(Clone, Debug)]
+pub struct FileEvent {
+    /// What happened.
+    pub kind: FileEventKind,
+    /// The absolute path of the file or directory, where `/` is the root of the storage, for
+    /// instance `/uploads/report.pdf`.
+    pub path: PathBuf,
+    /// The name the client logged in with.
+    pub username: Option<String>,
+    /// The ID of the session, as found in the logs.
+    pub session_id: String,
+    /// When the operation completed.
+    pub time: SystemTime,
+}
+
+/// Receives the events of a server.
+pub trait FileEventListener: Send + Sync {
+    /// Handles the event. This is called from the task of the session, so listeners that need to
+    /// do slow work, like network I/O, should hand it off to a task of their own.
+    fn receive(&self, event: FileEvent);
+}
+
+/// Sends the events over the channel. Events are dropped once the receiver is gone.
+impl FileEventListener for UnboundedSender<FileEvent> {
+    fn receive(&self, event: FileEvent) {
+        let _ = self.send(event);
+    }
+}
+
+// Emits the events of a single session.
+#[derive(Clone)]
+pub(crate) struct Notifier {
+    listener: Arc<dyn FileEventListener>,
+    session_id: String,
+}
+
+impl Notifier {
+    pub fn new(listener: Arc<dyn FileEventListener>, session_id: String) -> Self {
+        Notifier { listener, session_id }
+    }
+
+    pub fn notify(&self, kind: FileEventKind, path: PathBuf, username: Option<String>) {
+        self.listener.receive(FileEvent {
+            kind,
+            path,
+            username,
+            session_id: self.session_id.clone(),
+            time: SystemTime::now(),
+        });
+    }
+}
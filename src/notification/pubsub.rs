@@ -0,0 +1,135 @@
+//! A [`FileEventListener`] that publishes the events as JSON messages to a Google Cloud Pub/Sub
+//! topic.
+//!
+//! Every message carries the kind of event and the path in its attributes, so that subscriptions
+//! can filter on them, for instance to receive completed uploads only.
+//!
+//! [`FileEventListener`]: ../trait.FileEventListener.html
+
+use super::json::{event_name, payload};
+use super::{FileEvent, FileEventListener};
+
+use hyper::{
+    client::connect::HttpConnector,
+    http::{header, uri::InvalidUri, Method, Uri},
+    Body, Client, Request,
+};
+use hyper_rustls::HttpsConnector;
+use serde_json::json;
+use slog::{o, warn, Drain, Logger};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use yup_oauth2::authenticator::Authenticator;
+use yup_oauth2::{ServiceAccountAuthenticator, ServiceAccountKey};
+
+const PUBSUB_SCOPE: &str = "https://www.googleapis.com/auth/pubsub";
+
+/// Publishes the file events to a Pub/Sub topic.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use libunftp::notification::pubsub::PubSubNotifier;
+/// use libunftp::Server;
+///
+/// # async fn run() {
+/// let key = yup_oauth2::read_service_account_key("service-account.json").await.unwrap();
+/// let pubsub = PubSubNotifier::new("my-project", "ftp-events", key).unwrap().build();
+/// let server = Server::new_with_fs_root("/tmp").notify(pubsub);
+/// # }
+/// ```
+pub struct PubSubNotifier(Arc<PubSub>);
+
+/// Sets up a [`PubSubNotifier`], as returned by [`PubSubNotifier::new`].
+///
+/// [`PubSubNotifier`]: struct.PubSubNotifier.html
+/// [`PubSubNotifier::new`]: struct.PubSubNotifier.html#method.new
+pub struct PubSubNotifierBuilder(PubSub);
+
+struct PubSub {
+    client: Client<HttpsConnector<HttpConnector>>,
+    publish_uri: Uri,
+    service_account_key: ServiceAccountKey,
+    // Created on first use. It caches the access token until it expires.
+    authenticator: Mutex<Option<Authenticator<HttpsConnector<HttpConnector>>>>,
+    logger: Logger,
+}
+
+impl PubSubNotifier {
+    /// Starts setting up a notifier that publishes to the given topic of the given project,
+    /// authenticating with the service account key.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(project: &str, topic: &str, service_account_key: ServiceAccountKey) -> Result<PubSubNotifierBuilder, InvalidUri> {
+        let publish_uri = format!("https://pubsub.googleapis.com/v1/projects/{}/topics/{}:publish", project, topic).parse()?;
+        Ok(PubSubNotifierBuilder(PubSub {
+            client: Client::builder().build(HttpsConnector::new()),
+            publish_uri,
+            service_account_key,
+            authenticator: Mutex::new(None),
+            logger: Logger::root(slog_stdlog::StdLog.fuse(), o!()),
+        }))
+    }
+}
+
+impl PubSubNotifierBuilder {
+    /// Sets the logger that failed deliveries are reported to. By default they go to the `log`
+    /// crate.
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.0.logger = logger;
+        self
+    }
+
+    /// Creates the notifier.
+    pub fn build(self) -> PubSubNotifier {
+        PubSubNotifier(Arc::new(self.0))
+    }
+}
+
+impl FileEventListener for PubSubNotifier {
+    fn receive(&self, event: FileEvent) {
+        let pubsub = self.0.clone();
+        tokio::spawn(async move {
+            if let Err(err) = pubsub.publish(&event).await {
+                warn!(pubsub.logger, "Could not publish event to {}: {}", pubsub.publish_uri, err);
+            }
+        });
+    }
+}
+
+impl PubSub {
+    async fn token(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut authenticator = self.authenticator.lock().await;
+        if authenticator.is_none() {
+            let created = ServiceAccountAuthenticator::builder(self.service_account_key.clone())
+                .hyper_client(self.client.clone())
+                .build()
+                .await?;
+            *authenticator = Some(created);
+        }
+        let token = authenticator.as_ref().unwrap().token(&[PUBSUB_SCOPE]).await?;
+        Ok(token.as_str().to_string())
+    }
+
+    async fn publish(&self, event: &FileEvent) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let body = json!({
+            "messages": [{
+                "data": base64::encode(payload(event)),
+                "attributes": {
+                    "event": event_name(&event.kind),
+                    "path": event.path.to_string_lossy(),
+                },
+            }],
+        });
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.publish_uri.clone())
+            .header(header::AUTHORIZATION, format!("Bearer {}", self.token().await?))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))?;
+        let response = self.client.request(request).await?;
+        if !response.status().is_success() {
+            return Err(format!("Pub/Sub replied {}", response.status()).into());
+        }
+        Ok(())
+    }
+}
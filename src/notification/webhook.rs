@@ -0,0 +1,176 @@
+//! A [`FileEventListener`] that POSTs the events as JSON to a URL.
+//!
+//! When a secret is configured, every request carries an `X-Libunftp-Signature` header with the
+//! hex encoded HMAC-SHA256 of the body, prefixed by `sha256=`, so that the receiver can verify
+//! where the request came from. Deliveries that fail because of a connection error or a `5xx` or
+//! `429` response are retried with exponential back-off.
+//!
+//! [`FileEventListener`]: ../trait.FileEventListener.html
+
+use super::json::payload;
+use super::{FileEvent, FileEventListener};
+
+use hmac::{Hmac, Mac, NewMac};
+use hyper::client::HttpConnector;
+use hyper::{http::uri::InvalidUri, Body, Client, Method, Request, StatusCode, Uri};
+use hyper_tls::HttpsConnector;
+use sha2::Sha256;
+use slog::{error, o, warn, Drain, Logger};
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The header that holds the signature of the body.
+pub const SIGNATURE_HEADER: &str = "X-Libunftp-Signature";
+
+/// Sends the file events to a webhook.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::notification::webhook::WebhookNotifier;
+/// use libunftp::Server;
+/// use std::time::Duration;
+///
+/// let webhook = WebhookNotifier::new("https://ingest.example.com/ftp-events")
+///     .unwrap()
+///     .secret("s3cr3t")
+///     .max_retries(5)
+///     .retry_delay(Duration::from_millis(500))
+///     .build();
+/// let server = Server::new_with_fs_root("/tmp").notify(webhook);
+/// ```
+pub struct WebhookNotifier(Arc<Webhook>);
+
+/// Sets up a [`WebhookNotifier`], as returned by [`WebhookNotifier::new`].
+///
+/// [`WebhookNotifier`]: struct.WebhookNotifier.html
+/// [`WebhookNotifier::new`]: struct.WebhookNotifier.html#method.new
+pub struct WebhookNotifierBuilder(Webhook);
+
+struct Webhook {
+    client: Client<HttpsConnector<HttpConnector>>,
+    url: Uri,
+    secret: Option<Vec<u8>>,
+    max_retries: u32,
+    retry_delay: Duration,
+    logger: Logger,
+}
+
+impl WebhookNotifier {
+    /// Starts setting up a notifier that posts to the given URL, which may use `http` or `https`.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(url: &str) -> Result<WebhookNotifierBuilder, InvalidUri> {
+        Ok(WebhookNotifierBuilder(Webhook {
+            client: Client::builder().build(HttpsConnector::new()),
+            url: url.parse()?,
+            secret: None,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            logger: Logger::root(slog_stdlog::StdLog.fuse(), o!()),
+        }))
+    }
+}
+
+impl WebhookNotifierBuilder {
+    /// Signs the requests with the given secret.
+    pub fn secret<K: AsRef<[u8]>>(mut self, secret: K) -> Self {
+        self.0.secret = Some(secret.as_ref().to_vec());
+        self
+    }
+
+    /// Sets how many times a failed delivery is retried. Defaults to 3.
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.0.max_retries = retries;
+        self
+    }
+
+    /// Sets how long to wait before the first retry. The delay doubles with every retry. Defaults
+    /// to a second.
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.0.retry_delay = delay;
+        self
+    }
+
+    /// Sets the logger that failed deliveries are reported to. By default they go to the `log`
+    /// crate.
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.0.logger = logger;
+        self
+    }
+
+    /// Creates the notifier.
+    pub fn build(self) -> WebhookNotifier {
+        WebhookNotifier(Arc::new(self.0))
+    }
+}
+
+impl FileEventListener for WebhookNotifier {
+    fn receive(&self, event: FileEvent) {
+        let webhook = self.0.clone();
+        tokio::spawn(async move { webhook.deliver(&payload(&event)).await });
+    }
+}
+
+impl Webhook {
+    async fn deliver(&self, body: &str) {
+        let signature = self.secret.as_ref().map(|secret| sign(secret, body));
+        let mut delay = self.retry_delay;
+        for attempt in 0..=self.max_retries {
+            if attempt > 0 {
+                tokio::time::delay_for(delay).await;
+                delay *= 2;
+            }
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri(self.url.clone())
+                .header("Content-Type", "application/json");
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature.as_str());
+            }
+            let request = request.body(Body::from(body.to_string())).expect("the request is valid");
+            match self.client.request(request).await {
+                Ok(response) if response.status().is_success() => return,
+                Ok(response) if !is_retryable(response.status()) => {
+                    warn!(self.logger, "Webhook {} rejected the event with {}", self.url, response.status());
+                    return;
+                }
+                Ok(response) => warn!(self.logger, "Webhook {} replied {} (attempt {})", self.url, response.status(), attempt + 1),
+                Err(err) => warn!(self.logger, "Could not reach webhook {} (attempt {}): {}", self.url, attempt + 1, err),
+            }
+        }
+        error!(
+            self.logger,
+            "Giving up delivering an event to webhook {} after {} attempts",
+            self.url,
+            self.max_retries + 1
+        );
+    }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+// Returns the value of the signature header.
+fn sign(secret: &[u8], body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret).expect("HMAC takes keys of any size");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sign;
+
+    #[test]
+    fn signature() {
+        // Test case 2 of RFC 4231
+        assert_eq!(
+            sign(b"Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
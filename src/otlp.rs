@@ -0,0 +1,63 @@
+//! Contains the export of the tracing spans of sessions, commands and transfers to an
+//! OpenTelemetry collector over OTLP, so that they end up in the same traces as the spans of the
+//! storage backend.
+//!
+//! Install the exporter before starting the server and keep it for as long as the server runs:
+//!
+//! ```no_run
+//! let _exporter = libunftp::otlp::install("http://localhost:4317", "unftp").unwrap();
+//! let server = libunftp::Server::new_with_fs_root(std::env::temp_dir());
+//! # let _ = server;
+//! ```
+//!
+//! The exporter becomes the global default subscriber of the [`tracing`] crate. Applications
+//! that have a subscriber of their own can add a [`tracing-opentelemetry`] layer to it instead.
+//!
+//! [`tracing`]: https://docs.rs/tracing
+//! [`tracing-opentelemetry`]: https://docs.rs/tracing-opentelemetry
+
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::KeyValue;
+use std::error::Error;
+use std::fmt;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Exports the spans for as long as it lives. Spans that haven't been sent yet are sent when it
+/// is dropped.
+pub struct Exporter {
+    // The exporter runs on a runtime of its own, as the gRPC client needs a newer tokio than the
+    // server does.
+    runtime: Option<tokio_1::runtime::Runtime>,
+}
+
+impl fmt::Debug for Exporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Exporter").finish()
+    }
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            let _guard = runtime.enter();
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
+
+/// Exports the spans to the OpenTelemetry collector at `endpoint`, for instance
+/// `http://localhost:4317`, under the given service name.
+pub fn install(endpoint: &str, service_name: &str) -> Result<Exporter, Box<dyn Error + Send + Sync>> {
+    let runtime = tokio_1::runtime::Builder::new_multi_thread().worker_threads(1).enable_all().build()?;
+    let tracer = {
+        let _guard = runtime.enter();
+        opentelemetry_otlp::new_pipeline()
+            .with_endpoint(endpoint)
+            .with_trace_config(trace::config().with_resource(Resource::new(vec![KeyValue::new("service.name", service_name.to_string())])))
+            .with_tonic()
+            .install_batch(opentelemetry::runtime::Tokio)?
+    };
+    let subscriber = tracing_subscriber::Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok(Exporter { runtime: Some(runtime) })
+}
@@ -0,0 +1,141 @@
+//! Contains the conversion of line endings for transfers in ASCII mode (`TYPE A`), where lines
+//! end with CRLF on the wire whatever the convention of the storage backend.
+
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::AsyncRead;
+
+// How the line endings of what is read are converted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Conversion {
+    // Binary transfers, bytes are passed on as they are.
+    None,
+    // From the storage backend to the client: line feeds that are not preceded by a carriage
+    // return become CRLF.
+    ToCrlf,
+    // From the client to the storage backend: CRLF becomes a line feed. Carriage returns that
+    // are not followed by a line feed are kept.
+    FromCrlf,
+}
+
+// Reads from the inner reader, converting line endings on the way.
+pub(crate) struct LineEndings<R> {
+    inner: R,
+    conversion: Conversion,
+    // Converted bytes that did not fit in the buffer of the caller.
+    pending: VecDeque<u8>,
+    scratch: Vec<u8>,
+    // The last byte read was a carriage return. For `FromCrlf` it has not been passed on yet.
+    after_cr: bool,
+    eof: bool,
+}
+
+impl<R> LineEndings<R> {
+    pub fn new(inner: R, conversion: Conversion) -> Self {
+        LineEndings {
+            inner,
+            conversion,
+            pending: VecDeque::new(),
+            scratch: Vec::new(),
+            after_cr: false,
+            eof: false,
+        }
+    }
+
+    fn convert(&mut self, n: usize) {
+        for &byte in &self.scratch[..n] {
+            match self.conversion {
+                Conversion::None => self.pending.push_back(byte),
+                Conversion::ToCrlf => {
+                    if byte == b'\n' && !self.after_cr {
+                        self.pending.push_back(b'\r');
+                    }
+                    self.pending.push_back(byte);
+                }
+                Conversion::FromCrlf => {
+                    if self.after_cr && byte != b'\n' {
+                        self.pending.push_back(b'\r');
+                    }
+                    if byte != b'\r' {
+                        self.pending.push_back(byte);
+                    }
+                }
+            }
+            self.after_cr = byte == b'\r';
+        }
+    }
+
+    fn finish(&mut self) {
+        if self.conversion == Conversion::FromCrlf && self.after_cr {
+            self.pending.push_back(b'\r');
+        }
+        self.eof = true;
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for LineEndings<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.conversion == Conversion::None || buf.is_empty() {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+        loop {
+            if !this.pending.is_empty() {
+                let n = std::cmp::min(buf.len(), this.pending.len());
+                for (slot, byte) in buf.iter_mut().zip(this.pending.drain(..n)) {
+                    *slot = byte;
+                }
+                return Poll::Ready(Ok(n));
+            }
+            if this.eof {
+                return Poll::Ready(Ok(0));
+            }
+            this.scratch.resize(buf.len(), 0);
+            match futures::ready!(Pin::new(&mut this.inner).poll_read(cx, &mut this.scratch))? {
+                0 => this.finish(),
+                n => this.convert(n),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Conversion, LineEndings};
+    use tokio::io::AsyncReadExt;
+    use tokio::runtime::Runtime;
+
+    // Reads everything through a small buffer, so that line endings end up split over reads.
+    fn read_all(input: &'static [u8], conversion: Conversion) -> Vec<u8> {
+        Runtime::new().unwrap().block_on(async {
+            let mut reader = LineEndings::new(input, conversion);
+            let mut output = Vec::new();
+            let mut buf = [0u8; 3];
+            loop {
+                match reader.read(&mut buf).await.unwrap() {
+                    0 => return output,
+                    n => output.extend_from_slice(&buf[..n]),
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn to_crlf() {
+        assert_eq!(read_all(b"one\ntwo\n\nthree", Conversion::ToCrlf), b"one\r\ntwo\r\n\r\nthree".to_vec());
+        assert_eq!(read_all(b"already\r\ndone\r\n", Conversion::ToCrlf), b"already\r\ndone\r\n".to_vec());
+    }
+
+    #[test]
+    fn from_crlf() {
+        assert_eq!(read_all(b"one\r\ntwo\r\n\r\nthree", Conversion::FromCrlf), b"one\ntwo\n\nthree".to_vec());
+        assert_eq!(read_all(b"ab\r\r\nc\rd\r", Conversion::FromCrlf), b"ab\r\nc\rd\r".to_vec());
+    }
+
+    #[test]
+    fn no_conversion() {
+        assert_eq!(read_all(b"one\r\ntwo\n", Conversion::None), b"one\r\ntwo\n".to_vec());
+    }
+}
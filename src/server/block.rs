@@ -0,0 +1,264 @@
+//! Contains the framing of data in block mode (`MODE B`), where data is sent as a series of blocks
+//! that each start with a descriptor byte and a 16 bit byte count. Besides the data itself, blocks
+//! carry restart markers and mark the end of the file, so that the data connection doesn't have
+//! to be closed to end a transfer.
+//!
+//! The restart markers of the server are the offset in the file, in decimal, that the data up to
+//! the marker ends at. A client that gives one back in `REST` therefore restarts the transfer at
+//! the right place.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+// The bits of the descriptor byte. Records have no meaning to the storage backends, so blocks
+// that end one (128) are read as any other, and so are blocks with suspected errors (32).
+const END_OF_FILE: u8 = 64;
+const RESTART_MARKER: u8 = 16;
+
+// The most data a single block can carry.
+const MAX_BLOCK: usize = u16::MAX as usize;
+
+// How much data is sent between two restart markers in downloads.
+pub(crate) const MARKER_INTERVAL: u64 = 1024 * 1024;
+
+fn header(descriptor: u8, count: usize) -> [u8; 3] {
+    [descriptor, (count >> 8) as u8, count as u8]
+}
+
+// Writes what is written to it in blocks to the inner writer. Shutting it down sends the block
+// that ends the file.
+pub(crate) struct BlockWriter<W> {
+    inner: W,
+    // Framed bytes that have not been written to the inner writer yet.
+    pending: Vec<u8>,
+    // The offset in the file at the end of the data written so far.
+    offset: u64,
+    since_marker: u64,
+    // No markers are sent if this is 0.
+    marker_interval: u64,
+    finished: bool,
+}
+
+impl<W: AsyncWrite + Unpin> BlockWriter<W> {
+    pub fn new(inner: W, start_pos: u64, marker_interval: u64) -> Self {
+        BlockWriter {
+            inner,
+            pending: Vec::new(),
+            offset: start_pos,
+            since_marker: 0,
+            marker_interval,
+            finished: false,
+        }
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            match futures::ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending))? {
+                0 => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                n => drop(self.pending.drain(..n)),
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for BlockWriter<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        futures::ready!(this.poll_pending(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let n = std::cmp::min(buf.len(), MAX_BLOCK);
+        this.pending.extend_from_slice(&header(0, n));
+        this.pending.extend_from_slice(&buf[..n]);
+        this.offset += n as u64;
+        this.since_marker += n as u64;
+        if this.marker_interval > 0 && this.since_marker >= this.marker_interval {
+            let marker = this.offset.to_string();
+            this.pending.extend_from_slice(&header(RESTART_MARKER, marker.len()));
+            this.pending.extend_from_slice(marker.as_bytes());
+            this.since_marker = 0;
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        futures::ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.finished {
+            this.pending.extend_from_slice(&header(END_OF_FILE, 0));
+            this.finished = true;
+        }
+        futures::ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+// Reads the data out of the blocks that the inner reader gives, until the block that ends the
+// file. Restart markers are handed to `on_marker` together with the offset in the file that the
+// data before them ends at. Closing the connection before that block is an error.
+pub(crate) struct BlockReader<R, F> {
+    inner: R,
+    on_marker: F,
+    header: [u8; 3],
+    // How much of the header of the current block has been read. The data follows once all of it
+    // has.
+    header_read: usize,
+    // The data of the current block that is still to be read.
+    remaining: usize,
+    marker: Vec<u8>,
+    offset: u64,
+    eof: bool,
+}
+
+impl<R, F> BlockReader<R, F>
+where
+    R: AsyncRead + Unpin,
+    F: FnMut(&[u8], u64) + Unpin,
+{
+    pub fn new(inner: R, start_pos: u64, on_marker: F) -> Self {
+        BlockReader {
+            inner,
+            on_marker,
+            header: [0; 3],
+            header_read: 0,
+            remaining: 0,
+            marker: Vec::new(),
+            offset: start_pos,
+            eof: false,
+        }
+    }
+
+    fn descriptor(&self) -> u8 {
+        self.header[0]
+    }
+
+    fn end_block(&mut self) {
+        if self.descriptor() & RESTART_MARKER != 0 {
+            (self.on_marker)(&self.marker, self.offset);
+            self.marker.clear();
+        }
+        self.eof = self.descriptor() & END_OF_FILE != 0;
+        self.header_read = 0;
+    }
+}
+
+impl<R, F> AsyncRead for BlockReader<R, F>
+where
+    R: AsyncRead + Unpin,
+    F: FnMut(&[u8], u64) + Unpin,
+{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        loop {
+            if this.eof {
+                return Poll::Ready(Ok(0));
+            }
+            if this.header_read < this.header.len() {
+                let read = this.header_read;
+                match futures::ready!(Pin::new(&mut this.inner).poll_read(cx, &mut this.header[read..]))? {
+                    0 => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+                    n => this.header_read += n,
+                }
+                if this.header_read == this.header.len() {
+                    this.remaining = usize::from(this.header[1]) << 8 | usize::from(this.header[2]);
+                }
+                continue;
+            }
+            if this.remaining == 0 {
+                this.end_block();
+                continue;
+            }
+            let n = std::cmp::min(buf.len(), this.remaining);
+            let n = futures::ready!(Pin::new(&mut this.inner).poll_read(cx, &mut buf[..n]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            this.remaining -= n;
+            if this.descriptor() & RESTART_MARKER != 0 {
+                this.marker.extend_from_slice(&buf[..n]);
+                continue;
+            }
+            this.offset += n as u64;
+            return Poll::Ready(Ok(n));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockReader, BlockWriter};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn writes_blocks_and_markers() {
+        let output = Runtime::new().unwrap().block_on(async {
+            let mut output = Vec::new();
+            let mut writer = BlockWriter::new(&mut output, 100, 4);
+            writer.write_all(b"abc").await.unwrap();
+            writer.write_all(b"de").await.unwrap();
+            writer.shutdown().await.unwrap();
+            output
+        });
+        assert_eq!(output, b"\x00\x00\x03abc\x00\x00\x02de\x10\x00\x03105\x40\x00\x00".to_vec());
+    }
+
+    #[test]
+    fn reads_blocks_and_markers() {
+        let input: &[u8] = b"\x00\x00\x03abc\x10\x00\x02m1\x20\x00\x02de\x80\x00\x01f\x40\x00\x01g\x00\x00\x03xyz";
+        let (data, markers) = Runtime::new().unwrap().block_on(async {
+            let mut markers = Vec::new();
+            let mut data = Vec::new();
+            // A small buffer, so that blocks end up split over reads.
+            let mut buf = [0u8; 2];
+            let mut reader = BlockReader::new(input, 10, |marker: &[u8], offset| markers.push((marker.to_vec(), offset)));
+            loop {
+                match reader.read(&mut buf).await.unwrap() {
+                    0 => break,
+                    n => data.extend_from_slice(&buf[..n]),
+                }
+            }
+            drop(reader);
+            (data, markers)
+        });
+        assert_eq!(data, b"abcdefg".to_vec());
+        assert_eq!(markers, vec![(b"m1".to_vec(), 13)]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = (0..200_000u32).map(|n| n as u8).collect();
+        let output = Runtime::new().unwrap().block_on(async {
+            let mut framed = Vec::new();
+            let mut writer = BlockWriter::new(&mut framed, 0, 0);
+            writer.write_all(&data).await.unwrap();
+            writer.shutdown().await.unwrap();
+            let mut output = Vec::new();
+            BlockReader::new(&framed[..], 0, |_: &[u8], _| {}).read_to_end(&mut output).await.unwrap();
+            output
+        });
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn missing_end_of_file() {
+        let input: &[u8] = b"\x00\x00\x03abc";
+        let result = Runtime::new().unwrap().block_on(async {
+            let mut output = Vec::new();
+            BlockReader::new(input, 0, |_: &[u8], _| {}).read_to_end(&mut output).await
+        });
+        assert!(result.is_err());
+    }
+}
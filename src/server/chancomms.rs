@@ -6,7 +6,9 @@ use crate::auth::UserDetail;
 use crate::server::controlchan::ReplyCode;
 use crate::storage;
 use crate::storage::Error;
-use futures::channel::mpsc::{Receiver, Sender};
+#[cfg(feature = "proxy_protocol")]
+use futures::channel::mpsc::Receiver;
+use futures::channel::mpsc::Sender;
 
 // Commands that can be send to the data channel / data loop.
 #[derive(PartialEq, Debug)]
@@ -34,14 +36,32 @@ pub enum InternalMsg {
         /// The number of bytes transferred
         bytes: i64,
     },
-    /// Data connection was unexpectedly closed
+    /// Sent now and then while a file is transferred, when bytes moved since the last time, and
+    /// once more when the transfer ended
+    TransferProgress {
+        /// `upload` or `download`
+        direction: &'static str,
+        /// The number of bytes moved so far
+        bytes: u64,
+        /// The number of bytes moved since the last time
+        moved: u64,
+    },
+    /// Reading from or writing to the data connection failed, usually because the client closed it
     ConnectionReset,
+    /// No data moved over the data connection for too long, so the transfer was aborted
+    TransferStalled,
+    /// The client aborted the transfer with ABOR
+    TransferAborted,
     /// Data connection was closed on purpose or not on purpose. We don't know, but that is FTP
     DataConnectionClosedAfterStor,
     /// Failed to write data to disk
     WriteFailed,
     /// Started sending data to the client
     SendingData,
+    /// Started receiving data from the client
+    ReceivingData,
+    /// Started sending a directory listing to the client
+    SendingDirectoryList,
     /// Unknown Error retrieving file
     UnknownRetrieveError,
     /// Listed the directory successfully
@@ -54,6 +74,9 @@ pub enum InternalMsg {
     DelFail,
     /// Quit the client connection
     Quit,
+    /// Refuse further service to the client, for the given reason, and close the control
+    /// connection
+    ServiceNotAvailable(String),
     /// Successfully created directory
     MkdirSuccess(std::path::PathBuf),
     /// Failed to crate directory
@@ -72,16 +95,53 @@ pub enum InternalMsg {
     CommandChannelReply(ReplyCode, String),
 }
 
+impl InternalMsg {
+    // Tells if this is the final reply of the data channel to a transfer command.
+    pub(crate) fn ends_transfer(&self) -> bool {
+        use InternalMsg::*;
+        matches!(
+            self,
+            SendData { .. }
+                | WrittenData { .. }
+                | DirectorySuccessfullyListed
+                | ConnectionReset
+                | TransferStalled
+                | TransferAborted
+                | WriteFailed
+                | UnknownRetrieveError
+                | StorageError(_)
+        )
+    }
+
+    // Tells if the control connection is closed after replying to this message.
+    pub(crate) fn closes_connection(&self) -> bool {
+        matches!(self, InternalMsg::ServiceNotAvailable(_))
+    }
+}
+
 // ProxyLoopMsg is sent to the proxy loop when proxy protocol mode is enabled. See the
-// Server::proxy_protocol_mode and Server::listen_proxy_protocol_mode methods.
+// ServerBuilder::proxy_protocol_mode and Server::listen_proxy_protocol_mode methods.
+#[cfg_attr(not(feature = "proxy_protocol"), allow(dead_code))]
 pub enum ProxyLoopMsg<S, U>
 where
     S: storage::StorageBackend<U> + Send + Sync,
     U: UserDetail,
 {
     /// Command to assign a data port to a session
-    AssignDataPortCommand(SharedSession<S, U>),
+    AssignDataPortCommand(SharedSession<S, U>, PassiveMode),
+}
+
+// PassiveMode tells how the client asked for a data port and thus how the reply should be formatted.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum PassiveMode {
+    /// RFC 959 `PASV`, only usable on IPv4 connections
+    Standard,
+    /// RFC 2428 `EPSV`, usable on IPv4 and IPv6 connections
+    Extended,
+    /// RFC 1639 `LPSV`, usable on IPv4 and IPv6 connections
+    Long,
 }
 
 pub type ProxyLoopSender<S, U> = Sender<ProxyLoopMsg<S, U>>;
+#[cfg(feature = "proxy_protocol")]
 pub type ProxyLoopReceiver<S, U> = Receiver<ProxyLoopMsg<S, U>>;
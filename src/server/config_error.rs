@@ -0,0 +1,64 @@
+//! Contains the error that tells why a server could not be built from its configuration.
+
+use std::fmt;
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// The error returned by [`ServerBuilder::build`] when the configuration cannot work.
+///
+/// [`ServerBuilder::build`]: struct.ServerBuilder.html#method.build
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The range of passive ports contains no ports.
+    EmptyPassivePortRange(Range<u16>),
+    /// The certificate file for FTPS could not be read.
+    UnreadableCertificate(PathBuf, std::io::Error),
+    /// The certificate file for FTPS could not be decoded with the given password.
+    #[cfg(feature = "ftps")]
+    InvalidCertificate(PathBuf, native_tls::Error),
+    /// The external control port of the PROXY protocol mode lies in the range of passive ports,
+    /// so control connections could not be told apart from data connections.
+    ProxyControlPortInPassiveRange(u16, Range<u16>),
+    /// The limit of concurrent transfers is 0, so no transfer could ever start.
+    NoConcurrentTransfers,
+    /// Addresses for implicit FTPS were given without a certificate to use for them.
+    ImplicitFtpsWithoutCertificate,
+    /// Addresses for implicit FTPS were given in PROXY protocol mode, which doesn't support them.
+    ImplicitFtpsWithProxyProtocol,
+    /// The address of the HTTP endpoint is not a valid socket address.
+    InvalidHttpEndpoint(String, std::net::AddrParseError),
+    /// The configuration asks for something that needs a cargo feature of libunftp, named here,
+    /// that was left out of the build.
+    FeatureDisabled(&'static str),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::EmptyPassivePortRange(range) => write!(f, "The passive port range {:?} is empty", range),
+            ConfigError::UnreadableCertificate(path, err) => write!(f, "Could not read the certificate file {:?}: {}", path, err),
+            #[cfg(feature = "ftps")]
+            ConfigError::InvalidCertificate(path, err) => write!(f, "Could not decode the certificate file {:?}: {}", path, err),
+            ConfigError::ProxyControlPortInPassiveRange(port, range) => {
+                write!(f, "The external control port {} lies in the passive port range {:?}", port, range)
+            }
+            ConfigError::NoConcurrentTransfers => write!(f, "The maximum number of concurrent transfers must be at least 1"),
+            ConfigError::ImplicitFtpsWithoutCertificate => write!(f, "Implicit FTPS needs a certificate"),
+            ConfigError::ImplicitFtpsWithProxyProtocol => write!(f, "Implicit FTPS is not available in PROXY protocol mode"),
+            ConfigError::InvalidHttpEndpoint(address, err) => write!(f, "The HTTP endpoint address {} is invalid: {}", address, err),
+            ConfigError::FeatureDisabled(feature) => write!(f, "The configuration needs the {} feature, which is not enabled", feature),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::UnreadableCertificate(_, err) => Some(err),
+            #[cfg(feature = "ftps")]
+            ConfigError::InvalidCertificate(_, err) => Some(err),
+            ConfigError::InvalidHttpEndpoint(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
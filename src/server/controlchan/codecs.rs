@@ -1,9 +1,14 @@
 use super::command::Command;
-use super::error::ControlChanError;
+use super::error::{ControlChanError, ControlChanErrorKind};
+use super::parse_error::ParseError;
 use super::Reply;
+#[cfg(feature = "gssapi")]
+use crate::server::gssapi::{GssapiSession, Protection};
 
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
+use std::collections::HashSet;
 use std::io::Write;
+use std::sync::Arc;
 use tokio_util::codec::{Decoder, Encoder};
 
 // FTPCodec implements tokio's `Decoder` and `Encoder` traits for the control channel, that we'll
@@ -14,14 +19,161 @@ pub struct FTPCodec {
     // is the next index to examine. The next time `decode` is called with `abcde\n`, we will only
     // look at `de\n` before returning.
     next_index: usize,
+    // The longest line, line ending included, that we accept from the client.
+    max_line_length: usize,
+    // The verbs we don't know ourselves but for which custom handlers were registered.
+    custom_verbs: Arc<HashSet<String>>,
+    // Our answers to the Telnet option negotiations of the client, sent along with the next reply.
+    telnet_replies: Vec<u8>,
+    // The security context of the session, through which we unwrap MIC and ENC commands.
+    #[cfg(feature = "gssapi")]
+    gssapi: Option<GssapiSession>,
+    // The protection of the last command, which we give its replies too.
+    #[cfg(feature = "gssapi")]
+    protection: Option<Protection>,
 }
 
 impl FTPCodec {
     pub fn new() -> Self {
-        FTPCodec { next_index: 0 }
+        FTPCodec {
+            next_index: 0,
+            max_line_length: usize::MAX,
+            custom_verbs: Arc::new(HashSet::new()),
+            telnet_replies: vec![],
+            #[cfg(feature = "gssapi")]
+            gssapi: None,
+            #[cfg(feature = "gssapi")]
+            protection: None,
+        }
+    }
+
+    // Lets us decode commands protected with MIC and ENC, and protect their replies, once the
+    // client established a GSSAPI security context.
+    #[cfg(feature = "gssapi")]
+    pub fn gssapi(mut self, gssapi: Option<GssapiSession>) -> Self {
+        self.gssapi = gssapi;
+        self
+    }
+
+    // Decodes lines with these verbs, which are in upper case, into custom commands instead of
+    // refusing them as unknown commands.
+    pub fn custom_verbs(mut self, custom_verbs: Arc<HashSet<String>>) -> Self {
+        self.custom_verbs = custom_verbs;
+        self
+    }
+
+    // Limits the length of the lines we decode. Without a limit a client can make us buffer an
+    // endless line.
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.max_line_length = max_line_length;
+        self
+    }
+
+    // Removes the Telnet commands from the line. Clients send Interrupt Process and Synch (Data Mark)
+    // before an ABOR, the latter as urgent data which the socket leaves out, so an IAC that is not
+    // followed by a command is removed too. Options the client wants to negotiate are refused, as
+    // RFC 1123 recommends for FTP servers.
+    fn strip_telnet(&mut self, line: BytesMut) -> BytesMut {
+        if !line.contains(&telnet::IAC) {
+            return line;
+        }
+        let mut stripped = BytesMut::with_capacity(line.len());
+        let mut bytes = line.iter().copied().peekable();
+        while let Some(byte) = bytes.next() {
+            if byte != telnet::IAC {
+                stripped.extend_from_slice(&[byte]);
+                continue;
+            }
+            match bytes.peek().copied() {
+                Some(telnet::IAC) => {
+                    bytes.next();
+                    stripped.extend_from_slice(&[telnet::IAC]);
+                }
+                Some(verb @ telnet::WILL..=telnet::DONT) => {
+                    bytes.next();
+                    if let Some(option) = bytes.next() {
+                        match verb {
+                            telnet::WILL => self.telnet_replies.extend_from_slice(&[telnet::IAC, telnet::DONT, option]),
+                            telnet::DO => self.telnet_replies.extend_from_slice(&[telnet::IAC, telnet::WONT, option]),
+                            _ => {}
+                        }
+                    }
+                }
+                Some(telnet::SE..=telnet::SB) => {
+                    bytes.next();
+                }
+                _ => {}
+            }
+        }
+        stripped
+    }
+
+    fn parse(&self, line: Bytes) -> Result<Command, ControlChanError> {
+        let result = match Command::parse(line.clone()) {
+            Err(err) if matches!(&err, ParseError::UnknownCommand { command } if self.custom_verbs.contains(command)) => Command::parse_custom(line.clone()),
+            result => result,
+        };
+        result.map_err(|err| {
+            let verb = line.split(|b| b.is_ascii_whitespace()).next().unwrap_or_default();
+            ControlChanError::from(err).in_command(String::from_utf8_lossy(verb).to_uppercase())
+        })
+    }
+
+    // Takes the command out of a MIC or ENC command, as RFC 2228 describes, and remembers how it
+    // was protected. Other commands are returned as they are.
+    #[cfg(feature = "gssapi")]
+    fn unprotect(&mut self, command: Command) -> Result<Command, ControlChanError> {
+        let (protection, message) = match command {
+            Command::Mic { message } => (Protection::Integrity, message),
+            Command::Enc { message } => (Protection::Privacy, message),
+            command => {
+                self.protection = None;
+                return Ok(command);
+            }
+        };
+        self.protection = None;
+        let message = base64::decode(message.as_ref()).map_err(|_| ControlChanError::from(ControlChanErrorKind::InvalidCommand))?;
+        let mut line = match self.gssapi.as_ref().and_then(|gssapi| gssapi.unwrap(&message)) {
+            Some(Ok(line)) => line,
+            Some(Err(_)) => return Err(ControlChanErrorKind::SecurityCheckFailed.into()),
+            None => return Err(ControlChanErrorKind::NoSecurityContext.into()),
+        };
+        if !line.ends_with(b"\n") {
+            line.extend_from_slice(b"\r\n");
+        }
+        match self.parse(line.into())? {
+            Command::Mic { .. } | Command::Enc { .. } => Err(ControlChanErrorKind::InvalidCommand.into()),
+            command => {
+                self.protection = Some(protection);
+                Ok(command)
+            }
+        }
+    }
+
+    // Protects a reply to a protected command with a 631 or 632 reply that holds it, as RFC 2228
+    // describes. When that fails, the client gets to know about it unprotected.
+    #[cfg(feature = "gssapi")]
+    fn protect(&self, protection: Protection, reply: &[u8]) -> Vec<u8> {
+        let wrapped = self.gssapi.as_ref().map(|gssapi| gssapi.wrap(protection, reply));
+        match (wrapped, protection) {
+            (Some(Ok(wrapped)), Protection::Integrity) => format!("631 {}\r\n", base64::encode(wrapped)).into_bytes(),
+            (Some(Ok(wrapped)), Protection::Privacy) => format!("632 {}\r\n", base64::encode(wrapped)).into_bytes(),
+            _ => b"535 Failed to protect the reply\r\n".to_vec(),
+        }
     }
 }
 
+// The Telnet command codes of RFC 854 that can show up on the control connection.
+mod telnet {
+    pub const SE: u8 = 240;
+    pub const SB: u8 = 250;
+    pub const WILL: u8 = 251;
+    pub const WONT: u8 = 252;
+    pub const DO: u8 = 253;
+    pub const DONT: u8 = 254;
+    pub const IAC: u8 = 255;
+}
+
 impl Decoder for FTPCodec {
     type Item = Command;
     type Error = ControlChanError;
@@ -31,9 +183,18 @@ impl Decoder for FTPCodec {
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Command>, Self::Error> {
         if let Some(newline_offset) = buf[self.next_index..].iter().position(|b| *b == b'\n') {
             let newline_index = newline_offset + self.next_index;
+            if newline_index >= self.max_line_length {
+                return Err(ControlChanErrorKind::CommandTooLong.into());
+            }
             let line = buf.split_to(newline_index + 1);
             self.next_index = 0;
-            Ok(Some(Command::parse(line)?))
+            let line = self.strip_telnet(line).freeze();
+            let command = self.parse(line)?;
+            #[cfg(feature = "gssapi")]
+            let command = self.unprotect(command)?;
+            Ok(Some(command))
+        } else if buf.len() >= self.max_line_length {
+            Err(ControlChanErrorKind::CommandTooLong.into())
         } else {
             self.next_index = buf.len();
             Ok(None)
@@ -46,35 +207,298 @@ impl Encoder<Reply> for FTPCodec {
 
     // Here we encode the outgoing response
     fn encode(&mut self, reply: Reply, buf: &mut BytesMut) -> Result<(), Self::Error> {
-        let mut buffer = vec![];
+        let mut buffer = std::mem::take(&mut self.telnet_replies);
+        #[cfg(feature = "gssapi")]
+        let start = buffer.len();
         match reply {
             Reply::None => {
+                self.telnet_replies = buffer;
                 return Ok(());
             }
             Reply::CodeAndMsg { code, msg } => {
                 if msg.is_empty() {
-                    writeln!(buffer, "{}\r", code as u32)?;
+                    writeln!(buffer, "{}\r", u32::from(code))?;
                 } else {
-                    writeln!(buffer, "{} {}\r", code as u32, msg)?;
+                    writeln!(buffer, "{} {}\r", u32::from(code), msg)?;
                 }
             }
             Reply::MultiLine { code, mut lines } => {
                 // Get the last line since it needs to be preceded by the response code.
-                let last_line = lines.pop().unwrap();
-                // Lines starting with a digit should be indented
+                let last_line = lines.pop().unwrap_or_default();
+                // Lines starting with a digit should be indented, so that clients don't take
+                // them for the last line.
                 for it in lines.iter_mut() {
-                    if it.chars().next().unwrap().is_digit(10) {
+                    if it.starts_with(|c: char| c.is_ascii_digit()) {
                         it.insert(0, ' ');
                     }
                 }
                 if lines.is_empty() {
-                    writeln!(buffer, "{} {}\r", code as u32, last_line)?;
+                    writeln!(buffer, "{} {}\r", u32::from(code), last_line)?;
                 } else {
-                    write!(buffer, "{}-{}\r\n{} {}\r\n", code as u32, lines.join("\r\n"), code as u32, last_line)?;
+                    write!(buffer, "{}-{}\r\n{} {}\r\n", u32::from(code), lines.join("\r\n"), u32::from(code), last_line)?;
                 }
             }
         }
+        #[cfg(feature = "gssapi")]
+        {
+            if let Some(protection) = self.protection {
+                let reply = buffer.split_off(start);
+                buffer.extend(self.protect(protection, &reply));
+            }
+        }
         buf.extend(&buffer);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::FTPCodec;
+    use crate::server::controlchan::command::Command;
+    use crate::server::controlchan::{ControlChanErrorKind, Reply, ReplyCode};
+    use bytes::BytesMut;
+    use std::sync::Arc;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    fn encode(reply: Reply) -> String {
+        let mut buf = BytesMut::new();
+        FTPCodec::new().encode(reply, &mut buf).unwrap();
+        String::from_utf8(buf.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn multiline_reply() {
+        let reply = Reply::multiline(ReplyCode::SystemStatus, vec!["Extensions supported:", " SIZE", "END"]);
+        assert_eq!(encode(reply), "211-Extensions supported:\r\n SIZE\r\n211 END\r\n");
+    }
+
+    #[test]
+    fn multiline_reply_indents_lines_starting_with_digits() {
+        let reply = Reply::multiline(ReplyCode::SystemStatus, vec!["Status:", "211 lines ahead", "End"]);
+        assert_eq!(encode(reply), "211-Status:\r\n 211 lines ahead\r\n211 End\r\n");
+    }
+
+    #[test]
+    fn multiline_reply_splits_items_with_line_breaks() {
+        let reply = Reply::multiline(ReplyCode::FileStatus, vec!["Status of a:\r\nfirst\nsecond", "End"]);
+        assert_eq!(encode(reply), "213-Status of a:\r\nfirst\r\nsecond\r\n213 End\r\n");
+    }
+
+    #[test]
+    fn multiline_reply_with_one_line() {
+        assert_eq!(encode(Reply::multiline(ReplyCode::CommandOkay, vec!["Done"])), "200 Done\r\n");
+        assert_eq!(encode(Reply::multiline(ReplyCode::CommandOkay, Vec::<String>::new())), "200 \r\n");
+    }
+
+    #[test]
+    fn replies_with_custom_codes() {
+        let code = ReplyCode::new(252).unwrap();
+        assert_eq!(encode(Reply::builder(code).line("Maybe").build()), "252 Maybe\r\n");
+        assert_eq!(
+            encode(Reply::builder(code).line("Cannot verify").line("but will try").build()),
+            "252-Cannot verify\r\n252 but will try\r\n"
+        );
+        assert!(ReplyCode::new(99).is_none());
+    }
+
+    fn decode(codec: &mut FTPCodec, input: &[u8]) -> Command {
+        codec.decode(&mut BytesMut::from(input)).unwrap().unwrap()
+    }
+
+    #[test]
+    fn abor_after_interrupt_process_and_synch() {
+        let mut codec = FTPCodec::new();
+        assert_eq!(decode(&mut codec, b"\xff\xf4\xff\xf2ABOR\r\n"), Command::Abor);
+        // The Data Mark is sent as urgent data, so it may be missing.
+        assert_eq!(decode(&mut codec, b"\xff\xf4\xffABOR\r\n"), Command::Abor);
+    }
+
+    #[test]
+    fn telnet_option_negotiation_is_refused() {
+        let mut codec = FTPCodec::new();
+        assert_eq!(decode(&mut codec, b"\xff\xfd\x01\xff\xfb\x03NOOP\r\n"), Command::Noop);
+        let mut buf = BytesMut::new();
+        codec.encode(Reply::new(ReplyCode::CommandOkay, "Ok"), &mut buf).unwrap();
+        assert_eq!(&buf[..], &b"\xff\xfc\x01\xff\xfe\x03200 Ok\r\n"[..]);
+    }
+
+    #[test]
+    fn pipelined_commands_are_decoded_in_order() {
+        let mut codec = FTPCodec::new();
+        let mut buf = BytesMut::from(&b"USER hoi\r\nPASS jij\r\nPA"[..]);
+        assert!(matches!(codec.decode(&mut buf).unwrap(), Some(Command::User { .. })));
+        assert!(matches!(codec.decode(&mut buf).unwrap(), Some(Command::Pass { .. })));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"SV\r\n");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Command::Pasv));
+    }
+
+    #[test]
+    fn parse_errors_keep_the_verb() {
+        let mut codec = FTPCodec::new();
+        let mut buf = BytesMut::from(&b"type x\r\n"[..]);
+        let err = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), &ControlChanErrorKind::InvalidCommand);
+        assert_eq!(err.command(), Some("TYPE"));
+    }
+
+    #[test]
+    fn long_ports_of_both_address_families() {
+        let mut codec = FTPCodec::new();
+        assert_eq!(
+            decode(&mut codec, b"LPRT 4,4,192,168,1,2,2,195,80\r\n"),
+            Command::Lprt {
+                addr: "192.168.1.2:50000".parse().unwrap()
+            }
+        );
+        assert_eq!(
+            decode(&mut codec, b"LPRT 6,16,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,2,1,2\r\n"),
+            Command::Lprt {
+                addr: "[::1]:258".parse().unwrap()
+            }
+        );
+        let mut buf = BytesMut::from(&b"LPRT 5,4,192,168,1,2,2,195,80\r\n"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap_err().kind(), &ControlChanErrorKind::InvalidCommand);
+    }
+
+    #[test]
+    fn lines_longer_than_the_maximum_are_refused() {
+        let mut codec = FTPCodec::new().max_line_length(10);
+        let mut buf = BytesMut::from(&b"NOOP\r\nMKD abcde\r\n"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Command::Noop));
+        assert_eq!(codec.decode(&mut buf).unwrap_err().kind(), &ControlChanErrorKind::CommandTooLong);
+
+        let mut codec = FTPCodec::new().max_line_length(10);
+        let mut buf = BytesMut::from(&b"MKD abc"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"defgh");
+        assert_eq!(codec.decode(&mut buf).unwrap_err().kind(), &ControlChanErrorKind::CommandTooLong);
+    }
+
+    #[cfg(feature = "gssapi")]
+    mod gssapi {
+        use super::{decode, FTPCodec};
+        use crate::server::controlchan::command::Command;
+        use crate::server::controlchan::{ControlChanErrorKind, Reply, ReplyCode};
+        use crate::server::gssapi::{GssapiContext, GssapiResult, GssapiSession};
+        use bytes::BytesMut;
+        use std::sync::Arc;
+        use tokio_util::codec::{Decoder, Encoder};
+
+        // Completes after one token and protects messages by putting "mic:" or "enc:" in front.
+        struct Fake {
+            complete: bool,
+        }
+
+        impl GssapiContext for Fake {
+            fn step(&mut self, _token: &[u8]) -> GssapiResult<Option<Vec<u8>>> {
+                self.complete = true;
+                Ok(None)
+            }
+
+            fn is_complete(&self) -> bool {
+                self.complete
+            }
+
+            fn principal(&self) -> Option<String> {
+                Some("alice@EXAMPLE.COM".to_string())
+            }
+
+            fn unwrap(&mut self, message: &[u8]) -> GssapiResult<Vec<u8>> {
+                match message {
+                    [b'm', b'i', b'c', b':', rest @ ..] | [b'e', b'n', b'c', b':', rest @ ..] => Ok(rest.to_vec()),
+                    _ => Err("Bad protection".into()),
+                }
+            }
+
+            fn wrap(&mut self, encrypt: bool, message: &[u8]) -> GssapiResult<Vec<u8>> {
+                Ok([if encrypt { &b"enc:"[..] } else { &b"mic:"[..] }, message].concat())
+            }
+        }
+
+        fn codec() -> (FTPCodec, GssapiSession) {
+            let session = GssapiSession::new(Arc::new(|| Box::new(Fake { complete: false })));
+            (FTPCodec::new().gssapi(Some(session.clone())), session)
+        }
+
+        // Starts a context and completes it with one token.
+        fn establish(session: &GssapiSession) {
+            session.start();
+            let step = tokio::runtime::Runtime::new().unwrap().block_on(session.step(b"token".to_vec()));
+            assert!(matches!(step, Some(Ok((None, true)))));
+        }
+
+        fn encode(codec: &mut FTPCodec, reply: Reply) -> String {
+            let mut buf = BytesMut::new();
+            codec.encode(reply, &mut buf).unwrap();
+            String::from_utf8(buf.to_vec()).unwrap()
+        }
+
+        #[test]
+        fn protected_commands_and_replies() {
+            let (mut codec, session) = codec();
+            establish(&session);
+
+            let line = format!("MIC {}\r\n", base64::encode("mic:NOOP"));
+            assert_eq!(decode(&mut codec, line.as_bytes()), Command::Noop);
+            let reply = encode(&mut codec, Reply::new(ReplyCode::CommandOkay, "Ok"));
+            assert_eq!(reply, format!("631 {}\r\n", base64::encode("mic:200 Ok\r\n")));
+
+            let line = format!("ENC {}\r\n", base64::encode("enc:CWD /a\r\n"));
+            assert_eq!(decode(&mut codec, line.as_bytes()), Command::Cwd { path: "/a".into() });
+            let reply = encode(&mut codec, Reply::new(ReplyCode::FileActionOkay, "Ok"));
+            assert_eq!(reply, format!("632 {}\r\n", base64::encode("enc:250 Ok\r\n")));
+
+            // Replies to unprotected commands are not protected.
+            assert_eq!(decode(&mut codec, b"NOOP\r\n"), Command::Noop);
+            assert_eq!(encode(&mut codec, Reply::new(ReplyCode::CommandOkay, "Ok")), "200 Ok\r\n");
+        }
+
+        #[test]
+        fn protected_commands_are_checked() {
+            let (mut codec, session) = codec();
+            let line = format!("MIC {}\r\n", base64::encode("mic:NOOP"));
+            let err = codec.decode(&mut BytesMut::from(line.as_bytes())).unwrap_err();
+            assert_eq!(err.kind(), &ControlChanErrorKind::NoSecurityContext);
+
+            establish(&session);
+            let line = format!("MIC {}\r\n", base64::encode("NOOP"));
+            let err = codec.decode(&mut BytesMut::from(line.as_bytes())).unwrap_err();
+            assert_eq!(err.kind(), &ControlChanErrorKind::SecurityCheckFailed);
+
+            let line = format!("MIC {}\r\n", base64::encode(format!("mic:MIC {}", base64::encode("mic:NOOP"))));
+            let err = codec.decode(&mut BytesMut::from(line.as_bytes())).unwrap_err();
+            assert_eq!(err.kind(), &ControlChanErrorKind::InvalidCommand);
+        }
+
+        #[test]
+        fn users_are_bound_to_the_principal() {
+            let (_, session) = codec();
+            assert!(session.authorizes("bob"));
+            session.start();
+            assert!(session.authorizes("bob"));
+            establish(&session);
+            assert!(session.authorizes("alice"));
+            assert!(session.authorizes("alice@EXAMPLE.COM"));
+            assert!(!session.authorizes("bob"));
+            assert!(!session.authorizes("alice@OTHER.COM"));
+        }
+    }
+
+    #[test]
+    fn custom_verbs() {
+        let mut codec = FTPCodec::new().custom_verbs(Arc::new(vec!["XCHMOD".to_string()].into_iter().collect()));
+        let mut buf = BytesMut::from(&b"xchmod 644 a.txt\r\nXYZ\r\n"[..]);
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Command::Custom {
+                verb: "XCHMOD".to_string(),
+                argument: "644 a.txt".to_string()
+            })
+        );
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap_err().kind(),
+            ControlChanErrorKind::UnknownCommand { .. }
+        ));
+    }
+}
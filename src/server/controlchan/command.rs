@@ -1,9 +1,9 @@
-use super::parse_error::{ParseErrorKind, Result};
-use crate::server::controlchan::commands::{AuthParam, ModeParam, Opt, ProtParam, StruParam};
+use super::parse_error::{ParseError, Result};
+use crate::server::controlchan::commands::{parse_long_address, AuthParam, EpsvParam, FormatControl, ModeParam, Opt, ProtParam, StruParam, TypeParam};
 use crate::server::password::Password;
 
 use bytes::Bytes;
-use failure::*;
+use std::net::SocketAddr;
 use std::{fmt, str};
 
 #[derive(Debug, PartialEq, Clone)]
@@ -22,15 +22,20 @@ pub enum Command {
         password: Password,
     },
     Acct {
-        /// The bytes making up the account about which information is requested.
-        account: Bytes,
+        /// The bytes making up the account about which information is requested. Like the
+        /// password, account information may be secret so it is obscured when logged.
+        account: Password,
     },
     Syst,
     Stat {
         /// The bytes making up the path about which information is requested, if given.
         path: Option<Bytes>,
     },
-    Type,
+    Type {
+        /// The representation type the client would like to switch to. Only ASCII Non-print and
+        /// Image are supported by us.
+        param: TypeParam,
+    },
     Stru {
         /// The structure to which the client would like to switch. Only the `File` structure is
         /// supported by us.
@@ -44,7 +49,19 @@ pub enum Command {
     Help,
     Noop,
     Pasv,
+    Epsv {
+        /// The network protocol the client would like to use, or whether it only wants to use
+        /// `EPSV` from now on.
+        param: EpsvParam,
+    },
     Port,
+    /// The long passive mode of RFC 1639, which works for IPv4 and IPv6.
+    Lpsv,
+    /// The long active mode of RFC 1639.
+    Lprt {
+        /// The address the client would like the server to connect to.
+        addr: SocketAddr,
+    },
     Retr {
         /// The path to the file the client would like to retrieve.
         path: String,
@@ -63,6 +80,16 @@ pub enum Command {
         /// The path of the file/directory the clients wants to list.
         path: Option<String>,
     },
+    /// Machine readable listing of a directory (MLSD) as specified in RFC 3659.
+    Mlsd {
+        /// The path of the directory the client wants to list.
+        path: Option<String>,
+    },
+    /// Machine readable facts about a single file or directory (MLST) as specified in RFC 3659.
+    Mlst {
+        /// The path of the file or directory the client wants to know about.
+        path: Option<String>,
+    },
     Feat,
     Pwd,
     Cwd {
@@ -104,6 +131,23 @@ pub enum Command {
     Auth {
         protocol: AuthParam,
     },
+    /// Security data for the exchange that `AUTH GSSAPI` started, as specified in RFC 2228.
+    Adat {
+        /// The base64 encoded token. It is kept out of logs like a password, since it is part of
+        /// establishing the security context.
+        token: Password,
+    },
+    /// A command with integrity protection, as specified in RFC 2228.
+    Mic {
+        /// The base64 encoded, protected command. It is kept out of logs like a password, since
+        /// integrity protection leaves the command readable.
+        message: Password,
+    },
+    /// A command with integrity protection and encryption, as specified in RFC 2228.
+    Enc {
+        /// The base64 encoded, protected command.
+        message: Password,
+    },
     CCC,
     PBSZ {},
     PROT {
@@ -120,6 +164,20 @@ pub enum Command {
     MDTM {
         file: std::path::PathBuf,
     },
+    /// Services specific to the server (SITE), like `SITE HELP`.
+    Site {
+        /// The name of the subcommand, in upper case.
+        subcommand: String,
+        /// The rest of the line.
+        argument: String,
+    },
+    /// A command the server doesn't know itself, but for which a custom handler was registered.
+    Custom {
+        /// The verb of the command, in upper case.
+        verb: String,
+        /// The rest of the line.
+        argument: String,
+    },
 }
 
 impl fmt::Display for Command {
@@ -129,6 +187,115 @@ impl fmt::Display for Command {
 }
 
 impl Command {
+    /// The verb of the command as clients send it, in upper case, for instance `RETR`.
+    pub fn verb(&self) -> String {
+        let verb = match self {
+            Command::User { .. } => "USER",
+            Command::Pass { .. } => "PASS",
+            Command::Acct { .. } => "ACCT",
+            Command::Syst => "SYST",
+            Command::Stat { .. } => "STAT",
+            Command::Type { .. } => "TYPE",
+            Command::Stru { .. } => "STRU",
+            Command::Mode { .. } => "MODE",
+            Command::Help => "HELP",
+            Command::Noop => "NOOP",
+            Command::Pasv => "PASV",
+            Command::Epsv { .. } => "EPSV",
+            Command::Port => "PORT",
+            Command::Lpsv => "LPSV",
+            Command::Lprt { .. } => "LPRT",
+            Command::Retr { .. } => "RETR",
+            Command::Stor { .. } => "STOR",
+            Command::List { .. } => "LIST",
+            Command::Nlst { .. } => "NLST",
+            Command::Mlsd { .. } => "MLSD",
+            Command::Mlst { .. } => "MLST",
+            Command::Feat => "FEAT",
+            Command::Pwd => "PWD",
+            Command::Cwd { .. } => "CWD",
+            Command::Cdup => "CDUP",
+            Command::Opts { .. } => "OPTS",
+            Command::Dele { .. } => "DELE",
+            Command::Rmd { .. } => "RMD",
+            Command::Quit => "QUIT",
+            Command::Mkd { .. } => "MKD",
+            Command::Allo { .. } => "ALLO",
+            Command::Abor => "ABOR",
+            Command::Stou => "STOU",
+            Command::Rnfr { .. } => "RNFR",
+            Command::Rnto { .. } => "RNTO",
+            Command::Auth { .. } => "AUTH",
+            Command::Adat { .. } => "ADAT",
+            Command::Mic { .. } => "MIC",
+            Command::Enc { .. } => "ENC",
+            Command::CCC => "CCC",
+            Command::PBSZ {} => "PBSZ",
+            Command::PROT { .. } => "PROT",
+            Command::SIZE { .. } => "SIZE",
+            Command::Rest { .. } => "REST",
+            Command::MDTM { .. } => "MDTM",
+            Command::Site { .. } => "SITE",
+            Command::Custom { verb, .. } => verb,
+        };
+        verb.to_string()
+    }
+
+    /// The arguments of the command, as the client gave them. Passwords, account information,
+    /// security data and protected commands are left out.
+    pub fn arguments(&self) -> String {
+        match self {
+            Command::User { username } => String::from_utf8_lossy(username).into_owned(),
+            Command::Pass { .. } | Command::Acct { .. } | Command::Adat { .. } | Command::Mic { .. } | Command::Enc { .. } => "******".to_string(),
+            Command::Stat { path } => path.as_ref().map(|path| String::from_utf8_lossy(path).into_owned()).unwrap_or_default(),
+            Command::Type { param } => param.to_string(),
+            Command::Stru { structure } => structure.to_string(),
+            Command::Mode { mode } => mode.to_string(),
+            Command::Epsv { param } => match param {
+                EpsvParam::Any => String::new(),
+                EpsvParam::Protocol(protocol) => protocol.to_string(),
+                EpsvParam::All => "ALL".to_string(),
+            },
+            Command::Lprt { addr } => addr.to_string(),
+            Command::Retr { path } | Command::Stor { path } | Command::Dele { path } | Command::Rmd { path } => path.clone(),
+            Command::List { options, path } => options.iter().chain(path).cloned().collect::<Vec<_>>().join(" "),
+            Command::Nlst { path } | Command::Mlsd { path } | Command::Mlst { path } => path.clone().unwrap_or_default(),
+            Command::Cwd { path } | Command::Mkd { path } => path.display().to_string(),
+            Command::Rnfr { file } | Command::Rnto { file } | Command::SIZE { file } | Command::MDTM { file } => file.display().to_string(),
+            Command::Opts { option } => match option {
+                Opt::UTF8 { on: true } => "UTF8 ON".to_string(),
+                Opt::UTF8 { on: false } => "UTF8 OFF".to_string(),
+                Opt::Mlst { facts } => format!("MLST {}", facts),
+            },
+            Command::Auth { protocol } => protocol.name().to_string(),
+            Command::PROT { param } => match param {
+                ProtParam::Clear => "C",
+                ProtParam::Safe => "S",
+                ProtParam::Confidential => "E",
+                ProtParam::Private => "P",
+            }
+            .to_string(),
+            Command::Rest { offset } => offset.to_string(),
+            Command::Site { subcommand, argument } => format!("{} {}", subcommand, argument).trim_end().to_string(),
+            Command::Custom { argument, .. } => argument.clone(),
+            Command::Syst
+            | Command::Help
+            | Command::Noop
+            | Command::Pasv
+            | Command::Port
+            | Command::Lpsv
+            | Command::Feat
+            | Command::Pwd
+            | Command::Cdup
+            | Command::Quit
+            | Command::Allo {}
+            | Command::Abor
+            | Command::Stou
+            | Command::CCC
+            | Command::PBSZ {} => String::new(),
+        }
+    }
+
     /// Parse the given bytes into a [`Command`].
     ///
     /// [`Command`]: ./enum.Command.html
@@ -153,7 +320,9 @@ impl Command {
             }
             "ACCT" => {
                 let account = parse_to_eol(cmd_params)?;
-                Command::Acct { account }
+                Command::Acct {
+                    account: Password::new(account),
+                }
             }
             "SYST" => Command::Syst,
             "STAT" => {
@@ -162,32 +331,50 @@ impl Command {
                 Command::Stat { path }
             }
             "TYPE" => {
-                // We don't care about text format conversion, so we'll ignore the params and we're
-                // just always in binary mode.
-                Command::Type
+                let params = parse_to_eol(cmd_params)?.to_ascii_uppercase();
+                let params: Vec<&[u8]> = params.split(|b| *b == b' ').collect();
+                let format = |param: &[u8]| match param {
+                    b"N" => Ok(FormatControl::NonPrint),
+                    b"T" => Ok(FormatControl::Telnet),
+                    b"C" => Ok(FormatControl::Asa),
+                    _ => Err(ParseError::InvalidCommand),
+                };
+                let param = match params.as_slice() {
+                    [b"A"] => TypeParam::Ascii(FormatControl::NonPrint),
+                    [b"A", param] => TypeParam::Ascii(format(param)?),
+                    [b"E"] => TypeParam::Ebcdic(FormatControl::NonPrint),
+                    [b"E", param] => TypeParam::Ebcdic(format(param)?),
+                    [b"I"] => TypeParam::Image,
+                    [b"L", size] => match std::str::from_utf8(size).ok().and_then(|size| size.parse().ok()) {
+                        Some(size) => TypeParam::LocalByte(size),
+                        None => return Err(ParseError::InvalidCommand),
+                    },
+                    _ => return Err(ParseError::InvalidCommand),
+                };
+                Command::Type { param }
             }
             "STRU" => {
                 let params = parse_to_eol(cmd_params)?;
                 if params.len() > 1 {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 match params.first() {
                     Some(b'F') => Command::Stru { structure: StruParam::File },
                     Some(b'R') => Command::Stru { structure: StruParam::Record },
                     Some(b'P') => Command::Stru { structure: StruParam::Page },
-                    _ => return Err(ParseErrorKind::InvalidCommand.into()),
+                    _ => return Err(ParseError::InvalidCommand),
                 }
             }
             "MODE" => {
                 let params = parse_to_eol(cmd_params)?;
                 if params.len() > 1 {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 match params.first() {
                     Some(b'S') => Command::Mode { mode: ModeParam::Stream },
                     Some(b'B') => Command::Mode { mode: ModeParam::Block },
                     Some(b'C') => Command::Mode { mode: ModeParam::Compressed },
-                    _ => return Err(ParseErrorKind::InvalidCommand.into()),
+                    _ => return Err(ParseError::InvalidCommand),
                 }
             }
             "HELP" => Command::Help,
@@ -195,28 +382,50 @@ impl Command {
                 let params = parse_to_eol(cmd_params)?;
                 if !params.is_empty() {
                     // NOOP params are prohibited
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 Command::Noop
             }
             "PASV" => {
                 let params = parse_to_eol(cmd_params)?;
                 if !params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 Command::Pasv
             }
+            "EPSV" => {
+                let params = parse_to_eol(cmd_params)?;
+                let params = str::from_utf8(&params)?;
+                let param = match params {
+                    "" => EpsvParam::Any,
+                    _ if params.eq_ignore_ascii_case("ALL") => EpsvParam::All,
+                    _ => EpsvParam::Protocol(params.parse().map_err(|_| ParseError::InvalidCommand)?),
+                };
+                Command::Epsv { param }
+            }
             "PORT" => {
                 let params = parse_to_eol(cmd_params)?;
                 if params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 Command::Port
             }
+            "LPSV" => {
+                let params = parse_to_eol(cmd_params)?;
+                if !params.is_empty() {
+                    return Err(ParseError::InvalidCommand);
+                }
+                Command::Lpsv
+            }
+            "LPRT" => {
+                let params = parse_to_eol(cmd_params)?;
+                let addr = parse_long_address(str::from_utf8(&params)?).ok_or(ParseError::InvalidCommand)?;
+                Command::Lprt { addr }
+            }
             "RETR" => {
                 let path = parse_to_eol(cmd_params)?;
                 if path.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 let path = String::from_utf8_lossy(&path);
                 // TODO: Can we do this without allocation?
@@ -225,7 +434,7 @@ impl Command {
             "STOR" => {
                 let path = parse_to_eol(cmd_params)?;
                 if path.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 // TODO:: Can we do this without allocation?
                 let path = String::from_utf8_lossy(&path);
@@ -250,24 +459,37 @@ impl Command {
                 };
                 Command::Nlst { path }
             }
+            "MLSD" | "MLST" => {
+                let path = parse_to_eol(cmd_params)?;
+                let path = if path.is_empty() {
+                    None
+                } else {
+                    Some(String::from_utf8_lossy(&path).to_string())
+                };
+                if cmd_token == "MLSD" {
+                    Command::Mlsd { path }
+                } else {
+                    Command::Mlst { path }
+                }
+            }
             "FEAT" => {
                 let params = parse_to_eol(cmd_params)?;
                 if !params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 Command::Feat
             }
             "PWD" | "XPWD" => {
                 let params = parse_to_eol(cmd_params)?;
                 if !params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 Command::Pwd
             }
             "CWD" | "XCWD" => {
                 let path = parse_to_eol(cmd_params)?;
                 if path.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 let path = String::from_utf8_lossy(&path).to_string();
                 let path = path.into();
@@ -276,14 +498,14 @@ impl Command {
             "CDUP" => {
                 let params = parse_to_eol(cmd_params)?;
                 if !params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 Command::Cdup
             }
             "OPTS" => {
                 let params = parse_to_eol(cmd_params)?;
                 if params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
 
                 match &params[..] {
@@ -293,13 +515,18 @@ impl Command {
                     b"UTF8 OFF" => Command::Opts {
                         option: Opt::UTF8 { on: false },
                     },
-                    _ => return Err(ParseErrorKind::InvalidCommand.into()),
+                    [b'M', b'L', b'S', b'T'] | [b'M', b'L', b'S', b'T', b' ', ..] => Command::Opts {
+                        option: Opt::Mlst {
+                            facts: String::from_utf8_lossy(&params[4..]).trim().to_string(),
+                        },
+                    },
+                    _ => return Err(ParseError::InvalidCommand),
                 }
             }
             "DELE" => {
                 let path = parse_to_eol(cmd_params)?;
                 if path.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
 
                 let path = String::from_utf8_lossy(&path).to_string();
@@ -308,7 +535,7 @@ impl Command {
             "RMD" => {
                 let path = parse_to_eol(cmd_params)?;
                 if path.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
 
                 let path = String::from_utf8_lossy(&path).to_string();
@@ -317,7 +544,7 @@ impl Command {
             "QUIT" => {
                 let params = parse_to_eol(cmd_params)?;
                 if !params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
 
                 Command::Quit
@@ -325,7 +552,7 @@ impl Command {
             "MKD" | "XMKD" => {
                 let params = parse_to_eol(cmd_params)?;
                 if params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
 
                 let path = String::from_utf8_lossy(&params).to_string();
@@ -336,74 +563,58 @@ impl Command {
             "ABOR" => {
                 let params = parse_to_eol(cmd_params)?;
                 if !params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 Command::Abor
             }
             "STOU" => {
                 let params = parse_to_eol(cmd_params)?;
                 if !params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 Command::Stou
             }
             "RNFR" => {
                 let params = parse_to_eol(cmd_params)?;
                 if params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
-                }
-
-                let file = String::from_utf8_lossy(&params).to_string();
-                // We really match on "/" and not some cross-OS-portable delimiter, because RFC
-                // 3659 actually defines "/" as the standard delimiter.
-                if file.contains('/') {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
 
-                let file = file.into();
+                let file = String::from_utf8_lossy(&params).to_string().into();
                 Command::Rnfr { file }
             }
             "RNTO" => {
                 let params = parse_to_eol(cmd_params)?;
                 if params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
 
-                let file = String::from_utf8_lossy(&params).to_string();
-                // We really match on "/" and not some cross-OS-portable delimiter, because RFC
-                // 3659 actually defines "/" as the standard delimiter.
-                if file.contains('/') {
-                    return Err(ParseErrorKind::InvalidCommand.into());
-                }
-
-                let file = file.into();
+                let file = String::from_utf8_lossy(&params).to_string().into();
                 Command::Rnto { file }
             }
             "AUTH" => {
                 let params = parse_to_eol(cmd_params)?;
-                if params.len() > 3 {
-                    return Err(ParseErrorKind::InvalidCommand.into());
-                }
-                match str::from_utf8(&params)
-                    .context(ParseErrorKind::InvalidUTF8)?
-                    .to_string()
-                    .to_uppercase()
-                    .as_str()
-                {
-                    "TLS" => Command::Auth { protocol: AuthParam::Tls },
+                // RFC 4217 makes TLS-C a synonym of TLS. Mechanisms we don't know are refused by
+                // the handler, as are the ones the server doesn't accept.
+                match str::from_utf8(&params)?.trim().to_uppercase().as_str() {
+                    "" => return Err(ParseError::InvalidCommand),
+                    "TLS" | "TLS-C" => Command::Auth { protocol: AuthParam::Tls },
                     "SSL" => Command::Auth { protocol: AuthParam::Ssl },
-                    _ => return Err(ParseErrorKind::InvalidCommand.into()),
+                    "GSSAPI" => Command::Auth { protocol: AuthParam::Gssapi },
+                    other => Command::Auth {
+                        protocol: AuthParam::Other(other.to_string()),
+                    },
                 }
             }
             "PBSZ" => {
                 let params = parse_to_eol(cmd_params)?;
                 if params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
 
                 let size = String::from_utf8_lossy(&params).to_string();
                 if size != "0" {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
 
                 Command::PBSZ {}
@@ -411,10 +622,10 @@ impl Command {
             "PROT" => {
                 let params = parse_to_eol(cmd_params)?;
                 if params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 if params.len() > 1 {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 match params.first() {
                     Some(b'C') => Command::PROT { param: ProtParam::Clear },
@@ -423,20 +634,39 @@ impl Command {
                         param: ProtParam::Confidential,
                     },
                     Some(b'P') => Command::PROT { param: ProtParam::Private },
-                    _ => return Err(ParseErrorKind::InvalidCommand.into()),
+                    _ => return Err(ParseError::InvalidCommand),
+                }
+            }
+            "ADAT" => {
+                let params = parse_to_eol(cmd_params)?;
+                if params.is_empty() {
+                    return Err(ParseError::InvalidCommand);
+                }
+                Command::Adat { token: Password::new(params) }
+            }
+            "MIC" | "ENC" => {
+                let params = parse_to_eol(cmd_params)?;
+                if params.is_empty() {
+                    return Err(ParseError::InvalidCommand);
+                }
+                let message = Password::new(params);
+                if cmd_token == "MIC" {
+                    Command::Mic { message }
+                } else {
+                    Command::Enc { message }
                 }
             }
             "CCC" => {
                 let params = parse_to_eol(cmd_params)?;
                 if !params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 Command::CCC
             }
             "SIZE" => {
                 let params = parse_to_eol(cmd_params)?;
                 if params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
                 let file = String::from_utf8_lossy(&params).to_string().into();
                 Command::SIZE { file }
@@ -444,32 +674,57 @@ impl Command {
             "REST" => {
                 let params = parse_to_eol(cmd_params)?;
                 if params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
 
                 let offset = String::from_utf8_lossy(&params).to_string();
                 if let Ok(val) = offset.parse::<u64>() {
                     Command::Rest { offset: val }
                 } else {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
             }
             "MDTM" => {
                 let params = parse_to_eol(cmd_params)?;
                 if params.is_empty() {
-                    return Err(ParseErrorKind::InvalidCommand.into());
+                    return Err(ParseError::InvalidCommand);
                 }
 
                 let file = String::from_utf8_lossy(&params).to_string().into();
                 Command::MDTM { file }
             }
+            "SITE" => {
+                let params = parse_to_eol(cmd_params)?;
+                let params = str::from_utf8(&params)?;
+                let mut params = params.splitn(2, ' ');
+                let subcommand = params.next().unwrap_or_default().to_uppercase();
+                if subcommand.is_empty() {
+                    return Err(ParseError::InvalidCommand);
+                }
+                let argument = params.next().unwrap_or_default().trim().to_string();
+                Command::Site { subcommand, argument }
+            }
             _ => {
-                return Err(ParseErrorKind::UnknownCommand { command: cmd_token }.into());
+                return Err(ParseError::UnknownCommand { command: cmd_token });
             }
         };
 
         Ok(cmd)
     }
+
+    /// Parse the given bytes into a [`Command::Custom`], whatever the verb is.
+    ///
+    /// [`Command::Custom`]: ./enum.Command.html#variant.Custom
+    pub fn parse_custom<T: AsRef<[u8]> + Into<Bytes>>(buf: T) -> Result<Command> {
+        let vec = buf.into().to_vec();
+        let mut iter = vec.splitn(2, |&b| b == b' ' || b == b'\r' || b == b'\n');
+        let verb = normalize(iter.next().unwrap())?;
+        let argument = parse_to_eol(iter.next().unwrap_or(&[]).to_vec())?;
+        Ok(Command::Custom {
+            verb,
+            argument: String::from(str::from_utf8(&argument)?),
+        })
+    }
 }
 
 /// Try to parse a buffer of bytes, up to end of line into a `&str`.
@@ -481,13 +736,13 @@ fn parse_to_eol<T: AsRef<[u8]> + Into<Bytes>>(bytes: T) -> Result<Bytes> {
     loop {
         let b = match iter.next() {
             Some(b) => b,
-            _ => return Err(ParseErrorKind::InvalidEOL.into()),
+            _ => return Err(ParseError::InvalidEOL),
         };
 
         if *b == b'\r' {
             match iter.next() {
                 Some(b'\n') => return Ok(bytes.split_to(pos)),
-                _ => return Err(ParseErrorKind::InvalidEOL.into()),
+                _ => return Err(ParseError::InvalidEOL),
             }
         }
 
@@ -496,7 +751,7 @@ fn parse_to_eol<T: AsRef<[u8]> + Into<Bytes>>(bytes: T) -> Result<Bytes> {
         }
 
         if !is_valid_token_char(*b) {
-            return Err(ParseErrorKind::InvalidToken { token: *b }.into());
+            return Err(ParseError::InvalidToken { token: *b });
         }
 
         // We don't have to be afraid of an overflow here, since a `Bytes` can never be bigger than
@@ -516,7 +771,6 @@ fn is_valid_token_char(b: u8) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::server::controlchan::parse_error::{ParseError, ParseErrorKind};
     use pretty_assertions::assert_eq;
 
     #[test]
@@ -549,14 +803,14 @@ mod tests {
     // Although we accept requests ending in only '\n', we won't accept requests ending only in '\r'
     fn parse_user_cmd_cr() {
         let input = "USER Dolores\r";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidEOL))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidEOL));
     }
 
     #[test]
     // We should fail if the request does not end in '\n' or '\r'
     fn parse_user_cmd_no_eol() {
         let input = "USER Dolores";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidEOL))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidEOL));
     }
 
     #[test]
@@ -600,10 +854,42 @@ mod tests {
         assert_eq!(Command::parse(input).unwrap(), Command::Acct { account: "Teddy".into() });
     }
 
+    #[test]
+    fn credentials_obscured_when_logged() {
+        let pass = Command::parse("PASS s3cr3t\r\n").unwrap();
+        let acct = Command::parse("ACCT s3cr3t\r\n").unwrap();
+        for cmd in &[pass, acct] {
+            assert!(!format!("{}", cmd).contains("s3cr3t"));
+            assert!(!format!("{:?}", cmd).contains("s3cr3t"));
+        }
+    }
+
+    #[test]
+    fn parse_type() {
+        let cases = vec![
+            ("TYPE A\r\n", TypeParam::Ascii(FormatControl::NonPrint)),
+            ("TYPE a n\r\n", TypeParam::Ascii(FormatControl::NonPrint)),
+            ("TYPE A T\r\n", TypeParam::Ascii(FormatControl::Telnet)),
+            ("TYPE E C\r\n", TypeParam::Ebcdic(FormatControl::Asa)),
+            ("TYPE I\r\n", TypeParam::Image),
+            ("TYPE L 8\r\n", TypeParam::LocalByte(8)),
+        ];
+        for (input, param) in cases {
+            assert_eq!(Command::parse(input).unwrap(), Command::Type { param }, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn parse_type_garbage() {
+        for input in &["TYPE\r\n", "TYPE X\r\n", "TYPE A X\r\n", "TYPE L\r\n", "TYPE L eight\r\n", "TYPE I N\r\n"] {
+            assert_eq!(Command::parse(*input), Err(ParseError::InvalidCommand), "{:?}", input);
+        }
+    }
+
     #[test]
     fn parse_stru_no_params() {
         let input = "STRU\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
     }
 
     #[test]
@@ -627,13 +913,13 @@ mod tests {
     #[test]
     fn parse_stru_garbage() {
         let input = "STRU FSK\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
 
         let input = "STRU F lskdjf\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
 
         let input = "STRU\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
     }
 
     #[test]
@@ -657,13 +943,13 @@ mod tests {
     #[test]
     fn parse_mode_garbage() {
         let input = "MODE SKDJF\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
 
         let input = "MODE\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
 
         let input = "MODE S D\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
     }
 
     #[test]
@@ -681,7 +967,7 @@ mod tests {
         assert_eq!(Command::parse(input).unwrap(), Command::Noop);
 
         let input = "NOOP bla\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
     }
 
     #[test]
@@ -690,13 +976,44 @@ mod tests {
         assert_eq!(Command::parse(input).unwrap(), Command::Pasv);
 
         let input = "PASV bla\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
+    }
+
+    #[test]
+    fn parse_epsv() {
+        let input = "EPSV\r\n";
+        assert_eq!(Command::parse(input).unwrap(), Command::Epsv { param: EpsvParam::Any });
+
+        let input = "EPSV 2\r\n";
+        assert_eq!(Command::parse(input).unwrap(), Command::Epsv { param: EpsvParam::Protocol(2) });
+
+        let input = "EPSV all\r\n";
+        assert_eq!(Command::parse(input).unwrap(), Command::Epsv { param: EpsvParam::All });
+
+        let input = "EPSV bla\r\n";
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
+    }
+
+    #[test]
+    fn parse_long_passive_and_port() {
+        assert_eq!(Command::parse("LPSV\r\n").unwrap(), Command::Lpsv);
+        assert_eq!(Command::parse("LPSV 4\r\n"), Err(ParseError::InvalidCommand));
+
+        let input = "LPRT 4,4,127,0,0,1,2,4,1\r\n";
+        assert_eq!(
+            Command::parse(input).unwrap(),
+            Command::Lprt {
+                addr: "127.0.0.1:1025".parse().unwrap()
+            }
+        );
+        assert_eq!(Command::parse("LPRT\r\n"), Err(ParseError::InvalidCommand));
+        assert_eq!(Command::parse("LPRT 4,4,127,0,0,1\r\n"), Err(ParseError::InvalidCommand));
     }
 
     #[test]
     fn parse_port() {
         let input = "PORT\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
 
         let input = "PORT a1,a2,a3,a4,p1,p2\r\n";
         assert_eq!(Command::parse(input).unwrap(), Command::Port);
@@ -747,13 +1064,59 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_mlsd_mlst() {
+        let input = "MLSD\r\n";
+        assert_eq!(Command::parse(input), Ok(Command::Mlsd { path: None }));
+
+        let input = "MLSD some dir\r\n";
+        assert_eq!(
+            Command::parse(input),
+            Ok(Command::Mlsd {
+                path: Some("some dir".to_string())
+            })
+        );
+
+        let input = "MLST file.txt\r\n";
+        assert_eq!(
+            Command::parse(input),
+            Ok(Command::Mlst {
+                path: Some("file.txt".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn parse_site() {
+        let input = "SITE\r\n";
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
+
+        let input = "SITE help\r\n";
+        assert_eq!(
+            Command::parse(input),
+            Ok(Command::Site {
+                subcommand: "HELP".to_string(),
+                argument: "".to_string()
+            })
+        );
+
+        let input = "site chmod 644 a file.txt\r\n";
+        assert_eq!(
+            Command::parse(input),
+            Ok(Command::Site {
+                subcommand: "CHMOD".to_string(),
+                argument: "644 a file.txt".to_string()
+            })
+        );
+    }
+
     #[test]
     fn parse_feat() {
         let input = "FEAT\r\n";
         assert_eq!(Command::parse(input), Ok(Command::Feat));
 
         let input = "FEAT bla\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
     }
 
     #[test]
@@ -762,13 +1125,13 @@ mod tests {
         assert_eq!(Command::parse(input), Ok(Command::Pwd));
 
         let input = "PWD bla\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
     }
 
     #[test]
     fn parse_cwd() {
         let input = "CWD\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
 
         let input = "CWD /tmp\r\n";
         assert_eq!(Command::parse(input), Ok(Command::Cwd { path: "/tmp".into() }));
@@ -783,19 +1146,19 @@ mod tests {
         assert_eq!(Command::parse(input), Ok(Command::Cdup));
 
         let input = "CDUP bla\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
     }
 
     #[test]
     fn parse_opts() {
         let input = "OPTS\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
 
         let input = "OPTS bla\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
 
         let input = "OPTS UTF8\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
 
         let input = "OPTS UTF8 ON\r\n";
         assert_eq!(
@@ -812,12 +1175,30 @@ mod tests {
                 option: Opt::UTF8 { on: false }
             })
         );
+
+        let input = "OPTS MLST type;size;\r\n";
+        assert_eq!(
+            Command::parse(input),
+            Ok(Command::Opts {
+                option: Opt::Mlst {
+                    facts: "type;size;".to_string()
+                }
+            })
+        );
+
+        let input = "OPTS MLST\r\n";
+        assert_eq!(
+            Command::parse(input),
+            Ok(Command::Opts {
+                option: Opt::Mlst { facts: "".to_string() }
+            })
+        );
     }
 
     #[test]
     fn parse_dele() {
         let input = "DELE\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
 
         let input = "DELE some_file\r\n";
         assert_eq!(Command::parse(input), Ok(Command::Dele { path: "some_file".into() }));
@@ -826,7 +1207,7 @@ mod tests {
     #[test]
     fn parse_rmd() {
         let input = "RMD\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
 
         let input = "RMD some_directory\r\n";
         assert_eq!(Command::parse(input), Ok(Command::Rmd { path: "some_directory".into() }));
@@ -838,13 +1219,13 @@ mod tests {
         assert_eq!(Command::parse(input), Ok(Command::Quit));
 
         let input = "QUIT NOW\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
     }
 
     #[test]
     fn parse_mkd() {
         let input = "MKD\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
 
         let input = "MKD bla\r\n";
         assert_eq!(Command::parse(input), Ok(Command::Mkd { path: "bla".into() }));
@@ -870,7 +1251,7 @@ mod tests {
         assert_eq!(Command::parse(input), Ok(Command::Abor));
 
         let input = "ABOR bla\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
     }
 
     #[test]
@@ -879,16 +1260,16 @@ mod tests {
         assert_eq!(Command::parse(input), Ok(Command::Stou));
 
         let input = "STOU bla\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
     }
 
     #[test]
     fn parse_rnfr() {
         let input = "RNFR\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
 
         let input = "RNFR dir/file\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Ok(Command::Rnfr { file: "dir/file".into() }));
 
         let input = "RNFR myfile\r\n";
         assert_eq!(Command::parse(input), Ok(Command::Rnfr { file: "myfile".into() }));
@@ -900,10 +1281,10 @@ mod tests {
     #[test]
     fn parse_rnto() {
         let input = "RNTO\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
 
         let input = "RNTO dir/file\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(Command::parse(input), Ok(Command::Rnto { file: "dir/file".into() }));
 
         let input = "RNTO name with spaces\r\n";
         assert_eq!(
@@ -919,11 +1300,36 @@ mod tests {
 
     #[test]
     fn parse_auth() {
+        let input = "AUTH\r\n";
+        assert_eq!(Command::parse(input), Err(ParseError::InvalidCommand));
+
         let input = "AUTH xx\r\n";
-        assert_eq!(Command::parse(input), Err(ParseError::from(Context::new(ParseErrorKind::InvalidCommand))));
+        assert_eq!(
+            Command::parse(input),
+            Ok(Command::Auth {
+                protocol: AuthParam::Other("XX".to_string())
+            })
+        );
 
         let input = "AUTH tls\r\n";
         assert_eq!(Command::parse(input), Ok(Command::Auth { protocol: AuthParam::Tls }));
+
+        let input = "AUTH TLS-C\r\n";
+        assert_eq!(Command::parse(input), Ok(Command::Auth { protocol: AuthParam::Tls }));
+    }
+
+    #[test]
+    fn parse_security_data() {
+        let input = "ADAT YWJj\r\n";
+        assert_eq!(Command::parse(input), Ok(Command::Adat { token: "YWJj".into() }));
+        assert!(!format!("{:?}", Command::parse(input)).contains("YWJj"));
+        assert_eq!(Command::parse("ADAT\r\n"), Err(ParseError::InvalidCommand));
+
+        let input = "MIC YWJj\r\n";
+        assert_eq!(Command::parse(input), Ok(Command::Mic { message: "YWJj".into() }));
+        let input = "ENC YWJj\r\n";
+        assert_eq!(Command::parse(input), Ok(Command::Enc { message: "YWJj".into() }));
+        assert_eq!(Command::parse(input).unwrap().verb(), "ENC");
     }
 
     #[test]
@@ -936,11 +1342,11 @@ mod tests {
         let tests = [
             Test {
                 input: "REST\r\n",
-                expected: Err(ParseErrorKind::InvalidCommand.into()),
+                expected: Err(ParseError::InvalidCommand),
             },
             Test {
                 input: "REST xxx\r\n",
-                expected: Err(ParseErrorKind::InvalidCommand.into()),
+                expected: Err(ParseError::InvalidCommand),
             },
             Test {
                 input: "REST 1303\r\n",
@@ -948,7 +1354,7 @@ mod tests {
             },
             Test {
                 input: "REST 1303 343\r\n",
-                expected: Err(ParseErrorKind::InvalidCommand.into()),
+                expected: Err(ParseError::InvalidCommand),
             },
         ];
 
@@ -966,7 +1372,7 @@ mod tests {
         let tests = [
             Test {
                 input: "MDTM\r\n",
-                expected: Err(ParseErrorKind::InvalidCommand.into()),
+                expected: Err(ParseError::InvalidCommand),
             },
             Test {
                 input: "MDTM file.txt\r\n",
@@ -977,4 +1383,25 @@ mod tests {
             assert_eq!(Command::parse(test.input), test.expected);
         }
     }
+
+    #[test]
+    fn arguments() {
+        let arguments = |input: &'static str| Command::parse(input).unwrap().arguments();
+        assert_eq!(arguments("PASS secret\r\n"), "******");
+        assert_eq!(arguments("ADAT YWJj\r\n"), "******");
+        assert_eq!(arguments("LIST -la pub\r\n"), "pub");
+        assert_eq!(arguments("RETR a b.txt\r\n"), "a b.txt");
+        assert_eq!(arguments("SITE chmod 644 a.txt\r\n"), "CHMOD 644 a.txt");
+        assert_eq!(arguments("REST 100\r\n"), "100");
+        assert_eq!(arguments("PWD\r\n"), "");
+    }
+
+    #[test]
+    fn verb() {
+        let verb = |input: &'static str| Command::parse(input).unwrap().verb();
+        assert_eq!(verb("PBSZ 0\r\n"), "PBSZ");
+        assert_eq!(verb("mdtm a.txt\r\n"), "MDTM");
+        assert_eq!(verb("SITE chmod 644 a.txt\r\n"), "SITE");
+        assert_eq!(verb("EPSV ALL\r\n"), "EPSV");
+    }
 }
@@ -17,7 +17,6 @@ use crate::storage;
 
 use async_trait::async_trait;
 use futures::prelude::*;
-use log::warn;
 
 pub struct Abor;
 
@@ -32,13 +31,16 @@ where
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
         let mut session = args.session.lock().await;
         match session.data_abort_tx.take() {
+            // Until the data connection is made there is nothing to abort.
+            Some(_) if session.data_abort_rx.is_some() => Ok(Reply::new(ReplyCode::ClosingDataConnection, "Closed data channel")),
+            // The data channel replies, after telling that the transfer was aborted if there
+            // was one.
             Some(mut tx) => {
-                tokio::spawn(async move {
-                    if let Err(err) = tx.send(()).await {
-                        warn!("abort failed: {}", err);
-                    }
-                });
-                Ok(Reply::new(ReplyCode::ClosingDataConnection, "Closed data channel"))
+                drop(session);
+                match tx.send(()).await {
+                    Ok(_) => Ok(Reply::none()),
+                    Err(_) => Ok(Reply::new(ReplyCode::ClosingDataConnection, "Data channel already closed")),
+                }
             }
             None => Ok(Reply::new(ReplyCode::ClosingDataConnection, "Data channel already closed")),
         }
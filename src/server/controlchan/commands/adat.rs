@@ -0,0 +1,64 @@
+//! The RFC 2228 Authentication/Security Data (`ADAT`) command
+//!
+//! After `AUTH GSSAPI` the client and server exchange the tokens that establish the GSSAPI
+//! security context with ADAT, until the server replies with 235.
+
+use crate::auth::UserDetail;
+use crate::server::controlchan::error::ControlChanError;
+use crate::server::controlchan::handler::CommandContext;
+use crate::server::controlchan::handler::CommandHandler;
+use crate::server::controlchan::{Reply, ReplyCode};
+use crate::server::password::Password;
+use crate::storage;
+use async_trait::async_trait;
+use slog::warn;
+
+pub struct Adat {
+    token: Password,
+}
+
+impl Adat {
+    pub fn new(token: Password) -> Self {
+        Adat { token }
+    }
+}
+
+#[async_trait]
+impl<S, U> CommandHandler<S, U> for Adat
+where
+    U: UserDetail + 'static,
+    S: 'static + storage::StorageBackend<U> + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+{
+    async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let token = match base64::decode(String::from_utf8_lossy(self.token.as_ref()).trim()) {
+            Ok(token) => token,
+            Err(_) => return Ok(Reply::new(ReplyCode::ParameterSyntaxError, "Security data is not base64 encoded")),
+        };
+        let gssapi = match &args.session.lock().await.gssapi {
+            Some(gssapi) => gssapi.clone(),
+            None => return Ok(Reply::new(ReplyCode::CommandNotImplemented, "GSSAPI not supported")),
+        };
+        let step = gssapi.step(token).await;
+        match step {
+            None => Ok(Reply::new(ReplyCode::BadCommandSequence, "Use AUTH GSSAPI first")),
+            Some(Err(err)) => {
+                warn!(args.logger, "GSSAPI security data exchange failed: {}", err);
+                Ok(Reply::new(ReplyCode::FailedSecurityCheck, "Failed security check"))
+            }
+            Some(Ok((token, complete))) => {
+                let code = if complete {
+                    ReplyCode::SecurityDataExchangeComplete
+                } else {
+                    ReplyCode::SecurityDataAccepted
+                };
+                match token {
+                    Some(token) => Ok(Reply::new_with_string(code, format!("ADAT={}", base64::encode(token)))),
+                    None if complete => Ok(Reply::new(code, "Security data exchange complete")),
+                    None => Ok(Reply::new(code, "Security data accepted, send more")),
+                }
+            }
+        }
+    }
+}
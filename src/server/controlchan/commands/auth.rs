@@ -13,15 +13,34 @@ use crate::server::controlchan::{Reply, ReplyCode};
 use crate::storage;
 use async_trait::async_trait;
 use futures::prelude::*;
-use log::warn;
+use slog::warn;
 
 // The parameter that can be given to the `AUTH` command.
 #[derive(Debug, PartialEq, Clone)]
 pub enum AuthParam {
     Ssl,
     Tls,
+    Gssapi,
+    // A mechanism we don't support, in upper case.
+    Other(String),
 }
 
+impl AuthParam {
+    // The name of the mechanism as it is given to `ServerBuilder::ftps_auth_mechanisms`.
+    pub fn name(&self) -> &str {
+        match self {
+            AuthParam::Ssl => "SSL",
+            AuthParam::Tls => "TLS",
+            AuthParam::Gssapi => "GSSAPI",
+            AuthParam::Other(name) => name,
+        }
+    }
+}
+
+// The mechanisms that are accepted unless the server was configured otherwise. SSL is accepted
+// for older clients, but is answered with TLS like TLS is.
+pub const DEFAULT_AUTH_MECHANISMS: [&str; 2] = ["TLS", "SSL"];
+
 pub struct Auth {
     protocol: AuthParam,
 }
@@ -41,17 +60,43 @@ where
     S::Metadata: storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
         let mut tx = args.tx.clone();
-        match (args.tls_configured, self.protocol.clone()) {
-            (true, AuthParam::Tls) => {
+        let name = self.protocol.name();
+        #[cfg(feature = "gssapi")]
+        {
+            if self.protocol == AuthParam::Gssapi {
+                let session = args.session.lock().await;
+                if let Some(gssapi) = &session.gssapi {
+                    gssapi.start();
+                    return Ok(Reply::new(
+                        ReplyCode::SecurityMechanismAccepted,
+                        "Using authentication type GSSAPI; ADAT must follow",
+                    ));
+                }
+            }
+        }
+        // Implicit FTPS clients are already talking TLS and can't start it a second time.
+        if args.session.lock().await.cmd_tls {
+            return Ok(Reply::new(ReplyCode::BadCommandSequence, "The control channel is already secured"));
+        }
+        let accepted = match self.protocol {
+            AuthParam::Other(_) => false,
+            _ => args.auth_mechanisms.iter().any(|mechanism| mechanism == name),
+        };
+        match (args.tls_configured, accepted) {
+            (true, true) => {
                 tokio::spawn(async move {
                     if let Err(err) = tx.send(InternalMsg::SecureControlChannel).await {
-                        warn!("{}", err);
+                        warn!(logger, "{}", err);
                     }
                 });
                 Ok(Reply::new(ReplyCode::AuthOkayNoDataNeeded, "Upgrading to TLS"))
             }
-            (true, AuthParam::Ssl) => Ok(Reply::new(ReplyCode::CommandNotImplementedForParameter, "Auth SSL not implemented")),
+            (true, false) => Ok(Reply::new_with_string(
+                ReplyCode::CommandNotImplementedForParameter,
+                format!("AUTH {} not supported", name),
+            )),
             (false, _) => Ok(Reply::new(ReplyCode::CommandNotImplemented, "TLS/SSL not configured")),
         }
     }
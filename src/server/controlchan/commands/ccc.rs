@@ -10,7 +10,7 @@ use crate::storage;
 use async_trait::async_trait;
 use futures::channel::mpsc::Sender;
 use futures::prelude::*;
-use log::warn;
+use slog::warn;
 pub struct Ccc;
 
 #[async_trait]
@@ -22,12 +22,13 @@ where
     S::Metadata: storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
         let mut tx: Sender<InternalMsg> = args.tx.clone();
         let session = args.session.lock().await;
         if session.cmd_tls {
             tokio::spawn(async move {
                 if let Err(err) = tx.send(InternalMsg::PlaintextControlChannel).await {
-                    warn!("{}", err);
+                    warn!(logger, "{}", err);
                 }
             });
             Ok(Reply::new(ReplyCode::CommandOkay, "control channel in plaintext now"))
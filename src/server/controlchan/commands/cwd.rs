@@ -13,10 +13,11 @@ use crate::server::controlchan::error::ControlChanError;
 use crate::server::controlchan::handler::CommandContext;
 use crate::server::controlchan::handler::CommandHandler;
 use crate::server::controlchan::Reply;
+use crate::server::path;
 use crate::storage;
 use async_trait::async_trait;
 use futures::prelude::*;
-use log::warn;
+use slog::warn;
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -39,23 +40,28 @@ where
     S::Metadata: storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
         let mut session = args.session.lock().await;
         let storage: Arc<S> = Arc::clone(&session.storage);
-        let path = session.cwd.join(self.path.clone());
+        let path = match session.client_path(&self.path) {
+            Ok(path) => path,
+            Err(err) => return Ok(super::path_error_reply(err)),
+        };
         let mut tx_success = args.tx.clone();
         let mut tx_fail = args.tx.clone();
 
-        if let Err(err) = storage.cwd(&session.user, path.clone()).await {
-            warn!("Failed to cwd directory: {}", err);
+        let storage_path = path::to_storage(&session.path_mapper, &session.user, path.clone());
+        if let Err(err) = storage::with_timeout(&logger, session.storage_timeout, storage.cwd(&session.user, storage_path)).await {
+            warn!(logger, "Failed to cwd directory: {}", err);
             let r = tx_fail.send(InternalMsg::StorageError(err)).await;
             if let Err(e) = r {
-                warn!("Could not send internal message to notify of CWD error: {}", e);
+                warn!(logger, "Could not send internal message to notify of CWD error: {}", e);
             }
         } else {
             let r = tx_success.send(InternalMsg::CwdSuccess).await;
             session.cwd.push(path);
             if let Err(e) = r {
-                warn!("Could not send internal message to notify of CWD success: {}", e);
+                warn!(logger, "Could not send internal message to notify of CWD success: {}", e);
             }
         }
 
@@ -6,6 +6,7 @@
 // it should be provided by the user-FTP process.
 
 use crate::auth::UserDetail;
+use crate::notification::FileEventKind;
 use crate::server::chancomms::InternalMsg;
 use crate::server::controlchan::error::ControlChanError;
 use crate::server::controlchan::handler::CommandContext;
@@ -15,7 +16,7 @@ use crate::storage;
 use async_trait::async_trait;
 use futures::channel::mpsc::Sender;
 use futures::prelude::*;
-use log::warn;
+use slog::warn;
 use std::string::String;
 use std::sync::Arc;
 
@@ -38,22 +39,32 @@ where
     S::Metadata: storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
         let session = args.session.lock().await;
         let storage = Arc::clone(&session.storage);
         let user = session.user.clone();
-        let path = session.cwd.join(self.path.clone());
+        let path = match session.storage_path(&self.path) {
+            Ok(path) => path,
+            Err(err) => return Ok(super::path_error_reply(err)),
+        };
+        let storage_timeout = session.storage_timeout;
         let mut tx_success: Sender<InternalMsg> = args.tx.clone();
         let mut tx_fail: Sender<InternalMsg> = args.tx.clone();
+        let notifier = session.notifier.clone();
+        let username = session.username.clone();
         tokio::spawn(async move {
-            match storage.del(&user, path).await {
+            match storage::with_timeout(&logger, storage_timeout, storage.del(&user, path.clone())).await {
                 Ok(_) => {
+                    if let Some(notifier) = notifier {
+                        notifier.notify(FileEventKind::Deleted, path, username);
+                    }
                     if let Err(err) = tx_success.send(InternalMsg::DelSuccess).await {
-                        warn!("{}", err);
+                        warn!(logger, "{}", err);
                     }
                 }
                 Err(err) => {
                     if let Err(err) = tx_fail.send(InternalMsg::StorageError(err)).await {
-                        warn!("{}", err);
+                        warn!(logger, "{}", err);
                     }
                 }
             }
@@ -0,0 +1,102 @@
+//! The RFC 2428 Extended Passive Mode (`EPSV`) command
+//
+// The EPSV command requests that a server listen on a data port and
+// wait for a connection.  The EPSV command takes an optional argument.
+// The response to this command includes only the TCP port number of the
+// listening connection.  The format of the response, however, is
+// similar to the argument of the EPRT command.  This allows the same
+// parsing routines to be used for both commands.
+//
+// When the EPSV command is issued with no argument, the server will
+// choose the network protocol for the data connection based on the
+// protocol used for the control connection.
+
+use crate::auth::UserDetail;
+use crate::server::chancomms::{PassiveMode, ProxyLoopMsg};
+use crate::server::controlchan::commands::Pasv;
+use crate::server::controlchan::error::ControlChanError;
+use crate::server::controlchan::handler::CommandContext;
+use crate::server::controlchan::handler::CommandHandler;
+use crate::server::controlchan::{Reply, ReplyCode};
+use crate::server::ipfilter::unmap_ipv4;
+use crate::storage;
+use async_trait::async_trait;
+use futures::prelude::*;
+use std::net::IpAddr;
+
+/// The parameter that can be given to the `EPSV` command.
+#[derive(Debug, PartialEq, Clone)]
+pub enum EpsvParam {
+    /// Use the network protocol of the control connection.
+    Any,
+    /// Use the given network protocol, `1` for IPv4 and `2` for IPv6.
+    Protocol(u8),
+    /// Only allow `EPSV` for setting up data connections from here on.
+    All,
+}
+
+pub struct Epsv {
+    param: EpsvParam,
+}
+
+impl Epsv {
+    pub fn new(param: EpsvParam) -> Self {
+        Epsv { param }
+    }
+}
+
+// The RFC 2428 network protocol number of the given address.
+fn network_protocol(ip: IpAddr) -> u8 {
+    match unmap_ipv4(ip) {
+        IpAddr::V4(_) => 1,
+        IpAddr::V6(_) => 2,
+    }
+}
+
+/// Formats the reply to a successful `EPSV` command.
+pub(crate) fn extended_passive_mode_reply(port: u16) -> Reply {
+    Reply::new_with_string(ReplyCode::EnteringExtendedPassiveMode, format!("Entering Extended Passive Mode (|||{}|)", port))
+}
+
+#[async_trait]
+impl<S, U> CommandHandler<S, U> for Epsv
+where
+    U: UserDetail + 'static,
+    S: 'static + storage::StorageBackend<U> + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+{
+    async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let control_ip = match args.control_connection_info {
+            Some(conn) => conn.from_ip,
+            None => args.local_addr.ip(),
+        };
+        match self.param {
+            EpsvParam::All => {
+                args.session.lock().await.epsv_all = true;
+                return Ok(Reply::new(ReplyCode::CommandOkay, "EPSV ALL ok"));
+            }
+            EpsvParam::Protocol(protocol) if protocol != network_protocol(control_ip) => {
+                return Ok(Reply::new_with_string(
+                    ReplyCode::NetworkProtocolNotSupported,
+                    format!("Network protocol not supported, use ({})", network_protocol(control_ip)),
+                ));
+            }
+            _ => {}
+        }
+
+        match args.proxyloop_msg_tx.clone() {
+            Some(mut tx) => {
+                Pasv::setup_data_loop_comms(args.session.clone()).await;
+                match tx.send(ProxyLoopMsg::AssignDataPortCommand(args.session.clone(), PassiveMode::Extended)).await {
+                    Ok(()) => Ok(Reply::None),
+                    Err(_) => Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
+                }
+            }
+            None => match Pasv::listen_for_data_connection(&args).await {
+                Ok(port) => Ok(extended_passive_mode_reply(port)),
+                Err(_) => Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
+            },
+        }
+    }
+}
@@ -5,7 +5,8 @@ use crate::server::controlchan::error::ControlChanError;
 use crate::server::controlchan::handler::CommandContext;
 use crate::server::controlchan::handler::CommandHandler;
 use crate::server::controlchan::{Reply, ReplyCode};
-use crate::storage;
+use crate::server::facts;
+use crate::storage::{self, Capabilities};
 use async_trait::async_trait;
 
 pub struct Feat;
@@ -19,24 +20,42 @@ where
     S::Metadata: storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
-        let mut feat_text = vec![" SIZE", " MDTM", "UTF8"];
+        let session = args.session.lock().await;
+        let mlst = format!(" MLST {}", facts::feat(&session.mlst_facts));
+        let auth: Vec<String> = args.auth_mechanisms.iter().map(|mechanism| format!(" AUTH {}", mechanism)).collect();
+        let mut feat_text = vec![" SIZE", " MDTM", " EPSV", " UTF8", " TVFS", &mlst];
         // Add the features. According to the spec each feature line must be
         // indented by a space.
         if args.tls_configured {
-            feat_text.push(" AUTH TLS");
+            feat_text.extend(auth.iter().map(String::as_str));
             feat_text.push(" PBSZ");
             feat_text.push(" PROT");
         }
-        if args.storage_features & storage::FEATURE_RESTART > 0 {
+        #[cfg(feature = "gssapi")]
+        {
+            if session.gssapi.is_some() {
+                feat_text.push(" AUTH GSSAPI");
+            }
+        }
+        if args.storage_features.contains(Capabilities::RESTART) {
             feat_text.push(" REST STREAM");
         }
 
+        // Leave out the features of disabled commands. UTF8 is switched on with OPTS.
+        feat_text.retain(|feature| {
+            let verb = match feature.trim() {
+                "UTF8" => "OPTS",
+                feature => feature.split(' ').next().unwrap_or_default(),
+            };
+            !args.disabled_commands.contains(verb)
+        });
+
         // Show them in alphabetical order.
         feat_text.sort();
         feat_text.insert(0, "Extensions supported:");
         feat_text.push("END");
 
-        let reply = Reply::new_multiline(ReplyCode::SystemStatus, feat_text);
+        let reply = Reply::multiline(ReplyCode::SystemStatus, feat_text);
         Ok(reply)
     }
 }
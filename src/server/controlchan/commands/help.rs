@@ -10,9 +10,64 @@ use crate::server::controlchan::error::ControlChanError;
 use crate::server::controlchan::handler::CommandContext;
 use crate::server::controlchan::handler::CommandHandler;
 use crate::server::controlchan::{Reply, ReplyCode};
-use crate::storage;
+use crate::storage::{self, Capabilities};
 use async_trait::async_trait;
 
+// The commands the server implements, as listed in the reply, with the storage features they
+// need. Those it only answers with 502, like PORT, are left out.
+const COMMANDS: &[(&str, Capabilities)] = &[
+    ("ABOR", Capabilities::empty()),
+    ("ACCT", Capabilities::empty()),
+    ("ALLO", Capabilities::empty()),
+    ("AUTH", Capabilities::empty()),
+    ("CCC", Capabilities::empty()),
+    ("CDUP", Capabilities::empty()),
+    ("CWD", Capabilities::empty()),
+    ("DELE", Capabilities::empty()),
+    ("EPSV", Capabilities::empty()),
+    ("FEAT", Capabilities::empty()),
+    ("HELP", Capabilities::empty()),
+    ("LIST", Capabilities::empty()),
+    ("LPSV", Capabilities::empty()),
+    ("MDTM", Capabilities::empty()),
+    ("MKD", Capabilities::empty()),
+    ("MLSD", Capabilities::empty()),
+    ("MLST", Capabilities::empty()),
+    ("MODE", Capabilities::empty()),
+    ("NLST", Capabilities::empty()),
+    ("NOOP", Capabilities::empty()),
+    ("OPTS", Capabilities::empty()),
+    ("PASS", Capabilities::empty()),
+    ("PASV", Capabilities::empty()),
+    ("PBSZ", Capabilities::empty()),
+    ("PROT", Capabilities::empty()),
+    ("PWD", Capabilities::empty()),
+    ("QUIT", Capabilities::empty()),
+    ("REST", Capabilities::RESTART),
+    ("RETR", Capabilities::empty()),
+    ("RMD", Capabilities::RMD),
+    ("RNFR", Capabilities::RENAME),
+    ("RNTO", Capabilities::RENAME),
+    ("SITE", Capabilities::empty()),
+    ("SIZE", Capabilities::empty()),
+    ("STAT", Capabilities::empty()),
+    ("STOR", Capabilities::empty()),
+    ("STOU", Capabilities::empty()),
+    ("STRU", Capabilities::empty()),
+    ("SYST", Capabilities::empty()),
+    ("TYPE", Capabilities::empty()),
+    ("USER", Capabilities::empty()),
+];
+
+/// The storage features that the command with the given verb needs.
+pub(crate) fn required_capabilities(verb: &str) -> Capabilities {
+    COMMANDS
+        .iter()
+        .find(|(name, _)| *name == verb)
+        .map(|(_, features)| *features)
+        .unwrap_or_default()
+}
+
 pub struct Help;
 
 #[async_trait]
@@ -23,9 +78,19 @@ where
     S::File: tokio::io::AsyncRead + Send,
     S::Metadata: storage::Metadata,
 {
-    async fn handle(&self, _args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
-        let text = vec!["Help:", "Powered by libunftp"];
+    async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let mut text = vec!["Help:".to_string()];
+        if !args.identification.is_empty() {
+            text.push(args.identification.to_string());
+        }
         // TODO: Add useful information here like operating server type and app name.
-        Ok(Reply::new_multiline(ReplyCode::HelpMessage, text))
+        let commands: Vec<&str> = COMMANDS
+            .iter()
+            .filter(|(verb, features)| args.storage_features.contains(*features) && !args.disabled_commands.contains(*verb))
+            .map(|(verb, _)| *verb)
+            .collect();
+        text.push("The following commands are recognized:".to_string());
+        text.extend(commands.chunks(10).map(|verbs| verbs.join(" ")));
+        Ok(Reply::multiline(ReplyCode::HelpMessage, text))
     }
 }
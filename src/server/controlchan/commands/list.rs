@@ -22,7 +22,7 @@ use crate::server::controlchan::{Reply, ReplyCode};
 use crate::storage;
 use async_trait::async_trait;
 use futures::prelude::*;
-use log::warn;
+use slog::warn;
 
 pub struct List;
 
@@ -35,16 +35,17 @@ where
     S::Metadata: storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
         let mut session = args.session.lock().await;
         let cmd: Command = args.cmd.clone();
         match session.data_cmd_tx.take() {
             Some(mut tx) => {
                 tokio::spawn(async move {
                     if let Err(err) = tx.send(cmd).await {
-                        warn!("could not notify data channel to respond with LIST. {}", err);
+                        warn!(logger, "could not notify data channel to respond with LIST. {}", err);
                     }
                 });
-                Ok(Reply::new(ReplyCode::FileStatusOkay, "Sending directory list"))
+                Ok(Reply::none())
             }
             None => Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
         }
@@ -0,0 +1,81 @@
+//! The RFC 1639 Long Port (`LPRT`) command
+//
+// The LPRT command is the long address version of PORT. Its argument
+// gives the address family, the length and bytes of the host address
+// and the length and bytes of the port:
+//
+// LPRT af,hal,h1,h2,...,pal,p1,p2
+//
+// Like PORT it asks for an active mode data connection, which we don't
+// make, so clients are told to use passive mode.
+
+use crate::auth::UserDetail;
+use crate::server::controlchan::error::ControlChanError;
+use crate::server::controlchan::handler::CommandContext;
+use crate::server::controlchan::handler::CommandHandler;
+use crate::server::controlchan::{Reply, ReplyCode};
+use crate::storage;
+use async_trait::async_trait;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+pub struct Lprt;
+
+// Parses the argument of LPRT into the address it describes. Returns None if it is malformed or
+// names an address family other than IPv4 (4) or IPv6 (6).
+pub(crate) fn parse_long_address(arg: &str) -> Option<SocketAddr> {
+    let numbers: Vec<u8> = arg.split(',').map(|n| n.trim().parse().ok()).collect::<Option<_>>()?;
+    let (family, rest) = numbers.split_first()?;
+    let (address_len, rest) = rest.split_first()?;
+    let address_len = usize::from(*address_len);
+    if rest.len() != address_len + 3 || rest[address_len] != 2 {
+        return None;
+    }
+    let (address, port) = (&rest[..address_len], &rest[address_len + 1..]);
+    let ip = match (family, address_len) {
+        (4, 4) => IpAddr::V4(Ipv4Addr::new(address[0], address[1], address[2], address[3])),
+        (6, 16) => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(address);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, u16::from(port[0]) << 8 | u16::from(port[1])))
+}
+
+#[async_trait]
+impl<S, U> CommandHandler<S, U> for Lprt
+where
+    U: UserDetail + 'static,
+    S: 'static + storage::StorageBackend<U> + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+{
+    async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        if args.session.lock().await.epsv_all {
+            return Ok(Reply::new(ReplyCode::BadCommandSequence, "Only EPSV is allowed after EPSV ALL"));
+        }
+        Ok(Reply::new(
+            ReplyCode::CommandNotImplemented,
+            "ACTIVE mode is not supported - use LPSV or EPSV instead",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_long_address;
+
+    #[test]
+    fn long_addresses() {
+        assert_eq!(parse_long_address("4,4,192,168,1,2,2,195,80"), Some("192.168.1.2:50000".parse().unwrap()));
+        assert_eq!(
+            parse_long_address("6,16,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,2,1,2"),
+            Some("[::1]:258".parse().unwrap())
+        );
+        assert_eq!(parse_long_address("4,4,192,168,1,2,2,195"), None);
+        assert_eq!(parse_long_address("4,16,192,168,1,2,2,195,80"), None);
+        assert_eq!(parse_long_address("5,4,192,168,1,2,2,195,80"), None);
+        assert_eq!(parse_long_address("4,4,192,168,1,256,2,195,80"), None);
+    }
+}
@@ -0,0 +1,102 @@
+//! The RFC 1639 Long Passive (`LPSV`) command
+//
+// The LPSV command is the long address version of PASV. The reply
+// tells the client the address family, the length and bytes of the
+// host address and the length and bytes of the port to connect to:
+//
+// 228 Entering Long Passive Mode (af,hal,h1,h2,...,pal,p1,p2)
+//
+// where af is 4 for IPv4, with 4 address bytes, or 6 for IPv6, with
+// 16 address bytes.
+
+use crate::auth::UserDetail;
+use crate::server::chancomms::{PassiveMode, ProxyLoopMsg};
+use crate::server::controlchan::commands::Pasv;
+use crate::server::controlchan::error::ControlChanError;
+use crate::server::controlchan::handler::CommandContext;
+use crate::server::controlchan::handler::CommandHandler;
+use crate::server::controlchan::{Reply, ReplyCode};
+use crate::server::ipfilter::unmap_ipv4;
+use crate::storage;
+use async_trait::async_trait;
+use futures::prelude::*;
+use std::net::IpAddr;
+
+pub struct Lpsv;
+
+/// Formats the reply to a successful `LPSV` command.
+pub(crate) fn long_passive_mode_reply(ip: IpAddr, port: u16) -> Reply {
+    let (family, address) = match unmap_ipv4(ip) {
+        IpAddr::V4(ip) => (4, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (6, ip.octets().to_vec()),
+    };
+    let address: Vec<String> = address.iter().map(u8::to_string).collect();
+    Reply::new_with_string(
+        ReplyCode::EnteringLongPassiveMode,
+        format!(
+            "Entering Long Passive Mode ({},{},{},2,{},{})",
+            family,
+            address.len(),
+            address.join(","),
+            port >> 8,
+            port & 0xff
+        ),
+    )
+}
+
+#[async_trait]
+impl<S, U> CommandHandler<S, U> for Lpsv
+where
+    U: UserDetail + 'static,
+    S: 'static + storage::StorageBackend<U> + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+{
+    async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        if args.session.lock().await.epsv_all {
+            return Ok(Reply::new(ReplyCode::BadCommandSequence, "Only EPSV is allowed after EPSV ALL"));
+        }
+        match args.proxyloop_msg_tx.clone() {
+            Some(mut tx) => {
+                Pasv::setup_data_loop_comms(args.session.clone()).await;
+                match tx.send(ProxyLoopMsg::AssignDataPortCommand(args.session.clone(), PassiveMode::Long)).await {
+                    Ok(()) => Ok(Reply::None),
+                    Err(_) => Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
+                }
+            }
+            None => match Pasv::listen_for_data_connection(&args).await {
+                Ok(port) => Ok(long_passive_mode_reply(Pasv::data_ip(&args), port)),
+                Err(_) => Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::long_passive_mode_reply;
+    use crate::server::controlchan::Reply;
+
+    fn text(reply: Reply) -> String {
+        match reply {
+            Reply::CodeAndMsg { msg, .. } => msg,
+            reply => panic!("Unexpected reply: {:?}", reply),
+        }
+    }
+
+    #[test]
+    fn reply_formats() {
+        assert_eq!(
+            text(long_passive_mode_reply("192.168.1.2".parse().unwrap(), 50_000)),
+            "Entering Long Passive Mode (4,4,192,168,1,2,2,195,80)"
+        );
+        assert_eq!(
+            text(long_passive_mode_reply("::ffff:10.0.0.1".parse().unwrap(), 258)),
+            "Entering Long Passive Mode (4,4,10,0,0,1,2,1,2)"
+        );
+        assert_eq!(
+            text(long_passive_mode_reply("::1".parse().unwrap(), 258)),
+            "Entering Long Passive Mode (6,16,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,2,1,2)"
+        );
+    }
+}
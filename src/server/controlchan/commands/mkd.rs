@@ -6,16 +6,18 @@
 // the pathname is relative).
 
 use crate::auth::UserDetail;
+use crate::notification::FileEventKind;
 use crate::server::chancomms::InternalMsg;
 use crate::server::controlchan::error::ControlChanError;
 use crate::server::controlchan::handler::CommandContext;
 use crate::server::controlchan::handler::CommandHandler;
-use crate::server::controlchan::Reply;
+use crate::server::controlchan::{Reply, ReplyCode};
+use crate::server::path;
 use crate::storage;
 use async_trait::async_trait;
 use futures::channel::mpsc::Sender;
 use futures::prelude::*;
-use log::warn;
+use slog::warn;
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -38,19 +40,35 @@ where
     S::Metadata: storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
         let session = args.session.lock().await;
         let user = session.user.clone();
         let storage = Arc::clone(&session.storage);
-        let path: PathBuf = session.cwd.join(self.path.clone());
+        let path: PathBuf = match session.client_path(&self.path) {
+            Ok(path) => path,
+            Err(err) => return Ok(super::path_error_reply(err)),
+        };
+        if !session.filename_allowed(&path) {
+            return Ok(Reply::new(ReplyCode::BadFileName, "File name not allowed"));
+        }
+        let storage_path = path::to_storage(&session.path_mapper, &session.user, path.clone());
+        let storage_timeout = session.storage_timeout;
         let mut tx_success: Sender<InternalMsg> = args.tx.clone();
         let mut tx_fail: Sender<InternalMsg> = args.tx.clone();
+        let notifier = session.notifier.clone();
+        let username = session.username.clone();
         tokio::spawn(async move {
-            if let Err(err) = storage.mkd(&user, &path).await {
+            if let Err(err) = storage::with_timeout(&logger, storage_timeout, storage.mkd(&user, &storage_path)).await {
                 if let Err(err) = tx_fail.send(InternalMsg::StorageError(err)).await {
-                    warn!("{}", err);
+                    warn!(logger, "{}", err);
+                }
+            } else {
+                if let Some(notifier) = notifier {
+                    notifier.notify(FileEventKind::DirectoryCreated, storage_path, username);
+                }
+                if let Err(err) = tx_success.send(InternalMsg::MkdirSuccess(path)).await {
+                    warn!(logger, "{}", err);
                 }
-            } else if let Err(err) = tx_success.send(InternalMsg::MkdirSuccess(path)).await {
-                warn!("{}", err);
             }
         });
         Ok(Reply::none())
@@ -0,0 +1,44 @@
+//! The RFC 3659 List Directory (`MLSD`) command
+//
+// The MLSD command sends a listing of the directory to the data connection, with one entry per
+// line made up of the facts the client selected with OPTS MLST and the name of the entry. Unlike
+// the output of LIST, this format is the same on every server, so clients can parse it reliably.
+
+use crate::auth::UserDetail;
+use crate::server::controlchan::command::Command;
+use crate::server::controlchan::error::ControlChanError;
+use crate::server::controlchan::handler::CommandContext;
+use crate::server::controlchan::handler::CommandHandler;
+use crate::server::controlchan::{Reply, ReplyCode};
+use crate::storage;
+use async_trait::async_trait;
+use futures::prelude::*;
+use slog::warn;
+
+pub struct Mlsd;
+
+#[async_trait]
+impl<S, U> CommandHandler<S, U> for Mlsd
+where
+    U: UserDetail + 'static,
+    S: 'static + storage::StorageBackend<U> + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+{
+    async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
+        let mut session = args.session.lock().await;
+        let cmd: Command = args.cmd.clone();
+        match session.data_cmd_tx.take() {
+            Some(mut tx) => {
+                tokio::spawn(async move {
+                    if let Err(err) = tx.send(cmd).await {
+                        warn!(logger, "could not notify data channel to respond with MLSD. {}", err);
+                    }
+                });
+                Ok(Reply::none())
+            }
+            None => Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! The RFC 3659 List Single Object (`MLST`) command
+//
+// The MLST command shows the facts about a single file or directory on the control connection,
+// in the same format as the lines MLSD sends. Without a path it shows the facts about the
+// current working directory.
+
+use crate::auth::UserDetail;
+use crate::server::chancomms::InternalMsg;
+use crate::server::controlchan::error::ControlChanError;
+use crate::server::controlchan::handler::CommandContext;
+use crate::server::controlchan::handler::CommandHandler;
+use crate::server::controlchan::{Reply, ReplyCode};
+use crate::server::{facts, metadata_cache, path};
+use crate::storage;
+use async_trait::async_trait;
+use futures::channel::mpsc::Sender;
+use futures::prelude::*;
+use slog::warn;
+use std::sync::Arc;
+
+pub struct Mlst {
+    path: Option<String>,
+}
+
+impl Mlst {
+    pub fn new(path: Option<String>) -> Self {
+        Mlst { path }
+    }
+}
+
+#[async_trait]
+impl<S, U> CommandHandler<S, U> for Mlst
+where
+    U: UserDetail + 'static,
+    S: 'static + storage::StorageBackend<U> + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: 'static + storage::Metadata,
+{
+    async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
+        let session = args.session.lock().await;
+        let path = match session.client_path(self.path.as_deref().unwrap_or_default()) {
+            Ok(path) => path,
+            Err(err) => return Ok(super::path_error_reply(err)),
+        };
+        let storage_path = path::to_storage(&session.path_mapper, &session.user, path.clone());
+        let user = session.user.clone();
+        let storage: Arc<S> = Arc::clone(&session.storage);
+        let storage_timeout = session.storage_timeout;
+        let cache = session.metadata_cache.clone();
+        let selected = session.mlst_facts.clone();
+        let mut tx_success: Sender<InternalMsg> = args.tx.clone();
+        let mut tx_fail: Sender<InternalMsg> = args.tx.clone();
+
+        tokio::spawn(async move {
+            match metadata_cache::metadata(&cache, &*storage, &user, &storage_path, storage_timeout, &logger).await {
+                Ok(metadata) => {
+                    let line = facts::line(&selected, &path.to_string_lossy(), &*metadata);
+                    // The line with the facts starts with a space, as RFC 3659 asks.
+                    let text = format!("Listing {}\n {}\nEnd", path.display(), line);
+                    if let Err(err) = tx_success.send(InternalMsg::CommandChannelReply(ReplyCode::FileActionOkay, text)).await {
+                        warn!(logger, "{}", err);
+                    }
+                }
+                Err(err) => {
+                    if let Err(err) = tx_fail.send(InternalMsg::StorageError(err)).await {
+                        warn!(logger, "{}", err);
+                    }
+                }
+            }
+        });
+        Ok(Reply::none())
+    }
+}
@@ -3,20 +3,28 @@
 //! - [RFC 959 - FTP](https://tools.ietf.org/html/rfc959)
 //! - [RFC 3659 - Extensions to FTP](https://tools.ietf.org/html/rfc3659)
 //! - [RFC 2228 - FTP Security Extensions](https://tools.ietf.org/html/rfc2228)
+//! - [RFC 2428 - FTP Extensions for IPv6 and NATs](https://tools.ietf.org/html/rfc2428)
 
 mod abor;
 mod acct;
+#[cfg(feature = "gssapi")]
+mod adat;
 mod allo;
 mod auth;
 mod ccc;
 mod cdup;
 mod cwd;
 mod dele;
+mod epsv;
 mod feat;
 mod help;
 mod list;
+mod lprt;
+mod lpsv;
 mod mdtm;
 mod mkd;
+mod mlsd;
+mod mlst;
 mod mode;
 mod nlst;
 mod noop;
@@ -33,6 +41,7 @@ mod retr;
 mod rmd;
 mod rnfr;
 mod rnto;
+mod site;
 mod size;
 mod stat;
 mod stor;
@@ -44,23 +53,38 @@ mod user;
 
 pub use abor::Abor;
 pub use acct::Acct;
+#[cfg(feature = "gssapi")]
+pub use adat::Adat;
 pub use allo::Allo;
-pub use auth::{Auth, AuthParam};
+pub use auth::{Auth, AuthParam, DEFAULT_AUTH_MECHANISMS};
 pub use ccc::Ccc;
 pub use cdup::Cdup;
 pub use cwd::Cwd;
 pub use dele::Dele;
+#[cfg(feature = "proxy_protocol")]
+pub(crate) use epsv::extended_passive_mode_reply;
+pub use epsv::{Epsv, EpsvParam};
 pub use feat::Feat;
+pub(crate) use help::required_capabilities as command_capabilities;
 pub use help::Help;
 pub use list::List;
+pub(crate) use lprt::parse_long_address;
+pub use lprt::Lprt;
+#[cfg(feature = "proxy_protocol")]
+pub(crate) use lpsv::long_passive_mode_reply;
+pub use lpsv::Lpsv;
 pub use mdtm::Mdtm;
 pub use mkd::Mkd;
+pub use mlsd::Mlsd;
+pub use mlst::Mlst;
 pub use mode::{Mode, ModeParam};
 pub use nlst::Nlst;
 pub use noop::Noop;
 pub use opts::{Opt, Opts};
 pub use pass::Pass;
 pub use pasv::Pasv;
+#[cfg(feature = "proxy_protocol")]
+pub(crate) use pasv::{ipv6_not_supported, passive_mode_reply};
 pub use pbsz::Pbsz;
 pub use port::Port;
 pub use prot::{Prot, ProtParam};
@@ -71,11 +95,40 @@ pub use retr::Retr;
 pub use rmd::Rmd;
 pub use rnfr::Rnfr;
 pub use rnto::Rnto;
+pub(crate) use site::required_capabilities as site_capabilities;
+pub use site::Site;
 pub use size::Size;
 pub use stat::Stat;
 pub use stor::Stor;
 pub use stou::Stou;
 pub use stru::{Stru, StruParam};
 pub use syst::Syst;
-pub use type_::Type;
+pub use type_::{FormatControl, Type, TypeParam};
 pub use user::User;
+
+use crate::server::{Reply, ReplyCode};
+use crate::storage::{Error, ErrorKind};
+
+// The reply code and text for an error of the storage back-end.
+pub(crate) fn storage_error_reply(kind: ErrorKind) -> (ReplyCode, &'static str) {
+    match kind {
+        ErrorKind::ExceededStorageAllocationError => (ReplyCode::ExceededStorageAllocation, "Exceeded storage allocation"),
+        ErrorKind::FileNameNotAllowedError => (ReplyCode::BadFileName, "File name not allowed"),
+        ErrorKind::InsufficientStorageSpaceError => (ReplyCode::OutOfSpace, "Insufficient storage space"),
+        ErrorKind::LocalError => (ReplyCode::LocalError, "Local error"),
+        ErrorKind::PageTypeUnknown => (ReplyCode::PageTypeUnknown, "Page type unknown"),
+        ErrorKind::TransientFileNotAvailable => (ReplyCode::TransientFileError, "File not found"),
+        ErrorKind::PermanentFileNotAvailable => (ReplyCode::FileError, "File not found"),
+        ErrorKind::PermissionDenied => (ReplyCode::FileError, "Permission denied"),
+        ErrorKind::FileExists => (ReplyCode::BadFileName, "File exists"),
+        ErrorKind::NotSupported => (ReplyCode::CommandNotImplemented, "Not supported by the storage back-end"),
+    }
+}
+
+// The reply to a path that could not be resolved. Hidden paths look like they don't exist.
+fn path_error_reply(err: Error) -> Reply {
+    match err.kind() {
+        ErrorKind::PermanentFileNotAvailable => Reply::new(ReplyCode::FileError, "File not found"),
+        _ => Reply::new(ReplyCode::BadFileName, "File name not allowed"),
+    }
+}
@@ -19,10 +19,10 @@ use crate::server::controlchan::handler::CommandHandler;
 use crate::server::controlchan::{Reply, ReplyCode};
 use crate::storage;
 use async_trait::async_trait;
+use std::fmt;
 
-/// The parameter that can be given to the `MODE` command. The `MODE` command is obsolete, and we
-/// only support the `Stream` mode. We still have to support the command itself for compatibility
-/// reasons, though.
+/// The parameter that can be given to the `MODE` command. We support the `Stream` mode, the
+/// default, and the `Block` mode that checkpointed transfers with restart markers need.
 #[derive(Debug, PartialEq, Clone)]
 pub enum ModeParam {
     /// Data is sent in a continuous stream of bytes.
@@ -33,6 +33,16 @@ pub enum ModeParam {
     Compressed,
 }
 
+impl fmt::Display for ModeParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModeParam::Stream => write!(f, "Stream"),
+            ModeParam::Block => write!(f, "Block"),
+            ModeParam::Compressed => write!(f, "Compressed"),
+        }
+    }
+}
+
 pub struct Mode {
     params: ModeParam,
 }
@@ -51,12 +61,15 @@ where
     S::File: tokio::io::AsyncRead + Send,
     S::Metadata: storage::Metadata,
 {
-    async fn handle(&self, _args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+    async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
         match &self.params {
-            ModeParam::Stream => Ok(Reply::new(ReplyCode::CommandOkay, "Using Stream transfer mode")),
-            _ => Ok(Reply::new(
+            ModeParam::Stream | ModeParam::Block => {
+                args.session.lock().await.transfer_mode = self.params.clone();
+                Ok(Reply::new_with_string(ReplyCode::CommandOkay, format!("Using {} transfer mode", self.params)))
+            }
+            ModeParam::Compressed => Ok(Reply::new(
                 ReplyCode::CommandNotImplementedForParameter,
-                "Only Stream transfer mode is supported",
+                "Only Stream and Block transfer modes are supported",
             )),
         }
     }
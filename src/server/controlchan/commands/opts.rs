@@ -12,6 +12,7 @@ use crate::server::controlchan::error::ControlChanError;
 use crate::server::controlchan::handler::CommandContext;
 use crate::server::controlchan::handler::CommandHandler;
 use crate::server::controlchan::{Reply, ReplyCode};
+use crate::server::facts;
 use crate::storage;
 use async_trait::async_trait;
 
@@ -21,6 +22,9 @@ use async_trait::async_trait;
 pub enum Opt {
     /// The client wants us to enable UTF-8 encoding for file paths and such.
     UTF8 { on: bool },
+    /// The client selects the facts that `MLSD` and `MLST` show, given as a list like
+    /// `type;size;modify;`.
+    Mlst { facts: String },
 }
 
 pub struct Opts {
@@ -41,10 +45,17 @@ where
     S::File: tokio::io::AsyncRead + Send,
     S::Metadata: storage::Metadata,
 {
-    async fn handle(&self, _args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+    async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
         match &self.option {
             Opt::UTF8 { on: true } => Ok(Reply::new(ReplyCode::FileActionOkay, "Always in UTF-8 mode.")),
             Opt::UTF8 { on: false } => Ok(Reply::new(ReplyCode::CommandNotImplementedForParameter, "Non UTF-8 mode not supported")),
+            Opt::Mlst { facts } => {
+                // Facts we don't support are left out, the reply tells the client which remain.
+                let mut session = args.session.lock().await;
+                session.mlst_facts = facts::parse(facts);
+                let text = format!("MLST OPTS {}", facts::list(&session.mlst_facts));
+                Ok(Reply::new(ReplyCode::CommandOkay, text.trim_end()))
+            }
         }
     }
 }
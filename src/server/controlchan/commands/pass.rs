@@ -10,6 +10,7 @@
 // therefore the responsibility of the user-FTP process to hide
 // the sensitive password information.
 
+use crate::auth::anonymous::is_anonymous_user;
 use crate::auth::UserDetail;
 use crate::server::chancomms::InternalMsg;
 use crate::server::controlchan::error::ControlChanError;
@@ -23,8 +24,7 @@ use crate::storage;
 use async_trait::async_trait;
 use futures::channel::mpsc::Sender;
 use futures::prelude::*;
-use log::{error, info, warn};
-use std::sync::Arc;
+use slog::{error, info, warn};
 
 pub struct Pass {
     password: password::Password,
@@ -45,6 +45,7 @@ where
     S::Metadata: storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
         let session = args.session.lock().await;
         match &session.state {
             SessionState::WaitPass => {
@@ -53,11 +54,22 @@ where
                 let user: String = match session.username.clone() {
                     Some(v) => v,
                     None => {
-                        error!("NoneError for username. This shouldn't happen.");
+                        error!(logger, "NoneError for username. This shouldn't happen.");
                         return Ok(Reply::new(ReplyCode::NotLoggedIn, "Please open a new connection to re-authenticate"));
                     }
                 };
+                // The security context may have been established after USER.
+                #[cfg(feature = "gssapi")]
+                {
+                    if let Some(gssapi) = &session.gssapi {
+                        if !gssapi.authorizes(&user) {
+                            return Ok(Reply::new(ReplyCode::NotLoggedIn, "User not authorized by the security context"));
+                        }
+                    }
+                }
                 let mut tx: Sender<InternalMsg> = args.tx.clone();
+                let anonymous = is_anonymous_user(&user);
+                let metrics = session.metrics.clone();
 
                 let auther = args.authenticator.clone();
 
@@ -69,19 +81,22 @@ where
                         Ok(user) => {
                             if user.account_enabled() {
                                 let mut session = session2clone.lock().await;
-                                info!("User {} logged in", user);
-                                session.user = Arc::new(Some(user));
+                                info!(logger, "User {} logged in", user; "username" => user.to_string());
+                                session.log_in(user);
                                 InternalMsg::AuthSuccess
                             } else {
-                                warn!("User {} authenticated but account is disabled", user);
+                                warn!(logger, "User {} authenticated but account is disabled", user);
                                 InternalMsg::AuthFailed
                             }
                         }
                         Err(_) => InternalMsg::AuthFailed,
                     };
+                    if let Some(metrics) = metrics {
+                        metrics.add_login_metric(matches!(msg, InternalMsg::AuthSuccess), anonymous);
+                    }
                     tokio::spawn(async move {
                         if let Err(err) = tx.send(msg).await {
-                            warn!("{}", err);
+                            warn!(logger, "{}", err);
                         }
                     });
                 });
@@ -6,13 +6,14 @@
 // transfer command.  The response to this command includes the
 // host and port address this server is listening on.
 
-use crate::server::chancomms::{ProxyLoopMsg, ProxyLoopSender};
+use crate::server::chancomms::{PassiveMode, ProxyLoopMsg, ProxyLoopSender};
 use crate::server::controlchan::error::ControlChanError;
 use crate::server::controlchan::handler::CommandContext;
 use crate::server::controlchan::handler::CommandHandler;
 use crate::server::controlchan::Command;
 use crate::server::controlchan::{Reply, ReplyCode};
 use crate::server::datachan;
+use crate::server::ipfilter::unmap_ipv4;
 use crate::server::session::SharedSession;
 use crate::storage;
 
@@ -23,14 +24,13 @@ use futures::prelude::*;
 use rand::rngs::OsRng;
 use rand::RngCore;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::ops::Range;
 use tokio::net::TcpListener;
 use tokio::sync::Mutex;
 
 use lazy_static::*;
 
-const BIND_RETRIES: u8 = 10;
 lazy_static! {
     static ref OS_RNG: Mutex<OsRng> = Mutex::new(OsRng);
 }
@@ -42,26 +42,34 @@ impl Pasv {
         Pasv {}
     }
 
-    async fn try_port_range(local_addr: SocketAddr, passive_ports: Range<u16>) -> io::Result<TcpListener> {
-        let rng_length = passive_ports.end - passive_ports.start;
-
-        let mut listener: io::Result<TcpListener> = Err(io::Error::new(io::ErrorKind::InvalidInput, "Bind retries cannot be 0"));
-
-        let mut rng = OS_RNG.lock().await;
-        for _ in 1..BIND_RETRIES {
-            let port = rng.next_u32() % rng_length as u32 + passive_ports.start as u32;
-            listener = TcpListener::bind(std::net::SocketAddr::new(local_addr.ip(), port as u16)).await;
-            if listener.is_ok() {
-                break;
+    // Starts listening on a port in the range. The first port tried is a random one, so that
+    // sessions don't all compete for the same ports, after which the following ones are tried in
+    // turn while they are in use, for instance by another session. Only when every port in the
+    // range is taken does this fail. Like the standard library, tokio sets SO_REUSEADDR on Unix,
+    // so ports whose previous connections linger in TIME_WAIT can be bound again right away.
+    async fn try_port_range(ip: IpAddr, passive_ports: Range<u16>) -> io::Result<TcpListener> {
+        let rng_length = u32::from(passive_ports.end.saturating_sub(passive_ports.start));
+        if rng_length == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The passive port range is empty"));
+        }
+        let first = OS_RNG.lock().await.next_u32() % rng_length;
+
+        let mut last_error = None;
+        for i in 0..rng_length {
+            let port = passive_ports.start as u32 + (first + i) % rng_length;
+            match TcpListener::bind(SocketAddr::new(ip, port as u16)).await {
+                Ok(listener) => return Ok(listener),
+                Err(err) if err.kind() == io::ErrorKind::AddrInUse => last_error = Some(err),
+                // Other ports won't do better if the address itself can't be used.
+                Err(err) => return Err(err),
             }
         }
-
-        listener
+        Err(last_error.unwrap_or_else(|| io::Error::from(io::ErrorKind::AddrInUse)))
     }
 
     // modifies the session by adding channels that are used to communicate with the data connection
     // processing loop.
-    async fn setup_data_loop_comms<S, U>(&self, session: SharedSession<S, U>)
+    pub(super) async fn setup_data_loop_comms<S, U>(session: SharedSession<S, U>)
     where
         U: UserDetail + 'static,
         S: 'static + storage::StorageBackend<U> + Sync + Send,
@@ -78,40 +86,37 @@ impl Pasv {
         session.data_abort_rx = Some(data_abort_rx);
     }
 
-    // For non-proxy mode we choose a data port here and start listening on it while letting the control
-    // channel know (via method return) what the address is that the client should connect to.
-    async fn handle_nonproxy_mode<S, U>(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError>
+    // The address that clients connect their data connections to: the one data connections are
+    // bound to, unless that is the wildcard address, or else the one the client is connected to.
+    pub(super) fn data_ip<S, U>(args: &CommandContext<S, U>) -> IpAddr
     where
         U: UserDetail + 'static,
         S: 'static + storage::StorageBackend<U> + Sync + Send,
         S::File: tokio::io::AsyncRead + Send,
         S::Metadata: storage::Metadata,
     {
-        // obtain the ip address the client is connected to
-        let conn_addr = match args.local_addr {
-            std::net::SocketAddr::V4(addr) => addr,
-            std::net::SocketAddr::V6(_) => panic!("we only listen on ipv4, so this shouldn't happen"),
-        };
-
-        let listener = Pasv::try_port_range(args.local_addr, args.passive_ports).await;
-
-        let mut listener = match listener {
-            Err(_) => return Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
-            Ok(l) => l,
-        };
-
-        let addr = match listener.local_addr()? {
-            std::net::SocketAddr::V4(addr) => addr,
-            std::net::SocketAddr::V6(_) => panic!("we only listen on ipv4, so this shouldn't happen"),
-        };
+        match args.data_bind_ip {
+            Some(ip) if !ip.is_unspecified() => ip,
+            _ => args.local_addr.ip(),
+        }
+    }
 
-        let octets = conn_addr.ip().octets();
-        let port = addr.port();
-        let p1 = port >> 8;
-        let p2 = port - (p1 * 256);
+    // Chooses a data port on the configured data address, or else on the address the client is
+    // connected to, and starts listening on it, returning the port so that the caller can tell
+    // the client where to connect to.
+    pub(super) async fn listen_for_data_connection<S, U>(args: &CommandContext<S, U>) -> io::Result<u16>
+    where
+        U: UserDetail + 'static,
+        S: 'static + storage::StorageBackend<U> + Sync + Send,
+        S::File: tokio::io::AsyncRead + Send,
+        S::Metadata: storage::Metadata,
+    {
+        let bind_ip = args.data_bind_ip.unwrap_or_else(|| args.local_addr.ip());
+        let mut listener = Pasv::try_port_range(bind_ip, args.passive_ports.clone()).await?;
+        let port = listener.local_addr()?.port();
         let tx = args.tx.clone();
 
-        self.setup_data_loop_comms(args.session.clone()).await;
+        Pasv::setup_data_loop_comms(args.session.clone()).await;
 
         let session = args.session.clone();
 
@@ -126,10 +131,28 @@ impl Pasv {
             }
         });
 
-        Ok(Reply::new_with_string(
-            ReplyCode::EnteringPassiveMode,
-            format!("Entering Passive Mode ({},{},{},{},{},{})", octets[0], octets[1], octets[2], octets[3], p1, p2),
-        ))
+        Ok(port)
+    }
+
+    // For non-proxy mode we choose a data port here and start listening on it while letting the control
+    // channel know (via method return) what the address is that the client should connect to.
+    async fn handle_nonproxy_mode<S, U>(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError>
+    where
+        U: UserDetail + 'static,
+        S: 'static + storage::StorageBackend<U> + Sync + Send,
+        S::File: tokio::io::AsyncRead + Send,
+        S::Metadata: storage::Metadata,
+    {
+        // obtain the ip address the client should connect to
+        let conn_ip = match unmap_ipv4(Pasv::data_ip(&args)) {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => return Ok(ipv6_not_supported()),
+        };
+
+        match Pasv::listen_for_data_connection(&args).await {
+            Ok(port) => Ok(passive_mode_reply(conn_ip, port)),
+            Err(_) => Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
+        }
     }
 
     // For proxy mode we prepare the session and let the proxy loop know (via channel) that it
@@ -141,12 +164,35 @@ impl Pasv {
         S::File: tokio::io::AsyncRead + Send,
         S::Metadata: storage::Metadata,
     {
-        self.setup_data_loop_comms(args.session.clone()).await;
-        tx.send(ProxyLoopMsg::AssignDataPortCommand(args.session.clone())).await.unwrap();
-        Ok(Reply::None)
+        if let Some(conn) = args.control_connection_info {
+            if unmap_ipv4(conn.from_ip).is_ipv6() {
+                return Ok(ipv6_not_supported());
+            }
+        }
+        Pasv::setup_data_loop_comms(args.session.clone()).await;
+        match tx.send(ProxyLoopMsg::AssignDataPortCommand(args.session.clone(), PassiveMode::Standard)).await {
+            Ok(()) => Ok(Reply::None),
+            Err(_) => Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
+        }
     }
 }
 
+/// Formats the reply to a successful `PASV` command.
+pub(crate) fn passive_mode_reply(ip: Ipv4Addr, port: u16) -> Reply {
+    let octets = ip.octets();
+    let p1 = port >> 8;
+    let p2 = port - (p1 * 256);
+    Reply::new_with_string(
+        ReplyCode::EnteringPassiveMode,
+        format!("Entering Passive Mode ({},{},{},{},{},{})", octets[0], octets[1], octets[2], octets[3], p1, p2),
+    )
+}
+
+// The PASV reply can only hold an IPv4 address so IPv6 clients need to use EPSV instead.
+pub(crate) fn ipv6_not_supported() -> Reply {
+    Reply::new(ReplyCode::NetworkProtocolNotSupported, "PASV is not supported on IPv6 connections, use EPSV")
+}
+
 #[async_trait]
 impl<S, U> CommandHandler<S, U> for Pasv
 where
@@ -156,6 +202,9 @@ where
     S::Metadata: storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        if args.session.lock().await.epsv_all {
+            return Ok(Reply::new(ReplyCode::BadCommandSequence, "Only EPSV is allowed after EPSV ALL"));
+        }
         let sender: Option<ProxyLoopSender<S, U>> = args.proxyloop_msg_tx.clone();
         match sender {
             Some(tx) => self.handle_proxy_mode(args, tx.clone()).await,
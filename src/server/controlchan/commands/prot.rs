@@ -43,6 +43,12 @@ where
         match (args.tls_configured, self.param.clone()) {
             (true, ProtParam::Clear) => {
                 let mut session = args.session.lock().await;
+                if session.data_tls && session.data_downgrade_forbidden {
+                    return Ok(Reply::new(
+                        ReplyCode::DeniedForPolicyReasons,
+                        "PROT C denied, the data channel must stay private",
+                    ));
+                }
                 session.data_tls = false;
                 Ok(Reply::new(ReplyCode::CommandOkay, "PROT OK. Switching data channel to plaintext"))
             }
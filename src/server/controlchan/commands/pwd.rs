@@ -8,6 +8,7 @@ use crate::server::controlchan::error::ControlChanError;
 use crate::server::controlchan::handler::CommandContext;
 use crate::server::controlchan::handler::CommandHandler;
 use crate::server::controlchan::{Reply, ReplyCode};
+use crate::server::path;
 use crate::storage;
 use async_trait::async_trait;
 
@@ -23,10 +24,9 @@ where
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
         let session = args.session.lock().await;
-        // TODO: properly escape double quotes in `cwd`
         Ok(Reply::new_with_string(
             ReplyCode::DirCreated,
-            format!("\"{}\"", session.cwd.as_path().display()),
+            format!("{} is the current directory", path::quote(&session.cwd)),
         ))
     }
 }
@@ -22,7 +22,7 @@ use crate::storage;
 use async_trait::async_trait;
 use futures::channel::mpsc::Sender;
 use futures::prelude::*;
-use log::warn;
+use slog::warn;
 
 pub struct Quit;
 
@@ -35,10 +35,11 @@ where
     S::Metadata: storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
         let mut tx: Sender<InternalMsg> = args.tx.clone();
         //TODO does this make sense? The command is not sent and yet an Ok is replied
         if let Err(send_res) = tx.send(InternalMsg::Quit).await {
-            warn!("could not send internal message: QUIT. {}", send_res);
+            warn!(logger, "could not send internal message: QUIT. {}", send_res);
         }
         Ok(Reply::new(ReplyCode::ClosingControlConnection, "Bye!"))
     }
@@ -3,6 +3,9 @@
 //! partially transferred, both sides need some way to agree on where in
 //! the data stream to restart the data transfer.
 //!
+//! In block mode (`MODE B`) the argument is a restart marker. The markers of the server are
+//! byte offsets, like the argument in stream mode, so both are handled the same.
+//!
 //! See also: https://cr.yp.to/ftp/retr.html
 //!
 
@@ -33,9 +36,6 @@ where
     S::Metadata: 'static + storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
-        if args.storage_features & storage::FEATURE_RESTART == 0 {
-            return Ok(Reply::new(ReplyCode::CommandNotImplemented, "Not supported by the selected storage back-end."));
-        }
         let mut session = args.session.lock().await;
         session.start_pos = self.offset;
         let msg = format!("Restarting at {}. Now send STORE or RETRIEVE.", self.offset);
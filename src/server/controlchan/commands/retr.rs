@@ -14,7 +14,7 @@ use crate::server::controlchan::Reply;
 use crate::storage;
 use async_trait::async_trait;
 use futures::prelude::*;
-use log::warn;
+use slog::warn;
 
 pub struct Retr;
 
@@ -27,13 +27,14 @@ where
     S::Metadata: storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
         let mut session = args.session.lock().await;
         let cmd: Command = args.cmd.clone();
         match session.data_cmd_tx.take() {
             Some(mut tx) => {
                 tokio::spawn(async move {
                     if let Err(err) = tx.send(cmd).await {
-                        warn!("{}", err);
+                        warn!(logger, "{}", err);
                     }
                 });
                 Ok(Reply::none())
@@ -6,6 +6,7 @@
 // the pathname is relative).
 
 use crate::auth::UserDetail;
+use crate::notification::FileEventKind;
 use crate::server::chancomms::InternalMsg;
 use crate::server::controlchan::error::ControlChanError;
 use crate::server::controlchan::handler::CommandContext;
@@ -14,7 +15,7 @@ use crate::server::controlchan::Reply;
 use crate::storage;
 use async_trait::async_trait;
 use futures::prelude::*;
-use log::warn;
+use slog::warn;
 use std::string::String;
 use std::sync::Arc;
 
@@ -37,21 +38,28 @@ where
     S::Metadata: storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
         let session = args.session.lock().await;
         let storage: Arc<S> = Arc::clone(&session.storage);
-        let path = session.cwd.join(self.path.clone());
+        let path = match session.storage_path(&self.path) {
+            Ok(path) => path,
+            Err(err) => return Ok(super::path_error_reply(err)),
+        };
         let mut tx_success = args.tx.clone();
         let mut tx_fail = args.tx.clone();
-        if let Err(err) = storage.rmd(&session.user, path).await {
-            warn!("Failed to delete directory: {}", err);
+        if let Err(err) = storage::with_timeout(&logger, session.storage_timeout, storage.rmd(&session.user, path.clone())).await {
+            warn!(logger, "Failed to delete directory: {}", err);
             let r = tx_fail.send(InternalMsg::StorageError(err)).await;
             if let Err(e) = r {
-                warn!("Could not send internal message to notify of RMD error: {}", e);
+                warn!(logger, "Could not send internal message to notify of RMD error: {}", e);
             }
         } else {
+            if let Some(notifier) = &session.notifier {
+                notifier.notify(FileEventKind::DirectoryRemoved, path, session.username.clone());
+            }
             let r = tx_success.send(InternalMsg::DelSuccess).await;
             if let Err(e) = r {
-                warn!("Could not send internal message to notify of RMD success: {}", e);
+                warn!(logger, "Could not send internal message to notify of RMD success: {}", e);
             }
         }
         Ok(Reply::none())
@@ -1,13 +1,20 @@
 //! The RFC 959 Rename From (`RNFR`) command
+//
+// RNFR names the file or directory to rename and must be followed
+// immediately by RNTO with the new name. Any other command in between
+// cancels the rename.
 
 use crate::auth::UserDetail;
 use crate::server::controlchan::error::ControlChanError;
 use crate::server::controlchan::handler::CommandContext;
 use crate::server::controlchan::handler::CommandHandler;
 use crate::server::controlchan::{Reply, ReplyCode};
-use crate::storage;
+use crate::server::path;
+use crate::storage::{self, ErrorKind};
 use async_trait::async_trait;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
 
 pub struct Rnfr {
     path: PathBuf,
@@ -29,7 +36,21 @@ where
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
         let mut session = args.session.lock().await;
-        session.rename_from = Some(session.cwd.join(self.path.clone()));
-        Ok(Reply::new(ReplyCode::FileActionPending, "Tell me, what would you like the new name to be?"))
+        let (client_path, path) = match (session.client_path(&self.path), session.storage_path(&self.path)) {
+            (Ok(client_path), Ok(path)) => (client_path, path),
+            (Err(err), _) | (_, Err(err)) => return Ok(super::path_error_reply(err)),
+        };
+        // Clients learn that the file is missing before they send the new name.
+        let storage = Arc::clone(&session.storage);
+        if let Err(err) = storage::with_timeout(&args.logger, session.storage_timeout, storage.metadata(&session.user, &path)).await {
+            if err.kind() == ErrorKind::PermanentFileNotAvailable {
+                return Ok(Reply::new(ReplyCode::FileError, "File not found"));
+            }
+        }
+        session.rename_from = Some((path, Instant::now()));
+        Ok(Reply::new_with_string(
+            ReplyCode::FileActionPending,
+            format!("{} exists, ready for the new name", path::quote(&client_path)),
+        ))
     }
 }
@@ -1,15 +1,33 @@
 //! The RFC 959 Rename To (`RNTO`) command
 
 use crate::auth::UserDetail;
+use crate::notification::FileEventKind;
 use crate::server::controlchan::error::ControlChanError;
 use crate::server::controlchan::handler::CommandContext;
 use crate::server::controlchan::handler::CommandHandler;
 use crate::server::controlchan::{Reply, ReplyCode};
-use crate::storage;
+use crate::storage::{self, ErrorKind};
 use async_trait::async_trait;
-use log::warn;
+use slog::warn;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+
+// How long RNTO is accepted after RNFR.
+const RENAME_TIMEOUT: Duration = Duration::from_secs(60);
+
+// The reply to a rename that failed, which tells the client why.
+fn error_reply(err: storage::Error) -> Reply {
+    match err.kind() {
+        ErrorKind::PermanentFileNotAvailable => Reply::new(ReplyCode::FileError, "File not found"),
+        ErrorKind::FileExists => Reply::new(ReplyCode::BadFileName, "Destination already exists"),
+        ErrorKind::NotSupported => Reply::new(ReplyCode::CommandNotImplemented, "Cannot rename between these paths"),
+        ErrorKind::PermissionDenied => Reply::new(ReplyCode::FileError, "Permission denied"),
+        ErrorKind::TransientFileNotAvailable => Reply::new(ReplyCode::TransientFileError, "File busy, try again later"),
+        ErrorKind::FileNameNotAllowedError => Reply::new(ReplyCode::BadFileName, "File name not allowed"),
+        _ => Reply::new(ReplyCode::FileError, "Storage error while renaming"),
+    }
+}
 
 pub struct Rnto {
     path: PathBuf,
@@ -30,20 +48,31 @@ where
     S::Metadata: storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
         let mut session = args.session.lock().await;
         let storage = Arc::clone(&session.storage);
         let reply = match session.rename_from.take() {
-            Some(from) => {
-                let to = session.cwd.join(self.path.clone());
-                match storage.rename(&session.user, from, to).await {
-                    Ok(_) => Reply::new(ReplyCode::FileActionOkay, "Renamed"),
+            Some((_, at)) if at.elapsed() > RENAME_TIMEOUT => Reply::new(ReplyCode::BadCommandSequence, "RNFR expired, send it again"),
+            Some(_) if !session.filename_allowed(&self.path) => Reply::new(ReplyCode::BadFileName, "File name not allowed"),
+            Some((from, _)) => {
+                let to = match session.storage_path(&self.path) {
+                    Ok(to) => to,
+                    Err(err) => return Ok(super::path_error_reply(err)),
+                };
+                match storage::with_timeout(&logger, session.storage_timeout, storage.rename(&session.user, from.clone(), to.clone())).await {
+                    Ok(_) => {
+                        if let Some(notifier) = &session.notifier {
+                            notifier.notify(FileEventKind::Renamed { from }, to, session.username.clone());
+                        }
+                        Reply::new(ReplyCode::FileActionOkay, "Renamed")
+                    }
                     Err(err) => {
-                        warn!("Error renaming: {:?}", err);
-                        Reply::new(ReplyCode::FileError, "Storage error while renaming")
+                        warn!(logger, "Error renaming: {:?}", err);
+                        error_reply(err)
                     }
                 }
             }
-            None => Reply::new(ReplyCode::TransientFileError, "Please tell me what file you want to rename first"),
+            None => Reply::new(ReplyCode::BadCommandSequence, "Send RNFR first"),
         };
         Ok(reply)
     }
@@ -0,0 +1,304 @@
+//! The `SITE` command
+//
+// This command is used by the server to provide services specific to his system that are
+// essential to file transfer but not sufficiently universal to be included as commands in the
+// protocol. The nature of these services and the specification of their syntax can be stated in
+// a reply to the HELP SITE command.
+
+use crate::auth::UserDetail;
+use crate::notification::FileEventKind;
+use crate::server::chancomms::InternalMsg;
+use crate::server::controlchan::error::ControlChanError;
+use crate::server::controlchan::handler::CommandContext;
+use crate::server::controlchan::handler::CommandHandler;
+use crate::server::controlchan::{Reply, ReplyCode};
+use crate::server::path;
+use crate::storage::{self, Capabilities, Error, ErrorKind, Metadata};
+use async_trait::async_trait;
+use futures::prelude::*;
+use slog::warn;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// The SITE subcommands the server knows itself, as listed by SITE HELP, with the storage
+// features they need.
+const SUBCOMMANDS: &[(&str, Capabilities)] = &[
+    ("HELP", Capabilities::empty()),
+    ("LINK", Capabilities::SYMLINKS),
+    ("MKDIR", Capabilities::empty()),
+    ("RMDIR", Capabilities::RMD),
+    ("SYMLINK", Capabilities::SYMLINKS),
+];
+
+/// The storage features that the given SITE subcommand needs.
+pub(crate) fn required_capabilities(subcommand: &str) -> Capabilities {
+    SUBCOMMANDS
+        .iter()
+        .find(|(name, _)| *name == subcommand)
+        .map(|(_, features)| *features)
+        .unwrap_or_default()
+}
+
+// The most entries SITE RMDIR removes. Larger trees are refused before anything is removed.
+const RMDIR_MAX_ENTRIES: usize = 10_000;
+
+pub struct Site {
+    subcommand: String,
+    argument: String,
+}
+
+impl Site {
+    pub fn new(subcommand: String, argument: String) -> Self {
+        Site { subcommand, argument }
+    }
+}
+
+#[async_trait]
+impl<S, U> CommandHandler<S, U> for Site
+where
+    U: UserDetail + 'static,
+    S: 'static + storage::StorageBackend<U> + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+{
+    async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        if !SUBCOMMANDS.iter().any(|(name, _)| *name == self.subcommand) {
+            return Ok(Reply::new(ReplyCode::CommandSyntaxError, "Unknown SITE command"));
+        }
+        match self.subcommand.as_str() {
+            "HELP" => Ok(help(&args)),
+            "MKDIR" => mkdir(&self.argument, args).await,
+            "RMDIR" => rmdir(&self.argument, args).await,
+            _ => symlink(&self.argument, args).await,
+        }
+    }
+}
+
+fn help<S, U>(args: &CommandContext<S, U>) -> Reply
+where
+    U: UserDetail + 'static,
+    S: 'static + storage::StorageBackend<U> + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+{
+    let mut subcommands: Vec<&str> = SUBCOMMANDS
+        .iter()
+        .filter(|(_, features)| args.storage_features.contains(*features))
+        .map(|(name, _)| *name)
+        .chain(args.site_commands.keys().map(String::as_str))
+        .collect();
+    subcommands.sort_unstable();
+    subcommands.dedup();
+    let mut text = vec!["The following SITE commands are recognized:".to_string()];
+    text.extend(subcommands.chunks(10).map(|names| names.join(" ")));
+    Reply::multiline(ReplyCode::HelpMessage, text)
+}
+
+// SITE SYMLINK <target> <link>, or SITE LINK, creates a symbolic link. Both paths are resolved
+// like those of other commands, so the link cannot point outside of what the client can see.
+// Hidden paths that are matched as a whole could still be reached through a link to a directory
+// above them, so with such patterns the target has to be a file.
+async fn symlink<S, U>(argument: &str, args: CommandContext<S, U>) -> Result<Reply, ControlChanError>
+where
+    U: UserDetail + 'static,
+    S: 'static + storage::StorageBackend<U> + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+{
+    let (target, link) = match argument.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [target, link] => (target.to_string(), link.to_string()),
+        _ => return Ok(Reply::new(ReplyCode::ParameterSyntaxError, "Usage: SITE SYMLINK <target> <link>")),
+    };
+    let session = args.session.lock().await;
+    if session.drop_box {
+        return Ok(Reply::new(ReplyCode::FileError, "Permission denied"));
+    }
+    let (target, link) = match (session.storage_path(&target), session.storage_path(&link)) {
+        (Ok(target), Ok(link)) => (target, link),
+        (Err(err), _) | (_, Err(err)) => return Ok(super::path_error_reply(err)),
+    };
+    if !session.filename_allowed(&link) {
+        return Ok(Reply::new(ReplyCode::BadFileName, "File name not allowed"));
+    }
+    let files_only = session.hidden_paths.has_whole_path_patterns();
+    let user = session.user.clone();
+    let storage: Arc<S> = Arc::clone(&session.storage);
+    let storage_timeout = session.storage_timeout;
+    let logger = args.logger.clone();
+    let mut tx = args.tx.clone();
+    tokio::spawn(async move {
+        if files_only {
+            let metadata = storage::with_timeout(&logger, storage_timeout, storage.metadata(&user, &target)).await;
+            if !metadata.map(|metadata| metadata.is_file()).unwrap_or(false) {
+                let msg = InternalMsg::CommandChannelReply(ReplyCode::FileError, "Links can only point to files".to_string());
+                if let Err(err) = tx.send(msg).await {
+                    warn!(logger, "{}", err);
+                }
+                return;
+            }
+        }
+        let msg = match storage::with_timeout(&logger, storage_timeout, storage.symlink(&user, target, link)).await {
+            Ok(()) => InternalMsg::CommandChannelReply(ReplyCode::FileActionOkay, "Symbolic link created".to_string()),
+            Err(err) => InternalMsg::StorageError(err),
+        };
+        if let Err(err) = tx.send(msg).await {
+            warn!(logger, "{}", err);
+        }
+    });
+    Ok(Reply::none())
+}
+
+// SITE MKDIR [-p] <path> creates the directory along with the directories above it that don't
+// exist yet, like `mkdir -p` does. Directories that exist already are left alone.
+async fn mkdir<S, U>(argument: &str, args: CommandContext<S, U>) -> Result<Reply, ControlChanError>
+where
+    U: UserDetail + 'static,
+    S: 'static + storage::StorageBackend<U> + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+{
+    let argument = match argument.strip_prefix("-p") {
+        Some(rest) if rest.is_empty() || rest.starts_with(' ') => rest.trim(),
+        _ => argument,
+    };
+    if argument.is_empty() {
+        return Ok(Reply::new(ReplyCode::ParameterSyntaxError, "Usage: SITE MKDIR [-p] <path>"));
+    }
+    let session = args.session.lock().await;
+    // It would tell which of the directories exist already.
+    if session.drop_box {
+        return Ok(Reply::new(ReplyCode::FileError, "Permission denied"));
+    }
+    let path = match session.client_path(argument) {
+        Ok(path) => path,
+        Err(err) => return Ok(super::path_error_reply(err)),
+    };
+    // The directories from the top down, with their paths for the storage backend and whether
+    // their names are allowed in case they have to be created.
+    let mut dirs: Vec<(PathBuf, bool)> = path
+        .ancestors()
+        .filter(|dir| dir.parent().is_some())
+        .map(|dir| {
+            (
+                path::to_storage(&session.path_mapper, &session.user, dir.to_path_buf()),
+                session.filename_allowed(dir),
+            )
+        })
+        .collect();
+    dirs.reverse();
+    let user = session.user.clone();
+    let storage: Arc<S> = Arc::clone(&session.storage);
+    let storage_timeout = session.storage_timeout;
+    let notifier = session.notifier.clone();
+    let username = session.username.clone();
+    let logger = args.logger.clone();
+    let mut tx = args.tx.clone();
+    tokio::spawn(async move {
+        let create = async {
+            for (dir, allowed) in dirs {
+                match storage.metadata(&user, &dir).await {
+                    Ok(metadata) if metadata.is_dir() => continue,
+                    Ok(_) => return Err(Error::from(ErrorKind::FileNameNotAllowedError)),
+                    Err(_) if !allowed => return Err(Error::from(ErrorKind::FileNameNotAllowedError)),
+                    Err(_) => storage.mkd(&user, &dir).await?,
+                }
+                if let Some(notifier) = &notifier {
+                    notifier.notify(FileEventKind::DirectoryCreated, dir, username.clone());
+                }
+            }
+            Ok(())
+        };
+        let msg = match storage::with_timeout(&logger, storage_timeout, create).await {
+            Ok(()) => InternalMsg::MkdirSuccess(path),
+            Err(err) => InternalMsg::StorageError(err),
+        };
+        if let Err(err) = tx.send(msg).await {
+            warn!(logger, "{}", err);
+        }
+    });
+    Ok(Reply::none())
+}
+
+// SITE RMDIR <path> removes the directory with everything in it. The whole tree is listed first,
+// so nothing is removed when it has hidden entries or more than RMDIR_MAX_ENTRIES of them.
+async fn rmdir<S, U>(argument: &str, args: CommandContext<S, U>) -> Result<Reply, ControlChanError>
+where
+    U: UserDetail + 'static,
+    S: 'static + storage::StorageBackend<U> + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+{
+    if argument.is_empty() {
+        return Ok(Reply::new(ReplyCode::ParameterSyntaxError, "Usage: SITE RMDIR <path>"));
+    }
+    let session = args.session.lock().await;
+    if session.drop_box {
+        return Ok(Reply::new(ReplyCode::FileError, "Permission denied"));
+    }
+    let path = match session.client_path(argument) {
+        Ok(path) => path,
+        Err(err) => return Ok(super::path_error_reply(err)),
+    };
+    if path.parent().is_none() {
+        return Ok(Reply::new(ReplyCode::FileError, "Permission denied"));
+    }
+    let top = path::to_storage(&session.path_mapper, &session.user, path);
+    let user = session.user.clone();
+    let storage: Arc<S> = Arc::clone(&session.storage);
+    let storage_timeout = session.storage_timeout;
+    let path_mapper = session.path_mapper.clone();
+    let hidden_paths = session.hidden_paths.clone();
+    let notifier = session.notifier.clone();
+    let username = session.username.clone();
+    let logger = args.logger.clone();
+    let mut tx = args.tx.clone();
+    tokio::spawn(async move {
+        let remove = async {
+            // Lists the tree top down, so the directories are removed in the reverse order.
+            let mut files = vec![];
+            let mut dirs = vec![top];
+            let mut listed = 0;
+            while listed < dirs.len() {
+                for entry in storage.list(&user, &dirs[listed]).await? {
+                    if hidden_paths.is_hidden(&path::to_client(&path_mapper, &user, &entry.path)) {
+                        return Err(Error::from(ErrorKind::PermissionDenied));
+                    }
+                    if files.len() + dirs.len() > RMDIR_MAX_ENTRIES {
+                        return Err(Error::from(ErrorKind::ExceededStorageAllocationError));
+                    }
+                    if entry.metadata.is_dir() {
+                        dirs.push(entry.path);
+                    } else {
+                        files.push(entry.path);
+                    }
+                }
+                listed += 1;
+            }
+            for file in files {
+                storage.del(&user, &file).await?;
+                if let Some(notifier) = &notifier {
+                    notifier.notify(FileEventKind::Deleted, file, username.clone());
+                }
+            }
+            for dir in dirs.into_iter().rev() {
+                storage.rmd(&user, &dir).await?;
+                if let Some(notifier) = &notifier {
+                    notifier.notify(FileEventKind::DirectoryRemoved, dir, username.clone());
+                }
+            }
+            Ok(())
+        };
+        let msg = match storage::with_timeout(&logger, storage_timeout, remove).await {
+            Ok(()) => InternalMsg::CommandChannelReply(ReplyCode::FileActionOkay, "Directory removed".to_string()),
+            Err(err) if err.kind() == ErrorKind::ExceededStorageAllocationError => InternalMsg::CommandChannelReply(
+                ReplyCode::FileError,
+                format!("Directory has more than {} entries, not removed", RMDIR_MAX_ENTRIES),
+            ),
+            Err(err) => InternalMsg::StorageError(err),
+        };
+        if let Err(err) = tx.send(msg).await {
+            warn!(logger, "{}", err);
+        }
+    });
+    Ok(Reply::none())
+}
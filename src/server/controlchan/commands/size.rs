@@ -4,11 +4,12 @@ use crate::server::controlchan::error::ControlChanError;
 use crate::server::controlchan::handler::CommandContext;
 use crate::server::controlchan::handler::CommandHandler;
 use crate::server::controlchan::{Reply, ReplyCode};
+use crate::server::metadata_cache;
 use crate::storage::{self, Metadata};
 use async_trait::async_trait;
 use futures::channel::mpsc::Sender;
 use futures::prelude::*;
-use log::warn;
+use slog::warn;
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -31,16 +32,22 @@ where
     S::Metadata: 'static + storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
         let session = args.session.lock().await;
         let user = session.user.clone();
         let start_pos: u64 = session.start_pos;
         let storage: Arc<S> = Arc::clone(&session.storage);
-        let path = session.cwd.join(self.path.clone());
+        let path = match session.storage_path(&self.path) {
+            Ok(path) => path,
+            Err(err) => return Ok(super::path_error_reply(err)),
+        };
+        let storage_timeout = session.storage_timeout;
+        let cache = session.metadata_cache.clone();
         let mut tx_success: Sender<InternalMsg> = args.tx.clone();
         let mut tx_fail: Sender<InternalMsg> = args.tx.clone();
 
         tokio::spawn(async move {
-            match storage.metadata(&user, &path).await {
+            match metadata_cache::metadata(&cache, &*storage, &user, &path, storage_timeout, &logger).await {
                 Ok(metadata) => {
                     if let Err(err) = tx_success
                         .send(InternalMsg::CommandChannelReply(
@@ -49,12 +56,12 @@ where
                         ))
                         .await
                     {
-                        warn!("{}", err);
+                        warn!(logger, "{}", err);
                     }
                 }
                 Err(err) => {
                     if let Err(err) = tx_fail.send(InternalMsg::StorageError(err)).await {
-                        warn!("{}", err);
+                        warn!(logger, "{}", err);
                     }
                 }
             }
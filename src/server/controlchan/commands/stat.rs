@@ -16,6 +16,9 @@
 // status information about the server FTP process.  This
 // should include current values of all transfer parameters and
 // the status of connections.
+//
+// Like with LIST, the last component of the path may have wildcards, as in `STAT *.txt`, and a
+// path to a file lists just that file.
 
 use crate::auth::UserDetail;
 use crate::server::chancomms::InternalMsg;
@@ -23,14 +26,15 @@ use crate::server::controlchan::error::ControlChanError;
 use crate::server::controlchan::handler::CommandContext;
 use crate::server::controlchan::handler::CommandHandler;
 use crate::server::controlchan::{Reply, ReplyCode};
-use crate::storage::{self, Error, ErrorKind};
+use crate::server::glob;
+use crate::server::listing::Lister;
+use crate::server::path;
+use crate::storage;
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::channel::mpsc::Sender;
 use futures::prelude::*;
-use log::warn;
-use std::io::Read;
-use std::sync::Arc;
+use slog::warn;
 
 pub struct Stat {
     path: Option<Bytes>,
@@ -51,39 +55,72 @@ where
     S::Metadata: 'static + storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
         match self.path.clone() {
             None => {
-                let text: Vec<&str> = vec!["Status:", "Powered by libunftp"];
+                let mut text: Vec<String> = vec!["Status:".to_string()];
+                if !args.identification.is_empty() {
+                    text.push(args.identification.to_string());
+                }
                 // TODO: Add useful information here like libunftp version, auth type, storage type, IP etc.
-                Ok(Reply::new_multiline(ReplyCode::SystemStatus, text))
+                let session = args.session.lock().await;
+                if session.reveal_id {
+                    text.insert(1, format!("Session ID: {}", session.id));
+                }
+                text.push(format!(
+                    "TYPE: {}, STRU: {}, MODE: {}",
+                    session.transfer_type, session.file_structure, session.transfer_mode
+                ));
+                if session.transfer_in_progress {
+                    text.push(format!("Transfer in progress, {} bytes moved so far", session.transfer_bytes));
+                }
+                Ok(Reply::multiline(ReplyCode::SystemStatus, text))
             }
             Some(path) => {
-                let path: &str = std::str::from_utf8(&path)?;
-                let path = path.to_owned();
+                let arg: &str = std::str::from_utf8(&path)?;
+                let (path, pattern) = glob::split(arg);
+                let arg = arg.to_string();
 
                 let session = args.session.lock().await;
+                let path = match session.client_path(path) {
+                    Ok(path) => path,
+                    Err(err) => return Ok(super::path_error_reply(err)),
+                };
                 let user = session.user.clone();
-                let storage = Arc::clone(&session.storage);
+                let storage_timeout = session.storage_timeout;
+                let storage_path = path::to_storage(&session.path_mapper, &session.user, path.clone());
+                let formatter = session.list_formatter.clone();
+                let path_mapper = session.path_mapper.clone();
+                let lister = Lister::new(&session);
 
                 let mut tx_success: Sender<InternalMsg> = args.tx.clone();
                 let mut tx_fail: Sender<InternalMsg> = args.tx.clone();
 
                 tokio::spawn(async move {
-                    match storage.list_fmt(&user, path).await {
-                        Ok(mut cursor) => {
-                            let mut result: String = String::new();
-                            match cursor.read_to_string(&mut result) {
-                                Ok(_) => {
-                                    if let Err(err) = tx_success.send(InternalMsg::CommandChannelReply(ReplyCode::CommandOkay, result)).await {
-                                        warn!("{}", err);
-                                    }
-                                }
-                                Err(err) => warn!("{}", err),
+                    let listing = async { lister.list(storage_path, pattern.clone(), true).await?.try_collect::<Vec<_>>().await };
+                    match storage::with_timeout(&logger, storage_timeout, listing).await {
+                        Ok(entries) => {
+                            let result: String = entries
+                                .iter()
+                                .map(|entry| {
+                                    format!(
+                                        "{}\r\n",
+                                        formatter.list_line(&path::to_client(&path_mapper, &user, &entry.path), &entry.metadata)
+                                    )
+                                })
+                                .collect();
+                            let name = if pattern.is_some() { arg } else { path.display().to_string() };
+                            let text = format!("Status of {}:\n{}End of status", name, result);
+                            if let Err(err) = tx_success.send(InternalMsg::CommandChannelReply(ReplyCode::FileStatus, text)).await {
+                                warn!(logger, "{}", err);
                             }
                         }
-                        Err(_) => {
-                            if let Err(err) = tx_fail.send(InternalMsg::StorageError(Error::from(ErrorKind::LocalError))).await {
-                                warn!("{}", err);
+                        // Not as a storage error, as those end the transfer that STAT may be asking about.
+                        Err(err) => {
+                            warn!(logger, "{}", err);
+                            let (code, text) = super::storage_error_reply(err.kind());
+                            if let Err(err) = tx_fail.send(InternalMsg::CommandChannelReply(code, text.to_string())).await {
+                                warn!(logger, "{}", err);
                             }
                         }
                     }
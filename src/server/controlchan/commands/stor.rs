@@ -17,7 +17,8 @@ use crate::server::controlchan::{Reply, ReplyCode};
 use crate::storage;
 use async_trait::async_trait;
 use futures::prelude::*;
-use log::warn;
+use slog::warn;
+use std::sync::Arc;
 
 pub struct Stor;
 
@@ -30,16 +31,40 @@ where
     S::Metadata: storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
         let mut session = args.session.lock().await;
         let cmd: Command = args.cmd.clone();
+        if let Command::Stor { path } = &cmd {
+            if !session.filename_allowed(path) {
+                return Ok(Reply::new(ReplyCode::BadFileName, "File name not allowed"));
+            }
+            // Resumed uploads write into a file that exists already.
+            if session.no_clobber && session.start_pos > 0 {
+                session.start_pos = 0;
+                return Ok(Reply::new(ReplyCode::FileError, "Resuming uploads is not allowed"));
+            }
+            if session.drop_box || session.no_clobber {
+                let path = match session.storage_path(path) {
+                    Ok(path) => path,
+                    Err(_) => return Ok(Reply::new(ReplyCode::BadFileName, "File name not allowed")),
+                };
+                let storage = Arc::clone(&session.storage);
+                if storage::with_timeout(&logger, session.storage_timeout, storage.metadata(&session.user, path))
+                    .await
+                    .is_ok()
+                {
+                    return Ok(Reply::new(ReplyCode::BadFileName, "File exists"));
+                }
+            }
+        }
         match session.data_cmd_tx.take() {
             Some(mut tx) => {
                 tokio::spawn(async move {
                     if let Err(err) = tx.send(cmd).await {
-                        warn!("{}", err);
+                        warn!(logger, "{}", err);
                     }
                 });
-                Ok(Reply::new(ReplyCode::FileStatusOkay, "Ready to receive data"))
+                Ok(Reply::none())
             }
             None => Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
         }
@@ -6,14 +6,13 @@ use crate::server::controlchan::error::ControlChanError;
 use crate::server::controlchan::handler::CommandContext;
 use crate::server::controlchan::handler::CommandHandler;
 use crate::server::controlchan::{Reply, ReplyCode};
+use crate::server::path;
 use crate::storage;
 use async_trait::async_trait;
 use futures::prelude::*;
-use log::warn;
-use std::path::Path;
+use slog::warn;
 use uuid::Uuid;
 
-// TODO: Write functional test for STOU command.
 pub struct Stou;
 
 #[async_trait]
@@ -25,18 +24,24 @@ where
     S::Metadata: storage::Metadata,
 {
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let logger = args.logger.clone();
         let mut session = args.session.lock().await;
-        let uuid: String = Uuid::new_v4().to_string();
-        let filename: &Path = std::path::Path::new(&uuid);
-        let path: String = session.cwd.join(&filename).to_string_lossy().to_string();
+        // The name is resolved like the paths clients send, so that the path mapper applies to it
+        // when the data channel stores the file.
+        let path = match session.client_path(Uuid::new_v4().to_string()) {
+            Ok(path) => path,
+            Err(err) => return Ok(super::path_error_reply(err)),
+        };
+        let reply = format!("FILE: {}", path::quote(&path));
+        let path = path.to_string_lossy().to_string();
         match session.data_cmd_tx.take() {
             Some(mut tx) => {
                 tokio::spawn(async move {
                     if let Err(err) = tx.send(Command::Stor { path }).await {
-                        warn!("sending command failed. {}", err);
+                        warn!(logger, "sending command failed. {}", err);
                     }
                 });
-                Ok(Reply::new_with_string(ReplyCode::FileStatusOkay, filename.to_string_lossy().to_string()))
+                Ok(Reply::new_with_string(ReplyCode::FileStatusOkay, reply))
             }
             None => Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
         }
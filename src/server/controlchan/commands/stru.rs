@@ -19,6 +19,7 @@ use crate::server::controlchan::handler::CommandHandler;
 use crate::server::controlchan::{Reply, ReplyCode};
 use crate::storage;
 use async_trait::async_trait;
+use std::fmt;
 
 /// The parameter the can be given to the `STRU` command. It is used to set the file `STRU`cture to
 /// the given structure. This stems from a time where it was common for some operating
@@ -36,6 +37,16 @@ pub enum StruParam {
     Page,
 }
 
+impl fmt::Display for StruParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StruParam::File => write!(f, "File"),
+            StruParam::Record => write!(f, "Record"),
+            StruParam::Page => write!(f, "Page"),
+        }
+    }
+}
+
 pub struct Stru {
     params: StruParam,
 }
@@ -54,9 +65,12 @@ where
     S::File: tokio::io::AsyncRead + Send,
     S::Metadata: storage::Metadata,
 {
-    async fn handle(&self, _args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+    async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
         match &self.params {
-            StruParam::File => Ok(Reply::new(ReplyCode::CommandOkay, "In File structure mode")),
+            StruParam::File => {
+                args.session.lock().await.file_structure = self.params.clone();
+                Ok(Reply::new(ReplyCode::CommandOkay, "In File structure mode"))
+            }
             _ => Ok(Reply::new(
                 ReplyCode::CommandNotImplementedForParameter,
                 "Only File structure mode is supported",
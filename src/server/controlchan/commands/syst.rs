@@ -7,7 +7,8 @@
 //
 // This response is kind of like the User-Agent in http: very much mis-used to gauge
 // the capabilities of the other peer. D.J. Bernstein recommends to just respond with
-// `UNIX Type: L8` for greatest compatibility.
+// `UNIX Type: L8` for greatest compatibility, which is what we do unless
+// `ServerBuilder::system_type` says otherwise.
 
 use crate::auth::UserDetail;
 use crate::server::controlchan::error::ControlChanError;
@@ -27,7 +28,7 @@ where
     S::File: tokio::io::AsyncRead + Send,
     S::Metadata: storage::Metadata,
 {
-    async fn handle(&self, _args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
-        Ok(Reply::new(ReplyCode::SystemType, "UNIX Type: L8"))
+    async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        Ok(Reply::new(ReplyCode::SystemType, args.system_type.as_str()))
     }
 }
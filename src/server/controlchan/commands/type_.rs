@@ -33,8 +33,54 @@ use crate::server::controlchan::handler::CommandHandler;
 use crate::server::controlchan::{Reply, ReplyCode};
 use crate::storage;
 use async_trait::async_trait;
+use std::fmt;
 
-pub struct Type;
+/// The representation type that can be given to the `TYPE` command. We support ASCII with the
+/// Non-print format, in which line endings are converted, and Image, in which data is sent as is.
+/// A local byte size of 8 bits is the same as Image.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TypeParam {
+    /// Text in ASCII, with the given format control.
+    Ascii(FormatControl),
+    /// Text in EBCDIC, with the given format control.
+    Ebcdic(FormatControl),
+    /// Binary data.
+    Image,
+    /// Bytes of the given number of bits.
+    LocalByte(u8),
+}
+
+/// The format control that can be given with the ASCII and EBCDIC types.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FormatControl {
+    /// The text contains no vertical format information.
+    NonPrint,
+    /// The text contains Telnet format effectors.
+    Telnet,
+    /// The text contains ASA carriage control characters.
+    Asa,
+}
+
+impl fmt::Display for TypeParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeParam::Ascii(_) => write!(f, "ASCII"),
+            TypeParam::Ebcdic(_) => write!(f, "EBCDIC"),
+            TypeParam::Image => write!(f, "BINARY"),
+            TypeParam::LocalByte(size) => write!(f, "LOCAL {}", size),
+        }
+    }
+}
+
+pub struct Type {
+    param: TypeParam,
+}
+
+impl Type {
+    pub fn new(param: TypeParam) -> Self {
+        Type { param }
+    }
+}
 
 #[async_trait]
 impl<S, U> CommandHandler<S, U> for Type
@@ -44,7 +90,25 @@ where
     S::File: tokio::io::AsyncRead + Send,
     S::Metadata: storage::Metadata,
 {
-    async fn handle(&self, _args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
-        Ok(Reply::new(ReplyCode::CommandOkay, "Always in binary mode"))
+    async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError> {
+        let transfer_type = match self.param {
+            TypeParam::Ascii(FormatControl::NonPrint) => TypeParam::Ascii(FormatControl::NonPrint),
+            TypeParam::Image | TypeParam::LocalByte(8) => TypeParam::Image,
+            TypeParam::Ascii(_) => {
+                return Ok(Reply::new(
+                    ReplyCode::CommandNotImplementedForParameter,
+                    "Only the Non-print format is supported",
+                ))
+            }
+            TypeParam::Ebcdic(_) => return Ok(Reply::new(ReplyCode::CommandNotImplementedForParameter, "EBCDIC is not supported")),
+            TypeParam::LocalByte(_) => {
+                return Ok(Reply::new(
+                    ReplyCode::CommandNotImplementedForParameter,
+                    "Only a local byte size of 8 is supported",
+                ))
+            }
+        };
+        args.session.lock().await.transfer_type = transfer_type;
+        Ok(Reply::new_with_string(ReplyCode::CommandOkay, format!("Switching to {} mode", transfer_type)))
     }
 }
@@ -7,6 +7,8 @@ use crate::server::session::SessionState;
 use crate::storage;
 use async_trait::async_trait;
 use bytes::Bytes;
+#[cfg(feature = "gssapi")]
+use slog::{debug, info, warn};
 
 pub struct User {
     username: Bytes,
@@ -21,7 +23,7 @@ impl User {
 #[async_trait]
 impl<S, U> CommandHandler<S, U> for User
 where
-    U: UserDetail,
+    U: UserDetail + 'static,
     S: 'static + storage::StorageBackend<U> + Sync + Send,
     S::File: tokio::io::AsyncRead + Send,
     S::Metadata: storage::Metadata,
@@ -31,7 +33,53 @@ where
         match session.state {
             SessionState::New | SessionState::WaitPass => {
                 let user = std::str::from_utf8(&self.username)?;
+                #[cfg(feature = "gssapi")]
+                {
+                    if let Some(gssapi) = &session.gssapi {
+                        if !gssapi.authorizes(user) {
+                            return Ok(Reply::new(ReplyCode::NotLoggedIn, "User not authorized by the security context"));
+                        }
+                    }
+                }
                 session.username = Some(user.to_string());
+                #[cfg(feature = "gssapi")]
+                {
+                    if let Some(gssapi) = session.gssapi.clone() {
+                        // Once the security context authenticated the client, it doesn't need a
+                        // password if the authenticator accepts the principal.
+                        if let Some(principal) = gssapi.principal() {
+                            let user = user.to_string();
+                            drop(session);
+                            let result = args.authenticator.authenticate_principal(&user, &principal).await;
+                            let mut session = args.session.lock().await;
+                            match result {
+                                Ok(user) if user.account_enabled() => {
+                                    info!(args.logger, "User {} logged in as GSSAPI principal {}", user, principal; "username" => user.to_string());
+                                    if let Some(metrics) = &session.metrics {
+                                        metrics.add_login_metric(true, false);
+                                    }
+                                    session.log_in(user);
+                                    session.state = SessionState::WaitCmd;
+                                    return Ok(session.login_reply(ReplyCode::UserLoggedInAuthorized, "User logged in, authorized by security data exchange"));
+                                }
+                                Ok(user) => {
+                                    warn!(args.logger, "User {} authenticated but account is disabled", user);
+                                    if let Some(metrics) = &session.metrics {
+                                        metrics.add_login_metric(false, false);
+                                    }
+                                    session.username = None;
+                                    session.state = SessionState::New;
+                                    return Ok(Reply::new(ReplyCode::NotLoggedIn, "Authentication failed"));
+                                }
+                                Err(err) => {
+                                    debug!(args.logger, "Principal {} not accepted without a password: {}", principal, err);
+                                    session.state = SessionState::WaitPass;
+                                    return Ok(Reply::new(ReplyCode::NeedPassword, "Password Required"));
+                                }
+                            }
+                        }
+                    }
+                }
                 session.state = SessionState::WaitPass;
                 Ok(Reply::new(ReplyCode::NeedPassword, "Password Required"))
             }
@@ -1,119 +1,196 @@
 //! Contains the `ControlChanError` struct that that defines the control channel error type.
 
-use super::parse_error::{ParseError, ParseErrorKind};
+use super::parse_error::ParseError;
 
-use failure::{Backtrace, Context, Fail};
+use std::error::Error;
 use std::fmt;
 
-/// The error type returned by this library.
+/// The error type returned by this library. It tells what went wrong with its [kind] and keeps
+/// the error that caused it, if any, as its [source]. Errors in commands sent by the client also
+/// keep the [command] they were found in.
+///
+/// [kind]: #method.kind
+/// [source]: https://doc.rust-lang.org/std/error/trait.Error.html#method.source
+/// [command]: #method.command
 #[derive(Debug)]
 pub struct ControlChanError {
-    inner: Context<ControlChanErrorKind>,
+    kind: ControlChanErrorKind,
+    source: Option<Box<dyn Error + Send + Sync + 'static>>,
+    command: Option<String>,
 }
 
 /// A list specifying categories of FTP errors. It is meant to be used with the [ControlChanError] type.
-#[derive(Eq, PartialEq, Debug, Fail)]
+#[derive(Clone, Eq, PartialEq, Debug)]
 #[allow(dead_code)]
 pub enum ControlChanErrorKind {
     /// We encountered a system IO error.
-    #[fail(display = "Failed to perform IO")]
     IOError,
     /// Something went wrong parsing the client's command.
-    #[fail(display = "Failed to parse command")]
     ParseError,
     /// Internal Server Error. This is probably a bug, i.e. when we're unable to lock a resource we
     /// should be able to lock.
-    #[fail(display = "Internal Server Error")]
     InternalServerError,
     /// Authentication backend returned an error.
-    #[fail(display = "Something went wrong when trying to authenticate")]
     AuthenticationError,
     /// We received something on the data message channel that we don't understand. This should be
     /// impossible.
-    #[fail(display = "Failed to map event from data channel")]
     InternalMsgError,
     /// We encountered a non-UTF8 character in the command.
-    #[fail(display = "Non-UTF8 character in command")]
     UTF8Error,
     /// The client issued a command we don't know about.
-    #[fail(display = "Unknown command: {}", command)]
     UnknownCommand {
         /// The command that we don't know about
         command: String,
     },
     /// The client issued a command that we know about, but in an invalid way (e.g. `USER` without
     /// an username).
-    #[fail(display = "Invalid command (invalid parameter)")]
     InvalidCommand,
+    /// The client sent a line that is longer than the maximum length of a command.
+    CommandTooLong,
     /// The timer on the Control Channel elapsed.
-    #[fail(display = "Encountered read timeout on the control channel")]
     ControlChannelTimeout,
+    /// The client sent a protected command (`MIC` or `ENC`) without an established security
+    /// context.
+    NoSecurityContext,
+    /// The protection of a command the client sent could not be verified.
+    SecurityCheckFailed,
+}
+
+impl fmt::Display for ControlChanErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ControlChanErrorKind::IOError => write!(f, "Failed to perform IO"),
+            ControlChanErrorKind::ParseError => write!(f, "Failed to parse command"),
+            ControlChanErrorKind::InternalServerError => write!(f, "Internal Server Error"),
+            ControlChanErrorKind::AuthenticationError => write!(f, "Something went wrong when trying to authenticate"),
+            ControlChanErrorKind::InternalMsgError => write!(f, "Failed to map event from data channel"),
+            ControlChanErrorKind::UTF8Error => write!(f, "Non-UTF8 character in command"),
+            ControlChanErrorKind::UnknownCommand { command } => write!(f, "Unknown command: {}", command),
+            ControlChanErrorKind::InvalidCommand => write!(f, "Invalid command (invalid parameter)"),
+            ControlChanErrorKind::CommandTooLong => write!(f, "Command line too long"),
+            ControlChanErrorKind::ControlChannelTimeout => write!(f, "Encountered read timeout on the control channel"),
+            ControlChanErrorKind::NoSecurityContext => write!(f, "Protected command without a security context"),
+            ControlChanErrorKind::SecurityCheckFailed => write!(f, "Failed to verify a protected command"),
+        }
+    }
 }
 
 impl ControlChanError {
     /// Creates a new FTP Error with the specific kind
     pub fn new(kind: ControlChanErrorKind) -> Self {
-        ControlChanError { inner: Context::new(kind) }
+        ControlChanError {
+            kind,
+            source: None,
+            command: None,
+        }
     }
 
-    /// Return the inner error kind of this error.
-    #[allow(unused)]
-    pub fn kind(&self) -> &ControlChanErrorKind {
-        self.inner.get_context()
+    /// Creates a new FTP Error with the specific kind that was caused by the given error, for
+    /// instance one returned by a storage backend in a custom command handler.
+    pub fn with_source<E>(kind: ControlChanErrorKind, source: E) -> Self
+    where
+        E: Into<Box<dyn Error + Send + Sync + 'static>>,
+    {
+        ControlChanError {
+            kind,
+            source: Some(source.into()),
+            command: None,
+        }
     }
-}
 
-impl Fail for ControlChanError {
-    fn cause(&self) -> Option<&dyn Fail> {
-        self.inner.cause()
+    // Records the verb of the command line that the error was found in. Only the verb is kept,
+    // since the rest of the line can hold a password.
+    pub(crate) fn in_command(mut self, verb: String) -> Self {
+        self.command = Some(verb);
+        self
     }
 
-    fn backtrace(&self) -> Option<&Backtrace> {
-        self.inner.backtrace()
+    /// Return the inner error kind of this error.
+    pub fn kind(&self) -> &ControlChanErrorKind {
+        &self.kind
+    }
+
+    /// Returns the verb of the command that the client sent, if the error was found in one. For
+    /// instance `PORT` if its address could not be parsed.
+    pub fn command(&self) -> Option<&str> {
+        self.command.as_deref()
     }
 }
 
 impl fmt::Display for ControlChanError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(&self.inner, f)
+        fmt::Display::fmt(&self.kind, f)?;
+        match (&self.kind, &self.command) {
+            (ControlChanErrorKind::UnknownCommand { .. }, _) | (_, None) => Ok(()),
+            (_, Some(command)) => write!(f, " in command {}", command),
+        }
     }
 }
 
-impl From<ControlChanErrorKind> for ControlChanError {
-    fn from(kind: ControlChanErrorKind) -> ControlChanError {
-        ControlChanError { inner: Context::new(kind) }
+impl Error for ControlChanError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|source| source.as_ref() as &(dyn Error + 'static))
     }
 }
 
-impl From<Context<ControlChanErrorKind>> for ControlChanError {
-    fn from(inner: Context<ControlChanErrorKind>) -> ControlChanError {
-        ControlChanError { inner }
+impl From<ControlChanErrorKind> for ControlChanError {
+    fn from(kind: ControlChanErrorKind) -> ControlChanError {
+        ControlChanError::new(kind)
     }
 }
 
 impl From<std::io::Error> for ControlChanError {
     fn from(err: std::io::Error) -> ControlChanError {
-        err.context(ControlChanErrorKind::IOError).into()
+        ControlChanError::with_source(ControlChanErrorKind::IOError, err)
     }
 }
 
 impl From<std::str::Utf8Error> for ControlChanError {
     fn from(err: std::str::Utf8Error) -> ControlChanError {
-        err.context(ControlChanErrorKind::UTF8Error).into()
+        ControlChanError::with_source(ControlChanErrorKind::UTF8Error, err)
     }
 }
 
 impl From<ParseError> for ControlChanError {
     fn from(err: ParseError) -> ControlChanError {
-        match err.kind().clone() {
-            ParseErrorKind::UnknownCommand { command } => {
-                // TODO: Do something smart with CoW to prevent copying the command around.
-                err.context(ControlChanErrorKind::UnknownCommand { command }).into()
-            }
-            ParseErrorKind::InvalidUTF8 => err.context(ControlChanErrorKind::UTF8Error).into(),
-            ParseErrorKind::InvalidCommand => err.context(ControlChanErrorKind::InvalidCommand).into(),
-            ParseErrorKind::InvalidToken { .. } => err.context(ControlChanErrorKind::UTF8Error).into(),
-            _ => err.context(ControlChanErrorKind::InvalidCommand).into(),
-        }
+        let kind = match &err {
+            ParseError::UnknownCommand { command } => ControlChanErrorKind::UnknownCommand { command: command.clone() },
+            ParseError::InvalidUTF8(_) | ParseError::InvalidToken { .. } => ControlChanErrorKind::UTF8Error,
+            ParseError::InvalidCommand | ParseError::InvalidEOL => ControlChanErrorKind::InvalidCommand,
+        };
+        ControlChanError::with_source(kind, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ControlChanError, ControlChanErrorKind};
+    use crate::server::controlchan::parse_error::ParseError;
+    use std::error::Error;
+
+    #[test]
+    fn keeps_the_source() {
+        let err = ControlChanError::from(ParseError::UnknownCommand { command: "XYZ".to_string() });
+        assert_eq!(err.kind(), &ControlChanErrorKind::UnknownCommand { command: "XYZ".to_string() });
+        assert_eq!(err.to_string(), "Unknown command: XYZ");
+        assert_eq!(err.source().unwrap().to_string(), "Unknown command: XYZ");
+
+        let err = ControlChanError::from(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "pipe closed"));
+        assert_eq!(err.kind(), &ControlChanErrorKind::IOError);
+        assert_eq!(err.source().unwrap().to_string(), "pipe closed");
+
+        assert!(ControlChanError::new(ControlChanErrorKind::CommandTooLong).source().is_none());
+    }
+
+    #[test]
+    fn keeps_the_command() {
+        let err = ControlChanError::from(ParseError::InvalidCommand).in_command("PORT".to_string());
+        assert_eq!(err.command(), Some("PORT"));
+        assert_eq!(err.to_string(), "Invalid command (invalid parameter) in command PORT");
+
+        let err = ControlChanError::from(ParseError::UnknownCommand { command: "XYZ".to_string() }).in_command("XYZ".to_string());
+        assert_eq!(err.to_string(), "Unknown command: XYZ");
+
+        assert_eq!(ControlChanError::new(ControlChanErrorKind::CommandTooLong).command(), None);
     }
 }
@@ -6,11 +6,13 @@ use crate::server::controlchan::Reply;
 use crate::server::proxy_protocol::ConnectionTuple;
 use crate::server::session::SharedSession;
 use crate::server::InternalMsg;
-use crate::storage;
+use crate::storage::{self, Capabilities};
 
 use async_trait::async_trait;
 use futures::channel::mpsc::Sender;
+use std::collections::{HashMap, HashSet};
 use std::ops::Range;
+use std::path::PathBuf;
 use std::result::Result;
 use std::sync::Arc;
 
@@ -25,22 +27,112 @@ where
     async fn handle(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError>;
 }
 
-/// Convenience struct to group command args
-pub(crate) struct CommandContext<S, U>
+/// What the handler of a command gets to work with: the session of the client and the settings
+/// of the server that apply to it.
+pub struct CommandContext<S, U>
 where
     S: 'static + storage::StorageBackend<U> + Send + Sync,
     S::File: tokio::io::AsyncRead + Send + Sync,
     S::Metadata: storage::Metadata + Sync,
     U: UserDetail + 'static,
 {
-    pub cmd: Command,
-    pub session: SharedSession<S, U>,
-    pub authenticator: Arc<dyn Authenticator<U>>,
-    pub tls_configured: bool,
-    pub passive_ports: Range<u16>,
-    pub tx: Sender<InternalMsg>,
-    pub local_addr: std::net::SocketAddr,
-    pub storage_features: u32,
-    pub proxyloop_msg_tx: Option<ProxyLoopSender<S, U>>,
-    pub control_connection_info: Option<ConnectionTuple>,
+    pub(crate) cmd: Command,
+    pub(crate) session: SharedSession<S, U>,
+    pub(crate) authenticator: Arc<dyn Authenticator<U>>,
+    pub(crate) tls_configured: bool,
+    // The mechanisms that AUTH accepts and FEAT advertises, in upper case.
+    pub(crate) auth_mechanisms: Arc<Vec<String>>,
+    pub(crate) passive_ports: Range<u16>,
+    pub(crate) tx: Sender<InternalMsg>,
+    pub(crate) local_addr: std::net::SocketAddr,
+    // The local address passive listeners bind to instead of the one of the control connection.
+    pub(crate) data_bind_ip: Option<std::net::IpAddr>,
+    pub(crate) storage_features: Capabilities,
+    pub(crate) proxyloop_msg_tx: Option<ProxyLoopSender<S, U>>,
+    pub(crate) control_connection_info: Option<ConnectionTuple>,
+    // The verbs of the commands that are refused.
+    pub(crate) disabled_commands: Arc<HashSet<String>>,
+    // The handlers of the SITE subcommands that were added to those of the server.
+    pub(crate) site_commands: Arc<HashMap<String, Arc<dyn CustomCommandHandler<S, U>>>>,
+    // The reply to SYST.
+    pub(crate) system_type: Arc<String>,
+    // The line that identifies the server software in STAT and HELP, left out when empty.
+    pub(crate) identification: Arc<String>,
+    pub(crate) logger: slog::Logger,
+}
+
+impl<S, U> CommandContext<S, U>
+where
+    S: 'static + storage::StorageBackend<U> + Send + Sync,
+    S::File: tokio::io::AsyncRead + Send + Sync,
+    S::Metadata: storage::Metadata + Sync,
+    U: UserDetail + 'static,
+{
+    /// The user the client is logged in as.
+    pub async fn user(&self) -> Arc<Option<U>> {
+        self.session.lock().await.user.clone()
+    }
+
+    /// The name the client gave with `USER`, if it did so already.
+    pub async fn username(&self) -> Option<String> {
+        self.session.lock().await.username.clone()
+    }
+
+    /// The current working directory of the client.
+    pub async fn cwd(&self) -> PathBuf {
+        self.session.lock().await.cwd.clone()
+    }
+
+    /// The storage backend of the session.
+    pub async fn storage(&self) -> Arc<S> {
+        self.session.lock().await.storage.clone()
+    }
+
+    /// The logger of the session.
+    pub fn logger(&self) -> &slog::Logger {
+        &self.logger
+    }
+}
+
+/// Handles a command that the server doesn't know itself, like a proprietary extension. Register
+/// it for a verb with [`ServerBuilder::custom_command`]. Custom commands are only accepted from
+/// clients that are logged in.
+///
+/// # Example
+///
+/// ```rust
+/// use async_trait::async_trait;
+/// use libunftp::auth::DefaultUser;
+/// use libunftp::storage::filesystem::Filesystem;
+/// use libunftp::{CommandContext, ControlChanError, CustomCommandHandler, Reply, ReplyCode, Server};
+///
+/// // Answers `XCWD` with the current working directory.
+/// struct Xcwd;
+///
+/// #[async_trait]
+/// impl CustomCommandHandler<Filesystem, DefaultUser> for Xcwd {
+///     async fn handle(&self, _verb: &str, _argument: &str, context: CommandContext<Filesystem, DefaultUser>) -> Result<Reply, ControlChanError> {
+///         let cwd = context.cwd().await;
+///         Ok(Reply::new_with_string(ReplyCode::CommandOkay, cwd.display().to_string()))
+///     }
+/// }
+///
+/// let server = Server::new_with_fs_root("/tmp").custom_command("XCWD", Xcwd);
+/// ```
+///
+/// [`ServerBuilder::custom_command`]: struct.ServerBuilder.html#method.custom_command
+#[async_trait]
+pub trait CustomCommandHandler<S, U>: Send + Sync
+where
+    S: 'static + storage::StorageBackend<U> + Send + Sync,
+    S::File: tokio::io::AsyncRead + Send + Sync,
+    S::Metadata: storage::Metadata + Sync,
+    U: UserDetail + 'static,
+{
+    /// Handles the command. The verb is in upper case, the argument is the rest of the line
+    /// without the line ending. Handlers have no way to reply later, so a [`Reply::None`] is
+    /// answered with `451` to the client.
+    ///
+    /// [`Reply::None`]: enum.Reply.html#variant.None
+    async fn handle(&self, verb: &str, argument: &str, context: CommandContext<S, U>) -> Result<Reply, ControlChanError>;
 }
@@ -19,5 +19,4 @@ pub(crate) mod reply;
 pub(crate) use reply::{Reply, ReplyCode};
 
 mod error;
-pub(super) use error::ControlChanError;
-pub(crate) use error::ControlChanErrorKind;
+pub use error::{ControlChanError, ControlChanErrorKind};
@@ -1,5 +1,5 @@
-use failure::*;
 use std::{
+    error::Error,
     fmt::{self, Display, Formatter},
     result,
     str::Utf8Error,
@@ -8,87 +8,50 @@ use std::{
 /// The error type returned by the [Command::parse] method.
 ///
 /// [Command::parse]: ./enum.Command.html#method.parse
-#[derive(Debug)]
-pub struct ParseError {
-    inner: Context<ParseErrorKind>,
-}
-
-impl PartialEq for ParseError {
-    #[inline]
-    fn eq(&self, other: &ParseError) -> bool {
-        self.kind() == other.kind()
-    }
-}
-
-/// A list specifying categories of Parse errors. It is meant to be used with the [ParseError]
-/// type.
-///
-/// [ParseError]: ./struct.ParseError.html
-#[derive(Clone, Eq, PartialEq, Debug, Fail)]
-pub enum ParseErrorKind {
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum ParseError {
     /// The client issued a command that we don't know about.
-    #[fail(display = "Unknown command: {}", command)]
     UnknownCommand {
         /// The command that we don't know about.
         command: String,
     },
     /// The client issued an invalid command (e.g. required parameters are missing).
-    #[fail(display = "Invalid command")]
     InvalidCommand,
-    /// An invalid token (e.g. not UTF-8) was encountered while parsing the command.
-    #[fail(display = "Invalid token while parsing: {}", token)]
+    /// An invalid token (e.g. a control character) was encountered while parsing the command.
     InvalidToken {
-        /// The Token that is not UTF-8 encoded.
+        /// The offending byte.
         token: u8,
     },
     /// Non-UTF8 character encountered.
-    #[fail(display = "Non-UTF8 character while parsing")]
-    InvalidUTF8,
+    InvalidUTF8(Utf8Error),
     /// Invalid end-of-line character.
-    #[fail(display = "Invalid end-of-line")]
     InvalidEOL,
 }
 
-impl Fail for ParseError {
-    fn cause(&self) -> Option<&dyn Fail> {
-        self.inner.cause()
-    }
-
-    fn backtrace(&self) -> Option<&Backtrace> {
-        self.inner.backtrace()
-    }
-}
-
-impl ParseError {
-    /// Returns the corresponding `ParseErrorKind` for this error.
-    pub fn kind(&self) -> &ParseErrorKind {
-        self.inner.get_context()
-    }
-}
-
-impl From<ParseErrorKind> for ParseError {
-    fn from(kind: ParseErrorKind) -> ParseError {
-        ParseError { inner: Context::new(kind) }
-    }
-}
-
-impl From<Context<ParseErrorKind>> for ParseError {
-    fn from(inner: Context<ParseErrorKind>) -> ParseError {
-        ParseError { inner }
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnknownCommand { command } => write!(f, "Unknown command: {}", command),
+            ParseError::InvalidCommand => write!(f, "Invalid command"),
+            ParseError::InvalidToken { token } => write!(f, "Invalid token while parsing: {}", token),
+            ParseError::InvalidUTF8(_) => write!(f, "Non-UTF8 character while parsing"),
+            ParseError::InvalidEOL => write!(f, "Invalid end-of-line"),
+        }
     }
 }
 
-impl From<Utf8Error> for ParseError {
-    fn from(_: Utf8Error) -> ParseError {
-        ParseError {
-            inner: Context::new(ParseErrorKind::InvalidUTF8),
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::InvalidUTF8(err) => Some(err),
+            _ => None,
         }
     }
 }
 
-impl Display for ParseError {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        Display::fmt(&self.inner, f)
+impl From<Utf8Error> for ParseError {
+    fn from(err: Utf8Error) -> ParseError {
+        ParseError::InvalidUTF8(err)
     }
 }
 
@@ -1,12 +1,28 @@
 /// A reply to the FTP client
 #[derive(Debug, Clone)]
 pub enum Reply {
+    /// Nothing is sent (yet), for instance because the reply to a transfer is sent when it ends.
     None,
-    CodeAndMsg { code: ReplyCode, msg: String },
-    MultiLine { code: ReplyCode, lines: Vec<String> },
+    /// A reply on a single line.
+    CodeAndMsg {
+        /// The reply code.
+        code: ReplyCode,
+        /// The message that follows the code.
+        msg: String,
+    },
+    /// A reply that spans several lines.
+    MultiLine {
+        /// The reply code.
+        code: ReplyCode,
+        /// The lines of the reply, without the code.
+        lines: Vec<String>,
+    },
 }
 
-/// The reply codes according to RFC 959.
+/// The code of a reply. The constants are the codes of RFC 959 and its extensions, other codes
+/// can be made with [`ReplyCode::new`].
+///
+/// [`ReplyCode::new`]: #method.new
 //
 // From: https://cr.yp.to/ftp/request.html#response
 //
@@ -37,65 +53,165 @@ pub enum Reply {
 // - 421 if the server is about to close the connection;
 // - 500, 501, 502, or 504 for unacceptable syntax; or
 // - 530 if permission is denied.
-#[derive(Debug, Clone, Copy)]
-#[repr(u32)]
-#[allow(dead_code)]
-pub enum ReplyCode {
-    NoReply = 0,
-
-    GroupPreliminaryReply = 1,
-    GroupPositiveCompletion = 2,
-
-    RestartMarker = 110,
-    InNMinutes = 120,
-    ConnectionAlreadyOpen = 125,
-    FileStatusOkay = 150,
-
-    CommandOkay = 200,
-    CommandOkayNotImplemented = 202,
-    SystemStatus = 211,
-    DirectoryStatus = 212,
-    FileStatus = 213,
-    HelpMessage = 214,
-    SystemType = 215,
-    ServiceReady = 220,
-    ClosingControlConnection = 221,
-    DataConnectionOpen = 225,
-    ClosingDataConnection = 226,
-    EnteringPassiveMode = 227,
-    EnteringExtendedPassiveMode = 229,
-    UserLoggedIn = 230,
-    AuthOkayNoDataNeeded = 234,
-    FileActionOkay = 250,
-    DirCreated = 257,
-
-    NeedPassword = 331,
-    NeedAccount = 332,
-    FileActionPending = 350,
-
-    ServiceNotAvailable = 421,
-    CantOpenDataConnection = 425,
-    ConnectionClosed = 426,
-    TransientFileError = 450,
-    LocalError = 451,
-    OutOfSpace = 452,
-
-    CommandSyntaxError = 500,
-    ParameterSyntaxError = 501,
-    CommandNotImplemented = 502,
-    BadCommandSequence = 503,
-    CommandNotImplementedForParameter = 504,
-    NotLoggedIn = 530,
-    NeedAccountToStore = 532,
-    FileError = 550,
-    PageTypeUnknown = 551,
-    ExceededStorageAllocation = 552,
-    BadFileName = 553,
-
-    Resp533 = 533,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReplyCode(u32);
+
+#[allow(non_upper_case_globals)]
+impl ReplyCode {
+    /// No reply at all.
+    pub const NoReply: ReplyCode = ReplyCode(0);
+
+    /// Any positive preliminary reply (1xx).
+    pub const GroupPreliminaryReply: ReplyCode = ReplyCode(1);
+    /// Any positive completion reply (2xx).
+    pub const GroupPositiveCompletion: ReplyCode = ReplyCode(2);
+
+    /// 110 Restart marker reply.
+    pub const RestartMarker: ReplyCode = ReplyCode(110);
+    /// 120 Service ready in a few minutes.
+    pub const InNMinutes: ReplyCode = ReplyCode(120);
+    /// 125 Data connection already open, transfer starting.
+    pub const ConnectionAlreadyOpen: ReplyCode = ReplyCode(125);
+    /// 150 File status okay, about to open the data connection.
+    pub const FileStatusOkay: ReplyCode = ReplyCode(150);
+
+    /// 200 Command okay.
+    pub const CommandOkay: ReplyCode = ReplyCode(200);
+    /// 202 Command not implemented, superfluous at this site.
+    pub const CommandOkayNotImplemented: ReplyCode = ReplyCode(202);
+    /// 211 System status or help reply.
+    pub const SystemStatus: ReplyCode = ReplyCode(211);
+    /// 212 Directory status.
+    pub const DirectoryStatus: ReplyCode = ReplyCode(212);
+    /// 213 File status.
+    pub const FileStatus: ReplyCode = ReplyCode(213);
+    /// 214 Help message.
+    pub const HelpMessage: ReplyCode = ReplyCode(214);
+    /// 215 Name of the system type.
+    pub const SystemType: ReplyCode = ReplyCode(215);
+    /// 220 Service ready for new user.
+    pub const ServiceReady: ReplyCode = ReplyCode(220);
+    /// 221 Service closing control connection.
+    pub const ClosingControlConnection: ReplyCode = ReplyCode(221);
+    /// 225 Data connection open, no transfer in progress.
+    pub const DataConnectionOpen: ReplyCode = ReplyCode(225);
+    /// 226 Closing data connection, the requested file action was successful.
+    pub const ClosingDataConnection: ReplyCode = ReplyCode(226);
+    /// 227 Entering passive mode.
+    pub const EnteringPassiveMode: ReplyCode = ReplyCode(227);
+    /// 228 Entering long passive mode.
+    pub const EnteringLongPassiveMode: ReplyCode = ReplyCode(228);
+    /// 229 Entering extended passive mode.
+    pub const EnteringExtendedPassiveMode: ReplyCode = ReplyCode(229);
+    /// 230 User logged in, proceed.
+    pub const UserLoggedIn: ReplyCode = ReplyCode(230);
+    /// 232 User logged in, authorized by security data exchange.
+    pub const UserLoggedInAuthorized: ReplyCode = ReplyCode(232);
+    /// 234 Security mechanism accepted, no security data needed.
+    pub const AuthOkayNoDataNeeded: ReplyCode = ReplyCode(234);
+    /// 235 Security data exchange completed.
+    pub const SecurityDataExchangeComplete: ReplyCode = ReplyCode(235);
+    /// 250 Requested file action okay, completed.
+    pub const FileActionOkay: ReplyCode = ReplyCode(250);
+    /// 257 Pathname created.
+    pub const DirCreated: ReplyCode = ReplyCode(257);
+
+    /// 331 User name okay, need password.
+    pub const NeedPassword: ReplyCode = ReplyCode(331);
+    /// 332 Need account for login.
+    pub const NeedAccount: ReplyCode = ReplyCode(332);
+    /// 334 Security mechanism accepted, security data must follow.
+    pub const SecurityMechanismAccepted: ReplyCode = ReplyCode(334);
+    /// 335 Security data accepted, more is needed.
+    pub const SecurityDataAccepted: ReplyCode = ReplyCode(335);
+    /// 350 Requested file action pending further information.
+    pub const FileActionPending: ReplyCode = ReplyCode(350);
+
+    /// 421 Service not available, closing control connection.
+    pub const ServiceNotAvailable: ReplyCode = ReplyCode(421);
+    /// 425 Can't open data connection.
+    pub const CantOpenDataConnection: ReplyCode = ReplyCode(425);
+    /// 426 Connection closed, transfer aborted.
+    pub const ConnectionClosed: ReplyCode = ReplyCode(426);
+    /// 450 Requested file action not taken, the file is unavailable for now.
+    pub const TransientFileError: ReplyCode = ReplyCode(450);
+    /// 451 Requested action aborted, local error in processing.
+    pub const LocalError: ReplyCode = ReplyCode(451);
+    /// 452 Requested action not taken, insufficient storage space.
+    pub const OutOfSpace: ReplyCode = ReplyCode(452);
+
+    /// 500 Syntax error, command unrecognized.
+    pub const CommandSyntaxError: ReplyCode = ReplyCode(500);
+    /// 501 Syntax error in parameters or arguments.
+    pub const ParameterSyntaxError: ReplyCode = ReplyCode(501);
+    /// 502 Command not implemented.
+    pub const CommandNotImplemented: ReplyCode = ReplyCode(502);
+    /// 503 Bad sequence of commands.
+    pub const BadCommandSequence: ReplyCode = ReplyCode(503);
+    /// 504 Command not implemented for that parameter.
+    pub const CommandNotImplementedForParameter: ReplyCode = ReplyCode(504);
+    /// 522 Network protocol not supported.
+    pub const NetworkProtocolNotSupported: ReplyCode = ReplyCode(522);
+    /// 530 Not logged in.
+    pub const NotLoggedIn: ReplyCode = ReplyCode(530);
+    /// 532 Need account for storing files.
+    pub const NeedAccountToStore: ReplyCode = ReplyCode(532);
+    /// 534 Request denied for policy reasons.
+    pub const DeniedForPolicyReasons: ReplyCode = ReplyCode(534);
+    /// 535 Failed security check.
+    pub const FailedSecurityCheck: ReplyCode = ReplyCode(535);
+    /// 550 Requested action not taken, the file is unavailable.
+    pub const FileError: ReplyCode = ReplyCode(550);
+    /// 551 Requested action aborted, page type unknown.
+    pub const PageTypeUnknown: ReplyCode = ReplyCode(551);
+    /// 552 Requested file action aborted, exceeded storage allocation.
+    pub const ExceededStorageAllocation: ReplyCode = ReplyCode(552);
+    /// 553 Requested action not taken, file name not allowed.
+    pub const BadFileName: ReplyCode = ReplyCode(553);
+
+    /// 533 Command protection level denied for policy reasons.
+    pub const Resp533: ReplyCode = ReplyCode(533);
+
+    /// 631 Integrity protected reply.
+    pub const IntegrityProtectedReply: ReplyCode = ReplyCode(631);
+    /// 632 Confidentiality and integrity protected reply.
+    pub const PrivacyProtectedReply: ReplyCode = ReplyCode(632);
+
+    /// Returns the reply code with the given number, which may be one that has no constant here,
+    /// like 252. Returns `None` unless it is a three digit number that starts with 1 to 5.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::ReplyCode;
+    ///
+    /// assert_eq!(ReplyCode::new(550), Some(ReplyCode::FileError));
+    /// assert!(ReplyCode::new(252).is_some());
+    /// assert!(ReplyCode::new(600).is_none());
+    /// ```
+    pub fn new(code: u32) -> Option<ReplyCode> {
+        if (100..600).contains(&code) {
+            Some(ReplyCode(code))
+        } else {
+            None
+        }
+    }
+}
+
+impl From<ReplyCode> for u32 {
+    fn from(code: ReplyCode) -> u32 {
+        code.0
+    }
+}
+
+impl std::fmt::Display for ReplyCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
 impl Reply {
+    /// Creates a single line reply.
     pub fn new(code: ReplyCode, message: &str) -> Self {
         Reply::CodeAndMsg {
             code,
@@ -103,23 +219,88 @@ impl Reply {
         }
     }
 
+    /// Creates a single line reply with the given message.
     pub fn new_with_string(code: ReplyCode, msg: String) -> Self {
         Reply::CodeAndMsg { code, msg }
     }
 
-    pub fn new_multiline<I>(code: ReplyCode, lines: I) -> Self
+    /// Creates a multi-line reply that is sent in the RFC 959 format: the first line starts with
+    /// the code followed by a hyphen and the last one with the code followed by a space. Items
+    /// that span several lines are split up.
+    pub fn multiline<I>(code: ReplyCode, lines: I) -> Self
     where
         I: IntoIterator,
         I::Item: std::fmt::Display,
     {
         Reply::MultiLine {
             code,
-            lines: lines.into_iter().map(|item| format!("{}", item)).collect(),
+            lines: lines
+                .into_iter()
+                .flat_map(|item| {
+                    item.to_string()
+                        .split('\n')
+                        .map(|line| line.trim_end_matches('\r').to_string())
+                        .collect::<Vec<String>>()
+                })
+                .collect(),
+        }
+    }
+
+    /// Creates a single line reply, or a multi-line reply if the given text spans several lines.
+    pub fn new_from_text(code: ReplyCode, text: &str) -> Self {
+        if text.trim_end().lines().count() > 1 {
+            Reply::multiline(code, text.trim_end().lines())
+        } else {
+            Reply::new(code, text.trim_end())
         }
     }
 
-    // A no-reply
+    /// A no-reply
     pub fn none() -> Self {
         Reply::None
     }
+
+    /// Starts building a reply with the given code, to which lines are added one by one.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{Reply, ReplyCode};
+    ///
+    /// let reply = Reply::builder(ReplyCode::new(252).unwrap())
+    ///     .line("Cannot verify the user")
+    ///     .line("but will accept the message and attempt delivery")
+    ///     .build();
+    /// ```
+    pub fn builder(code: ReplyCode) -> ReplyBuilder {
+        ReplyBuilder { code, lines: Vec::new() }
+    }
+}
+
+/// Builds a [`Reply`], see [`Reply::builder`].
+///
+/// [`Reply`]: enum.Reply.html
+/// [`Reply::builder`]: enum.Reply.html#method.builder
+#[derive(Debug, Clone)]
+pub struct ReplyBuilder {
+    code: ReplyCode,
+    lines: Vec<String>,
+}
+
+impl ReplyBuilder {
+    /// Adds a line to the text of the reply. Text that spans several lines is split up.
+    pub fn line<T: std::fmt::Display>(mut self, line: T) -> Self {
+        self.lines.push(line.to_string());
+        self
+    }
+
+    /// Builds the reply, which is sent on a single line if the text has just one line and as a
+    /// multi-line reply otherwise.
+    pub fn build(mut self) -> Reply {
+        if self.lines.len() > 1 || self.lines.iter().any(|line| line.contains('\n')) {
+            Reply::multiline(self.code, self.lines)
+        } else {
+            Reply::new_with_string(self.code, self.lines.pop().unwrap_or_default())
+        }
+    }
 }
@@ -1,18 +1,273 @@
 //! Contains code pertaining to the FTP *data* channel
 
+use super::ascii::{Conversion, LineEndings};
+use super::block::{BlockReader, BlockWriter, MARKER_INTERVAL};
 use super::chancomms::{DataCommand, InternalMsg};
 use super::controlchan::command::Command;
+use super::controlchan::commands::{FormatControl, ModeParam, TypeParam};
+use super::facts::{self, Fact};
+use super::glob;
+use super::hidden::HiddenPaths;
+use super::list_format::ListFormatter;
+use super::listing::Lister;
+use super::partial_upload::{self, PartialUploads};
+use super::path::{self, PathMapper};
+use super::registry::SessionTracker;
+use super::spans::SessionSpan;
+use super::spool::UploadSpool;
+use super::throttle::{RateLimiter, Throttled, TransferLimiter};
+use super::xferlog::{Direction, SessionXferlog};
 use crate::auth::UserDetail;
-use crate::server::Session;
+use crate::metrics::Metrics;
+use crate::notification::{FileEventKind, Notifier};
+use crate::server::{ReplyCode, Session};
 use crate::storage::{self, Error, ErrorKind};
 
-use futures::channel::mpsc::Sender;
+use futures::channel::mpsc::{Receiver, Sender};
 use futures::prelude::*;
-use log::info;
-use log::{debug, warn};
+use slog::{debug, info, warn, Logger};
+use std::io;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use tokio::io::AsyncWriteExt;
+use tokio::sync::OwnedSemaphorePermit;
+
+// Keeps track of the last time bytes moved over a data connection and of how many did, and of
+// whether the connection failed. The latter tells transfers that the client broke off apart from
+// transfers that failed in the storage backend.
+#[derive(Clone)]
+struct Activity {
+    last: Arc<Mutex<Instant>>,
+    bytes: Arc<AtomicU64>,
+    failed: Arc<AtomicBool>,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Activity {
+            last: Arc::new(Mutex::new(Instant::now())),
+            bytes: Arc::new(AtomicU64::new(0)),
+            failed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn touch(&self, bytes: usize) {
+        *self.last.lock().unwrap() = Instant::now();
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn last(&self) -> Instant {
+        *self.last.lock().unwrap()
+    }
+
+    fn bytes_moved(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    // Records the outcome of an operation on the data connection.
+    fn check<T>(&self, result: &Poll<io::Result<T>>) {
+        if let Poll::Ready(Err(_)) = result {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // Tells if reading from or writing to the data connection failed, for instance because the
+    // client closed it in the middle of a transfer.
+    fn client_failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+// Records every successful read or write on the wrapped stream, and every failed one, in an
+// `Activity`.
+struct Tracked<T> {
+    inner: T,
+    activity: Activity,
+}
+
+impl<T: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for Tracked<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            if n > 0 {
+                this.activity.touch(n);
+            }
+        }
+        this.activity.check(&result);
+        result
+    }
+}
+
+impl<T: tokio::io::AsyncWrite + Unpin> tokio::io::AsyncWrite for Tracked<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            if n > 0 {
+                this.activity.touch(n);
+            }
+        }
+        this.activity.check(&result);
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_flush(cx);
+        this.activity.check(&result);
+        result
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_shutdown(cx);
+        this.activity.check(&result);
+        result
+    }
+}
+
+// Records failed reads of the wrapped reader in an `Activity`, without counting the bytes read
+// again. Reading the blocks of block mode fails when the client closes the data connection before
+// the end of the file, which is a failure of the client as much as a broken connection.
+struct Checked<T> {
+    inner: T,
+    activity: Activity,
+}
+
+impl<T: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for Checked<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        this.activity.check(&result);
+        result
+    }
+}
+
+// Why a transfer was stopped by `unless_interrupted`.
+enum Interruption {
+    // The session was kicked through `ServerHandle::kick`.
+    Kicked,
+    // The client sent ABOR.
+    Aborted,
+}
+
+// Tells how a transfer that ran through `unless_interrupted` and `unless_stalled` ended, as used
+// in the metrics. Failures are put down to the client when the data connection failed and to the
+// storage backend otherwise.
+fn transfer_result<T, E>(result: &std::result::Result<Option<Result<T, E>>, Interruption>, activity: &Activity) -> &'static str {
+    match result {
+        Ok(Some(Ok(_))) => "success",
+        Ok(Some(Err(_))) if activity.client_failed() => "client_error",
+        Ok(Some(Err(_))) => "backend_error",
+        Ok(None) => "stalled",
+        Err(_) => "aborted",
+    }
+}
+
+// Runs the given transfer future to completion unless the session gets kicked or the client
+// aborts the transfer in the meantime, in which case the future is dropped and the reason is
+// returned.
+async fn unless_interrupted<F: Future>(transfer: F, tracker: &SessionTracker, abort: &mut Option<AbortSignal>) -> std::result::Result<F::Output, Interruption> {
+    let aborted = async {
+        match abort {
+            Some(abort) => abort.received().await,
+            None => future::pending().await,
+        }
+    };
+    tokio::select! {
+        output = transfer => Ok(output),
+        _ = tracker.kicked() => Err(Interruption::Kicked),
+        Some(_) = aborted => Err(Interruption::Aborted),
+    }
+}
+
+// Receives the ABOR commands of the client for a data connection. Every ABOR gets a reply from
+// the data channel: the ones that didn't abort anything are answered when this is dropped.
+pub struct AbortSignal {
+    rx: Receiver<()>,
+    tx: Sender<InternalMsg>,
+    logger: Logger,
+}
+
+impl AbortSignal {
+    async fn received(&mut self) -> Option<()> {
+        self.rx.next().await
+    }
+}
+
+impl Drop for AbortSignal {
+    fn drop(&mut self) {
+        // Closing first makes sure that no ABOR comes in after we looked.
+        self.rx.close();
+        while let Ok(()) = self.rx.try_recv() {
+            let reply = InternalMsg::CommandChannelReply(ReplyCode::ClosingDataConnection, "Data channel already closed".to_string());
+            if let Err(err) = self.tx.try_send(reply) {
+                warn!(self.logger, "Could not reply to ABOR: {}", err);
+            }
+        }
+    }
+}
+
+// How often the control channel hears how far a transfer got.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+// Runs the given transfer future to completion unless no bytes moved over the data connection
+// for the duration of `timeout`, in which case the future is dropped and None is returned. A zero
+// `timeout` never stops the transfer. In the meantime the bytes moved so far are reported to the
+// control channel every `PROGRESS_INTERVAL`, and the rest of them once the transfer ended.
+async fn unless_stalled<F: Future>(
+    transfer: F,
+    activity: &Activity,
+    timeout: Duration,
+    direction: &'static str,
+    mut progress: Sender<InternalMsg>,
+) -> Option<F::Output> {
+    let mut reported = 0;
+    let output = {
+        let enabled = timeout != Duration::from_secs(0);
+        let watchdog = async {
+            loop {
+                let now = Instant::now();
+                let deadline = if enabled { activity.last() + timeout } else { now + PROGRESS_INTERVAL };
+                if now >= deadline {
+                    return;
+                }
+                let bytes = activity.bytes_moved();
+                if bytes != reported {
+                    // Progress reports in the meantime are skipped rather than holding up the
+                    // transfer when the control channel falls behind.
+                    let _ = progress.try_send(InternalMsg::TransferProgress {
+                        direction,
+                        bytes,
+                        moved: bytes - reported,
+                    });
+                    reported = bytes;
+                }
+                tokio::time::delay_until(tokio::time::Instant::from_std(deadline.min(now + PROGRESS_INTERVAL))).await;
+            }
+        };
+        tokio::select! {
+            output = transfer => Some(output),
+            _ = watchdog => None,
+        }
+    };
+    let bytes = activity.bytes_moved();
+    if bytes != reported {
+        let _ = progress
+            .send(InternalMsg::TransferProgress {
+                direction,
+                bytes,
+                moved: bytes - reported,
+            })
+            .await;
+    }
+    output
+}
 
 pub struct DataCommandExecutor<S, U>
 where
@@ -28,8 +283,32 @@ where
     pub storage: Arc<S>,
     pub cwd: PathBuf,
     pub start_pos: u64,
+    pub transfer_type: TypeParam,
+    pub transfer_mode: ModeParam,
+    pub list_formatter: Arc<dyn ListFormatter>,
+    pub path_mapper: Option<Arc<dyn PathMapper<U>>>,
+    pub lister: Lister<S, U>,
+    pub hidden_paths: Arc<HiddenPaths>,
+    pub mlst_facts: Vec<Fact>,
     pub identity_file: Option<PathBuf>,
     pub identity_password: Option<String>,
+    pub upload_limiters: Vec<Arc<RateLimiter>>,
+    pub download_limiters: Vec<Arc<RateLimiter>>,
+    pub stalled_transfer_timeout: Duration,
+    pub logger: Logger,
+    pub tracker: SessionTracker,
+    pub metrics: Option<Arc<Metrics>>,
+    pub span: SessionSpan,
+    pub xferlog: Option<SessionXferlog>,
+    pub notifier: Option<Notifier>,
+    pub username: Option<String>,
+    pub upload_spool: Option<UploadSpool>,
+    pub partial_uploads: PartialUploads,
+    pub storage_timeout: Option<Duration>,
+    pub transfer_limiter: Option<TransferLimiter>,
+    // Keeps the transfer counted against the limit of the transfer limiter while it runs.
+    pub transfer_permit: Option<OwnedSemaphorePermit>,
+    pub abort: Option<AbortSignal>,
 }
 
 impl<S, U: Send + Sync + 'static> DataCommandExecutor<S, U>
@@ -42,137 +321,413 @@ where
     pub async fn execute(self, cmd: Command) {
         match cmd {
             Command::Retr { path } => {
-                self.exec_retr(path).await;
+                if let Some(executor) = self.admit_transfer().await {
+                    executor.exec_retr(path).await;
+                }
             }
             Command::Stor { path } => {
-                self.exec_stor(path).await;
+                if let Some(executor) = self.admit_transfer().await {
+                    executor.exec_stor(path).await;
+                }
             }
             Command::List { path, .. } => {
-                self.exec_list(path).await;
+                self.exec_listing(path, Listing::List).await;
             }
             Command::Nlst { path } => {
-                self.exec_nlst(path).await;
+                self.exec_listing(path, Listing::Nlst).await;
+            }
+            Command::Mlsd { path } => {
+                self.exec_listing(path, Listing::Mlsd).await;
             }
             _ => unimplemented!(),
         }
     }
 
-    async fn exec_retr(self, path: String) {
-        let path = self.cwd.join(path);
+    // Waits for the transfer limiter to let the transfer start. When there are too many transfers
+    // for too long the client is told to try again later and None is returned.
+    async fn admit_transfer(mut self) -> Option<Self> {
+        let limiter = match &self.transfer_limiter {
+            Some(limiter) => limiter,
+            None => return Some(self),
+        };
+        match limiter.admit().await {
+            Some(permit) => {
+                self.transfer_permit = Some(permit);
+                Some(self)
+            }
+            None => {
+                warn!(self.logger, "Refusing transfer because too many transfers are running");
+                let msg = "Too many transfers, try again later".to_string();
+                if let Err(err) = self.tx.send(InternalMsg::CommandChannelReply(ReplyCode::TransientFileError, msg)).await {
+                    warn!(self.logger, "Could not notify control channel of refused transfer: {}", err);
+                }
+                None
+            }
+        }
+    }
+
+    // Tells the client that the transfer was aborted and that its ABOR succeeded, in that order.
+    async fn reply_aborted(mut tx: Sender<InternalMsg>, logger: &Logger) {
+        if let Err(err) = tx.send(InternalMsg::TransferAborted).await {
+            warn!(logger, "Could not notify control channel of aborted transfer: {}", err);
+            return;
+        }
+        let reply = InternalMsg::CommandChannelReply(ReplyCode::ClosingDataConnection, "Closed data channel".to_string());
+        if let Err(err) = tx.send(reply).await {
+            warn!(logger, "Could not reply to ABOR: {}", err);
+        }
+    }
+
+    // Resolves the path given with a data command. When the path is not allowed or hidden the
+    // client is told so and None is returned.
+    async fn resolve(&self, path: Option<String>) -> Option<PathBuf> {
+        match path::resolve_visible(&self.cwd, &self.hidden_paths, path.unwrap_or_default()) {
+            Ok(path) => Some(path::to_storage(&self.path_mapper, &self.user, path)),
+            Err(err) => {
+                let mut tx = self.tx.clone();
+                if let Err(err) = tx.send(InternalMsg::StorageError(err)).await {
+                    warn!(self.logger, "Could not notify control channel of invalid path: {}", err);
+                }
+                None
+            }
+        }
+    }
+
+    // The conversion of line endings to apply in the current transfer type. Only ASCII with
+    // non-print format control is converted, the other types are transferred as they are.
+    fn conversion(&self, ascii: Conversion) -> Conversion {
+        match self.transfer_type {
+            TypeParam::Ascii(FormatControl::NonPrint) => ascii,
+            _ => Conversion::None,
+        }
+    }
+
+    async fn exec_retr(mut self, path: String) {
+        let path = match self.resolve(Some(path)).await {
+            Some(path) => path,
+            None => return,
+        };
+        let conversion = self.conversion(Conversion::ToCrlf);
         let mut tx_sending: Sender<InternalMsg> = self.tx.clone();
         let mut tx_error: Sender<InternalMsg> = self.tx.clone();
         tokio::spawn(async move {
-            match self.storage.get(&self.user, path, self.start_pos).await {
+            match storage::with_timeout(&self.logger, self.storage_timeout, self.storage.get(&self.user, path.clone(), self.start_pos)).await {
                 Ok(mut f) => match tx_sending.send(InternalMsg::SendingData).await {
                     Ok(_) => {
-                        let mut output = Self::writer(self.socket, self.tls, self.identity_file, self.identity_password);
-                        match tokio::io::copy(&mut f, &mut output).await {
-                            Ok(bytes_copied) => {
+                        let started = Instant::now();
+                        let activity = Activity::new();
+                        let span = self.span.transfer("RETR", &path);
+                        self.tracker.transfer_started("RETR", path.clone(), activity.bytes.clone());
+                        let output = Self::writer(
+                            self.socket,
+                            self.tls,
+                            self.identity_file,
+                            self.identity_password,
+                            self.download_limiters,
+                            activity.clone(),
+                        )
+                        .await;
+                        let mut output = in_blocks(output, &self.transfer_mode, self.start_pos, MARKER_INTERVAL);
+                        let mut input = LineEndings::new(&mut f, conversion);
+                        let transfer = unless_stalled(
+                            tokio::io::copy(&mut input, &mut output),
+                            &activity,
+                            self.stalled_transfer_timeout,
+                            "download",
+                            self.tx.clone(),
+                        );
+                        let result = unless_interrupted(span.instrument(transfer), &self.tracker, &mut self.abort).await;
+                        self.tracker.transfer_ended();
+                        span.finish(activity.bytes_moved(), transfer_result(&result, &activity));
+                        if let Some(xferlog) = &self.xferlog {
+                            let complete = transfer_result(&result, &activity) == "success";
+                            if let Err(err) = xferlog
+                                .log(&path, Direction::Outgoing, started.elapsed(), activity.bytes_moved(), complete)
+                                .await
+                            {
+                                warn!(self.logger, "Could not write to the transfer log: {}", err);
+                            }
+                        }
+                        if let Some(metrics) = &self.metrics {
+                            metrics.add_transferred_bytes_metric("download", transfer_result(&result, &activity), activity.bytes_moved());
+                        }
+                        let result = match result {
+                            Ok(result) => result,
+                            Err(Interruption::Kicked) => {
+                                info!(self.logger, "RETR aborted because the session was terminated");
+                                return;
+                            }
+                            Err(Interruption::Aborted) => {
+                                info!(self.logger, "RETR aborted by the client");
+                                Self::reply_aborted(self.tx.clone(), &self.logger).await;
+                                return;
+                            }
+                        };
+                        match result {
+                            Some(Ok(bytes_copied)) => {
                                 if let Err(err) = output.shutdown().await {
-                                    warn!("Could not shutdown output stream after RETR: {}", err);
+                                    warn!(self.logger, "Could not shutdown output stream after RETR: {}", err);
+                                }
+                                if let Some(metrics) = &self.metrics {
+                                    metrics.add_transfer_histogram_metrics("download", started.elapsed(), bytes_copied);
+                                }
+                                if let Some(notifier) = &self.notifier {
+                                    notifier.notify(FileEventKind::Downloaded { bytes: bytes_copied }, path, self.username.clone());
                                 }
                                 if let Err(err) = tx_sending.send(InternalMsg::SendData { bytes: bytes_copied as i64 }).await {
-                                    warn!("Could not notify control channel of successful RETR: {}", err);
+                                    warn!(self.logger, "Could not notify control channel of successful RETR: {}", err);
+                                }
+                            }
+                            Some(Err(err)) => {
+                                let msg = if activity.client_failed() {
+                                    warn!(self.logger, "Could not send the file to the client during RETR: {}", err);
+                                    InternalMsg::ConnectionReset
+                                } else {
+                                    warn!(self.logger, "Could not read the file from the storage backend during RETR: {}", err);
+                                    InternalMsg::StorageError(Error::from(ErrorKind::LocalError))
+                                };
+                                if let Err(err) = tx_error.send(msg).await {
+                                    warn!(self.logger, "Could not notify control channel of failed RETR: {}", err);
+                                }
+                            }
+                            None => {
+                                warn!(self.logger, "RETR stalled for {:?}, aborting the transfer", self.stalled_transfer_timeout);
+                                if let Err(err) = tx_error.send(InternalMsg::TransferStalled).await {
+                                    warn!(self.logger, "Could not notify control channel of stalled RETR: {}", err);
                                 }
                             }
-                            Err(err) => warn!("Error copying streams during RETR: {}", err),
                         }
                     }
-                    Err(err) => warn!("Error notifying control channel of progress during RETR: {}", err),
+                    Err(err) => warn!(self.logger, "Error notifying control channel of progress during RETR: {}", err),
                 },
                 Err(err) => {
                     if let Err(err) = tx_error.send(InternalMsg::StorageError(err)).await {
-                        warn!("Could not notify control channel of error with RETR: {}", err);
+                        warn!(self.logger, "Could not notify control channel of error with RETR: {}", err);
                     }
                 }
             }
         });
     }
 
-    async fn exec_stor(self, path: String) {
-        let path = self.cwd.join(path);
+    async fn exec_stor(mut self, path: String) {
+        let path = match self.resolve(Some(path)).await {
+            Some(path) => path,
+            None => return,
+        };
+        let conversion = self.conversion(Conversion::FromCrlf);
         let mut tx_ok = self.tx.clone();
         let mut tx_error = self.tx.clone();
         tokio::spawn(async move {
-            match self
-                .storage
-                .put(
-                    &self.user,
-                    Self::reader(self.socket, self.tls, self.identity_file, self.identity_password),
-                    path,
-                    self.start_pos,
-                )
-                .await
-            {
-                Ok(bytes) => {
+            if let Err(err) = tx_ok.send(InternalMsg::ReceivingData).await {
+                warn!(self.logger, "Error notifying control channel of progress during STOR: {}", err);
+                return;
+            }
+            let started = Instant::now();
+            let activity = Activity::new();
+            let span = self.span.transfer("STOR", &path);
+            self.tracker.transfer_started("STOR", path.clone(), activity.bytes.clone());
+            let input = Self::reader(
+                self.socket,
+                self.tls,
+                self.identity_file,
+                self.identity_password,
+                self.upload_limiters,
+                activity.clone(),
+            )
+            .await;
+            let input: Box<dyn tokio::io::AsyncRead + Send + Unpin + Sync> = match self.transfer_mode {
+                ModeParam::Block => {
+                    let (mut tx_marker, logger) = (self.tx.clone(), self.logger.clone());
+                    let blocks = BlockReader::new(input, self.start_pos, move |marker: &[u8], offset| {
+                        let text = format!("MARK {} = {}", String::from_utf8_lossy(marker), offset);
+                        if let Err(err) = tx_marker.try_send(InternalMsg::CommandChannelReply(ReplyCode::RestartMarker, text)) {
+                            warn!(logger, "Could not reply to a restart marker during STOR: {}", err);
+                        }
+                    });
+                    Box::new(Checked {
+                        inner: blocks,
+                        activity: activity.clone(),
+                    })
+                }
+                _ => input,
+            };
+            let input = LineEndings::new(input, conversion);
+            let (storage, user, logger, upload_spool) = (&self.storage, &self.user, &self.logger, &self.upload_spool);
+            let (start_pos, stalled_transfer_timeout, progress) = (self.start_pos, self.stalled_transfer_timeout, self.tx.clone());
+            let transfer = async {
+                match upload_spool {
+                    None => {
+                        unless_stalled(
+                            storage.put(user, input, path.clone(), start_pos),
+                            &activity,
+                            stalled_transfer_timeout,
+                            "upload",
+                            progress,
+                        )
+                        .await
+                    }
+                    // Only receiving the upload can stall, writing the spooled file to the
+                    // backend doesn't involve the client.
+                    Some(spool) => match unless_stalled(spool.receive(input), &activity, stalled_transfer_timeout, "upload", progress).await {
+                        Some(Ok(upload)) => Some(spool.store(&**storage, user, &upload, &path, start_pos, logger).await),
+                        Some(Err(err)) => Some(Err(err)),
+                        None => None,
+                    },
+                }
+            };
+            let result = unless_interrupted(span.instrument(transfer), &self.tracker, &mut self.abort).await;
+            self.tracker.transfer_ended();
+            span.finish(activity.bytes_moved(), transfer_result(&result, &activity));
+            if let Some(xferlog) = &self.xferlog {
+                let complete = transfer_result(&result, &activity) == "success";
+                if let Err(err) = xferlog
+                    .log(&path, Direction::Incoming, started.elapsed(), activity.bytes_moved(), complete)
+                    .await
+                {
+                    warn!(self.logger, "Could not write to the transfer log: {}", err);
+                }
+            }
+            if let Some(metrics) = &self.metrics {
+                metrics.add_transferred_bytes_metric("upload", transfer_result(&result, &activity), activity.bytes_moved());
+            }
+            if matches!(transfer_result(&result, &activity), "client_error" | "stalled" | "aborted") {
+                partial_upload::clean_up(self.partial_uploads, &*self.storage, &self.user, &path, self.start_pos, &self.logger).await;
+            }
+            let result = match result {
+                Ok(result) => result,
+                Err(Interruption::Kicked) => {
+                    info!(self.logger, "STOR aborted because the session was terminated");
+                    return;
+                }
+                Err(Interruption::Aborted) => {
+                    info!(self.logger, "STOR aborted by the client");
+                    Self::reply_aborted(self.tx.clone(), &self.logger).await;
+                    return;
+                }
+            };
+            match result {
+                Some(Ok(bytes)) => {
+                    if let Some(metrics) = &self.metrics {
+                        metrics.add_transfer_histogram_metrics("upload", started.elapsed(), bytes);
+                    }
+                    if let Some(notifier) = &self.notifier {
+                        notifier.notify(FileEventKind::Uploaded { bytes }, path, self.username.clone());
+                    }
                     if let Err(err) = tx_ok.send(InternalMsg::WrittenData { bytes: bytes as i64 }).await {
-                        warn!("Could not notify control channel of successful STOR: {}", err);
+                        warn!(self.logger, "Could not notify control channel of successful STOR: {}", err);
                     }
                 }
-                Err(err) => {
-                    if let Err(err) = tx_error.send(InternalMsg::StorageError(err)).await {
-                        warn!("Could not notify control channel of error with STOR: {}", err);
+                Some(Err(err)) => {
+                    let msg = if activity.client_failed() {
+                        warn!(self.logger, "Could not receive the file from the client during STOR: {}", err);
+                        InternalMsg::ConnectionReset
+                    } else {
+                        warn!(self.logger, "Could not write the file to the storage backend during STOR: {}", err);
+                        InternalMsg::StorageError(err)
+                    };
+                    if let Err(err) = tx_error.send(msg).await {
+                        warn!(self.logger, "Could not notify control channel of error with STOR: {}", err);
                     }
                 }
-            }
-        });
-    }
-
-    async fn exec_list(self, path: Option<String>) {
-        let path = match path {
-            Some(path) => self.cwd.join(path),
-            None => self.cwd.clone(),
-        };
-        let mut tx_ok = self.tx.clone();
-        tokio::spawn(async move {
-            match self.storage.list_fmt(&self.user, path).await {
-                Ok(cursor) => {
-                    debug!("Copying future for List");
-                    let mut input = cursor;
-                    let mut output = Self::writer(self.socket, self.tls, self.identity_file, self.identity_password);
-                    match tokio::io::copy(&mut input, &mut output).await {
-                        Ok(_) => {
-                            if let Err(err) = output.shutdown().await {
-                                warn!("Could not shutdown output stream during LIST: {}", err);
-                            }
-                            if let Err(err) = tx_ok.send(InternalMsg::DirectorySuccessfullyListed).await {
-                                warn!("Could not notify control channel of successful LIST: {}", err);
-                            }
-                        }
-                        Err(err) => warn!("Could not copy from storage implementation during LIST: {}", err),
+                None => {
+                    warn!(self.logger, "STOR stalled for {:?}, aborting the transfer", self.stalled_transfer_timeout);
+                    if let Err(err) = tx_error.send(InternalMsg::TransferStalled).await {
+                        warn!(self.logger, "Could not notify control channel of stalled STOR: {}", err);
                     }
                 }
-                Err(err) => warn!("Failed to send directory list: {:?}", err),
             }
         });
     }
 
-    async fn exec_nlst(self, path: Option<String>) {
-        let path = match path {
-            Some(path) => self.cwd.join(path),
-            None => self.cwd.clone(),
+    // Sends the listing of the directory for LIST, NLST or MLSD, which only differ in the line
+    // they show for every entry. LIST and NLST take wildcards in the last component of the path.
+    async fn exec_listing(mut self, path: Option<String>, listing: Listing) {
+        let (path, pattern) = match (path, listing) {
+            (Some(path), Listing::List) | (Some(path), Listing::Nlst) => {
+                let (path, pattern) = glob::split(&path);
+                (Some(path), pattern)
+            }
+            (path, _) => (path, None),
+        };
+        let path = match self.resolve(path).await {
+            Some(path) => path,
+            None => return,
         };
+        let name = listing.name();
         let mut tx_ok = self.tx.clone();
         let mut tx_error = self.tx.clone();
         tokio::spawn(async move {
-            match self.storage.nlst(&self.user, path).await {
-                Ok(mut input) => {
-                    let mut output = Self::writer(self.socket, self.tls, self.identity_file, self.identity_password);
-                    match tokio::io::copy(&mut input, &mut output).await {
+            match storage::with_timeout(&self.logger, self.storage_timeout, self.lister.list(path, pattern, listing != Listing::Mlsd)).await {
+                Ok(entries) => {
+                    if let Err(err) = tx_ok.send(InternalMsg::SendingDirectoryList).await {
+                        warn!(self.logger, "Error notifying control channel of progress during {}: {}", name, err);
+                        return;
+                    }
+                    debug!(self.logger, "Streaming directory listing for {}", name);
+                    let activity = Activity::new();
+                    let output = Self::writer(
+                        self.socket,
+                        self.tls,
+                        self.identity_file,
+                        self.identity_password,
+                        self.download_limiters,
+                        activity.clone(),
+                    )
+                    .await;
+                    let mut output = in_blocks(output, &self.transfer_mode, 0, 0);
+                    let (formatter, path_mapper, user, mlst_facts) = (self.list_formatter, self.path_mapper, self.user, self.mlst_facts);
+                    let line = |fileinfo: &storage::Fileinfo<PathBuf, S::Metadata>| {
+                        let path = path::to_client(&path_mapper, &user, &fileinfo.path);
+                        match listing {
+                            Listing::List => formatter.list_line(&path, &fileinfo.metadata),
+                            Listing::Nlst => formatter.nlst_line(&path, &fileinfo.metadata),
+                            Listing::Mlsd => {
+                                let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+                                facts::line(&mlst_facts, &name, &fileinfo.metadata)
+                            }
+                        }
+                    };
+                    // Listings of large directories are streamed and can take a while, so they can be
+                    // aborted like transfers.
+                    let result = match unless_interrupted(write_listing(entries, &mut output, line), &self.tracker, &mut self.abort).await {
+                        Ok(result) => result,
+                        Err(Interruption::Kicked) => {
+                            info!(self.logger, "{} aborted because the session was terminated", name);
+                            return;
+                        }
+                        Err(Interruption::Aborted) => {
+                            info!(self.logger, "{} aborted by the client", name);
+                            Self::reply_aborted(self.tx.clone(), &self.logger).await;
+                            return;
+                        }
+                    };
+                    match result {
                         Ok(_) => {
                             if let Err(err) = output.shutdown().await {
-                                warn!("Could not shutdown output stream during NLIST: {}", err);
+                                warn!(self.logger, "Could not shutdown output stream during {}: {}", name, err);
                             }
                             if let Err(err) = tx_ok.send(InternalMsg::DirectorySuccessfullyListed).await {
-                                warn!("Could not notify control channel of successful NLIST: {}", err);
+                                warn!(self.logger, "Could not notify control channel of successful {}: {}", name, err);
+                            }
+                        }
+                        Err(err) => {
+                            let msg = if activity.client_failed() {
+                                warn!(self.logger, "Could not send directory listing during {}: {}", name, err);
+                                InternalMsg::ConnectionReset
+                            } else {
+                                warn!(self.logger, "Could not list the directory in the storage backend during {}: {}", name, err);
+                                InternalMsg::StorageError(err)
+                            };
+                            if let Err(err) = tx_error.send(msg).await {
+                                warn!(self.logger, "Could not notify control channel of failed {}: {}", name, err);
                             }
                         }
-                        Err(err) => warn!("Could not copy from storage implementation during NLST: {}", err),
                     }
                 }
-                Err(_) => {
-                    if let Err(err) = tx_error.send(InternalMsg::StorageError(Error::from(ErrorKind::LocalError))).await {
-                        warn!("Could not notify control channel of error with NLIST: {}", err);
+                Err(err) => {
+                    warn!(self.logger, "Failed to send directory list: {:?}", err);
+                    if let Err(err) = tx_error.send(InternalMsg::StorageError(err)).await {
+                        warn!(self.logger, "Could not notify control channel of error with {}: {}", name, err);
                     }
                 }
             }
@@ -180,44 +735,114 @@ where
     }
 
     // Lots of code duplication here. Should disappear completely when the storage backends are rewritten in async/.await style
-    fn writer(
+    #[cfg_attr(not(feature = "ftps"), allow(unused_variables))]
+    async fn writer(
         socket: tokio::net::TcpStream,
         tls: bool,
         identity_file: Option<PathBuf>,
         indentity_password: Option<String>,
+        rate_limiters: Vec<Arc<RateLimiter>>,
+        activity: Activity,
     ) -> Box<dyn tokio::io::AsyncWrite + Send + Unpin + Sync> {
-        if tls {
-            let io = futures::executor::block_on(async move {
-                let identity = crate::server::tls::identity(identity_file.unwrap(), indentity_password.unwrap());
-                let acceptor = tokio_tls::TlsAcceptor::from(native_tls::TlsAcceptor::builder(identity).build().unwrap());
-                acceptor.accept(socket).await.unwrap()
-            });
+        #[cfg(feature = "ftps")]
+        let io: Box<dyn tokio::io::AsyncWrite + Send + Unpin + Sync> = if tls {
+            let identity = crate::server::tls::identity(identity_file.unwrap(), indentity_password.unwrap());
+            let acceptor = tokio_tls::TlsAcceptor::from(native_tls::TlsAcceptor::builder(identity).build().unwrap());
+            let io = acceptor.accept(socket).await.unwrap();
             Box::new(io)
         } else {
             Box::new(socket)
-        }
+        };
+        // PROT P is refused without FTPS, so data connections are never secured.
+        #[cfg(not(feature = "ftps"))]
+        let io: Box<dyn tokio::io::AsyncWrite + Send + Unpin + Sync> = Box::new(socket);
+        let io: Box<dyn tokio::io::AsyncWrite + Send + Unpin + Sync> = if rate_limiters.is_empty() {
+            io
+        } else {
+            Box::new(Throttled::new(io, rate_limiters))
+        };
+        Box::new(Tracked { inner: io, activity })
     }
 
     // Lots of code duplication here. Should disappear completely when the storage backends are rewritten in async/.await style
-    fn reader(
+    #[cfg_attr(not(feature = "ftps"), allow(unused_variables))]
+    async fn reader(
         socket: tokio::net::TcpStream,
         tls: bool,
         identity_file: Option<PathBuf>,
         indentity_password: Option<String>,
+        rate_limiters: Vec<Arc<RateLimiter>>,
+        activity: Activity,
     ) -> Box<dyn tokio::io::AsyncRead + Send + Unpin + Sync> {
-        if tls {
-            let io = futures::executor::block_on(async move {
-                let identity = crate::server::tls::identity(identity_file.unwrap(), indentity_password.unwrap());
-                let acceptor = tokio_tls::TlsAcceptor::from(native_tls::TlsAcceptor::builder(identity).build().unwrap());
-                acceptor.accept(socket).await.unwrap()
-            });
+        #[cfg(feature = "ftps")]
+        let io: Box<dyn tokio::io::AsyncRead + Send + Unpin + Sync> = if tls {
+            let identity = crate::server::tls::identity(identity_file.unwrap(), indentity_password.unwrap());
+            let acceptor = tokio_tls::TlsAcceptor::from(native_tls::TlsAcceptor::builder(identity).build().unwrap());
+            let io = acceptor.accept(socket).await.unwrap();
             Box::new(io)
         } else {
             Box::new(socket)
+        };
+        // PROT P is refused without FTPS, so data connections are never secured.
+        #[cfg(not(feature = "ftps"))]
+        let io: Box<dyn tokio::io::AsyncRead + Send + Unpin + Sync> = Box::new(socket);
+        let io: Box<dyn tokio::io::AsyncRead + Send + Unpin + Sync> = if rate_limiters.is_empty() {
+            io
+        } else {
+            Box::new(Throttled::new(io, rate_limiters))
+        };
+        Box::new(Tracked { inner: io, activity })
+    }
+}
+
+// The kinds of directory listings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Listing {
+    List,
+    Nlst,
+    Mlsd,
+}
+
+impl Listing {
+    fn name(self) -> &'static str {
+        match self {
+            Listing::List => "LIST",
+            Listing::Nlst => "NLST",
+            Listing::Mlsd => "MLSD",
         }
     }
 }
 
+// Frames what is written to the data connection in blocks in block mode, with a restart marker
+// after every `marker_interval` bytes if that isn't 0. Other modes send the data as it is.
+fn in_blocks(
+    output: Box<dyn tokio::io::AsyncWrite + Send + Unpin + Sync>,
+    mode: &ModeParam,
+    start_pos: u64,
+    marker_interval: u64,
+) -> Box<dyn tokio::io::AsyncWrite + Send + Unpin + Sync> {
+    match mode {
+        ModeParam::Block => Box::new(BlockWriter::new(output, start_pos, marker_interval)),
+        _ => output,
+    }
+}
+
+// Writes the entries of a directory to the data connection as the storage backend produces them,
+// one line each, so that memory use doesn't grow with the size of the directory.
+async fn write_listing<M, W, F>(mut entries: storage::ListStream<M>, output: &mut W, line: F) -> storage::Result<()>
+where
+    M: storage::Metadata,
+    W: tokio::io::AsyncWrite + Unpin,
+    F: Fn(&storage::Fileinfo<PathBuf, M>) -> String,
+{
+    let mut output = tokio::io::BufWriter::new(output);
+    while let Some(entry) = entries.next().await {
+        output.write_all(format!("{}\r\n", line(&entry?)).as_bytes()).await?;
+    }
+    output.flush().await?;
+    Ok(())
+}
+
 /// Processing for the data connection. This will spawn a new async task with the actual processing.
 ///
 /// socket: the data socket we'll be working with
@@ -230,10 +855,15 @@ where
     S::Metadata: storage::Metadata,
     U: UserDetail + 'static,
 {
+    session.data_socket_options.apply(&socket, &session.logger);
     let mut data_cmd_rx = session.data_cmd_rx.take().unwrap().fuse();
-    let mut data_abort_rx = session.data_abort_rx.take().unwrap().fuse();
+    let mut abort = AbortSignal {
+        rx: session.data_abort_rx.take().unwrap(),
+        tx: tx.clone(),
+        logger: session.logger.clone(),
+    };
     let tls = session.data_tls;
-    let command_executor = DataCommandExecutor {
+    let mut command_executor = DataCommandExecutor {
         user: session.user.clone(),
         socket,
         tls,
@@ -241,28 +871,68 @@ where
         storage: Arc::clone(&session.storage),
         cwd: session.cwd.clone(),
         start_pos: session.start_pos,
+        transfer_type: session.transfer_type,
+        transfer_mode: session.transfer_mode.clone(),
+        list_formatter: session.list_formatter.clone(),
+        path_mapper: session.path_mapper.clone(),
+        lister: Lister::new(session),
+        hidden_paths: session.hidden_paths.clone(),
+        mlst_facts: session.mlst_facts.clone(),
         identity_file: if tls { Some(session.certs_file.clone().unwrap()) } else { None },
         identity_password: if tls { Some(session.certs_password.clone().unwrap()) } else { None },
+        upload_limiters: session.bandwidth_limiter.iter().chain(session.upload_limiter.iter()).cloned().collect(),
+        download_limiters: session.bandwidth_limiter.iter().chain(session.download_limiter.iter()).cloned().collect(),
+        stalled_transfer_timeout: session.stalled_transfer_timeout,
+        logger: session.logger.clone(),
+        tracker: session.tracker.clone(),
+        metrics: session.metrics.clone(),
+        span: session.span.clone(),
+        xferlog: session
+            .xferlog
+            .as_ref()
+            .map(|xferlog| xferlog.for_session(session.peer_ip, session.username.clone().unwrap_or_default())),
+        notifier: session.notifier.clone(),
+        username: session.username.clone(),
+        upload_spool: session.upload_spool.clone(),
+        partial_uploads: session.partial_uploads,
+        storage_timeout: session.storage_timeout,
+        transfer_limiter: session.transfer_limiter.clone(),
+        transfer_permit: None,
+        abort: None,
     };
+    let logger = session.logger.clone();
+    let metrics = session.metrics.clone();
 
     tokio::spawn(async move {
+        if let Some(metrics) = &metrics {
+            metrics.inc_data_connection();
+        }
         let mut timeout_delay = tokio::time::delay_for(std::time::Duration::from_secs(5 * 60));
         // TODO: Use configured timeout
-        tokio::select! {
-            Some(command) = data_cmd_rx.next() => {
-                handle_incoming(DataCommand::ExternalCommand(command), command_executor).await;
-            },
-            Some(_) = data_abort_rx.next() => {
-                handle_incoming(DataCommand::Abort, command_executor).await;
-            },
+        let incoming = tokio::select! {
+            Some(command) = data_cmd_rx.next() => Some(DataCommand::ExternalCommand(command)),
+            Some(_) = abort.received() => Some(DataCommand::Abort),
             _ = &mut timeout_delay => {
-                info!("Connection timed out");
-                return;
+                info!(logger, "Connection timed out");
+                None
             }
         };
+        let timed_out = match incoming {
+            Some(incoming) => {
+                command_executor.abort = Some(abort);
+                handle_incoming(incoming, command_executor).await;
+                false
+            }
+            None => true,
+        };
 
-        // This probably happened because the control channel was closed before we got here
-        warn!("Nothing received");
+        if !timed_out {
+            // This probably happened because the control channel was closed before we got here
+            warn!(logger, "Nothing received");
+        }
+        if let Some(metrics) = &metrics {
+            metrics.dec_data_connection();
+        }
     });
 }
 
@@ -275,10 +945,15 @@ where
 {
     match incoming {
         DataCommand::Abort => {
-            info!("Abort received");
+            info!(command_executor.logger, "Abort received");
+            let mut tx = command_executor.tx.clone();
+            let reply = InternalMsg::CommandChannelReply(ReplyCode::ClosingDataConnection, "Closed data channel".to_string());
+            if let Err(err) = tx.send(reply).await {
+                warn!(command_executor.logger, "Could not reply to ABOR: {}", err);
+            }
         }
         DataCommand::ExternalCommand(command) => {
-            info!("Data command received");
+            info!(command_executor.logger, "Data command received");
             command_executor.execute(command).await;
         }
     }
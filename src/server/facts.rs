@@ -0,0 +1,143 @@
+//! Contains the facts about files that `MLSD` and `MLST` show, as defined in RFC 3659, and the
+//! selection of them that clients make with `OPTS MLST`.
+
+use crate::storage::Metadata;
+
+use chrono::{DateTime, Utc};
+
+// A fact the server can show about a file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Fact {
+    Type,
+    Size,
+    Modify,
+    Unique,
+    UnixUid,
+    UnixGid,
+}
+
+impl Fact {
+    // All the supported facts, in the order in which they are shown.
+    pub const ALL: [Fact; 6] = [Fact::Type, Fact::Size, Fact::Modify, Fact::Unique, Fact::UnixUid, Fact::UnixGid];
+
+    // The facts that are shown until the client selects others.
+    pub const DEFAULT: [Fact; 4] = [Fact::Type, Fact::Size, Fact::Modify, Fact::Unique];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Fact::Type => "type",
+            Fact::Size => "size",
+            Fact::Modify => "modify",
+            Fact::Unique => "unique",
+            Fact::UnixUid => "UNIX.uid",
+            Fact::UnixGid => "UNIX.gid",
+        }
+    }
+
+    // The value of the fact for the file, if the storage backend knows it.
+    fn value(self, metadata: &dyn Metadata) -> Option<String> {
+        match self {
+            Fact::Type if metadata.is_dir() => Some("dir".to_string()),
+            Fact::Type => Some("file".to_string()),
+            Fact::Size => Some(metadata.len().to_string()),
+            Fact::Modify => metadata
+                .modified()
+                .ok()
+                .map(|time| DateTime::<Utc>::from(time).format("%Y%m%d%H%M%S").to_string()),
+            Fact::Unique => metadata.unique(),
+            Fact::UnixUid => Some(metadata.uid().to_string()),
+            Fact::UnixGid => Some(metadata.gid().to_string()),
+        }
+    }
+}
+
+// Parses the list of facts given with `OPTS MLST`, like `type;size;modify;`. Fact names are case
+// insensitive and the ones the server doesn't support are left out, as RFC 3659 asks.
+pub(crate) fn parse(list: &str) -> Vec<Fact> {
+    let names: Vec<&str> = list.split(';').map(str::trim).collect();
+    Fact::ALL
+        .iter()
+        .copied()
+        .filter(|fact| names.iter().any(|name| name.eq_ignore_ascii_case(fact.name())))
+        .collect()
+}
+
+// Lists the facts like `type;size;modify;`, as in the reply to `OPTS MLST`.
+pub(crate) fn list(facts: &[Fact]) -> String {
+    facts.iter().map(|fact| format!("{};", fact.name())).collect()
+}
+
+// Lists all supported facts for `FEAT`, marking the selected ones with a `*`.
+pub(crate) fn feat(selected: &[Fact]) -> String {
+    Fact::ALL
+        .iter()
+        .map(|fact| format!("{}{};", fact.name(), if selected.contains(fact) { "*" } else { "" }))
+        .collect()
+}
+
+// The line for the file in the output of `MLSD` and `MLST`, like
+// `type=file;size=1234;modify=20201016143000; report.pdf`.
+pub(crate) fn line(facts: &[Fact], name: &str, metadata: &dyn Metadata) -> String {
+    let facts: String = facts
+        .iter()
+        .filter_map(|fact| fact.value(metadata).map(|value| format!("{}={};", fact.name(), value)))
+        .collect();
+    format!("{} {}", facts, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fact;
+    use crate::storage::{self, Metadata};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    struct File;
+
+    impl Metadata for File {
+        fn len(&self) -> u64 {
+            1234
+        }
+
+        fn is_dir(&self) -> bool {
+            false
+        }
+
+        fn is_file(&self) -> bool {
+            true
+        }
+
+        fn is_symlink(&self) -> bool {
+            false
+        }
+
+        fn modified(&self) -> storage::Result<SystemTime> {
+            Ok(UNIX_EPOCH + Duration::from_secs(1_602_858_600))
+        }
+
+        fn gid(&self) -> u32 {
+            100
+        }
+
+        fn uid(&self) -> u32 {
+            1000
+        }
+    }
+
+    #[test]
+    fn parse_and_list() {
+        let facts = super::parse("Size;UNIX.uid;color;");
+        assert_eq!(facts, vec![Fact::Size, Fact::UnixUid]);
+        assert_eq!(super::list(&facts), "size;UNIX.uid;");
+        assert_eq!(super::list(&super::parse("")), "");
+        assert_eq!(super::feat(&Fact::DEFAULT), "type*;size*;modify*;unique*;UNIX.uid;UNIX.gid;");
+    }
+
+    #[test]
+    fn line() {
+        assert_eq!(
+            super::line(&Fact::DEFAULT, "report.pdf", &File),
+            "type=file;size=1234;modify=20201016143000; report.pdf"
+        );
+        assert_eq!(super::line(&[], "report.pdf", &File), " report.pdf");
+    }
+}
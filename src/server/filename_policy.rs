@@ -0,0 +1,48 @@
+//! Contains the [`FilenamePolicy`] trait that lets operators decide which names files and
+//! directories may get.
+//!
+//! [`FilenamePolicy`]: trait.FilenamePolicy.html
+
+use regex::Regex;
+
+/// Decides which names clients may give to the files they upload with `STOR` and the directories
+/// they create with `MKD`, and which names they may rename things to with `RNTO`. Other names are
+/// refused with a `553` reply. Set it with [`ServerBuilder::filename_policy`].
+///
+/// Only the last component of the path is checked. The policy is implemented for regular
+/// expressions, which must match the name, and for functions that return whether the name is
+/// allowed.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::Server;
+/// use regex::Regex;
+///
+/// // Only CSV files with names in lower case.
+/// let server = Server::new_with_fs_root("/tmp").filename_policy(Regex::new(r"^[a-z0-9_-]+\.csv$").unwrap());
+///
+/// // No hidden files.
+/// let server = Server::new_with_fs_root("/tmp").filename_policy(|name: &str| !name.starts_with('.'));
+/// ```
+///
+/// [`ServerBuilder::filename_policy`]: struct.ServerBuilder.html#method.filename_policy
+pub trait FilenamePolicy: Send + Sync {
+    /// Tells if the given name is allowed.
+    fn allows(&self, name: &str) -> bool;
+}
+
+impl FilenamePolicy for Regex {
+    fn allows(&self, name: &str) -> bool {
+        self.is_match(name)
+    }
+}
+
+impl<F> FilenamePolicy for F
+where
+    F: Fn(&str) -> bool + Send + Sync,
+{
+    fn allows(&self, name: &str) -> bool {
+        self(name)
+    }
+}
@@ -1,53 +1,145 @@
-use super::chancomms::{InternalMsg, ProxyLoopMsg, ProxyLoopReceiver, ProxyLoopSender};
+use super::chancomms::{InternalMsg, ProxyLoopSender};
+#[cfg(feature = "proxy_protocol")]
+use super::chancomms::{PassiveMode, ProxyLoopMsg, ProxyLoopReceiver};
 use super::controlchan::command::Command;
-use super::controlchan::handler::{CommandContext, CommandHandler};
+use super::controlchan::handler::{CommandContext, CommandHandler, CustomCommandHandler};
 use super::controlchan::FTPCodec;
 use super::controlchan::{ControlChanError, ControlChanErrorKind};
+#[cfg(feature = "gssapi")]
+use super::gssapi::{GssapiContext, GssapiSession, NewGssapiContext};
+use super::handle::{shutdown_initiated, ReloadableSettings, ServerHandle};
+use super::health::{HealthCheck, HealthState};
+use super::hidden::HiddenPaths;
+#[cfg(feature = "http_endpoint")]
+use super::http_endpoint;
 use super::io::*;
+#[cfg(feature = "proxy_protocol")]
+use super::ipfilter::unmap_ipv4;
+use super::ipfilter::IpFilter;
+use super::list_format::{ListFormatter, UnixListFormatter};
+use super::middleware::{Middleware, Next, Request};
+use super::path::PathMapper;
 use super::proxy_protocol::*;
+use super::registry::SessionRegistry;
+use super::reply_filter::{self, ReplyFilter};
+use super::socket::SocketOptions;
+use super::spans::SessionSpan;
+use super::spool::UploadSpool;
+use super::throttle::{RateLimiter, TransferLimiter};
+use super::xferlog::Xferlog;
+use super::ConfigError;
+use super::FilenamePolicy;
+use super::ReplyCatalog;
 use super::*;
 use super::{Reply, ReplyCode};
 use super::{Session, SessionState};
+use crate::audit::{AuditSink, AuditTrail};
 use crate::auth::{anonymous::AnonymousAuthenticator, Authenticator, DefaultUser, UserDetail};
-use crate::metrics;
+#[cfg(feature = "config")]
+use crate::config::{FromStorageConfig, ServerConfig};
+use crate::metrics::{command_label, Metrics};
+use crate::notification::{FileEventListener, Notifier};
 use crate::server::session::SharedSession;
-use crate::storage::{self, filesystem::Filesystem, ErrorKind};
+use crate::storage::{self, filesystem::Filesystem, Capabilities};
 use controlchan::commands;
 
 use futures::channel::mpsc::{channel, Receiver, Sender};
-use futures::{SinkExt, StreamExt};
-use log::{error, info, warn};
-use std::net::{IpAddr, Shutdown, SocketAddr};
+use futures::{FutureExt, SinkExt, StreamExt};
+use slog::{error, info, o, warn, Drain, Logger};
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "proxy_protocol")]
+use std::net::Shutdown;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::Range;
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Mutex};
 use tokio_util::codec::*;
+use uuid::Uuid;
 
 const DEFAULT_GREETING: &str = "Welcome to the libunftp FTP server";
+const DEFAULT_SYSTEM_TYPE: &str = "UNIX Type: L8";
+const DEFAULT_IDENTIFICATION: &str = "Powered by libunftp";
 const DEFAULT_IDLE_SESSION_TIMEOUT_SECS: u64 = 600;
+pub(super) const DEFAULT_STALLED_TRANSFER_TIMEOUT_SECS: u64 = 300;
+const DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS: u64 = 10;
+#[cfg(feature = "proxy_protocol")]
+const DEFAULT_PROXY_PROTOCOL_RESERVATION_TTL_SECS: u64 = 60;
+const DEFAULT_CONTROL_MSG_CHANNEL_CAPACITY: usize = 16;
+const DEFAULT_MAX_COMMAND_LENGTH: usize = 8 * 1024;
+const DEFAULT_SPOOLED_UPLOAD_RETRIES: u32 = 2;
+const DEFAULT_TRANSFER_QUEUE_TIMEOUT_SECS: u64 = 5;
+// The proxy loop serves the passive mode requests of all sessions.
+#[cfg(feature = "proxy_protocol")]
+const PROXY_LOOP_CHANNEL_CAPACITY: usize = 64;
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+// How long a client that is refused gets to read the reply that tells why.
+const REFUSAL_TIMEOUT: Duration = Duration::from_secs(5);
 
+// The greeting sent to clients after connecting: either fixed text or generated per connection.
+pub(crate) enum Greeting {
+    Text(String),
+    Generated(Box<dyn Fn(SocketAddr, SocketAddr) -> String + Send + Sync>),
+}
+
+// The external IP address is part of the reloadable settings.
+#[cfg(feature = "proxy_protocol")]
 #[derive(Clone, Copy)]
 struct ProxyParams {
-    #[allow(dead_code)]
-    external_ip: IpAddr,
     external_control_port: u16,
 }
 
-impl ProxyParams {
-    fn new(ip: &str, port: u16) -> Result<Self, Box<dyn std::error::Error>> {
-        Ok(ProxyParams {
-            external_ip: ip.parse()?,
-            external_control_port: port,
-        })
+// Keeps track of the number of open control connections. The count is decremented again when the
+// guard is dropped at the end of the control channel loop.
+struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl ConnectionGuard {
+    // Registers a new connection, returning None if that would exceed the given maximum.
+    fn acquire(count: &Arc<AtomicUsize>, max: Option<usize>) -> Option<Self> {
+        let previous = count.fetch_add(1, Ordering::SeqCst);
+        let guard = ConnectionGuard(count.clone());
+        match max {
+            Some(max) if previous >= max => None,
+            _ => Some(guard),
+        }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Accepting a connection can fail because of a problem with that connection only, for example
+// when the client already went away, but also because we ran out of resources like file
+// descriptors. We keep accepting in both cases, backing off for a while in the latter so that we
+// don't spin while the problem persists.
+async fn handle_accept_error(logger: &Logger, e: std::io::Error) {
+    use std::io::ErrorKind::*;
+    match e.kind() {
+        ConnectionAborted | ConnectionReset | ConnectionRefused | Interrupted | WouldBlock | TimedOut => {
+            warn!(logger, "Could not accept connection: {:?}", e);
+        }
+        _ => {
+            error!(logger, "Could not accept connection, backing off for {:?}: {:?}", ACCEPT_ERROR_BACKOFF, e);
+            tokio::time::delay_for(ACCEPT_ERROR_BACKOFF).await;
+        }
     }
 }
 
+// Unless the embedder sets a logger of their own, log records are forwarded to the `log` crate.
+fn default_logger() -> Logger {
+    Logger::root(slog_stdlog::StdLog.fuse(), o!())
+}
+
 /// An instance of a FTP server. It contains a reference to an [`Authenticator`] that will be used
 /// for authentication, and a [`StorageBackend`] that will be used as the storage backend.
 ///
-/// The server can be started with the `listen` method.
+/// A server is configured with a [`ServerBuilder`], which checks the configuration when it builds
+/// the server. The server can then be started with the `listen` method.
 ///
 /// # Example
 ///
@@ -56,12 +148,13 @@ impl ProxyParams {
 /// use tokio::runtime::Runtime;
 ///
 /// let mut rt = Runtime::new().unwrap();
-/// let server = Server::new_with_fs_root("/srv/ftp");
+/// let server = Server::new_with_fs_root("/srv/ftp").build().unwrap();
 /// rt.spawn(server.listen("127.0.0.1:2121"));
 /// // ...
 /// drop(rt);
 /// ```
 ///
+/// [`ServerBuilder`]: struct.ServerBuilder.html
 /// [`Authenticator`]: auth/trait.Authenticator.html
 /// [`StorageBackend`]: storage/trait.StorageBackend.html
 pub struct Server<S, U>
@@ -70,28 +163,114 @@ where
     U: UserDetail,
 {
     storage: Box<dyn (Fn() -> S) + Sync + Send>,
-    greeting: &'static str,
+    login_message: Option<String>,
+    reply_catalog: Arc<ReplyCatalog>,
+    reply_filter: Option<Arc<dyn ReplyFilter>>,
+    list_formatter: Arc<dyn ListFormatter>,
+    path_mapper: Option<Arc<dyn PathMapper<U>>>,
     authenticator: Arc<dyn Authenticator<U> + Send + Sync>,
     passive_ports: Range<u16>,
     certs_file: Option<PathBuf>,
     certs_password: Option<String>,
-    collect_metrics: bool,
-    idle_session_timeout: std::time::Duration,
+    data_downgrade_forbidden: bool,
+    // The addresses to listen on for implicit FTPS, where clients start TLS right away.
+    ftps_implicit_addresses: Vec<String>,
+    auth_mechanisms: Arc<Vec<String>>,
+    #[cfg(feature = "gssapi")]
+    gssapi: Option<NewGssapiContext>,
+    metrics: Option<Arc<Metrics>>,
+    stalled_transfer_timeout: std::time::Duration,
+    control_msg_channel_capacity: usize,
+    max_command_length: usize,
+    upload_spool_dir: Option<PathBuf>,
+    spooled_upload_retries: u32,
+    control_socket_options: SocketOptions,
+    data_socket_options: SocketOptions,
+    data_bind_ip: Option<IpAddr>,
+    storage_timeout: Option<Duration>,
+    metadata_cache_ttl: Option<Duration>,
+    transfer_permits: Option<Arc<tokio::sync::Semaphore>>,
+    transfer_queue_timeout: Duration,
+    #[cfg(feature = "proxy_protocol")]
     proxy_protocol_mode: Option<ProxyParams>,
+    #[cfg(feature = "proxy_protocol")]
     proxy_protocol_switchboard: Option<ProxyProtocolSwitchboard<S, U>>,
+    #[cfg(feature = "proxy_protocol")]
+    proxy_protocol_reservation_ttl: Duration,
+    #[cfg(feature = "proxy_protocol")]
+    trusted_proxies: IpFilter,
+    max_connections: Option<usize>,
+    connection_count: Arc<AtomicUsize>,
+    bandwidth_limiter: Option<Arc<RateLimiter>>,
+    upload_bandwidth_limit: Option<u64>,
+    download_bandwidth_limit: Option<u64>,
+    shutdown_tx: Arc<watch::Sender<bool>>,
+    shutdown_rx: watch::Receiver<bool>,
+    shutdown_grace_period: Duration,
+    settings: Arc<RwLock<ReloadableSettings>>,
+    sessions: SessionRegistry,
+    health: Arc<HealthState>,
+    logger: Logger,
+    reveal_session_id: bool,
+    system_type: Arc<String>,
+    identification: Arc<String>,
+    xferlog: Option<Xferlog>,
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    session_observer: Option<Arc<dyn SessionObserver>>,
+    file_event_listener: Option<Arc<dyn FileEventListener>>,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    custom_commands: HashMap<String, Arc<dyn CustomCommandHandler<S, U>>>,
+    site_commands: HashMap<String, Arc<dyn CustomCommandHandler<S, U>>>,
+    disabled_commands: Arc<HashSet<String>>,
+    drop_box: bool,
+    no_clobber: bool,
+    partial_uploads: PartialUploads,
+    filename_policy: Option<Arc<dyn FilenamePolicy>>,
+    hidden_paths: HiddenPaths,
+    #[cfg(feature = "http_endpoint")]
+    http_endpoint: Option<String>,
+}
+
+/// Configures a [`Server`]. Start with [`Server::new_with_fs_root`], [`Server::new`] or
+/// [`Server::new_with_authenticator`] and call [`build`] once everything is set. Nothing binds
+/// or connects before the server listens.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::Server;
+///
+/// let server = Server::new_with_fs_root("/srv/ftp")
+///     .greeting("Welcome to my FTP server")
+///     .passive_ports(50000..51000)
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// [`Server`]: struct.Server.html
+/// [`Server::new_with_fs_root`]: struct.Server.html#method.new_with_fs_root
+/// [`Server::new`]: struct.Server.html#method.new
+/// [`Server::new_with_authenticator`]: struct.Server.html#method.new_with_authenticator
+/// [`build`]: #method.build
+pub struct ServerBuilder<S, U>
+where
+    S: storage::StorageBackend<U> + Send + Sync,
+    U: UserDetail,
+{
+    server: Server<S, U>,
 }
 
 impl Server<Filesystem, DefaultUser> {
-    /// Create a new `Server` with the given filesystem root.
+    /// Start building a `Server` with the given filesystem root.
     ///
     /// # Example
     ///
     /// ```rust
     /// use libunftp::Server;
     ///
-    /// let server = Server::new_with_fs_root("/srv/ftp");
+    /// let server = Server::new_with_fs_root("/srv/ftp").build().unwrap();
     /// ```
-    pub fn new_with_fs_root<P: Into<PathBuf> + Send + 'static>(path: P) -> Self {
+    pub fn new_with_fs_root<P: Into<PathBuf> + Send + 'static>(path: P) -> ServerBuilder<Filesystem, DefaultUser> {
         let p = path.into();
         Server::new(Box::new(move || {
             let p = &p.clone();
@@ -100,6 +279,34 @@ impl Server<Filesystem, DefaultUser> {
     }
 }
 
+#[cfg(feature = "config")]
+impl<S> Server<S, DefaultUser>
+where
+    S: 'static + storage::StorageBackend<DefaultUser> + FromStorageConfig + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+{
+    /// Start building a `Server` with the storage backend and settings of the given [`ServerConfig`].
+    /// The address to listen on is part of the configuration as well.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::config::ServerConfig;
+    /// use libunftp::storage::filesystem::Filesystem;
+    /// use libunftp::Server;
+    ///
+    /// let config = ServerConfig::from_yaml("storage: {backend: filesystem, root: /srv/ftp}").unwrap();
+    /// let server: Server<Filesystem, _> = Server::from_config(&config).unwrap().build().unwrap();
+    /// let listening = server.listen(config.address);
+    /// ```
+    ///
+    /// [`ServerConfig`]: config/struct.ServerConfig.html
+    pub fn from_config(config: &ServerConfig) -> Result<ServerBuilder<S, DefaultUser>, Box<dyn std::error::Error>> {
+        Server::new(S::factory(&config.storage)?).with_config(config)
+    }
+}
+
 impl<S, U> Server<S, U>
 where
     S: 'static + storage::StorageBackend<U> + Sync + Send,
@@ -107,49 +314,111 @@ where
     S::Metadata: storage::Metadata,
     U: UserDetail + 'static,
 {
-    /// Construct a new [`Server`] with the given [`StorageBackend`]. The other parameters will be
+    /// Start building a [`Server`] with the given [`StorageBackend`]. The other parameters will be
     /// set to defaults.
     ///
     /// [`Server`]: struct.Server.html
     /// [`StorageBackend`]: ../storage/trait.StorageBackend.html
-    pub fn new(s: Box<dyn (Fn() -> S) + Send + Sync>) -> Self
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(s: Box<dyn (Fn() -> S) + Send + Sync>) -> ServerBuilder<S, U>
     where
         AnonymousAuthenticator: Authenticator<U>,
     {
-        Server {
-            storage: s,
-            greeting: DEFAULT_GREETING,
-            authenticator: Arc::new(AnonymousAuthenticator {}),
-            passive_ports: 49152..65535,
-            certs_file: Option::None,
-            certs_password: Option::None,
-            collect_metrics: false,
-            idle_session_timeout: Duration::from_secs(DEFAULT_IDLE_SESSION_TIMEOUT_SECS),
-            proxy_protocol_mode: Option::None,
-            proxy_protocol_switchboard: Option::None,
-        }
+        Server::new_with_authenticator(s, Arc::new(AnonymousAuthenticator {}))
     }
 
-    /// Construct a new [`Server`] with the given [`StorageBackend`] and [`Authenticator`]. The other parameters will be set to defaults.
+    /// Start building a [`Server`] with the given [`StorageBackend`] and [`Authenticator`]. The other parameters will be set to defaults.
     ///
     /// [`Server`]: struct.Server.html
     /// [`StorageBackend`]: ../storage/trait.StorageBackend.html
     /// [`Authenticator`]: ../auth/trait.Authenticator.html
-    pub fn new_with_authenticator(s: Box<dyn (Fn() -> S) + Send + Sync>, authenticator: Arc<dyn Authenticator<U> + Send + Sync>) -> Self {
-        Server {
-            storage: s,
-            greeting: DEFAULT_GREETING,
-            authenticator,
-            passive_ports: 49152..65535,
-            certs_file: Option::None,
-            certs_password: Option::None,
-            collect_metrics: false,
-            idle_session_timeout: Duration::from_secs(DEFAULT_IDLE_SESSION_TIMEOUT_SECS),
-            proxy_protocol_mode: Option::None,
-            proxy_protocol_switchboard: Option::None,
+    pub fn new_with_authenticator(s: Box<dyn (Fn() -> S) + Send + Sync>, authenticator: Arc<dyn Authenticator<U> + Send + Sync>) -> ServerBuilder<S, U> {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        ServerBuilder {
+            server: Server {
+                storage: s,
+                login_message: Option::None,
+                reply_catalog: Arc::new(ReplyCatalog::new()),
+                reply_filter: None,
+                list_formatter: Arc::new(UnixListFormatter::default()),
+                path_mapper: None,
+                authenticator,
+                passive_ports: 49152..65535,
+                certs_file: Option::None,
+                certs_password: Option::None,
+                data_downgrade_forbidden: false,
+                ftps_implicit_addresses: Vec::new(),
+                auth_mechanisms: Arc::new(commands::DEFAULT_AUTH_MECHANISMS.iter().map(|mechanism| mechanism.to_string()).collect()),
+                #[cfg(feature = "gssapi")]
+                gssapi: None,
+                metrics: None,
+                stalled_transfer_timeout: Duration::from_secs(DEFAULT_STALLED_TRANSFER_TIMEOUT_SECS),
+                control_msg_channel_capacity: DEFAULT_CONTROL_MSG_CHANNEL_CAPACITY,
+                max_command_length: DEFAULT_MAX_COMMAND_LENGTH,
+                upload_spool_dir: None,
+                spooled_upload_retries: DEFAULT_SPOOLED_UPLOAD_RETRIES,
+                control_socket_options: SocketOptions::default(),
+                data_socket_options: SocketOptions::default(),
+                data_bind_ip: None,
+                storage_timeout: None,
+                metadata_cache_ttl: None,
+                transfer_permits: None,
+                transfer_queue_timeout: Duration::from_secs(DEFAULT_TRANSFER_QUEUE_TIMEOUT_SECS),
+                #[cfg(feature = "proxy_protocol")]
+                proxy_protocol_mode: Option::None,
+                #[cfg(feature = "proxy_protocol")]
+                proxy_protocol_switchboard: Option::None,
+                #[cfg(feature = "proxy_protocol")]
+                proxy_protocol_reservation_ttl: Duration::from_secs(DEFAULT_PROXY_PROTOCOL_RESERVATION_TTL_SECS),
+                #[cfg(feature = "proxy_protocol")]
+                trusted_proxies: IpFilter::default(),
+                max_connections: Option::None,
+                connection_count: Arc::new(AtomicUsize::new(0)),
+                bandwidth_limiter: Option::None,
+                upload_bandwidth_limit: Option::None,
+                download_bandwidth_limit: Option::None,
+                shutdown_tx: Arc::new(shutdown_tx),
+                shutdown_rx,
+                shutdown_grace_period: Duration::from_secs(DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS),
+                settings: Arc::new(RwLock::new(ReloadableSettings {
+                    greeting: Greeting::Text(DEFAULT_GREETING.to_string()),
+                    idle_session_timeout: Duration::from_secs(DEFAULT_IDLE_SESSION_TIMEOUT_SECS),
+                    ip_filter: IpFilter::default(),
+                    passive_external_ip: None,
+                })),
+                sessions: SessionRegistry::default(),
+                health: Arc::new(HealthState::default()),
+                logger: default_logger(),
+                reveal_session_id: false,
+                system_type: Arc::new(DEFAULT_SYSTEM_TYPE.to_string()),
+                identification: Arc::new(DEFAULT_IDENTIFICATION.to_string()),
+                xferlog: None,
+                audit_sink: None,
+                session_observer: None,
+                file_event_listener: None,
+                middlewares: Vec::new(),
+                custom_commands: HashMap::new(),
+                site_commands: HashMap::new(),
+                disabled_commands: Arc::new(HashSet::new()),
+                drop_box: false,
+                no_clobber: false,
+                partial_uploads: PartialUploads::Keep,
+                filename_policy: None,
+                hidden_paths: HiddenPaths::default(),
+                #[cfg(feature = "http_endpoint")]
+                http_endpoint: None,
+            },
         }
     }
+}
 
+impl<S, U> ServerBuilder<S, U>
+where
+    S: 'static + storage::StorageBackend<U> + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+    U: UserDetail + 'static,
+{
     /// Set the greeting that will be sent to the client after connecting.
     ///
     /// # Example
@@ -164,8 +433,148 @@ where
     /// let mut server = Server::new_with_fs_root("/tmp");
     /// server.greeting("Welcome to my FTP Server");
     /// ```
-    pub fn greeting(mut self, greeting: &'static str) -> Self {
-        self.greeting = greeting;
+    pub fn greeting<T: Into<String>>(self, greeting: T) -> Self {
+        self.server.settings.write().unwrap().greeting = Greeting::Text(greeting.into());
+        self
+    }
+
+    /// Read the greeting that will be sent to the client after connecting from a file. Like any
+    /// greeting, it may span multiple lines.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").greeting_file("/etc/issue.net");
+    /// ```
+    pub fn greeting_file<P: AsRef<Path>>(self, path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let greeting = std::fs::read_to_string(path)?;
+        Ok(self.greeting(greeting))
+    }
+
+    /// Set a message of the day that is shown to the client after logging in. Multiple lines
+    /// are sent as continuation lines of the `230` reply.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let mut server = Server::new_with_fs_root("/tmp").login_message("Welcome back!\nMaintenance is planned for Sunday.");
+    /// ```
+    pub fn login_message<T: Into<String>>(mut self, message: T) -> Self {
+        self.server.login_message = Some(message.into());
+        self
+    }
+
+    /// Read the message of the day that is shown to the client after logging in from a file.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").login_message_file("/etc/motd");
+    /// ```
+    pub fn login_message_file<P: AsRef<Path>>(self, path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let message = std::fs::read_to_string(path)?;
+        Ok(self.login_message(message))
+    }
+
+    /// Set the [`ReplyCatalog`] used to replace the text of the messages sent to clients.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{ReplyCatalog, Server};
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").reply_catalog(ReplyCatalog::new().message("Bye!", "Tot ziens!"));
+    /// ```
+    ///
+    /// [`ReplyCatalog`]: struct.ReplyCatalog.html
+    pub fn reply_catalog(mut self, catalog: ReplyCatalog) -> Self {
+        self.server.reply_catalog = Arc::new(catalog);
+        self
+    }
+
+    /// Set the [`ReplyFilter`] that can rewrite every reply before it is sent to the client.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{Reply, ReplyFilter, Server};
+    ///
+    /// struct Shout;
+    ///
+    /// impl ReplyFilter for Shout {
+    ///     fn filter(&self, _command: Option<&str>, reply: Reply) -> Reply {
+    ///         match reply {
+    ///             Reply::CodeAndMsg { code, msg } => Reply::new_with_string(code, msg.to_uppercase()),
+    ///             reply => reply,
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").reply_filter(Shout);
+    /// ```
+    ///
+    /// [`ReplyFilter`]: trait.ReplyFilter.html
+    pub fn reply_filter<F: ReplyFilter + 'static>(mut self, filter: F) -> Self {
+        self.server.reply_filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Set the [`ListFormatter`] that formats the entries of directory listings, for instance
+    /// [`DosListFormatter`] for clients that expect the listings of Windows servers. By default
+    /// entries are formatted like `ls -l` does.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{DosListFormatter, Server};
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").list_formatter(DosListFormatter);
+    /// ```
+    ///
+    /// [`ListFormatter`]: trait.ListFormatter.html
+    /// [`DosListFormatter`]: struct.DosListFormatter.html
+    pub fn list_formatter<F: ListFormatter + 'static>(mut self, formatter: F) -> Self {
+        self.server.list_formatter = Arc::new(formatter);
+        self
+    }
+
+    /// Set the [`PathMapper`] that translates the paths that clients see into the paths that the
+    /// storage backend gets, for instance to give every user their own directory.
+    ///
+    /// # Example
+    ///
+    /// See [`PathMapper`].
+    ///
+    /// [`PathMapper`]: trait.PathMapper.html
+    pub fn path_mapper<M: PathMapper<U> + 'static>(mut self, mapper: M) -> Self {
+        self.server.path_mapper = Some(Arc::new(mapper));
+        self
+    }
+
+    /// Set a function that generates the greeting sent to the client after connecting. The
+    /// function receives the address of the client and the local address the client connected to.
+    /// In PROXY protocol mode these are the addresses from the PROXY header.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let mut server = Server::new_with_fs_root("/tmp").greeting_fn(|peer, local| {
+    ///     format!("Welcome {}, you're connected to {}", peer.ip(), local)
+    /// });
+    /// ```
+    pub fn greeting_fn<F>(self, f: F) -> Self
+    where
+        F: Fn(SocketAddr, SocketAddr) -> String + Send + Sync + 'static,
+    {
+        self.server.settings.write().unwrap().greeting = Greeting::Generated(Box::new(f));
         self
     }
 
@@ -184,7 +593,7 @@ where
     ///
     /// [`Authenticator`]: ../auth/trait.Authenticator.html
     pub fn authenticator(mut self, authenticator: Arc<dyn Authenticator<U> + Send + Sync>) -> Self {
-        self.authenticator = authenticator;
+        self.server.authenticator = authenticator;
         self
     }
 
@@ -204,7 +613,7 @@ where
     /// server.passive_ports(49152..65535);
     /// ```
     pub fn passive_ports(mut self, range: Range<u16>) -> Self {
-        self.passive_ports = range;
+        self.server.passive_ports = range;
         self
     }
 
@@ -218,9 +627,132 @@ where
     ///
     /// let mut server = Server::new_with_fs_root("/tmp").ftps("/srv/unftp/server-certs.pfx", "thepassword");
     /// ```
+    #[cfg(feature = "ftps")]
     pub fn ftps<P: Into<PathBuf>, T: Into<String>>(mut self, certs_file: P, password: T) -> Self {
-        self.certs_file = Option::Some(certs_file.into());
-        self.certs_password = Option::Some(password.into());
+        self.server.certs_file = Option::Some(certs_file.into());
+        self.server.certs_password = Option::Some(password.into());
+        self
+    }
+
+    /// Refuse `PROT C` with a `534` reply in sessions that protected their data connections with
+    /// `PROT P`, so that transfers stay encrypted once a client asked for that. By default
+    /// clients may switch back and forth, and each data connection is protected or not as was
+    /// set when it was opened.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp")
+    ///     .ftps("/srv/unftp/server-certs.pfx", "thepassword")
+    ///     .ftps_forbid_data_downgrade();
+    /// ```
+    #[cfg(feature = "ftps")]
+    pub fn ftps_forbid_data_downgrade(mut self) -> Self {
+        self.server.data_downgrade_forbidden = true;
+        self
+    }
+
+    /// Also listen on the given address for implicit FTPS, where clients start TLS as soon as they
+    /// connect instead of asking for it with `AUTH TLS`. It is usually port 990. The address is
+    /// bound by [`listen`] and [`listen_all`] next to the addresses they get, and the certificate
+    /// set with [`ftps`] is used for it. Implicit FTPS is not available in PROXY protocol mode.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp")
+    ///     .ftps("/srv/unftp/server-certs.pfx", "thepassword")
+    ///     .ftps_implicit("0.0.0.0:990");
+    /// ```
+    ///
+    /// [`listen`]: struct.Server.html#method.listen
+    /// [`listen_all`]: struct.Server.html#method.listen_all
+    /// [`ftps`]: #method.ftps
+    #[cfg(feature = "ftps")]
+    pub fn ftps_implicit<T: Into<String>>(mut self, bind_address: T) -> Self {
+        self.server.ftps_implicit_addresses.push(bind_address.into());
+        self
+    }
+
+    /// Set the mechanisms that clients may ask for with `AUTH`, which `FEAT` advertises too.
+    /// libunftp knows `TLS` and `SSL`, and answers both with TLS since SSL is insecure; clients
+    /// that still send `AUTH SSL` expect that. Other mechanisms are left out. `AUTH` with a
+    /// mechanism that isn't accepted gets a `504` reply. By default both are accepted.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp")
+    ///     .ftps("/srv/unftp/server-certs.pfx", "thepassword")
+    ///     .ftps_auth_mechanisms(&["TLS"]);
+    /// ```
+    #[cfg(feature = "ftps")]
+    pub fn ftps_auth_mechanisms<I, T>(mut self, mechanisms: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let mechanisms = mechanisms
+            .into_iter()
+            .map(|mechanism| mechanism.as_ref().trim().to_uppercase())
+            .filter(|mechanism| commands::DEFAULT_AUTH_MECHANISMS.contains(&mechanism.as_str()));
+        self.server.auth_mechanisms = Arc::new(mechanisms.collect());
+        self
+    }
+
+    /// Authenticate clients with GSSAPI, for instance Kerberos, as RFC 2228 describes. The given
+    /// function makes the [`GssapiContext`] for every `AUTH GSSAPI` of a client, after which the
+    /// client exchanges security data with `ADAT` and may protect its commands with `MIC` or
+    /// `ENC`. `FEAT` advertises `AUTH GSSAPI` then. Once the context is established, clients can
+    /// only log in as the principal that it authenticated, as told by [`GssapiContext::principal`].
+    /// They do so with `USER` alone, replied to with 232, when the authenticator accepts the
+    /// principal through [`Authenticator::authenticate_principal`], and with `USER` and `PASS`
+    /// otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{GssapiContext, GssapiResult, Server};
+    ///
+    /// // Hands the tokens to the GSSAPI implementation of the platform, which is left out here.
+    /// struct Kerberos;
+    ///
+    /// impl GssapiContext for Kerberos {
+    ///     fn step(&mut self, _token: &[u8]) -> GssapiResult<Option<Vec<u8>>> {
+    ///         Err("Kerberos is not available".into())
+    ///     }
+    ///     fn is_complete(&self) -> bool {
+    ///         false
+    ///     }
+    ///     fn principal(&self) -> Option<String> {
+    ///         None
+    ///     }
+    ///     fn unwrap(&mut self, _message: &[u8]) -> GssapiResult<Vec<u8>> {
+    ///         Err("No security context".into())
+    ///     }
+    ///     fn wrap(&mut self, _encrypt: bool, _message: &[u8]) -> GssapiResult<Vec<u8>> {
+    ///         Err("No security context".into())
+    ///     }
+    /// }
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").gssapi(|| Box::new(Kerberos));
+    /// ```
+    ///
+    /// [`GssapiContext`]: trait.GssapiContext.html
+    /// [`GssapiContext::principal`]: trait.GssapiContext.html#tymethod.principal
+    /// [`Authenticator::authenticate_principal`]: auth/trait.Authenticator.html#method.authenticate_principal
+    #[cfg(feature = "gssapi")]
+    pub fn gssapi<F>(mut self, new_context: F) -> Self
+    where
+        F: Fn() -> Box<dyn GssapiContext> + Send + Sync + 'static,
+    {
+        self.server.gssapi = Some(Arc::new(new_context));
         self
     }
 
@@ -238,68 +770,1138 @@ where
     /// let mut server = Server::new_with_fs_root("/tmp");
     /// server.metrics();
     /// ```
+    #[cfg(feature = "metrics")]
     pub fn metrics(mut self) -> Self {
-        self.collect_metrics = true;
+        self.server.metrics = Some(Metrics::default_registry());
+        self
+    }
+
+    /// Enable the collection of prometheus metrics and register them in the given registry instead
+    /// of the default one. The names of the metrics get the given prefix, unless it is empty, and
+    /// the constant labels are added to all of them. This fails if the metrics are registered in
+    /// the registry already.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use std::collections::HashMap;
+    ///
+    /// let registry = prometheus::Registry::new();
+    /// let mut labels = HashMap::new();
+    /// labels.insert("instance".to_string(), "ftp1".to_string());
+    /// let server = Server::new_with_fs_root("/tmp").metrics_registry(&registry, "myapp", labels).unwrap();
+    /// ```
+    #[cfg(feature = "metrics")]
+    pub fn metrics_registry(
+        mut self,
+        registry: &prometheus::Registry,
+        prefix: &str,
+        const_labels: HashMap<String, String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        self.server.metrics = Some(Arc::new(Metrics::new(registry, prefix, const_labels)?));
+        Ok(self)
+    }
+
+    /// Set the idle session timeout in seconds. The default is 600 seconds. Time spent on data
+    /// transfers doesn't count, those are covered by the [stalled transfer timeout] instead.
+    ///
+    /// [stalled transfer timeout]: #method.stalled_transfer_timeout
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// // Use it in a builder-like pattern:
+    /// let mut server = Server::new_with_fs_root("/tmp").idle_session_timeout(600);
+    ///
+    /// // Or instead if you prefer:
+    /// let mut server = Server::new_with_fs_root("/tmp");
+    /// server.idle_session_timeout(600);
+    /// ```
+    pub fn idle_session_timeout(self, secs: u64) -> Self {
+        self.server.settings.write().unwrap().idle_session_timeout = Duration::from_secs(secs);
+        self
+    }
+
+    /// Set the stalled transfer timeout in seconds. A data transfer (`RETR` or `STOR`) is aborted
+    /// with a `426` reply when no bytes moved over the data connection for this long. The default
+    /// is 300 seconds. A timeout of 0 disables it, so that transfers are never aborted for
+    /// stalling.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// // Use it in a builder-like pattern:
+    /// let mut server = Server::new_with_fs_root("/tmp").stalled_transfer_timeout(60);
+    /// ```
+    pub fn stalled_transfer_timeout(mut self, secs: u64) -> Self {
+        self.server.stalled_transfer_timeout = Duration::from_secs(secs);
+        self
+    }
+
+    /// Spool uploads to temporary files in the given directory and hand them to the storage
+    /// backend once the client has sent them completely. This suits backends that need to know
+    /// the size of a file up front, like object stores, and allows retrying failed writes to the
+    /// backend without involving the client. By default uploads are streamed to the backend.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").spool_uploads("/var/spool/ftp");
+    /// ```
+    pub fn spool_uploads<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.server.upload_spool_dir = Some(dir.into());
+        self
+    }
+
+    /// Set how many times writing a spooled upload to the storage backend is retried when it
+    /// fails with a local or transient error. Only applies when uploads are spooled with
+    /// [`spool_uploads`]. The default is 2.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").spool_uploads("/var/spool/ftp").spooled_upload_retries(5);
+    /// ```
+    ///
+    /// [`spool_uploads`]: #method.spool_uploads
+    pub fn spooled_upload_retries(mut self, retries: u32) -> Self {
+        self.server.spooled_upload_retries = retries;
+        self
+    }
+
+    /// Set the TCP options of the sockets of control connections. By default the options of the
+    /// operating system are kept.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{Server, SocketOptions};
+    /// use std::time::Duration;
+    ///
+    /// let options = SocketOptions::default().nodelay(true).keepalive(Duration::from_secs(60));
+    /// let server = Server::new_with_fs_root("/tmp").control_socket_options(options);
+    /// ```
+    pub fn control_socket_options(mut self, options: SocketOptions) -> Self {
+        self.server.control_socket_options = options;
+        self
+    }
+
+    /// Set the TCP options of the sockets of data connections. By default the options of the
+    /// operating system are kept.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{Server, SocketOptions};
+    ///
+    /// let options = SocketOptions::default().send_buffer_size(1024 * 1024).recv_buffer_size(1024 * 1024);
+    /// let server = Server::new_with_fs_root("/tmp").data_socket_options(options);
+    /// ```
+    pub fn data_socket_options(mut self, options: SocketOptions) -> Self {
+        self.server.data_socket_options = options;
         self
     }
 
-    /// Set the idle session timeout in seconds. The default is 600 seconds.
+    /// Set the local address that passive data connections are accepted on, for hosts with
+    /// several network interfaces where data traffic must go through a specific one. `PASV`
+    /// replies advertise this address, unless it is unspecified (`0.0.0.0`), which accepts data
+    /// connections on all interfaces. Clients that use `EPSV` connect to the address of the
+    /// control connection, so it must reach this address as well. By default data connections
+    /// are accepted on the address the client connected the control connection to. This has no
+    /// effect in proxy protocol mode.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").data_bind_address("10.0.1.5".parse().unwrap());
+    /// ```
+    pub fn data_bind_address(mut self, ip: IpAddr) -> Self {
+        self.server.data_bind_ip = Some(ip);
+        self
+    }
+
+    /// Set how many internal messages, like the outcome of a transfer or of a storage call, can be
+    /// queued for a session before their senders have to wait for the control channel to catch up.
+    /// The default is 16.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").control_message_channel_capacity(64);
+    /// ```
+    pub fn control_message_channel_capacity(mut self, capacity: usize) -> Self {
+        self.server.control_msg_channel_capacity = capacity;
+        self
+    }
+
+    /// Set the maximum length, in bytes, of a line on the control connection. A client that
+    /// sends a longer line gets a `500` reply and is disconnected, so that it cannot make the
+    /// server buffer an endless command. The default is 8 KiB.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").max_command_length(16 * 1024);
+    /// ```
+    pub fn max_command_length(mut self, length: usize) -> Self {
+        self.server.max_command_length = length;
+        self
+    }
+
+    /// Set how long, in seconds, a call to the storage backend may take, for instance to look up
+    /// the metadata of a file or to open it. Calls that take longer fail with a `451` reply, so
+    /// that a backend that hangs doesn't hang the sessions that use it. Transfers themselves are
+    /// bounded by the [`stalled_transfer_timeout`] instead. By default calls are not limited.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").storage_timeout(30);
+    /// ```
+    ///
+    /// [`stalled_transfer_timeout`]: #method.stalled_transfer_timeout
+    pub fn storage_timeout(mut self, secs: u64) -> Self {
+        self.server.storage_timeout = Some(Duration::from_secs(secs));
+        self
+    }
+
+    /// Set for how many seconds a session remembers the metadata of the files that `SIZE`, `MDTM`
+    /// and `MLST` looked up, so that clients that ask for the size and modification time of the
+    /// same files in a row don't make the storage backend look them up again. Each session
+    /// forgets what it remembered when its client changes something in storage, but changes
+    /// made by others show up only once the time passed. By default nothing is remembered.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").metadata_cache_ttl(5);
+    /// ```
+    pub fn metadata_cache_ttl(mut self, secs: u64) -> Self {
+        self.server.metadata_cache_ttl = Some(Duration::from_secs(secs));
+        self
+    }
+
+    /// Set the maximum number of file transfers (`RETR` and `STOR`) that may run at the same time
+    /// over all sessions, so that many parallel clients can't exhaust file descriptors or
+    /// connections to the storage backend. Transfers beyond the limit wait for a running one to
+    /// end, for up to the [`transfer_queue_timeout`], and then get a `450` reply. By default there
+    /// is no limit. A limit of 0 is refused by [`build`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").max_concurrent_transfers(100);
+    /// ```
+    ///
+    /// [`transfer_queue_timeout`]: #method.transfer_queue_timeout
+    /// [`build`]: #method.build
+    pub fn max_concurrent_transfers(mut self, limit: usize) -> Self {
+        self.server.transfer_permits = Some(Arc::new(tokio::sync::Semaphore::new(limit)));
+        self
+    }
+
+    /// Set how many seconds a transfer waits to start when the [`max_concurrent_transfers`] are
+    /// running before it is refused. The default is 5 seconds.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").max_concurrent_transfers(100).transfer_queue_timeout(10);
+    /// ```
+    ///
+    /// [`max_concurrent_transfers`]: #method.max_concurrent_transfers
+    pub fn transfer_queue_timeout(mut self, secs: u64) -> Self {
+        self.server.transfer_queue_timeout = Duration::from_secs(secs);
+        self
+    }
+
+    /// Set the maximum number of concurrent control connections. When the limit is reached new
+    /// connections get a `421` reply and are closed immediately. By default there is no limit.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// // Use it in a builder-like pattern:
+    /// let mut server = Server::new_with_fs_root("/tmp").max_connections(100);
+    /// ```
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.server.max_connections = Some(max);
+        self
+    }
+
+    /// Limit the combined bandwidth of all data transfers (uploads, downloads and directory
+    /// listings) of the server to the given number of bytes per second. There is no limit by
+    /// default.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// // Cap data transfers to 200 Mbit/s
+    /// let mut server = Server::new_with_fs_root("/tmp").bandwidth_limit(25_000_000);
+    /// ```
+    pub fn bandwidth_limit(mut self, bytes_per_second: u64) -> Self {
+        self.server.bandwidth_limiter = Some(Arc::new(RateLimiter::new(bytes_per_second)));
+        self
+    }
+
+    /// Limit the upload bandwidth of every session to the given number of bytes per second. This
+    /// default can be overridden per user through [`UserDetail::upload_bandwidth_limit`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let mut server = Server::new_with_fs_root("/tmp").upload_bandwidth_limit(1_000_000);
+    /// ```
+    ///
+    /// [`UserDetail::upload_bandwidth_limit`]: ../auth/trait.UserDetail.html#method.upload_bandwidth_limit
+    pub fn upload_bandwidth_limit(mut self, bytes_per_second: u64) -> Self {
+        self.server.upload_bandwidth_limit = Some(bytes_per_second);
+        self
+    }
+
+    /// Limit the download bandwidth of every session to the given number of bytes per second.
+    /// This default can be overridden per user through [`UserDetail::download_bandwidth_limit`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let mut server = Server::new_with_fs_root("/tmp").download_bandwidth_limit(1_000_000);
+    /// ```
+    ///
+    /// [`UserDetail::download_bandwidth_limit`]: ../auth/trait.UserDetail.html#method.download_bandwidth_limit
+    pub fn download_bandwidth_limit(mut self, bytes_per_second: u64) -> Self {
+        self.server.download_bandwidth_limit = Some(bytes_per_second);
+        self
+    }
+
+    /// Only accept control connections from the given networks. Networks are given in CIDR
+    /// notation (e.g. `10.0.0.0/8` or `2001:db8::/32`) or as single IP addresses. Connections
+    /// from other addresses get a `421` reply and are closed before the greeting is sent.
+    ///
+    /// In PROXY protocol mode the client address from the PROXY header is checked.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let mut server = Server::new_with_fs_root("/tmp").allow_ips(vec!["10.0.0.0/8", "192.168.1.1"]).unwrap();
+    /// ```
+    pub fn allow_ips<I, T>(self, networks: I) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        self.server.settings.write().unwrap().ip_filter.allow(networks)?;
+        Ok(self)
+    }
+
+    /// Refuse control connections from the given networks. Networks are given in the same way
+    /// as for [`allow_ips`]. The deny list takes precedence over the allow list.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let mut server = Server::new_with_fs_root("/tmp").deny_ips(vec!["10.0.0.0/24"]).unwrap();
+    /// ```
+    ///
+    /// [`allow_ips`]: #method.allow_ips
+    pub fn deny_ips<I, T>(self, networks: I) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        self.server.settings.write().unwrap().ip_filter.deny(networks)?;
+        Ok(self)
+    }
+
+    /// Enable PROXY protocol mode.
+    ///
+    /// If you use a proxy such as haproxy or nginx, you can enable
+    /// the PROXY protocol
+    /// (https://www.haproxy.org/download/1.8/doc/proxy-protocol.txt).
+    ///
+    /// Configure your proxy to enable PROXY protocol encoding for
+    /// control and data external listening ports, forwarding these
+    /// connections to the libunFTP listening port in proxy protocol
+    /// mode.
+    ///
+    /// In PROXY protocol mode, libunftp receives both control and
+    /// data connections on the listening port. It then distinguishes
+    /// control and data connections by comparing the original
+    /// destination port (extracted from the PROXY header) with the
+    /// port specified as the `external_control_port`
+    /// `proxy_protocol_mode` parameter.
+    ///
+    /// For the passive listening port, libunftp reports the IP
+    /// address specified as the `external_ip` `proxy_protocol_mode`
+    /// parameter.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// // Use it in a builder-like pattern:
+    /// let mut server = Server::new_with_fs_root("/tmp").proxy_protocol_mode("10.0.0.1", 2121).unwrap();
+    /// ```
+    #[cfg(feature = "proxy_protocol")]
+    pub fn proxy_protocol_mode(mut self, external_ip: &str, external_control_port: u16) -> Result<Self, Box<dyn std::error::Error>> {
+        self.server.settings.write().unwrap().passive_external_ip = Some(external_ip.parse()?);
+        self.server.proxy_protocol_mode = Some(ProxyParams { external_control_port });
+
+        Ok(self)
+    }
+
+    /// Set the time in seconds that a passive port stays reserved for a session in proxy protocol
+    /// mode while the client hasn't connected to it yet. After that the port becomes available to
+    /// other sessions again, so that clients that abandon a `PASV` don't use up the passive port
+    /// range. The default is 60 seconds.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp")
+    ///     .proxy_protocol_mode("10.0.0.1", 2121)
+    ///     .unwrap()
+    ///     .proxy_protocol_reservation_ttl(30);
+    /// ```
+    #[cfg(feature = "proxy_protocol")]
+    pub fn proxy_protocol_reservation_ttl(mut self, secs: u64) -> Self {
+        self.server.proxy_protocol_reservation_ttl = Duration::from_secs(secs);
+        self
+    }
+
+    /// Only accept connections from the given proxies in proxy protocol mode. Networks are given
+    /// in the same way as for [`allow_ips`]. Connections from other addresses are closed before
+    /// their PROXY header is read, so that clients that bypass the proxy cannot pretend to come
+    /// from an address of their choosing. By default connections from any address are accepted.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp")
+    ///     .proxy_protocol_mode("10.0.0.1", 2121)
+    ///     .unwrap()
+    ///     .proxy_protocol_trusted_proxies(vec!["10.1.0.0/16"])
+    ///     .unwrap();
+    /// ```
+    ///
+    /// [`allow_ips`]: #method.allow_ips
+    #[cfg(feature = "proxy_protocol")]
+    pub fn proxy_protocol_trusted_proxies<I, T>(mut self, networks: I) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        self.server.trusted_proxies.allow(networks)?;
+        Ok(self)
+    }
+
+    /// Set the time in seconds that a shutdown initiated through the [`ServerHandle`] waits for
+    /// active sessions to end. The default is 10 seconds.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// // Use it in a builder-like pattern:
+    /// let mut server = Server::new_with_fs_root("/tmp").shutdown_grace_period(30);
+    /// ```
+    ///
+    /// [`ServerHandle`]: struct.ServerHandle.html
+    pub fn shutdown_grace_period(mut self, secs: u64) -> Self {
+        self.server.shutdown_grace_period = Duration::from_secs(secs);
+        self
+    }
+
+    /// Set the [`Logger`] that receives the log records of the server and its sessions. Session
+    /// records carry the ID of the session as `session` and the address of the client as `peer` so
+    /// that embedders can route them as they see fit. By default records are forwarded to the
+    /// [`log`] crate.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let logger = slog::Logger::root(slog::Discard, slog::o!("tenant" => "acme"));
+    /// let server = Server::new_with_fs_root("/tmp").logger(logger);
+    /// ```
+    ///
+    /// [`Logger`]: https://docs.rs/slog/2/slog/struct.Logger.html
+    /// [`log`]: https://docs.rs/log
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.server.logger = logger;
+        self
+    }
+
+    /// Include the ID of the session in the reply to the `STAT` command so that users can pass it
+    /// on when asking for support. The same ID is attached to the log records of the session.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").reveal_session_id();
+    /// ```
+    pub fn reveal_session_id(mut self) -> Self {
+        self.server.reveal_session_id = true;
+        self
+    }
+
+    /// Set the reply to the `SYST` command, `UNIX Type: L8` by default whatever the platform the
+    /// server runs on. Clients use it to guess the format of directory listings.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").system_type("Windows_NT");
+    /// ```
+    pub fn system_type<T: Into<String>>(mut self, system_type: T) -> Self {
+        self.server.system_type = Arc::new(system_type.into());
+        self
+    }
+
+    /// Set the line that identifies the server software in the replies to `STAT` and `HELP`,
+    /// `Powered by libunftp` by default. An empty line leaves it out, for when security policies
+    /// forbid disclosing the software in use.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").identification("");
+    /// ```
+    pub fn identification<T: Into<String>>(mut self, identification: T) -> Self {
+        self.server.identification = Arc::new(identification.into());
+        self
+    }
+
+    /// Write a line in the xferlog format of wu-ftpd and vsftpd to the given sink after every file
+    /// transfer, whether it completed or not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").xferlog(std::io::stdout());
+    /// ```
+    pub fn xferlog<W: std::io::Write + Send + 'static>(mut self, sink: W) -> Self {
+        self.server.xferlog = Some(Xferlog::new(Box::new(sink)));
+        self
+    }
+
+    /// Like [`xferlog`], but append the lines to the file at the given path, creating it if needed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").xferlog_file(std::env::temp_dir().join("xferlog")).unwrap();
+    /// ```
+    ///
+    /// [`xferlog`]: #method.xferlog
+    pub fn xferlog_file<P: AsRef<Path>>(self, path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(self.xferlog(file))
+    }
+
+    /// Record every command the clients send, together with the code of the reply, in the given
+    /// audit log sink. Passwords are redacted. See the [`audit`] module for the available sinks.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::audit::JsonLinesSink;
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").audit_sink(JsonLinesSink::new(std::io::stdout()));
+    /// ```
+    ///
+    /// [`audit`]: audit/index.html
+    pub fn audit_sink<A: AuditSink + 'static>(mut self, sink: A) -> Self {
+        self.server.audit_sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Tell the given observer when clients connect, log in, log out and disconnect. The observer
+    /// can refuse sessions when they connect. See [`SessionObserver`] for the callbacks.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{Server, SessionInfo, SessionObserver};
+    ///
+    /// struct Presence;
+    ///
+    /// impl SessionObserver for Presence {
+    ///     fn on_login(&self, session: &SessionInfo) {
+    ///         println!("{} is online", session.username.as_deref().unwrap_or_default());
+    ///     }
+    /// }
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").session_observer(Presence);
+    /// ```
+    ///
+    /// [`SessionObserver`]: trait.SessionObserver.html
+    pub fn session_observer<O: SessionObserver + 'static>(mut self, observer: O) -> Self {
+        self.server.session_observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Send an event to the given listener whenever a client completes an upload or download,
+    /// deletes or renames a file, or creates or removes a directory. See the [`notification`]
+    /// module for the events.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::notification::FileEvent;
+    /// use libunftp::Server;
+    ///
+    /// let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<FileEvent>();
+    /// let server = Server::new_with_fs_root("/tmp").notify(tx);
+    /// ```
+    ///
+    /// [`notification`]: notification/index.html
+    pub fn notify<L: FileEventListener + 'static>(mut self, listener: L) -> Self {
+        self.server.file_event_listener = Some(Arc::new(listener));
+        self
+    }
+
+    /// Add a [`Middleware`] that every command of the clients passes through before it is
+    /// handled. Middleware runs in the order in which it was added.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use async_trait::async_trait;
+    /// use libunftp::{ControlChanError, Middleware, Next, Reply, Request, Server};
+    ///
+    /// struct PrintCommands;
+    ///
+    /// #[async_trait]
+    /// impl Middleware for PrintCommands {
+    ///     async fn handle(&self, request: Request, next: Next<'_>) -> Result<Reply, ControlChanError> {
+    ///         println!("{} sent {}", request.peer_ip, request);
+    ///         next.run(request).await
+    ///     }
+    /// }
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").add_middleware(PrintCommands);
+    /// ```
+    ///
+    /// [`Middleware`]: trait.Middleware.html
+    pub fn add_middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.server.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Handle the commands with the given verb, which the server doesn't know itself, with the
+    /// given handler. Without a handler such commands are refused with a `500` reply. Verbs that
+    /// the server knows cannot be taken over.
+    ///
+    /// See [`CustomCommandHandler`] for an example.
+    ///
+    /// [`CustomCommandHandler`]: trait.CustomCommandHandler.html
+    pub fn custom_command<H: CustomCommandHandler<S, U> + 'static>(mut self, verb: &str, handler: H) -> Self {
+        self.server.custom_commands.insert(verb.to_uppercase(), Arc::new(handler));
+        self
+    }
+
+    /// Handle the `SITE` subcommand with the given name with the given handler, which gets the
+    /// name of the subcommand in upper case as the verb. Subcommands added this way are listed by
+    /// `SITE HELP` and take precedence over those of the server.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use async_trait::async_trait;
+    /// use libunftp::auth::DefaultUser;
+    /// use libunftp::storage::filesystem::Filesystem;
+    /// use libunftp::{CommandContext, ControlChanError, CustomCommandHandler, Reply, ReplyCode, Server};
+    ///
+    /// // Answers `SITE WHO` with the name of the user.
+    /// struct Who;
+    ///
+    /// #[async_trait]
+    /// impl CustomCommandHandler<Filesystem, DefaultUser> for Who {
+    ///     async fn handle(&self, _verb: &str, _argument: &str, context: CommandContext<Filesystem, DefaultUser>) -> Result<Reply, ControlChanError> {
+    ///         let username = context.username().await.unwrap_or_default();
+    ///         Ok(Reply::new_with_string(ReplyCode::CommandOkay, username))
+    ///     }
+    /// }
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").site_command("WHO", Who);
+    /// ```
+    pub fn site_command<H: CustomCommandHandler<S, U> + 'static>(mut self, name: &str, handler: H) -> Self {
+        self.server.site_commands.insert(name.to_uppercase(), Arc::new(handler));
+        self
+    }
+
+    /// Run the server as a drop box: clients can upload files and create directories, but cannot
+    /// download, overwrite, delete or rename anything and see empty directory listings. Uploads to
+    /// paths where a file exists already get a `553` reply. This lets others deliver files without
+    /// seeing or changing what was delivered before.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/srv/incoming").drop_box();
+    /// ```
+    pub fn drop_box(mut self) -> Self {
+        self.server.drop_box = true;
+        self
+    }
+
+    /// Refuse uploads to paths where a file exists already with a `553` reply, so that clients
+    /// sharing a directory cannot overwrite each other's files by accident. Resuming an upload
+    /// with `REST` is refused with a `550` reply, as it writes into an existing file. Users can
+    /// be exempted through [`UserDetail::no_clobber`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/srv/shared").no_clobber();
+    /// ```
+    ///
+    /// [`UserDetail::no_clobber`]: auth/trait.UserDetail.html#method.no_clobber
+    pub fn no_clobber(mut self) -> Self {
+        self.server.no_clobber = true;
+        self
+    }
+
+    /// Set what happens to the file of an upload that the client aborted with `ABOR`, that
+    /// stalled, that broke off because of the connection to the client or whose session was
+    /// terminated. By default the partial file is kept. See [`PartialUploads`] for the options.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{PartialUploads, Server};
+    ///
+    /// let server = Server::new_with_fs_root("/srv/incoming").partial_uploads(PartialUploads::Delete);
+    /// ```
+    ///
+    /// [`PartialUploads`]: enum.PartialUploads.html
+    pub fn partial_uploads(mut self, policy: PartialUploads) -> Self {
+        self.server.partial_uploads = policy;
+        self
+    }
+
+    /// Set the [`FilenamePolicy`] that decides which names uploaded files, new directories and
+    /// renamed files may get.
+    ///
+    /// # Example
+    ///
+    /// See [`FilenamePolicy`].
+    ///
+    /// [`FilenamePolicy`]: trait.FilenamePolicy.html
+    pub fn filename_policy<P: FilenamePolicy + 'static>(mut self, policy: P) -> Self {
+        self.server.filename_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Hide files and directories whose names start with a dot, like `.trash`, from clients. They
+    /// are left out of directory listings and cannot be accessed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").hide_dotfiles();
+    /// ```
+    pub fn hide_dotfiles(mut self) -> Self {
+        self.server.hidden_paths = self.server.hidden_paths.dotfiles();
+        self
+    }
+
+    /// Hide the paths that match the given glob patterns from clients, like [`hide_dotfiles`]
+    /// does. Patterns without a slash match names anywhere, others match paths from the root. A
+    /// `*` matches any part of a name, `**` any part of a path, `?` a single character and
+    /// `[...]` one of the characters in the brackets, like `[a-z]` or `[!0-9]`. With patterns
+    /// that match paths from the root, `SITE SYMLINK` only creates links to files, since a link to
+    /// a directory would show what is below it under another path.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").hide_paths(&["*.partial", "/internal"]);
+    /// ```
+    ///
+    /// [`hide_dotfiles`]: #method.hide_dotfiles
+    pub fn hide_paths<I, T>(mut self, globs: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        self.server.hidden_paths = self.server.hidden_paths.patterns(globs);
+        self
+    }
+
+    /// Refuse the commands with the given verbs with a `502` reply, for instance to make sure
+    /// files cannot be deleted. The commands are left out of the replies to `FEAT` and `HELP` too.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").disable_commands(&["DELE", "RMD", "RNFR", "RNTO"]);
+    /// ```
+    pub fn disable_commands<I, T>(mut self, verbs: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        self.server.disabled_commands = Arc::new(verbs.into_iter().map(|verb| verb.as_ref().trim().to_uppercase()).collect());
+        self
+    }
+
+    /// Serve the prometheus metrics on `/metrics` and health checks on `/health` and
+    /// `/health/live` over HTTP on the given address, next to the FTP listeners. The metrics are
+    /// only available when enabled with [`metrics`] or [`metrics_registry`]. The health checks
+    /// reply `503 Service Unavailable` when the server is not ready, respectively not alive, as
+    /// described by [`HealthStatus`]. Use them for readiness and liveness probes.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let server = Server::new_with_fs_root("/tmp").http_endpoint("127.0.0.1:9090");
+    /// ```
+    ///
+    /// [`build`] fails when the address is not a valid socket address. When the endpoint can't
+    /// bind to it, the error is logged and the server doesn't start.
+    ///
+    /// [`build`]: #method.build
+    /// [`metrics`]: #method.metrics
+    /// [`metrics_registry`]: #method.metrics_registry
+    /// [`HealthStatus`]: struct.HealthStatus.html
+    #[cfg(feature = "http_endpoint")]
+    pub fn http_endpoint<T: Into<String>>(mut self, bind_address: T) -> Self {
+        self.server.http_endpoint = Some(bind_address.into());
+        self
+    }
+
+    /// Set the check that tells if the storage backend, or anything else the server depends on,
+    /// can be reached. Its outcome is part of the [`HealthStatus`] returned by
+    /// [`ServerHandle::health`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use async_trait::async_trait;
+    /// use libunftp::{HealthCheck, Server};
+    ///
+    /// struct RootExists;
+    ///
+    /// #[async_trait]
+    /// impl HealthCheck for RootExists {
+    ///     async fn check(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    ///         tokio::fs::metadata("/srv/ftp").await?;
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let server = Server::new_with_fs_root("/srv/ftp").health_check(RootExists);
+    /// ```
+    ///
+    /// [`HealthStatus`]: struct.HealthStatus.html
+    /// [`ServerHandle::health`]: struct.ServerHandle.html#method.health
+    pub fn health_check<C: HealthCheck + 'static>(self, check: C) -> Self {
+        self.server.health.set_check(Arc::new(check));
+        self
+    }
+
+    /// Apply the settings of the given [`ServerConfig`], leaving out the storage backend and the
+    /// address to listen on. Settings that aren't in the configuration keep their current value.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::config::ServerConfig;
+    /// use libunftp::Server;
+    ///
+    /// let config = ServerConfig::from_toml(r#"
+    ///     max_connections = 100
+    ///     storage = { backend = "filesystem", root = "/srv/ftp" }
+    /// "#).unwrap();
+    /// let server = Server::new_with_fs_root("/srv/ftp").with_config(&config).unwrap();
+    /// ```
+    ///
+    /// [`ServerConfig`]: config/struct.ServerConfig.html
+    #[cfg(feature = "config")]
+    pub fn with_config(mut self, config: &ServerConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Some(greeting) = &config.greeting {
+            self = self.greeting(greeting.as_str());
+        }
+        if let Some(message) = &config.login_message {
+            self = self.login_message(message.as_str());
+        }
+        if let Some(system_type) = &config.system_type {
+            self = self.system_type(system_type.as_str());
+        }
+        if let Some(identification) = &config.identification {
+            self = self.identification(identification.as_str());
+        }
+        if let Some(range) = &config.passive_ports {
+            self = self.passive_ports(range.clone());
+        }
+        #[cfg(feature = "ftps")]
+        {
+            if let Some(ftps) = &config.ftps {
+                self = self.ftps(ftps.certs_file.clone(), ftps.certs_password.as_str());
+                if !ftps.auth_mechanisms.is_empty() {
+                    self = self.ftps_auth_mechanisms(&ftps.auth_mechanisms);
+                }
+            }
+        }
+        #[cfg(not(feature = "ftps"))]
+        {
+            if config.ftps.is_some() {
+                return Err(ConfigError::FeatureDisabled("ftps").into());
+            }
+        }
+        #[cfg(feature = "proxy_protocol")]
+        {
+            if let Some(proxy) = &config.proxy_protocol {
+                self = self.proxy_protocol_mode(&proxy.external_ip, proxy.external_control_port)?;
+            }
+        }
+        #[cfg(not(feature = "proxy_protocol"))]
+        {
+            if config.proxy_protocol.is_some() {
+                return Err(ConfigError::FeatureDisabled("proxy_protocol").into());
+            }
+        }
+        if let Some(secs) = config.idle_session_timeout {
+            self = self.idle_session_timeout(secs);
+        }
+        if let Some(secs) = config.stalled_transfer_timeout {
+            self = self.stalled_transfer_timeout(secs);
+        }
+        if let Some(secs) = config.storage_timeout {
+            self = self.storage_timeout(secs);
+        }
+        if let Some(secs) = config.metadata_cache_ttl {
+            self = self.metadata_cache_ttl(secs);
+        }
+        if let Some(secs) = config.shutdown_grace_period {
+            self = self.shutdown_grace_period(secs);
+        }
+        if let Some(max) = config.max_connections {
+            self = self.max_connections(max);
+        }
+        if let Some(limit) = config.bandwidth_limit {
+            self = self.bandwidth_limit(limit);
+        }
+        if let Some(limit) = config.upload_bandwidth_limit {
+            self = self.upload_bandwidth_limit(limit);
+        }
+        if let Some(limit) = config.download_bandwidth_limit {
+            self = self.download_bandwidth_limit(limit);
+        }
+        if !config.allow_ips.is_empty() {
+            self = self.allow_ips(&config.allow_ips)?;
+        }
+        if !config.deny_ips.is_empty() {
+            self = self.deny_ips(&config.deny_ips)?;
+        }
+        if !config.disabled_commands.is_empty() {
+            self = self.disable_commands(&config.disabled_commands);
+        }
+        if config.no_clobber {
+            self = self.no_clobber();
+        }
+        if let Some(policy) = config.partial_uploads {
+            self = self.partial_uploads(policy);
+        }
+        if config.hide_dotfiles {
+            self = self.hide_dotfiles();
+        }
+        if !config.hidden_paths.is_empty() {
+            self = self.hide_paths(&config.hidden_paths);
+        }
+        if config.drop_box {
+            self = self.drop_box();
+        }
+        if config.metrics {
+            #[cfg(feature = "metrics")]
+            {
+                self = self.metrics();
+            }
+            #[cfg(not(feature = "metrics"))]
+            return Err(ConfigError::FeatureDisabled("metrics").into());
+        }
+        Ok(self)
+    }
+
+    /// Checks the configuration and builds the server. The range of passive ports may not be
+    /// empty, the limit of concurrent transfers may not be 0, the certificate file for FTPS must be
+    /// readable with the given password and, in PROXY protocol mode, the external control port may
+    /// not lie in the range of passive ports.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{ConfigError, Server};
+    ///
+    /// let result = Server::new_with_fs_root("/srv/ftp").passive_ports(50000..50000).build();
+    /// assert!(matches!(result, Err(ConfigError::EmptyPassivePortRange(_))));
+    /// ```
+    pub fn build(self) -> Result<Server<S, U>, ConfigError> {
+        let server = self.server;
+        if server.passive_ports.is_empty() {
+            return Err(ConfigError::EmptyPassivePortRange(server.passive_ports));
+        }
+        // Nothing has taken a permit yet, so the available ones are the limit.
+        if let Some(permits) = &server.transfer_permits {
+            if permits.available_permits() == 0 {
+                return Err(ConfigError::NoConcurrentTransfers);
+            }
+        }
+        #[cfg(feature = "ftps")]
+        if let (Some(certs_file), Some(certs_password)) = (&server.certs_file, &server.certs_password) {
+            let identity = std::fs::read(certs_file).map_err(|err| ConfigError::UnreadableCertificate(certs_file.clone(), err))?;
+            native_tls::Identity::from_pkcs12(&identity, certs_password).map_err(|err| ConfigError::InvalidCertificate(certs_file.clone(), err))?;
+        }
+        if !server.ftps_implicit_addresses.is_empty() && (server.certs_file.is_none() || server.certs_password.is_none()) {
+            return Err(ConfigError::ImplicitFtpsWithoutCertificate);
+        }
+        #[cfg(feature = "proxy_protocol")]
+        if let Some(proxy) = &server.proxy_protocol_mode {
+            if server.passive_ports.contains(&proxy.external_control_port) {
+                return Err(ConfigError::ProxyControlPortInPassiveRange(proxy.external_control_port, server.passive_ports));
+            }
+            if !server.ftps_implicit_addresses.is_empty() {
+                return Err(ConfigError::ImplicitFtpsWithProxyProtocol);
+            }
+        }
+        #[cfg(feature = "http_endpoint")]
+        if let Some(address) = &server.http_endpoint {
+            if let Err(err) = address.parse::<std::net::SocketAddr>() {
+                return Err(ConfigError::InvalidHttpEndpoint(address.clone(), err));
+            }
+        }
+        Ok(server)
+    }
+}
+
+impl<S, U> Server<S, U>
+where
+    S: 'static + storage::StorageBackend<U> + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+    U: UserDetail + 'static,
+{
+    /// Returns a [`ServerHandle`] that can be used to control the server once it's running.
     ///
     /// # Example
     ///
     /// ```rust
     /// use libunftp::Server;
     ///
-    /// // Use it in a builder-like pattern:
-    /// let mut server = Server::new_with_fs_root("/tmp").idle_session_timeout(600);
-    ///
-    /// // Or instead if you prefer:
-    /// let mut server = Server::new_with_fs_root("/tmp");
-    /// server.idle_session_timeout(600);
+    /// let server = Server::new_with_fs_root("/tmp").build().unwrap();
+    /// let handle = server.handle();
     /// ```
-    pub fn idle_session_timeout(mut self, secs: u64) -> Self {
-        self.idle_session_timeout = Duration::from_secs(secs);
-        self
+    ///
+    /// [`ServerHandle`]: struct.ServerHandle.html
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle::new(
+            self.shutdown_tx.clone(),
+            self.shutdown_rx.clone(),
+            self.settings.clone(),
+            self.sessions.clone(),
+            self.health.clone(),
+        )
     }
 
-    /// Enable PROXY protocol mode.
-    ///
-    /// If you use a proxy such as haproxy or nginx, you can enable
-    /// the PROXY protocol
-    /// (https://www.haproxy.org/download/1.8/doc/proxy-protocol.txt).
-    ///
-    /// Configure your proxy to enable PROXY protocol encoding for
-    /// control and data external listening ports, forwarding these
-    /// connections to the libunFTP listening port in proxy protocol
-    /// mode.
-    ///
-    /// In PROXY protocol mode, libunftp receives both control and
-    /// data connections on the listening port. It then distinguishes
-    /// control and data connections by comparing the original
-    /// destination port (extracted from the PROXY header) with the
-    /// port specified as the `external_control_port`
-    /// `proxy_protocol_mode` parameter.
-    ///
-    /// For the passive listening port, libunftp reports the IP
-    /// address specified as the `external_ip` `proxy_protocol_mode`
-    /// parameter.
+    /// Runs the main ftp process asynchronously. Should be started in a async runtime context.
     ///
     /// # Example
     ///
     /// ```rust
     /// use libunftp::Server;
+    /// use tokio::runtime::Runtime;
     ///
-    /// // Use it in a builder-like pattern:
-    /// let mut server = Server::new_with_fs_root("/tmp").proxy_protocol_mode("10.0.0.1", 2121).unwrap();
+    /// let mut rt = Runtime::new().unwrap();
+    /// let server = Server::new_with_fs_root("/srv/ftp").build().unwrap();
+    /// rt.spawn(server.listen("127.0.0.1:2121"));
+    /// // ...
+    /// drop(rt);
     /// ```
-    pub fn proxy_protocol_mode(mut self, external_ip: &str, external_control_port: u16) -> Result<Self, Box<dyn std::error::Error>> {
-        self.proxy_protocol_mode = Some(ProxyParams::new(external_ip, external_control_port)?);
-        self.proxy_protocol_switchboard = Some(ProxyProtocolSwitchboard::new(self.passive_ports.clone()));
-
-        Ok(self)
+    ///
+    /// The returned future completes after a shutdown was initiated through the server's
+    /// [`ServerHandle`] and the active sessions have ended.
+    ///
+    /// # Panics
+    ///
+    /// This function panics when called with invalid addresses or when the process is unable to
+    /// `bind()` to the address.
+    ///
+    /// [`ServerHandle`]: struct.ServerHandle.html
+    pub async fn listen<T: Into<String>>(self, bind_address: T) {
+        self.listen_all(vec![bind_address]).await
     }
 
-    /// Runs the main ftp process asynchronously. Should be started in a async runtime context.
+    /// Runs the main ftp process asynchronously on several bind addresses at once, for instance
+    /// to serve both IPv4 and IPv6 clients. All listeners share the same authenticator, storage
+    /// backend factory and passive port range, including those for [implicit FTPS]. Should be
+    /// started in a async runtime context.
     ///
     /// # Example
     ///
@@ -308,68 +1910,189 @@ where
     /// use tokio::runtime::Runtime;
     ///
     /// let mut rt = Runtime::new().unwrap();
-    /// let server = Server::new_with_fs_root("/srv/ftp");
-    /// rt.spawn(server.listen("127.0.0.1:2121"));
+    /// let server = Server::new_with_fs_root("/srv/ftp").build().unwrap();
+    /// rt.spawn(server.listen_all(vec!["127.0.0.1:2121", "[::1]:2121"]));
     /// // ...
     /// drop(rt);
     /// ```
     ///
     /// # Panics
     ///
-    /// This function panics when called with invalid addresses or when the process is unable to
-    /// `bind()` to the address.
-    pub async fn listen<T: Into<String>>(self, bind_address: T) {
-        match self.proxy_protocol_mode {
-            Some(_) => self.listen_proxy_protocol_mode(bind_address).await,
-            None => self.listen_normal_mode(bind_address).await,
+    /// This function panics when called without addresses, with invalid addresses or when the
+    /// process is unable to `bind()` to one of the addresses.
+    ///
+    /// [implicit FTPS]: struct.ServerBuilder.html#method.ftps_implicit
+    pub async fn listen_all<I, T>(self, bind_addresses: I)
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        // TODO: Propagate errors to caller instead of doing unwraps.
+        // The listeners, each with whether its clients start TLS right away.
+        let mut listeners = Vec::new();
+        for bind_address in bind_addresses {
+            let addr: std::net::SocketAddr = bind_address.into().parse().unwrap();
+            listeners.push((tokio::net::TcpListener::bind(addr).await.unwrap(), false));
+        }
+        assert!(!listeners.is_empty(), "At least one bind address is required");
+        for bind_address in &self.ftps_implicit_addresses {
+            let addr: std::net::SocketAddr = bind_address.parse().unwrap();
+            listeners.push((tokio::net::TcpListener::bind(addr).await.unwrap(), true));
+        }
+
+        // The HTTP endpoint keeps running until we're done draining sessions.
+        #[cfg(feature = "http_endpoint")]
+        let _stop_http_endpoint = match self.http_endpoint.as_deref().map(http_endpoint::bind).transpose() {
+            Ok(builder) => builder.map(|builder| {
+                let (stop_tx, stop_rx) = futures::channel::oneshot::channel::<()>();
+                tokio::spawn(http_endpoint::serve(builder, self.metrics.clone(), self.handle(), stop_rx, self.logger.clone()));
+                stop_tx
+            }),
+            Err(err) => {
+                error!(self.logger, "Could not start the HTTP endpoint, not starting the server: {}", err);
+                return;
+            }
+        };
+
+        self.health.set_listening(true);
+        let _accepting = self.health.accepting();
+
+        // build() made sure that there are no implicit FTPS listeners in this mode.
+        #[cfg(feature = "proxy_protocol")]
+        if self.proxy_protocol_mode.is_some() {
+            return self
+                .listen_proxy_protocol_mode(listeners.into_iter().map(|(listener, _)| listener).collect())
+                .await;
         }
+        self.listen_normal_mode(listeners).await
     }
 
-    async fn listen_normal_mode<T: Into<String>>(self, bind_address: T) {
-        // TODO: Propagate errors to caller instead of doing unwraps.
-        let addr: std::net::SocketAddr = bind_address.into().parse().unwrap();
-        let mut listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    async fn listen_normal_mode(self, mut listeners: Vec<(tokio::net::TcpListener, bool)>) {
+        let mut shutdown_rx = self.shutdown_rx.clone();
+        let mut incoming = futures::stream::select_all(
+            listeners
+                .iter_mut()
+                .map(|(listener, implicit_tls)| listener.incoming().map(move |tcp_stream| (tcp_stream, *implicit_tls))),
+        );
         loop {
-            let (tcp_stream, socket_addr) = listener.accept().await.unwrap();
-            info!("Incoming control channel connection from {:?}", socket_addr);
-            let result = self.spawn_control_channel_loop(tcp_stream, None, None).await;
+            let (tcp_stream, implicit_tls) = tokio::select! {
+                Some(tcp_stream) = incoming.next() => tcp_stream,
+                _ = shutdown_initiated(&mut shutdown_rx) => break,
+            };
+            let tcp_stream = match tcp_stream {
+                Ok(s) => s,
+                Err(e) => {
+                    handle_accept_error(&self.logger, e).await;
+                    continue;
+                }
+            };
+            info!(self.logger, "Incoming control channel connection from {:?}", tcp_stream.peer_addr());
+            let result = self.spawn_control_channel_loop(tcp_stream, implicit_tls, None, None).await;
             if result.is_err() {
-                warn!("Could not spawn control channel loop for connection: {:?}", result.err().unwrap())
+                warn!(self.logger, "Could not spawn control channel loop for connection: {:?}", result.err().unwrap())
             }
         }
+
+        // Stop listening right away so that new clients can go elsewhere while we drain.
+        drop(incoming);
+        drop(listeners);
+        self.health.set_listening(false);
+        self.drain_connections().await;
+    }
+
+    // Waits until all control connections are closed or the shutdown grace period has passed.
+    async fn drain_connections(&self) {
+        info!(
+            self.logger,
+            "Shutting down, waiting for {} sessions to end",
+            self.connection_count.load(Ordering::SeqCst)
+        );
+        let deadline = tokio::time::Instant::now() + self.shutdown_grace_period;
+        while !self.drained(deadline) {
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+        }
+    }
+
+    // Tells if we're done draining connections during shutdown.
+    fn drained(&self, deadline: tokio::time::Instant) -> bool {
+        let active = self.connection_count.load(Ordering::SeqCst);
+        if active == 0 {
+            info!(self.logger, "All sessions ended, shutdown complete");
+            true
+        } else if tokio::time::Instant::now() >= deadline {
+            warn!(self.logger, "Shutdown grace period expired with {} sessions still active", active);
+            true
+        } else {
+            false
+        }
     }
 
-    async fn listen_proxy_protocol_mode<T: Into<String>>(mut self, bind_address: T) {
+    #[cfg(feature = "proxy_protocol")]
+    async fn listen_proxy_protocol_mode(mut self, mut listeners: Vec<tokio::net::TcpListener>) {
         let proxy_params = self
             .proxy_protocol_mode
             .expect("You cannot use the proxy protocol listener without setting the proxy_protocol_mode parameters.");
-
-        // TODO: Propagate errors to caller instead of doing unwraps.
-        let addr: std::net::SocketAddr = bind_address.into().parse().unwrap();
-        let mut listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        self.proxy_protocol_switchboard = Some(ProxyProtocolSwitchboard::new(
+            self.logger.clone(),
+            self.passive_ports.clone(),
+            self.proxy_protocol_reservation_ttl,
+            self.metrics.clone(),
+        ));
+        // Reservations are also released when ports are reserved, but without this sessions that
+        // ended would stay referenced until then.
+        let mut cleanup_interval = tokio::time::interval(std::cmp::max(self.proxy_protocol_reservation_ttl, Duration::from_secs(1)));
 
         // this callback is used by all sessions, basically only to
         // request for a passive listening port.
-        let (proxyloop_msg_tx, mut proxyloop_msg_rx): (ProxyLoopSender<S, U>, ProxyLoopReceiver<S, U>) = channel(1);
+        let (proxyloop_msg_tx, mut proxyloop_msg_rx): (ProxyLoopSender<S, U>, ProxyLoopReceiver<S, U>) = channel(PROXY_LOOP_CHANNEL_CAPACITY);
 
-        let mut incoming = listener.incoming();
+        let mut incoming = futures::stream::select_all(listeners.iter_mut().map(|listener| listener.incoming()));
+        let mut shutdown_rx = self.shutdown_rx.clone();
+        let mut drain_deadline = None;
 
         loop {
-            // The 'proxy loop' handles two kinds of events:
+            // The 'proxy loop' handles four kinds of events:
             // - incoming tcp connections originating from the proxy
             // - channel messages originating from PASV, to handle the passive listening port
+            // - the initiation of a shutdown. We keep accepting connections while draining because
+            //   the data connections of running transfers come in through the same listener. New
+            //   control connections get refused in spawn_control_channel_loop.
+            // - the periodic release of expired passive port reservations
 
             tokio::select! {
-
+                _ = shutdown_initiated(&mut shutdown_rx), if drain_deadline.is_none() => {
+                    info!(self.logger, "Shutting down, waiting for {} sessions to end", self.connection_count.load(Ordering::SeqCst));
+                    drain_deadline = Some(tokio::time::Instant::now() + self.shutdown_grace_period);
+                },
+                _ = tokio::time::delay_for(Duration::from_millis(100)), if drain_deadline.is_some() => {
+                    if self.drained(drain_deadline.unwrap()) {
+                        return;
+                    }
+                },
                 Some(tcp_stream) = incoming.next() => {
-                    let mut tcp_stream = tcp_stream.unwrap();
+                    let mut tcp_stream = match tcp_stream {
+                        Ok(s) => s,
+                        Err(e) => {
+                            handle_accept_error(&self.logger, e).await;
+                            continue;
+                        }
+                    };
                     let socket_addr = tcp_stream.peer_addr();
+                    if let Ok(addr) = socket_addr {
+                        if !self.trusted_proxies.permits(addr.ip()) {
+                            warn!(self.logger, "Refusing connection from {}: not a trusted proxy", addr);
+                            if let Err(e) = tcp_stream.shutdown(Shutdown::Both) {
+                                warn!(self.logger, "Could not shut down untrusted connection: {:?}", e);
+                            }
+                            continue;
+                        }
+                    }
 
-                    info!("Incoming proxy connection from {:?}", socket_addr);
+                    info!(self.logger, "Incoming proxy connection from {:?}", socket_addr);
                     let connection = match get_peer_from_proxy_header(&mut tcp_stream).await {
                         Ok(v) => v,
                         Err(e) => {
-                            warn!("proxy protocol decode error: {:?}", e);
+                            warn!(self.logger, "proxy protocol decode error: {}", e);
                             continue;
                         }
                     };
@@ -379,18 +2102,20 @@ where
                     // and connections for the data channel.
                     if connection.to_port == proxy_params.external_control_port {
                         let socket_addr = SocketAddr::new(connection.from_ip, connection.from_port);
-                        info!("Incoming control channel connection from {:?}", socket_addr);
+                        info!(self.logger, "Incoming control channel connection from {:?}", socket_addr);
 
-                        let result = self.spawn_control_channel_loop(tcp_stream, Some(connection), Some(proxyloop_msg_tx.clone())).await;
+                        let result = self.spawn_control_channel_loop(tcp_stream, false, Some(connection), Some(proxyloop_msg_tx.clone())).await;
                         if result.is_err() {
-                            warn!("Could not spawn control channel loop for connection: {:?}", result.err().unwrap())
+                            warn!(self.logger, "Could not spawn control channel loop for connection: {:?}", result.err().unwrap())
                         }
                     } else {
                         // handle incoming data connections
                         println!("{:?}, {}", self.passive_ports, connection.to_port);
                         if !self.passive_ports.contains(&connection.to_port) {
-                            error!("Incoming proxy connection going to unconfigured port! This port is not configured as a passive listening port: port {} not in passive port range {:?}", connection.to_port, self.passive_ports);
-                            tcp_stream.shutdown(Shutdown::Both).unwrap();
+                            error!(self.logger, "Incoming proxy connection going to unconfigured port! This port is not configured as a passive listening port: port {} not in passive port range {:?}", connection.to_port, self.passive_ports);
+                            if let Err(e) = tcp_stream.shutdown(Shutdown::Both) {
+                                warn!(self.logger, "Could not shut down proxy connection: {:?}", e);
+                            }
                             continue;
                         }
 
@@ -398,10 +2123,15 @@ where
 
                     }
                 },
+                _ = cleanup_interval.tick() => {
+                    if let Some(switchboard) = &mut self.proxy_protocol_switchboard {
+                        switchboard.remove_expired();
+                    }
+                },
                 Some(msg) = proxyloop_msg_rx.next() => {
                     match msg {
-                        ProxyLoopMsg::AssignDataPortCommand (session_arc) => {
-                            self.select_and_register_passive_port(session_arc).await;
+                        ProxyLoopMsg::AssignDataPortCommand (session_arc, mode) => {
+                            self.select_and_register_passive_port(session_arc, mode).await;
                         },
                     }
                 },
@@ -413,6 +2143,7 @@ where
     // that requested this data channel connection in the proxy
     // protocol switchboard hashmap, and then calls the
     // spawn_data_processing function with the tcp_stream
+    #[cfg(feature = "proxy_protocol")]
     async fn dispatch_data_connection(&mut self, tcp_stream: tokio::net::TcpStream, connection: ConnectionTuple) {
         if let Some(switchboard) = &mut self.proxy_protocol_switchboard {
             match switchboard.get_session_by_incoming_data_connection(&connection).await {
@@ -425,56 +2156,123 @@ where
                     }
                 }
                 None => {
-                    warn!("Unexpected connection ({:?})", connection);
-                    tcp_stream.shutdown(Shutdown::Both).unwrap();
+                    warn!(self.logger, "Unexpected connection ({:?})", connection);
+                    if let Err(e) = tcp_stream.shutdown(Shutdown::Both) {
+                        warn!(self.logger, "Could not shut down unexpected data connection: {:?}", e);
+                    }
                     return;
                 }
             }
         }
     }
 
-    async fn select_and_register_passive_port(&mut self, session_arc: SharedSession<S, U>) {
-        info!("Received command to allocate data port");
+    #[cfg(feature = "proxy_protocol")]
+    async fn select_and_register_passive_port(&mut self, session_arc: SharedSession<S, U>, mode: PassiveMode) {
+        info!(self.logger, "Received command to allocate data port");
         // 1. reserve a port
         // 2. put the session_arc and tx in the hashmap with srcip+dstport as key
         // 3. put expiry time in the LIFO list
-        // 4. send reply to client: "Entering Passive Mode ({},{},{},{},{},{})"
+        // 4. send reply to client: "Entering Passive Mode ({},{},{},{},{},{})" or "Entering Extended Passive Mode (|||{}|)"
 
-        let mut p1 = 0;
-        let mut p2 = 0;
+        let mut port = 0;
         if let Some(switchboard) = &mut self.proxy_protocol_switchboard {
-            let port = switchboard.reserve_next_free_port(session_arc.clone()).await.unwrap();
-            warn!("port: {:?}", port);
-            p1 = port >> 8;
-            p2 = port - (p1 * 256);
+            match switchboard.reserve_next_free_port(session_arc.clone()).await {
+                Ok(reserved) => port = reserved,
+                Err(e) => {
+                    // All ports are taken, or we were unlucky picking them. The client may try again.
+                    warn!(self.logger, "Could not reserve a passive port: {:?}", e);
+                    let session = session_arc.lock().await;
+                    if let Some(mut tx) = session.control_msg_tx.clone() {
+                        let msg = "No passive port available, try again later".to_string();
+                        let logger = self.logger.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = tx.send(InternalMsg::CommandChannelReply(ReplyCode::CantOpenDataConnection, msg)).await {
+                                warn!(logger, "Could not send the reply to the control channel: {:?}", e);
+                            }
+                        });
+                    }
+                    return;
+                }
+            }
         }
+        let external_ip = self.settings.read().unwrap().passive_external_ip;
         let session = session_arc.lock().await;
         if let Some(conn) = session.control_connection_info {
-            let octets = match conn.from_ip {
-                IpAddr::V4(ip) => ip.octets(),
-                IpAddr::V6(_) => panic!("Won't happen."),
+            let reply = match (mode, unmap_ipv4(external_ip.unwrap_or(conn.to_ip))) {
+                (PassiveMode::Extended, _) => commands::extended_passive_mode_reply(port),
+                (PassiveMode::Long, ip) => commands::long_passive_mode_reply(ip, port),
+                (PassiveMode::Standard, IpAddr::V4(ip)) => commands::passive_mode_reply(ip, port),
+                (PassiveMode::Standard, IpAddr::V6(_)) => commands::ipv6_not_supported(),
             };
             let tx_some = session.control_msg_tx.clone();
-            if let Some(tx) = tx_some {
-                let mut tx = tx.clone();
-                tx.send(InternalMsg::CommandChannelReply(
-                    ReplyCode::EnteringPassiveMode,
-                    format!("Entering Passive Mode ({},{},{},{},{},{})", octets[0], octets[1], octets[2], octets[3], p1, p2),
-                ))
-                .await
-                .unwrap();
+            if let (Some(mut tx), Reply::CodeAndMsg { code, msg }) = (tx_some, reply) {
+                // The proxy loop serves all sessions, so it must not wait for a slow one.
+                let logger = self.logger.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = tx.send(InternalMsg::CommandChannelReply(code, msg)).await {
+                        warn!(logger, "Could not send the passive mode reply to the control channel: {:?}", e);
+                    }
+                });
             }
         }
     }
 
     /// Does TCP processing when a FTP client connects
+    // Sets up the session of a control connection and runs it in a task of its own. With
+    // `implicit_tls` the client starts TLS right away, before the greeting.
     async fn spawn_control_channel_loop(
         &self,
         tcp_stream: tokio::net::TcpStream,
+        implicit_tls: bool,
         control_connection_info: Option<ConnectionTuple>,
         proxyloop_msg_tx: Option<ProxyLoopSender<S, U>>,
     ) -> Result<(), ControlChanError> {
-        let with_metrics = self.collect_metrics;
+        let (peer_addr, destination_addr) = match control_connection_info {
+            Some(connection) => (
+                SocketAddr::new(connection.from_ip, connection.from_port),
+                SocketAddr::new(connection.to_ip, connection.to_port),
+            ),
+            None => (tcp_stream.peer_addr()?, tcp_stream.local_addr()?),
+        };
+        let session_id = Uuid::new_v4().to_string();
+        let logger = self.logger.new(o!("session" => session_id.clone(), "peer" => peer_addr.to_string()));
+        self.control_socket_options.apply(&tcp_stream, &logger);
+        // Settings can change while the server runs, so we take them as they are when the session starts.
+        let (permitted, idle_session_timeout) = {
+            let settings = self.settings.read().unwrap();
+            (settings.ip_filter.permits(peer_addr.ip()), settings.idle_session_timeout)
+        };
+        if !permitted {
+            warn!(logger, "Refusing control channel connection from {}: address not allowed", peer_addr.ip());
+            self.refuse(tcp_stream, Reply::new(ReplyCode::ServiceNotAvailable, "Access denied"), logger);
+            return Ok(());
+        }
+
+        if *self.shutdown_rx.borrow() {
+            info!(logger, "Refusing control channel connection from {}: shutting down", peer_addr);
+            self.refuse(
+                tcp_stream,
+                Reply::new(ReplyCode::ServiceNotAvailable, "Service closing control connection"),
+                logger,
+            );
+            return Ok(());
+        }
+
+        let connection_guard = match ConnectionGuard::acquire(&self.connection_count, self.max_connections) {
+            Some(guard) => guard,
+            None => {
+                warn!(
+                    logger,
+                    "Refusing control channel connection: maximum of {} connections reached",
+                    self.max_connections.unwrap()
+                );
+                self.refuse(tcp_stream, Reply::new(ReplyCode::ServiceNotAvailable, "Too many connections"), logger);
+                return Ok(());
+            }
+        };
+        // The observer is only set on the tracker once it accepted the session, in its task.
+        let tracker = self.sessions.register(session_id.clone(), peer_addr.ip());
+        let metrics = self.metrics.clone();
         let tls_configured = if let (Some(_), Some(_)) = (&self.certs_file, &self.certs_password) {
             true
         } else {
@@ -484,21 +2282,64 @@ where
         let storage_features = storage.supported_features();
         let authenticator = self.authenticator.clone();
         let mut session = Session::new(storage)
+            .id(session_id.clone(), self.reveal_session_id)
             .ftps(self.certs_file.clone(), self.certs_password.clone())
-            .metrics(with_metrics);
-        let (control_msg_tx, control_msg_rx): (Sender<InternalMsg>, Receiver<InternalMsg>) = channel(1);
+            .data_downgrade_forbidden(self.data_downgrade_forbidden)
+            .bandwidth_limiter(self.bandwidth_limiter.clone())
+            .session_bandwidth_limits(self.upload_bandwidth_limit, self.download_bandwidth_limit)
+            .stalled_transfer_timeout(self.stalled_transfer_timeout)
+            .login_message(self.login_message.clone())
+            .logger(logger.clone())
+            .tracker(tracker)
+            .span(SessionSpan::new(&session_id, peer_addr.ip()))
+            .peer_ip(peer_addr.ip())
+            .xferlog(self.xferlog.clone())
+            .notifier(self.file_event_listener.clone().map(|listener| Notifier::new(listener, session_id.clone())))
+            .upload_spool(self.upload_spool_dir.clone().map(|dir| UploadSpool::new(dir, self.spooled_upload_retries)))
+            .data_socket_options(self.data_socket_options.clone())
+            .list_formatter(self.list_formatter.clone())
+            .path_mapper(self.path_mapper.clone())
+            .drop_box(self.drop_box)
+            .no_clobber(self.no_clobber)
+            .partial_uploads(self.partial_uploads)
+            .filename_policy(self.filename_policy.clone())
+            .hidden_paths(Arc::new(self.hidden_paths.clone()))
+            .storage_timeout(self.storage_timeout)
+            .metadata_cache(self.metadata_cache_ttl)
+            .transfer_limiter(
+                self.transfer_permits
+                    .clone()
+                    .map(|permits| TransferLimiter::new(permits, self.transfer_queue_timeout)),
+            )
+            .metrics(metrics.clone());
+        #[cfg(feature = "gssapi")]
+        let gssapi = self.gssapi.clone().map(GssapiSession::new);
+        #[cfg(feature = "gssapi")]
+        {
+            session = session.gssapi(gssapi.clone());
+        }
+        let (control_msg_tx, control_msg_rx): (Sender<InternalMsg>, Receiver<InternalMsg>) = channel(self.control_msg_channel_capacity);
         session.control_msg_tx = Some(control_msg_tx.clone());
         session.control_connection_info = control_connection_info;
+        session.cmd_tls = implicit_tls;
+        let tracker = session.tracker.clone();
+        let span = session.span.clone();
         let session = Arc::new(Mutex::new(session));
+        let mut audit_trail = self.audit_sink.clone().map(|sink| AuditTrail::new(sink, session_id.clone(), peer_addr.ip()));
+        // For the audit trail and the middleware, that need the name of the user.
+        let shared_session = session.clone();
+        let middlewares = self.middlewares.clone();
+        let peer_ip = peer_addr.ip();
         let passive_ports = self.passive_ports.clone();
-        let idle_session_timeout = self.idle_session_timeout;
         let local_addr = tcp_stream.local_addr().unwrap();
+        #[cfg(feature = "ftps")]
         let identity_file: Option<PathBuf> = if tls_configured {
             let p: PathBuf = self.certs_file.clone().unwrap();
             Some(p)
         } else {
             None
         };
+        #[cfg(feature = "ftps")]
         let identity_password: Option<String> = if tls_configured {
             let p: String = self.certs_password.clone().unwrap();
             Some(p)
@@ -506,67 +2347,156 @@ where
             None
         };
 
-        let event_handler_chain = Self::handle_event(
-            session.clone(),
+        let event_handler_chain = EventHandlerChain {
+            session,
             authenticator,
             tls_configured,
+            auth_mechanisms: self.auth_mechanisms.clone(),
             passive_ports,
-            control_msg_tx,
+            tx: control_msg_tx,
             local_addr,
+            data_bind_ip: self.data_bind_ip,
             storage_features,
             proxyloop_msg_tx,
             control_connection_info,
-        );
-        let event_handler_chain = Self::handle_with_auth(session, event_handler_chain);
-        let event_handler_chain = Self::handle_with_logging(event_handler_chain);
-
-        let codec = FTPCodec::new();
-        let cmd_and_reply_stream = codec.framed(tcp_stream.as_async_io());
-        let (mut reply_sink, command_source) = cmd_and_reply_stream.split();
-
-        reply_sink.send(Reply::new(ReplyCode::ServiceReady, self.greeting)).await?;
-        reply_sink.flush().await?;
+            custom_commands: Arc::new(self.custom_commands.clone()),
+            site_commands: Arc::new(self.site_commands.clone()),
+            system_type: self.system_type.clone(),
+            identification: self.identification.clone(),
+            disabled_commands: self.disabled_commands.clone(),
+            drop_box: self.drop_box,
+            logger: logger.clone(),
+        };
 
-        let mut command_source = command_source.fuse();
+        let max_command_length = self.max_command_length;
+        let custom_verbs: Arc<HashSet<String>> = Arc::new(self.custom_commands.keys().cloned().collect());
+        let io = tcp_stream.as_async_io();
         let mut control_msg_rx = control_msg_rx.fuse();
+        let reply_catalog = self.reply_catalog.clone();
+        let reply_filter = self.reply_filter.clone();
+        let shutdown_rx = self.shutdown_rx.clone();
+        let settings = self.settings.clone();
+        let session_observer = self.session_observer.clone();
 
         tokio::spawn(async move {
+            let _connection_guard = connection_guard;
+            // The TLS handshake of implicit FTPS happens in the task of the session, so that a
+            // slow client doesn't hold up the accept loop.
+            #[cfg(feature = "ftps")]
+            let io = if implicit_tls {
+                let identity = tls::identity(identity_file.clone().unwrap(), identity_password.clone().unwrap());
+                let acceptor = tokio_tls::TlsAcceptor::from(native_tls::TlsAcceptor::builder(identity).build().unwrap());
+                match tokio::time::timeout(idle_session_timeout, acceptor.accept(io)).await {
+                    Ok(Ok(io)) => io.as_async_io(),
+                    Ok(Err(err)) => {
+                        warn!(logger, "Closing implicit FTPS connection, the TLS handshake failed: {}", err);
+                        return;
+                    }
+                    Err(_) => {
+                        warn!(logger, "Closing implicit FTPS connection, the client did not start TLS");
+                        return;
+                    }
+                }
+            } else {
+                io
+            };
+            let codec = FTPCodec::new().max_line_length(max_command_length).custom_verbs(custom_verbs.clone());
+            #[cfg(feature = "gssapi")]
+            let codec = codec.gssapi(gssapi.clone());
+            let (mut reply_sink, command_source) = codec.framed(io).split();
+            let mut command_source = command_source.fuse();
+
+            if let (Some(observer), Some(info)) = (session_observer, tracker.info()) {
+                if let Err(reason) = observer.on_connect(&info) {
+                    info!(logger, "Refusing control channel connection from {}: {}", peer_ip, reason);
+                    let reply = reply_filter::outgoing(
+                        &reply_catalog,
+                        &reply_filter,
+                        None,
+                        Reply::new_with_string(ReplyCode::ServiceNotAvailable, reason),
+                    );
+                    let _ = tokio::time::timeout(REFUSAL_TIMEOUT, reply_sink.send(reply)).await;
+                    return;
+                }
+                let mut session = shared_session.lock().await;
+                session.tracker = session.tracker.clone().observer(Some(observer));
+            }
+            // A generated greeting is made in the task of the session, so that a slow greeting
+            // function doesn't hold up the accept loop.
+            let greeting = match &settings.read().unwrap().greeting {
+                Greeting::Text(text) => text.clone(),
+                Greeting::Generated(f) => f(peer_addr, destination_addr),
+            };
+            let greeting = reply_filter::outgoing(&reply_catalog, &reply_filter, None, Reply::new_from_text(ReplyCode::ServiceReady, &greeting));
+            if let Err(err) = reply_sink.send(greeting).await {
+                warn!(logger, "Could not send the greeting: {}", err);
+                return;
+            }
+            // Set when the handler of a command left the reply to a spawned task or the data
+            // channel, until their reply came in as an internal message. Commands that clients
+            // pipeline are not read in the meantime, so that their replies come in the order of
+            // the commands and, for transfers, after the preliminary reply.
+            let mut awaiting_reply = false;
             // The control channel event loop
             loop {
                 #[allow(unused_assignments)]
                 let mut incoming = None;
                 let mut timeout_delay = tokio::time::delay_for(idle_session_timeout);
                 tokio::select! {
-                    Some(cmd_result) = command_source.next() => {
+                    Some(cmd_result) = command_source.next(), if !awaiting_reply => {
                         incoming = Some(cmd_result.map(Event::Command));
                     },
                     Some(msg) = control_msg_rx.next() => {
                         incoming = Some(Ok(Event::InternalMsg(msg)));
                     },
                     _ = &mut timeout_delay => {
-                        info!("Connection timed out");
+                        // Clients have nothing to say on the control channel while a transfer
+                        // runs, however long it takes, so the session only becomes idle when it
+                        // ended. Transfers that stop moving data are aborted by their own timeout.
+                        if shared_session.lock().await.transfer_in_progress {
+                            continue;
+                        }
+                        info!(logger, "Connection timed out");
+                        if let Some(metrics) = &metrics {
+                            metrics.inc_idle_timeout();
+                        }
                         incoming = Some(Err(ControlChanError::new(ControlChanErrorKind::ControlChannelTimeout)));
+                    },
+                    _ = tracker.kicked() => {
+                        info!(logger, "Closing control connection because the session was terminated");
+                        incoming = Some(Ok(Event::InternalMsg(InternalMsg::ServiceNotAvailable("Session terminated".to_string()))));
                     }
                 };
 
                 match incoming {
                     None => {
                         // Should not happen.
-                        warn!("No event polled...");
+                        warn!(logger, "No event polled...");
                         return;
                     }
-                    Some(Ok(event)) => {
-                        if with_metrics {
-                            metrics::add_event_metric(&event);
+                    Some(Ok(mut event)) => {
+                        if let Some(metrics) = &metrics {
+                            metrics.add_event_metric(&event);
                         };
 
+                        if let Event::Command(_) = event {
+                            if *shutdown_rx.borrow() {
+                                info!(logger, "Closing control connection because the server is shutting down");
+                                event = Event::InternalMsg(InternalMsg::ServiceNotAvailable("Service closing control connection".to_string()));
+                            }
+                        }
+
+                        let is_command = matches!(event, Event::Command(_));
+                        let closes_connection = matches!(&event, Event::InternalMsg(msg) if msg.closes_connection());
+
                         if let Event::InternalMsg(InternalMsg::Quit) = event {
-                            info!("Quit received");
+                            info!(logger, "Quit received");
                             return;
                         }
 
+                        #[cfg(feature = "ftps")]
                         if let Event::InternalMsg(InternalMsg::SecureControlChannel) = event {
-                            info!("Upgrading to TLS");
+                            info!(logger, "Upgrading to TLS");
 
                             // Get back the original TCP Stream
                             let codec_io = reply_sink.reunite(command_source.into_inner()).unwrap();
@@ -579,7 +2509,11 @@ where
                             let io = acceptor.accept(io).await.unwrap().as_async_io();
 
                             // Wrap in codec again and get sink + source
-                            let codec = controlchan::FTPCodec::new();
+                            let codec = controlchan::FTPCodec::new()
+                                .max_line_length(max_command_length)
+                                .custom_verbs(custom_verbs.clone());
+                            #[cfg(feature = "gssapi")]
+                            let codec = codec.gssapi(gssapi.clone());
                             let cmd_and_reply_stream = codec.framed(io);
                             let (sink, src) = cmd_and_reply_stream.split();
                             let src = src.fuse();
@@ -589,26 +2523,106 @@ where
 
                         // TODO: Handle Event::InternalMsg(InternalMsg::PlaintextControlChannel)
 
-                        match event_handler_chain(event) {
+                        let timed_command = match (&event, &metrics) {
+                            (Event::Command(cmd), Some(_)) => Some((cmd.clone(), Instant::now())),
+                            _ => None,
+                        };
+                        let audited_command = match (&event, &audit_trail) {
+                            (Event::Command(cmd), Some(_)) => Some(cmd.clone()),
+                            _ => None,
+                        };
+                        let verb = match &event {
+                            Event::Command(cmd) => Some(cmd.verb()),
+                            _ => None,
+                        };
+                        let command_span = match &event {
+                            Event::Command(cmd) => Some(span.command(&command_label(cmd))),
+                            _ => None,
+                        };
+                        let handling = async {
+                            match event {
+                                Event::Command(command) if !middlewares.is_empty() => {
+                                    let request = Request {
+                                        session_id: session_id.clone(),
+                                        username: shared_session.lock().await.username.clone(),
+                                        peer_ip,
+                                        command,
+                                    };
+                                    // Set when the server left the reply to the command to a spawned
+                                    // task, which middleware can't do.
+                                    let reply_pending = AtomicBool::new(false);
+                                    let endpoint = |command: Command| {
+                                        event_handler_chain
+                                            .handle(Event::Command(command))
+                                            .inspect(|result| reply_pending.store(matches!(result, Ok(Reply::None)), Ordering::Relaxed))
+                                            .boxed()
+                                    };
+                                    match Next::new(&middlewares, &endpoint).run(request).await {
+                                        Ok(Reply::None) if !reply_pending.load(Ordering::Relaxed) => {
+                                            warn!(logger, "Middleware gave no reply to the command");
+                                            Ok(Reply::new(ReplyCode::LocalError, "Local error in processing"))
+                                        }
+                                        result => result,
+                                    }
+                                }
+                                event => event_handler_chain.handle(event).await,
+                            }
+                        };
+                        let result = match &command_span {
+                            Some(command_span) => command_span.instrument(handling).await,
+                            None => handling.await,
+                        };
+                        if let (Some((cmd, started)), Some(metrics)) = (timed_command, &metrics) {
+                            metrics.add_command_duration_metric(&cmd, started.elapsed());
+                        }
+                        match result {
                             Err(e) => {
-                                warn!("Event handler chain error: {:?}", e);
+                                warn!(logger, "Event handler chain error: {:?}", e);
+                                let reply = Reply::new(ReplyCode::ServiceNotAvailable, "Service not available, closing control connection");
+                                let reply = reply_filter::outgoing(&reply_catalog, &reply_filter, verb.as_deref(), reply);
+                                if let Err(err) = reply_sink.send(reply).await {
+                                    warn!(logger, "could not send reply: {:?}", err);
+                                }
+                                let _ = reply_sink.close().await;
                                 return;
                             }
                             Ok(reply) => {
-                                if with_metrics {
-                                    metrics::add_reply_metric(&reply);
+                                match reply {
+                                    Reply::None => awaiting_reply = awaiting_reply || is_command,
+                                    _ => awaiting_reply = false,
+                                }
+                                if let Some(metrics) = &metrics {
+                                    metrics.add_reply_metric(&reply);
                                 }
+                                if let Some(command_span) = &command_span {
+                                    command_span.reply(&reply);
+                                }
+                                if let Some(audit_trail) = &mut audit_trail {
+                                    match audited_command {
+                                        Some(cmd) => {
+                                            let username = shared_session.lock().await.username.clone();
+                                            audit_trail.command(&cmd, username, &reply);
+                                        }
+                                        None => audit_trail.reply(&reply),
+                                    }
+                                }
+                                let reply = reply_filter::outgoing(&reply_catalog, &reply_filter, verb.as_deref(), reply);
                                 let result = reply_sink.send(reply).await;
                                 if result.is_err() {
-                                    warn!("could not send reply");
+                                    warn!(logger, "could not send reply");
+                                    return;
+                                }
+                                if closes_connection {
+                                    let _ = reply_sink.close().await;
                                     return;
                                 }
                             }
                         }
                     }
                     Some(Err(e)) => {
-                        let reply = Self::handle_control_channel_error(e, with_metrics);
-                        let mut close_connection = false;
+                        // The rest of a line that is too long would be read as commands.
+                        let mut close_connection = *e.kind() == ControlChanErrorKind::CommandTooLong;
+                        let reply = Self::handle_control_channel_error(&logger, e, metrics.as_deref());
                         if let Reply::CodeAndMsg {
                             code: ReplyCode::ClosingControlConnection,
                             ..
@@ -616,9 +2630,9 @@ where
                         {
                             close_connection = true;
                         }
-                        let result = reply_sink.send(reply).await;
+                        let result = reply_sink.send(reply_filter::outgoing(&reply_catalog, &reply_filter, None, reply)).await;
                         if result.is_err() {
-                            warn!("could not send error reply");
+                            warn!(logger, "could not send error reply");
                             return;
                         }
                         if close_connection {
@@ -632,99 +2646,207 @@ where
         Ok(())
     }
 
-    fn handle_with_auth(
-        session: SharedSession<S, U>,
-        next: impl Fn(Event) -> Result<Reply, ControlChanError>,
-    ) -> impl Fn(Event) -> Result<Reply, ControlChanError> {
-        move |event| match event {
+    // The reply as it is sent to a client that is turned away before its session starts, or greeted.
+    fn outgoing(&self, reply: Reply) -> Reply {
+        reply_filter::outgoing(&self.reply_catalog, &self.reply_filter, None, reply)
+    }
+
+    // Tells a client why its connection is refused and closes it. The reply is sent from a task of
+    // its own, so that a client that doesn't read can't hold up the accept loop.
+    fn refuse(&self, tcp_stream: tokio::net::TcpStream, reply: Reply, logger: Logger) {
+        let reply = self.outgoing(reply);
+        tokio::spawn(async move {
+            let mut reply_sink = FTPCodec::new().framed(tcp_stream.as_async_io());
+            match tokio::time::timeout(REFUSAL_TIMEOUT, reply_sink.send(reply)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => warn!(logger, "Could not send the reply refusing the connection: {}", err),
+                Err(_) => warn!(logger, "Timed out sending the reply refusing the connection"),
+            }
+        });
+    }
+
+    fn handle_control_channel_error(logger: &Logger, error: ControlChanError, metrics: Option<&Metrics>) -> Reply {
+        if let Some(metrics) = metrics {
+            metrics.add_error_metric(&error.kind());
+        };
+        warn!(logger, "Control channel error: {}", error);
+        match error.kind() {
+            ControlChanErrorKind::UnknownCommand { .. } => Reply::new(ReplyCode::CommandSyntaxError, "Command not implemented"),
+            ControlChanErrorKind::UTF8Error => Reply::new(ReplyCode::CommandSyntaxError, "Invalid UTF8 in command"),
+            ControlChanErrorKind::InvalidCommand => Reply::new(ReplyCode::ParameterSyntaxError, "Invalid Parameter"),
+            ControlChanErrorKind::CommandTooLong => Reply::new(ReplyCode::CommandSyntaxError, "Command line too long"),
+            ControlChanErrorKind::ControlChannelTimeout => Reply::new(ReplyCode::ClosingControlConnection, "Session timed out. Closing control connection"),
+            ControlChanErrorKind::NoSecurityContext => Reply::new(ReplyCode::BadCommandSequence, "Use AUTH GSSAPI and ADAT first"),
+            ControlChanErrorKind::SecurityCheckFailed => Reply::new(ReplyCode::FailedSecurityCheck, "Failed security check"),
+            _ => Reply::new(ReplyCode::LocalError, "Unknown internal server error, please try again later"),
+        }
+    }
+}
+
+// Handles the events of a control channel: they are logged, commands that need a logged in user
+// are refused to others and the rest is passed on to the command handlers or turned into replies.
+struct EventHandlerChain<S, U>
+where
+    S: storage::StorageBackend<U> + Send + Sync,
+    U: UserDetail,
+{
+    session: SharedSession<S, U>,
+    authenticator: Arc<dyn Authenticator<U> + Send + Sync>,
+    tls_configured: bool,
+    auth_mechanisms: Arc<Vec<String>>,
+    passive_ports: Range<u16>,
+    tx: Sender<InternalMsg>,
+    local_addr: std::net::SocketAddr,
+    data_bind_ip: Option<IpAddr>,
+    storage_features: Capabilities,
+    proxyloop_msg_tx: Option<ProxyLoopSender<S, U>>,
+    control_connection_info: Option<ConnectionTuple>,
+    custom_commands: Arc<HashMap<String, Arc<dyn CustomCommandHandler<S, U>>>>,
+    site_commands: Arc<HashMap<String, Arc<dyn CustomCommandHandler<S, U>>>>,
+    system_type: Arc<String>,
+    identification: Arc<String>,
+    disabled_commands: Arc<HashSet<String>>,
+    drop_box: bool,
+    logger: Logger,
+}
+
+impl<S, U> EventHandlerChain<S, U>
+where
+    S: 'static + storage::StorageBackend<U> + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+    U: UserDetail + 'static,
+{
+    async fn handle(&self, event: Event) -> Result<Reply, ControlChanError> {
+        self.handle_with_logging(event).await
+    }
+
+    async fn handle_with_logging(&self, event: Event) -> Result<Reply, ControlChanError> {
+        // The secrets passed with PASS and ACCT, and the GSSAPI tokens and protected commands of
+        // ADAT, MIC and ENC, are obscured by their Debug implementation.
+        info!(self.logger, "Processing event {:?}", event);
+        self.handle_with_auth(event).await
+    }
+
+    async fn handle_with_auth(&self, event: Event) -> Result<Reply, ControlChanError> {
+        if let Event::Command(cmd) = &event {
+            if self.disabled_commands.contains(&cmd.verb()) {
+                return Ok(Reply::new(ReplyCode::CommandNotImplemented, "Command disabled"));
+            }
+        }
+        match event {
             // internal messages and the below commands are exempt from auth checks.
             Event::InternalMsg(_)
             | Event::Command(Command::Help)
             | Event::Command(Command::User { .. })
             | Event::Command(Command::Pass { .. })
             | Event::Command(Command::Auth { .. })
+            | Event::Command(Command::Adat { .. })
             | Event::Command(Command::Feat)
-            | Event::Command(Command::Quit) => next(event),
+            | Event::Command(Command::Quit) => self.handle_event(event).await,
             _ => {
-                let r = futures::executor::block_on(async {
-                    let session = session.lock().await;
-                    if session.state != SessionState::WaitCmd {
-                        Ok(Reply::new(ReplyCode::NotLoggedIn, "Please authenticate"))
-                    } else {
-                        Err(())
-                    }
-                });
-                if let Ok(r) = r {
-                    return Ok(r);
+                if self.session.lock().await.state != SessionState::WaitCmd {
+                    return Ok(Reply::new(ReplyCode::NotLoggedIn, "Please authenticate"));
                 }
-                next(event)
+                self.handle_event(event).await
             }
         }
     }
 
-    fn handle_with_logging(next: impl Fn(Event) -> Result<Reply, ControlChanError>) -> impl Fn(Event) -> Result<Reply, ControlChanError> {
-        move |event| {
-            info!("Processing event {:?}", event);
-            next(event)
+    async fn handle_event(&self, event: Event) -> Result<Reply, ControlChanError> {
+        match event {
+            Event::Command(cmd) => {
+                // Clients send NOOP, and some TYPE, to keep the control connection open through
+                // NAT gateways during long transfers. TYPE only applies to later transfers, since
+                // the running one took the type when it started.
+                let allowed_during_transfer = matches!(
+                    cmd,
+                    Command::Abor | Command::Stat { path: None } | Command::Noop | Command::Type { .. } | Command::Quit
+                );
+                if !allowed_during_transfer && self.session.lock().await.transfer_in_progress {
+                    return Ok(Reply::new(
+                        ReplyCode::BadCommandSequence,
+                        "Transfer in progress, only ABOR, STAT, NOOP, TYPE and QUIT are allowed",
+                    ));
+                }
+                // What SIZE, MDTM and MLST remember may no longer be true once the client changed
+                // something.
+                let changes_storage = matches!(
+                    cmd,
+                    Command::Stor { .. }
+                        | Command::Stou
+                        | Command::Dele { .. }
+                        | Command::Rmd { .. }
+                        | Command::Mkd { .. }
+                        | Command::Rnto { .. }
+                        | Command::Site { .. }
+                        | Command::Custom { .. }
+                );
+                if changes_storage {
+                    self.clear_metadata_cache().await;
+                }
+                // RNTO has to follow RNFR immediately, anything else cancels the rename.
+                if !matches!(cmd, Command::Rnto { .. }) {
+                    self.session.lock().await.rename_from = None;
+                }
+                self.handle_command(cmd).await
+            }
+            Event::InternalMsg(msg) => self.handle_internal_msg(msg).await,
         }
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn handle_event(
-        session: SharedSession<S, U>,
-        authenticator: Arc<dyn Authenticator<U> + Send + Sync>,
-        tls_configured: bool,
-        passive_ports: Range<u16>,
-        tx: Sender<InternalMsg>,
-        local_addr: std::net::SocketAddr,
-        storage_features: u32,
-        proxyloop_msg_tx: Option<ProxyLoopSender<S, U>>,
-        control_connection_info: Option<ConnectionTuple>,
-    ) -> impl Fn(Event) -> Result<Reply, ControlChanError> {
-        move |event| -> Result<Reply, ControlChanError> {
-            match event {
-                Event::Command(cmd) => futures::executor::block_on(Self::handle_command(
-                    cmd,
-                    session.clone(),
-                    authenticator.clone(),
-                    tls_configured,
-                    passive_ports.clone(),
-                    tx.clone(),
-                    local_addr,
-                    storage_features,
-                    proxyloop_msg_tx.clone(),
-                    control_connection_info,
-                )),
-                Event::InternalMsg(msg) => futures::executor::block_on(Self::handle_internal_msg(msg, session.clone())),
-            }
-        }
-    }
-
-    #[allow(clippy::too_many_arguments)]
-    async fn handle_command(
-        cmd: Command,
-        session: SharedSession<S, U>,
-        authenticator: Arc<dyn Authenticator<U>>,
-        tls_configured: bool,
-        passive_ports: Range<u16>,
-        tx: Sender<InternalMsg>,
-        local_addr: std::net::SocketAddr,
-        storage_features: u32,
-        proxyloop_msg_tx: Option<ProxyLoopSender<S, U>>,
-        control_connection_info: Option<ConnectionTuple>,
-    ) -> Result<Reply, ControlChanError> {
+    async fn clear_metadata_cache(&self) {
+        if let Some(cache) = &self.session.lock().await.metadata_cache {
+            cache.clear();
+        }
+    }
+
+    async fn handle_command(&self, cmd: Command) -> Result<Reply, ControlChanError> {
         let args = CommandContext {
             cmd: cmd.clone(),
-            session,
-            authenticator,
-            tls_configured,
-            passive_ports,
-            tx,
-            local_addr,
-            storage_features,
-            proxyloop_msg_tx,
-            control_connection_info,
+            session: self.session.clone(),
+            authenticator: self.authenticator.clone(),
+            tls_configured: self.tls_configured,
+            auth_mechanisms: self.auth_mechanisms.clone(),
+            passive_ports: self.passive_ports.clone(),
+            tx: self.tx.clone(),
+            local_addr: self.local_addr,
+            data_bind_ip: self.data_bind_ip,
+            storage_features: self.storage_features,
+            proxyloop_msg_tx: self.proxyloop_msg_tx.clone(),
+            control_connection_info: self.control_connection_info,
+            disabled_commands: self.disabled_commands.clone(),
+            site_commands: self.site_commands.clone(),
+            system_type: self.system_type.clone(),
+            identification: self.identification.clone(),
+            logger: self.logger.clone(),
+        };
+
+        // In a drop box nothing that was uploaded can be downloaded, looked at or changed.
+        if self.drop_box
+            && matches!(
+                cmd,
+                Command::Retr { .. }
+                    | Command::SIZE { .. }
+                    | Command::MDTM { .. }
+                    | Command::Mlst { .. }
+                    | Command::Dele { .. }
+                    | Command::Rmd { .. }
+                    | Command::Rnfr { .. }
+            )
+        {
+            return Ok(Reply::new(ReplyCode::FileError, "Permission denied"));
+        }
+
+        // Commands that need a feature the storage backend lacks aren't implemented as far as the
+        // client is concerned.
+        let required = match &cmd {
+            Command::Site { subcommand, .. } => commands::site_capabilities(subcommand),
+            cmd => commands::command_capabilities(&cmd.verb()),
         };
+        if !self.storage_features.contains(required) {
+            return Ok(Reply::new(ReplyCode::CommandNotImplemented, "Not supported by the selected storage back-end."));
+        }
 
         let handler: Box<dyn CommandHandler<S, U>> = match cmd {
             Command::User { username } => Box::new(commands::User::new(username)),
@@ -732,17 +2854,22 @@ where
             Command::Syst => Box::new(commands::Syst),
             Command::Stat { path } => Box::new(commands::Stat::new(path)),
             Command::Acct { .. } => Box::new(commands::Acct),
-            Command::Type => Box::new(commands::Type),
+            Command::Type { param } => Box::new(commands::Type::new(param)),
             Command::Stru { structure } => Box::new(commands::Stru::new(structure)),
             Command::Mode { mode } => Box::new(commands::Mode::new(mode)),
             Command::Help => Box::new(commands::Help),
             Command::Noop => Box::new(commands::Noop),
             Command::Pasv => Box::new(commands::Pasv::new()),
+            Command::Epsv { param } => Box::new(commands::Epsv::new(param)),
             Command::Port => Box::new(commands::Port),
+            Command::Lpsv => Box::new(commands::Lpsv),
+            Command::Lprt { .. } => Box::new(commands::Lprt),
             Command::Retr { .. } => Box::new(commands::Retr),
             Command::Stor { .. } => Box::new(commands::Stor),
             Command::List { .. } => Box::new(commands::List),
             Command::Nlst { .. } => Box::new(commands::Nlst),
+            Command::Mlsd { .. } => Box::new(commands::Mlsd),
+            Command::Mlst { path } => Box::new(commands::Mlst::new(path)),
             Command::Feat => Box::new(commands::Feat),
             Command::Pwd => Box::new(commands::Pwd),
             Command::Cwd { path } => Box::new(commands::Cwd::new(path)),
@@ -758,32 +2885,98 @@ where
             Command::Rnfr { file } => Box::new(commands::Rnfr::new(file)),
             Command::Rnto { file } => Box::new(commands::Rnto::new(file)),
             Command::Auth { protocol } => Box::new(commands::Auth::new(protocol)),
+            #[cfg(feature = "gssapi")]
+            Command::Adat { token } => Box::new(commands::Adat::new(token)),
+            #[cfg(not(feature = "gssapi"))]
+            Command::Adat { .. } => return Ok(Reply::new(ReplyCode::CommandNotImplemented, "GSSAPI not supported")),
+            // The codec unwraps these, when they reach us the server doesn't support them.
+            Command::Mic { .. } | Command::Enc { .. } => return Ok(Reply::new(ReplyCode::CommandNotImplemented, "GSSAPI not supported")),
             Command::PBSZ {} => Box::new(commands::Pbsz),
             Command::CCC {} => Box::new(commands::Ccc),
             Command::PROT { param } => Box::new(commands::Prot::new(param)),
             Command::SIZE { file } => Box::new(commands::Size::new(file)),
             Command::Rest { offset } => Box::new(commands::Rest::new(offset)),
             Command::MDTM { file } => Box::new(commands::Mdtm::new(file)),
+            Command::Site { subcommand, argument } => match self.site_commands.get(&subcommand) {
+                Some(handler) => return self.custom_reply(handler.handle(&subcommand, &argument, args).await),
+                None => Box::new(commands::Site::new(subcommand, argument)),
+            },
+            Command::Custom { verb, argument } => {
+                return match self.custom_commands.get(&verb) {
+                    Some(handler) => self.custom_reply(handler.handle(&verb, &argument, args).await),
+                    None => Ok(Reply::new(ReplyCode::CommandSyntaxError, "Command not implemented")),
+                };
+            }
         };
 
         handler.handle(args).await
     }
 
-    async fn handle_internal_msg(msg: InternalMsg, session: SharedSession<S, U>) -> Result<Reply, ControlChanError> {
+    // Custom command handlers have no way to reply later, so they must give a reply right away.
+    fn custom_reply(&self, result: Result<Reply, ControlChanError>) -> Result<Reply, ControlChanError> {
+        match result {
+            Ok(Reply::None) => {
+                warn!(self.logger, "Custom command handler gave no reply to the command");
+                Ok(Reply::new(ReplyCode::LocalError, "Local error in processing"))
+            }
+            result => result,
+        }
+    }
+
+    async fn handle_internal_msg(&self, msg: InternalMsg) -> Result<Reply, ControlChanError> {
         use self::InternalMsg::*;
         use SessionState::*;
 
+        let session = &self.session;
+
+        if msg.ends_transfer() {
+            session.lock().await.transfer_in_progress = false;
+            // An upload changed the file, even one that failed halfway.
+            self.clear_metadata_cache().await;
+        }
+
         match msg {
             NotFound => Ok(Reply::new(ReplyCode::FileError, "File not found")),
             PermissionDenied => Ok(Reply::new(ReplyCode::FileError, "Permision denied")),
-            SendingData => Ok(Reply::new(ReplyCode::FileStatusOkay, "Sending Data")),
+            SendingData => {
+                let mut session = session.lock().await;
+                session.transfer_in_progress = true;
+                session.transfer_bytes = 0;
+                Ok(Reply::new(ReplyCode::FileStatusOkay, "Sending Data"))
+            }
+            ReceivingData => {
+                let mut session = session.lock().await;
+                session.transfer_in_progress = true;
+                session.transfer_bytes = 0;
+                Ok(Reply::new(ReplyCode::FileStatusOkay, "Ready to receive data"))
+            }
+            SendingDirectoryList => {
+                let mut session = session.lock().await;
+                session.transfer_in_progress = true;
+                session.transfer_bytes = 0;
+                Ok(Reply::new(ReplyCode::FileStatusOkay, "Sending directory list"))
+            }
             SendData { .. } => {
                 let mut session = session.lock().await;
                 session.start_pos = 0;
                 Ok(Reply::new(ReplyCode::ClosingDataConnection, "Successfully sent"))
             }
+            TransferProgress { bytes, .. } => {
+                let tracker = {
+                    let mut session = session.lock().await;
+                    session.transfer_bytes = bytes;
+                    session.tracker.clone()
+                };
+                tracker.transfer_progressed();
+                Ok(Reply::none())
+            }
             WriteFailed => Ok(Reply::new(ReplyCode::TransientFileError, "Failed to write file")),
-            ConnectionReset => Ok(Reply::new(ReplyCode::ConnectionClosed, "Datachannel unexpectedly closed")),
+            ConnectionReset => Ok(Reply::new(
+                ReplyCode::ConnectionClosed,
+                "Data connection closed by the client, transfer aborted",
+            )),
+            TransferStalled => Ok(Reply::new(ReplyCode::ConnectionClosed, "Data transfer stalled, transfer aborted")),
+            TransferAborted => Ok(Reply::new(ReplyCode::ConnectionClosed, "Transfer aborted")),
             WrittenData { .. } => {
                 let mut session = session.lock().await;
                 session.start_pos = 0;
@@ -808,39 +3001,20 @@ where
                 session.cmd_tls = false;
                 Ok(Reply::none())
             }
-            MkdirSuccess(path) => Ok(Reply::new_with_string(ReplyCode::DirCreated, path.to_string_lossy().to_string())),
+            MkdirSuccess(path) => Ok(Reply::new_with_string(ReplyCode::DirCreated, format!("{} created", path::quote(&path)))),
             MkdirFail => Ok(Reply::new(ReplyCode::FileError, "Failed to create directory")),
             AuthSuccess => {
                 let mut session = session.lock().await;
                 session.state = WaitCmd;
-                Ok(Reply::new(ReplyCode::UserLoggedIn, "User logged in, proceed"))
+                Ok(session.login_reply(ReplyCode::UserLoggedIn, "User logged in, proceed"))
             }
             AuthFailed => Ok(Reply::new(ReplyCode::NotLoggedIn, "Authentication failed")),
-            StorageError(error_type) => match error_type.kind() {
-                ErrorKind::ExceededStorageAllocationError => Ok(Reply::new(ReplyCode::ExceededStorageAllocation, "Exceeded storage allocation")),
-                ErrorKind::FileNameNotAllowedError => Ok(Reply::new(ReplyCode::BadFileName, "File name not allowed")),
-                ErrorKind::InsufficientStorageSpaceError => Ok(Reply::new(ReplyCode::OutOfSpace, "Insufficient storage space")),
-                ErrorKind::LocalError => Ok(Reply::new(ReplyCode::LocalError, "Local error")),
-                ErrorKind::PageTypeUnknown => Ok(Reply::new(ReplyCode::PageTypeUnknown, "Page type unknown")),
-                ErrorKind::TransientFileNotAvailable => Ok(Reply::new(ReplyCode::TransientFileError, "File not found")),
-                ErrorKind::PermanentFileNotAvailable => Ok(Reply::new(ReplyCode::FileError, "File not found")),
-                ErrorKind::PermissionDenied => Ok(Reply::new(ReplyCode::FileError, "Permission denied")),
-            },
-            CommandChannelReply(reply_code, message) => Ok(Reply::new(reply_code, &message)),
-        }
-    }
-
-    fn handle_control_channel_error(error: ControlChanError, with_metrics: bool) -> Reply {
-        if with_metrics {
-            metrics::add_error_metric(&error.kind());
-        };
-        warn!("Control channel error: {}", error);
-        match error.kind() {
-            ControlChanErrorKind::UnknownCommand { .. } => Reply::new(ReplyCode::CommandSyntaxError, "Command not implemented"),
-            ControlChanErrorKind::UTF8Error => Reply::new(ReplyCode::CommandSyntaxError, "Invalid UTF8 in command"),
-            ControlChanErrorKind::InvalidCommand => Reply::new(ReplyCode::ParameterSyntaxError, "Invalid Parameter"),
-            ControlChanErrorKind::ControlChannelTimeout => Reply::new(ReplyCode::ClosingControlConnection, "Session timed out. Closing control connection"),
-            _ => Reply::new(ReplyCode::LocalError, "Unknown internal server error, please try again later"),
+            StorageError(error_type) => {
+                let (code, text) = commands::storage_error_reply(error_type.kind());
+                Ok(Reply::new(code, text))
+            }
+            CommandChannelReply(reply_code, message) => Ok(Reply::new_from_text(reply_code, &message)),
+            ServiceNotAvailable(reason) => Ok(Reply::new_with_string(ReplyCode::ServiceNotAvailable, reason)),
         }
     }
 }
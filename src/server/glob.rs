@@ -0,0 +1,186 @@
+//! Contains the matching of glob patterns: the wildcards that clients use in the arguments of
+//! `LIST` and `NLST`, like `LIST *.log`, and the patterns of the paths that are hidden from them.
+
+use regex::Regex;
+
+// The characters that a backslash can take the special meaning away from.
+const SPECIAL: &[char] = &['*', '?', '[', ']', '\\'];
+
+// A pattern for names or paths. `*` matches any number of characters but slashes, `**` any
+// number of characters, `?` a single character but a slash and `[...]` one of the characters in
+// the brackets, like `[a-z]` or `[!0-9]`. A backslash takes away the special meaning of the
+// character after it.
+#[derive(Clone, Debug)]
+pub(crate) struct Glob {
+    regex: Regex,
+    wildcards: bool,
+}
+
+impl Glob {
+    pub fn new(glob: &str) -> Glob {
+        let mut regex = String::from("^");
+        let mut wildcards = false;
+        let mut chars = glob.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if matches!(chars.peek(), Some(c) if SPECIAL.contains(c)) => {
+                    regex.push_str(&regex::escape(&chars.next().unwrap().to_string()));
+                }
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    wildcards = true;
+                    regex.push_str(".*");
+                }
+                '*' => {
+                    wildcards = true;
+                    regex.push_str("[^/]*");
+                }
+                '?' => {
+                    wildcards = true;
+                    regex.push_str("[^/]");
+                }
+                '[' => match class(&mut chars) {
+                    Some(class) => {
+                        wildcards = true;
+                        regex.push_str(&class);
+                    }
+                    None => regex.push_str(r"\["),
+                },
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+        Glob {
+            // Everything but the wildcards is escaped, so this is always a valid expression.
+            regex: Regex::new(&regex).unwrap(),
+            wildcards,
+        }
+    }
+
+    // Tells if the pattern has wildcards, rather than only matching itself.
+    pub fn has_wildcards(&self) -> bool {
+        self.wildcards
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        self.regex.is_match(name)
+    }
+}
+
+// Turns the rest of a bracket expression, after the `[`, into a character class. Returns None,
+// consuming nothing, if the bracket is not closed.
+fn class(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Option<String> {
+    let rest: String = chars.clone().collect();
+    let negated = rest.starts_with('!');
+    let rest = if negated { &rest[1..] } else { &rest[..] };
+    // A `]` right at the start is part of the class rather than its end.
+    let end = rest.char_indices().skip(1).find(|&(_, c)| c == ']')?.0;
+    let members: Vec<char> = rest[..end].chars().collect();
+    let mut class = String::from(if negated { "[^" } else { "[" });
+    for (i, c) in members.iter().enumerate() {
+        if *c == '-' && i > 0 && i < members.len() - 1 {
+            class.push('-');
+        } else {
+            class.push_str(&regex::escape(&c.to_string()));
+        }
+    }
+    class.push(']');
+    let consumed = usize::from(negated) + rest[..=end].chars().count();
+    for _ in 0..consumed {
+        chars.next();
+    }
+    Some(class)
+}
+
+// Splits the argument of `LIST` or `NLST` into the path to list and the pattern that the names
+// of the entries must match, when the last component of the argument has wildcards. Otherwise
+// the escapes are taken out and the path is returned alone.
+pub(crate) fn split(arg: &str) -> (String, Option<Glob>) {
+    let (dir, name) = match arg.rfind('/') {
+        Some(i) => (&arg[..=i], &arg[i + 1..]),
+        None => ("", arg),
+    };
+    let glob = Glob::new(name);
+    if glob.has_wildcards() {
+        (dir.to_string(), Some(glob))
+    } else {
+        (unescape(arg), None)
+    }
+}
+
+fn unescape(path: &str) -> String {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some(c) if SPECIAL.contains(c)) => unescaped.push(chars.next().unwrap()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split, Glob};
+
+    #[test]
+    fn wildcards() {
+        let glob = Glob::new("data-2024*.log");
+        assert!(glob.is_match("data-2024-01.log"));
+        assert!(glob.is_match("data-2024.log"));
+        assert!(!glob.is_match("data-2023-01.log"));
+
+        let glob = Glob::new("file?.[a-c]");
+        assert!(glob.is_match("file1.b"));
+        assert!(!glob.is_match("file12.b"));
+        assert!(!glob.is_match("file1.d"));
+
+        let glob = Glob::new("[!0-9]*");
+        assert!(glob.is_match("report"));
+        assert!(!glob.is_match("2024"));
+    }
+
+    #[test]
+    fn paths() {
+        let glob = Glob::new("/data/*/meta?");
+        assert!(glob.is_match("/data/a/meta1"));
+        assert!(!glob.is_match("/data/a/b/meta1"));
+        assert!(!glob.is_match("/data/a/meta/"));
+
+        let glob = Glob::new("/data/**/meta?");
+        assert!(glob.is_match("/data/a/b/meta1"));
+        assert!(!glob.is_match("/data/meta1"));
+    }
+
+    #[test]
+    fn no_wildcards() {
+        assert!(!Glob::new("report.txt").has_wildcards());
+        assert!(Glob::new("report.txt").is_match("report.txt"));
+        assert!(!Glob::new(r"report\*.txt").has_wildcards());
+        assert!(Glob::new(r"report\*.txt").is_match("report*.txt"));
+        assert!(!Glob::new("unclosed[bracket").has_wildcards());
+    }
+
+    #[test]
+    fn escapes() {
+        let glob = Glob::new(r"\[draft\]*");
+        assert!(glob.is_match("[draft] report"));
+        assert!(!glob.is_match("d report"));
+    }
+
+    #[test]
+    fn split_argument() {
+        let (path, glob) = split("logs/*.log");
+        assert_eq!(path, "logs/");
+        assert!(glob.unwrap().is_match("server.log"));
+
+        let (path, glob) = split("*.log");
+        assert_eq!(path, "");
+        assert!(glob.is_some());
+
+        let (path, glob) = split(r"logs/what\?.txt");
+        assert_eq!(path, "logs/what?.txt");
+        assert!(glob.is_none());
+    }
+}
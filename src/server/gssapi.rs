@@ -0,0 +1,151 @@
+//! Contains the [`GssapiContext`] trait through which the server authenticates clients with
+//! GSSAPI, as RFC 2228 describes: with `AUTH GSSAPI`, the `ADAT` exchange of security data and
+//! commands protected with `MIC` or `ENC`.
+//!
+//! [`GssapiContext`]: trait.GssapiContext.html
+
+use std::sync::{Arc, Mutex};
+
+/// The result of the operations of a [`GssapiContext`].
+///
+/// [`GssapiContext`]: trait.GssapiContext.html
+pub type GssapiResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// A GSSAPI security context on the side of the server, through which a session authenticates
+/// its client and protects the commands and replies on the control connection. libunftp doesn't
+/// link a GSSAPI implementation itself: implement this trait with the one your platform uses,
+/// for instance Kerberos through the `libgssapi` crate, and set it with [`ServerBuilder::gssapi`].
+///
+/// A context is made for every `AUTH GSSAPI` of a client. After the `ADAT` exchange completed,
+/// clients can send their commands protected with `MIC`, for integrity, or `ENC`, for integrity
+/// and privacy. The replies to protected commands are protected the same way. Clients then log in
+/// with `USER` alone, as the principal that the context authenticated, when the authenticator
+/// accepts it through [`Authenticator::authenticate_principal`]. Otherwise they still have to
+/// send `PASS`.
+///
+/// `step` runs on a thread where blocking is allowed, so it may for instance read a keytab.
+/// `wrap` and `unwrap` are called for every command and reply on the control connection and
+/// should only compute.
+///
+/// [`ServerBuilder::gssapi`]: struct.ServerBuilder.html#method.gssapi
+/// [`Authenticator::authenticate_principal`]: auth/trait.Authenticator.html#method.authenticate_principal
+pub trait GssapiContext: Send {
+    /// Processes a token that the client sent with `ADAT` and returns the token to send back, if
+    /// there is one.
+    fn step(&mut self, token: &[u8]) -> GssapiResult<Option<Vec<u8>>>;
+
+    /// Tells if the context is established, after which the client may send protected commands.
+    fn is_complete(&self) -> bool;
+
+    /// The name of the principal of the client, like `alice@EXAMPLE.COM`, once the context is
+    /// established. Clients can then only log in with this name, with or without the realm.
+    /// When it returns `None`, they can't log in at all.
+    fn principal(&self) -> Option<String>;
+
+    /// Checks the protection of a message from the client and returns the message without it.
+    fn unwrap(&mut self, message: &[u8]) -> GssapiResult<Vec<u8>>;
+
+    /// Protects a message to the client, with integrity protection and, if `encrypt` is true,
+    /// encryption.
+    fn wrap(&mut self, encrypt: bool, message: &[u8]) -> GssapiResult<Vec<u8>>;
+}
+
+// Makes a new context for a session.
+pub(crate) type NewGssapiContext = Arc<dyn Fn() -> Box<dyn GssapiContext> + Send + Sync>;
+
+// How a command was protected, which its replies are protected with too.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Protection {
+    // MIC, answered with 631 replies.
+    Integrity,
+    // ENC, answered with 632 replies.
+    Privacy,
+}
+
+// The GSSAPI state of a session. The command handlers start the context and the codec of the
+// control channel uses it to unwrap commands and wrap replies. The lock is never held while
+// awaiting: the security data exchange takes the context out of it for the time it runs.
+#[derive(Clone)]
+pub(crate) struct GssapiSession {
+    new_context: NewGssapiContext,
+    context: Arc<Mutex<Option<Box<dyn GssapiContext>>>>,
+}
+
+impl GssapiSession {
+    pub fn new(new_context: NewGssapiContext) -> Self {
+        GssapiSession {
+            new_context,
+            context: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // Starts a new context for AUTH GSSAPI, replacing the one of an earlier AUTH.
+    pub fn start(&self) {
+        *self.context.lock().unwrap() = Some((self.new_context)());
+    }
+
+    // Processes an ADAT token on the blocking thread pool. Returns the token for the client and
+    // if the context is complete, or `None` without a context to pass the token to. A context
+    // that fails is dropped, so that the client has to start over with AUTH.
+    pub async fn step(&self, token: Vec<u8>) -> Option<GssapiResult<(Option<Vec<u8>>, bool)>> {
+        let mut ctx = {
+            let mut context = self.context.lock().unwrap();
+            match context.take() {
+                Some(ctx) if !ctx.is_complete() => ctx,
+                other => {
+                    *context = other;
+                    return None;
+                }
+            }
+        };
+        let stepped = tokio::task::spawn_blocking(move || {
+            let result = ctx.step(&token).map(|output| (output, ctx.is_complete()));
+            (ctx, result)
+        })
+        .await;
+        match stepped {
+            Ok((ctx, Ok(result))) => {
+                *self.context.lock().unwrap() = Some(ctx);
+                Some(Ok(result))
+            }
+            Ok((_, Err(err))) => Some(Err(err)),
+            Err(err) => Some(Err(err.into())),
+        }
+    }
+
+    // Tells if the client may log in as the given user: once the context is established, only as
+    // the principal it authenticated, with or without the realm.
+    pub fn authorizes(&self, username: &str) -> bool {
+        match self.context.lock().unwrap().as_ref() {
+            Some(ctx) if ctx.is_complete() => match ctx.principal() {
+                Some(principal) => principal == username || principal.rfind('@').map(|at| &principal[..at]) == Some(username),
+                None => false,
+            },
+            _ => true,
+        }
+    }
+
+    // The principal that the established context authenticated, if there is one.
+    pub fn principal(&self) -> Option<String> {
+        match self.context.lock().unwrap().as_ref() {
+            Some(ctx) if ctx.is_complete() => ctx.principal(),
+            _ => None,
+        }
+    }
+
+    // Unwraps a protected command. Returns `None` when there is no established context.
+    pub fn unwrap(&self, message: &[u8]) -> Option<GssapiResult<Vec<u8>>> {
+        match self.context.lock().unwrap().as_mut() {
+            Some(ctx) if ctx.is_complete() => Some(ctx.unwrap(message)),
+            _ => None,
+        }
+    }
+
+    // Wraps a reply to a protected command.
+    pub fn wrap(&self, protection: Protection, message: &[u8]) -> GssapiResult<Vec<u8>> {
+        match self.context.lock().unwrap().as_mut() {
+            Some(ctx) => ctx.wrap(protection == Protection::Privacy, message),
+            None => Err("No security context".into()),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Contains the `ServerHandle` that is used to control a running `Server`.
+
+use super::ftpserver::Greeting;
+use super::health::{HealthState, HealthStatus};
+use super::ipfilter::IpFilter;
+use super::registry::{SessionInfo, SessionRegistry};
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::watch;
+
+// The settings that can be changed through a `ServerHandle` while the server runs. Sessions take
+// them over when they start, so changes only apply to sessions that start afterwards.
+pub(crate) struct ReloadableSettings {
+    pub greeting: Greeting,
+    pub idle_session_timeout: Duration,
+    pub ip_filter: IpFilter,
+    // The address reported in PASV replies in proxy protocol mode.
+    pub passive_external_ip: Option<IpAddr>,
+}
+
+/// A handle to control a [`Server`] after it has been started with [`listen`] or [`listen_all`].
+/// Handles are cheap to clone and can be obtained with [`Server::handle`] once
+/// [`ServerBuilder::build`] returned the server, before starting it. Besides shutting down the
+/// server, a handle can change some of its settings without a restart. Such changes apply to
+/// sessions that start afterwards.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::Server;
+/// use tokio::runtime::Runtime;
+///
+/// let mut rt = Runtime::new().unwrap();
+/// let server = Server::new_with_fs_root("/srv/ftp").build().unwrap();
+/// let handle = server.handle();
+/// rt.spawn(server.listen("127.0.0.1:2121"));
+/// // ...
+/// handle.shutdown();
+/// ```
+///
+/// [`Server`]: struct.Server.html
+/// [`listen`]: struct.Server.html#method.listen
+/// [`listen_all`]: struct.Server.html#method.listen_all
+/// [`Server::handle`]: struct.Server.html#method.handle
+/// [`ServerBuilder::build`]: struct.ServerBuilder.html#method.build
+#[derive(Clone)]
+pub struct ServerHandle {
+    shutdown_tx: Arc<watch::Sender<bool>>,
+    shutdown_rx: watch::Receiver<bool>,
+    settings: Arc<RwLock<ReloadableSettings>>,
+    sessions: SessionRegistry,
+    health: Arc<HealthState>,
+}
+
+impl ServerHandle {
+    pub(crate) fn new(
+        shutdown_tx: Arc<watch::Sender<bool>>,
+        shutdown_rx: watch::Receiver<bool>,
+        settings: Arc<RwLock<ReloadableSettings>>,
+        sessions: SessionRegistry,
+        health: Arc<HealthState>,
+    ) -> Self {
+        ServerHandle {
+            shutdown_tx,
+            shutdown_rx,
+            settings,
+            sessions,
+            health,
+        }
+    }
+
+    /// Returns the health of the server: whether it listens and accepts connections, whether a
+    /// shutdown was initiated and the outcome of the [`HealthCheck`], if there is one. Use
+    /// [`HealthStatus::is_live`] and [`HealthStatus::is_ready`] to answer liveness and readiness
+    /// probes.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use tokio::runtime::Runtime;
+    ///
+    /// let handle = Server::new_with_fs_root("/srv/ftp").build().unwrap().handle();
+    /// let health = Runtime::new().unwrap().block_on(handle.health());
+    /// // The server isn't listening yet.
+    /// assert!(!health.is_live());
+    /// ```
+    ///
+    /// [`HealthCheck`]: trait.HealthCheck.html
+    /// [`HealthStatus::is_live`]: struct.HealthStatus.html#method.is_live
+    /// [`HealthStatus::is_ready`]: struct.HealthStatus.html#method.is_ready
+    pub async fn health(&self) -> HealthStatus {
+        let shutting_down = *self.shutdown_rx.borrow();
+        self.health.status(shutting_down).await
+    }
+
+    // Like `health().is_live()`, but without running the health check.
+    #[cfg(feature = "http_endpoint")]
+    pub(crate) fn is_live(&self) -> bool {
+        self.health.is_live()
+    }
+
+    /// Returns information about the sessions that are active at this moment, ordered by the time
+    /// the clients connected.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let handle = Server::new_with_fs_root("/srv/ftp").build().unwrap().handle();
+    /// for session in handle.sessions() {
+    ///     println!("{} {:?} from {}", session.id, session.username, session.peer_ip);
+    /// }
+    /// ```
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        self.sessions.sessions()
+    }
+
+    /// Terminates the session with the given ID. A transfer in progress is aborted, the client
+    /// receives a `421` reply and its control connection is closed. Returns `false` if there is no
+    /// session with this ID.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    ///
+    /// let handle = Server::new_with_fs_root("/srv/ftp").build().unwrap().handle();
+    /// for session in handle.sessions() {
+    ///     if session.username.as_deref() == Some("mallory") {
+    ///         handle.kick(&session.id);
+    ///     }
+    /// }
+    /// ```
+    pub fn kick(&self, session_id: &str) -> bool {
+        self.sessions.kick(session_id)
+    }
+
+    /// Changes the greeting that is sent to clients after connecting. See
+    /// [`ServerBuilder::greeting`].
+    ///
+    /// [`ServerBuilder::greeting`]: struct.ServerBuilder.html#method.greeting
+    pub fn set_greeting<T: Into<String>>(&self, greeting: T) {
+        self.settings.write().unwrap().greeting = Greeting::Text(greeting.into());
+    }
+
+    /// Changes the idle session timeout. See [`ServerBuilder::idle_session_timeout`].
+    ///
+    /// [`ServerBuilder::idle_session_timeout`]: struct.ServerBuilder.html#method.idle_session_timeout
+    pub fn set_idle_session_timeout(&self, secs: u64) {
+        self.settings.write().unwrap().idle_session_timeout = Duration::from_secs(secs);
+    }
+
+    /// Replaces the list of networks that clients may not connect from. See
+    /// [`ServerBuilder::deny_ips`]. The list is left as it is if one of the networks is invalid.
+    ///
+    /// [`ServerBuilder::deny_ips`]: struct.ServerBuilder.html#method.deny_ips
+    pub fn set_deny_ips<I, T>(&self, networks: I) -> Result<(), Box<dyn std::error::Error>>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        self.settings.write().unwrap().ip_filter.set_deny(networks)
+    }
+
+    /// Changes the IP address that is reported to clients for passive data connections in proxy
+    /// protocol mode. See [`ServerBuilder::proxy_protocol_mode`].
+    ///
+    /// [`ServerBuilder::proxy_protocol_mode`]: struct.ServerBuilder.html#method.proxy_protocol_mode
+    pub fn set_passive_external_ip(&self, external_ip: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.settings.write().unwrap().passive_external_ip = Some(external_ip.parse()?);
+        Ok(())
+    }
+
+    /// Initiates a graceful shutdown of the server. The server stops accepting new connections,
+    /// and active sessions receive a `421` reply to their next command, after which their control
+    /// connection is closed. The future returned by [`listen`] completes once all sessions are
+    /// gone or the shutdown grace period has passed.
+    ///
+    /// [`listen`]: struct.Server.html#method.listen
+    pub fn shutdown(&self) {
+        // This can only fail when the server is gone already.
+        let _ = self.shutdown_tx.broadcast(true);
+    }
+}
+
+// Completes once a shutdown has been initiated through a `ServerHandle`.
+pub(crate) async fn shutdown_initiated(shutdown_rx: &mut watch::Receiver<bool>) {
+    loop {
+        if *shutdown_rx.borrow() {
+            return;
+        }
+        if shutdown_rx.recv().await.is_none() {
+            // The server, and with it the sender, is gone. Nobody can initiate a shutdown anymore.
+            futures::future::pending::<()>().await;
+        }
+    }
+}
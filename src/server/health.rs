@@ -0,0 +1,121 @@
+//! Contains the health status that tells if a server is alive and ready to serve clients, for
+//! instance to answer the liveness and readiness probes of Kubernetes.
+
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+// How long the backend check may take before we consider the backend unreachable.
+const BACKEND_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Checks if the storage backend, or anything else the server depends on, can be reached. Set it
+/// with [`ServerBuilder::health_check`].
+///
+/// [`ServerBuilder::health_check`]: struct.ServerBuilder.html#method.health_check
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    /// Returns an error if the backend cannot be used. Checks that take longer than five seconds
+    /// count as failed.
+    async fn check(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// The outcome of the [`HealthCheck`] of a server.
+///
+/// [`HealthCheck`]: trait.HealthCheck.html
+#[derive(Clone, Debug, PartialEq)]
+pub enum BackendStatus {
+    /// No health check was configured.
+    Unchecked,
+    /// The check succeeded.
+    Reachable,
+    /// The check failed or timed out, for the given reason.
+    Unreachable(String),
+}
+
+/// The health of a server as returned by [`ServerHandle::health`].
+///
+/// [`ServerHandle::health`]: struct.ServerHandle.html#method.health
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthStatus {
+    /// The server is bound to its addresses.
+    pub listening: bool,
+    /// The loop that accepts connections is running.
+    pub accepting: bool,
+    /// A shutdown was initiated.
+    pub shutting_down: bool,
+    /// The outcome of the health check.
+    pub backend: BackendStatus,
+}
+
+impl HealthStatus {
+    /// Tells if the server is alive, meaning that it listens and accepts connections. A server
+    /// that is not alive should be restarted.
+    pub fn is_live(&self) -> bool {
+        self.listening && self.accepting
+    }
+
+    /// Tells if the server is ready to serve new clients: it is alive, not shutting down and the
+    /// backend can be reached.
+    pub fn is_ready(&self) -> bool {
+        self.is_live() && !self.shutting_down && !matches!(self.backend, BackendStatus::Unreachable(_))
+    }
+}
+
+// What the health status is made of, shared by a server and its handles.
+#[derive(Default)]
+pub(crate) struct HealthState {
+    listening: AtomicBool,
+    accepting: AtomicBool,
+    check: RwLock<Option<Arc<dyn HealthCheck>>>,
+}
+
+impl HealthState {
+    pub fn set_check(&self, check: Arc<dyn HealthCheck>) {
+        *self.check.write().unwrap() = Some(check);
+    }
+
+    pub fn set_listening(&self, listening: bool) {
+        self.listening.store(listening, Ordering::SeqCst);
+    }
+
+    // Marks the accept loop as running until the returned guard is dropped, which also happens
+    // when the loop panics.
+    pub fn accepting(self: &Arc<Self>) -> AcceptingGuard {
+        self.accepting.store(true, Ordering::SeqCst);
+        AcceptingGuard(self.clone())
+    }
+
+    // The part of the status that tells if the server is alive, without running the check.
+    #[cfg(feature = "http_endpoint")]
+    pub fn is_live(&self) -> bool {
+        self.listening.load(Ordering::SeqCst) && self.accepting.load(Ordering::SeqCst)
+    }
+
+    pub async fn status(&self, shutting_down: bool) -> HealthStatus {
+        let check = self.check.read().unwrap().clone();
+        let backend = match check {
+            None => BackendStatus::Unchecked,
+            Some(check) => match tokio::time::timeout(BACKEND_CHECK_TIMEOUT, check.check()).await {
+                Ok(Ok(())) => BackendStatus::Reachable,
+                Ok(Err(err)) => BackendStatus::Unreachable(err.to_string()),
+                Err(_) => BackendStatus::Unreachable(format!("no answer within {:?}", BACKEND_CHECK_TIMEOUT)),
+            },
+        };
+        HealthStatus {
+            listening: self.listening.load(Ordering::SeqCst),
+            accepting: self.accepting.load(Ordering::SeqCst),
+            shutting_down,
+            backend,
+        }
+    }
+}
+
+pub(crate) struct AcceptingGuard(Arc<HealthState>);
+
+impl Drop for AcceptingGuard {
+    fn drop(&mut self) {
+        self.0.accepting.store(false, Ordering::SeqCst);
+        self.0.listening.store(false, Ordering::SeqCst);
+    }
+}
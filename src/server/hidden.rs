@@ -0,0 +1,118 @@
+//! Contains the rules for paths that are hidden from clients: they are left out of directory
+//! listings and cannot be accessed directly.
+
+use super::glob::Glob;
+use std::path::{Component, Path, PathBuf};
+
+// The paths that are hidden from clients, in terms of the paths they see.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct HiddenPaths {
+    // Hide files and directories whose names start with a dot.
+    dotfiles: bool,
+    patterns: Vec<Pattern>,
+}
+
+// A glob pattern. Patterns without a slash match the names of files and directories anywhere,
+// others match whole paths from the root.
+#[derive(Clone, Debug)]
+struct Pattern {
+    glob: Glob,
+    whole_path: bool,
+}
+
+impl Pattern {
+    fn new(glob: &str) -> Self {
+        let whole_path = glob.contains('/');
+        let glob = if whole_path && !glob.starts_with('/') {
+            format!("/{}", glob)
+        } else {
+            glob.to_string()
+        };
+        Pattern {
+            glob: Glob::new(&glob),
+            whole_path,
+        }
+    }
+}
+
+impl HiddenPaths {
+    pub fn dotfiles(mut self) -> Self {
+        self.dotfiles = true;
+        self
+    }
+
+    pub fn patterns<I, T>(mut self, globs: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        self.patterns = globs.into_iter().map(|glob| Pattern::new(glob.as_ref())).collect();
+        self
+    }
+
+    // Tells if nothing is hidden.
+    pub fn is_empty(&self) -> bool {
+        !self.dotfiles && self.patterns.is_empty()
+    }
+
+    // Tells if some patterns match whole paths. Those only see the paths clients use, so a link to
+    // a directory above a hidden path would make it reachable under another name.
+    pub fn has_whole_path_patterns(&self) -> bool {
+        self.patterns.iter().any(|pattern| pattern.whole_path)
+    }
+
+    // Tells if the absolute path is hidden. Everything in a hidden directory is hidden too.
+    pub fn is_hidden(&self, path: &Path) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let mut current = PathBuf::from("/");
+        for component in path.components() {
+            if let Component::Normal(name) = component {
+                current.push(name);
+                let name = name.to_string_lossy();
+                if self.dotfiles && name.starts_with('.') {
+                    return true;
+                }
+                let whole_path = current.to_string_lossy();
+                let matches = |pattern: &Pattern| pattern.glob.is_match(if pattern.whole_path { &whole_path } else { &name });
+                if self.patterns.iter().any(matches) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HiddenPaths;
+    use std::path::Path;
+
+    #[test]
+    fn nothing_is_hidden_by_default() {
+        assert!(!HiddenPaths::default().is_hidden(Path::new("/.trash/file")));
+    }
+
+    #[test]
+    fn dotfiles() {
+        let hidden = HiddenPaths::default().dotfiles();
+        assert!(hidden.is_hidden(Path::new("/.trash")));
+        assert!(hidden.is_hidden(Path::new("/dir/.trash/file")));
+        assert!(!hidden.is_hidden(Path::new("/dir/file.txt")));
+    }
+
+    #[test]
+    fn patterns() {
+        let hidden = HiddenPaths::default().patterns(["*.tmp", "/internal", "data/**/meta?"]);
+        assert!(hidden.is_hidden(Path::new("/dir/upload.tmp")));
+        assert!(hidden.is_hidden(Path::new("/internal/state")));
+        assert!(!hidden.is_hidden(Path::new("/dir/internal")));
+        assert!(hidden.is_hidden(Path::new("/data/a/b/meta1")));
+        assert!(!hidden.is_hidden(Path::new("/data/meta1")));
+        assert!(!hidden.is_hidden(Path::new("/dir/upload.txt")));
+        assert!(hidden.has_whole_path_patterns());
+        assert!(!HiddenPaths::default().patterns(["*.tmp"]).has_whole_path_patterns());
+    }
+}
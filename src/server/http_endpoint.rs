@@ -0,0 +1,83 @@
+//! Contains the optional HTTP listener that serves the prometheus metrics and health checks.
+
+use super::ServerHandle;
+use crate::metrics::Metrics;
+
+use futures::channel::oneshot;
+use hyper::server::{conn::AddrIncoming, Builder};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use slog::{error, info, Logger};
+use std::convert::Infallible;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+// Binds the listener of the endpoint, so that a failure to do so can be reported before the
+// server starts.
+pub(super) fn bind(address: &str) -> Result<Builder<AddrIncoming>, Box<dyn Error + Send + Sync>> {
+    let addr: SocketAddr = address.parse()?;
+    Ok(hyper::Server::try_bind(&addr)?)
+}
+
+// Serves `/metrics`, `/health` and `/health/live` until `stop_rx` completes. The readiness check
+// on `/health` fails as soon as a shutdown was initiated, so that load balancers stop sending
+// clients while the server drains.
+pub(super) async fn serve(builder: Builder<AddrIncoming>, metrics: Option<Arc<Metrics>>, handle: ServerHandle, stop_rx: oneshot::Receiver<()>, logger: Logger) {
+    let error_logger = logger.clone();
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        let handle = handle.clone();
+        let logger = logger.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let metrics = metrics.clone();
+                let handle = handle.clone();
+                let logger = logger.clone();
+                async move { Ok::<_, Infallible>(respond(&request, metrics.as_deref(), &handle, &logger).await) }
+            }))
+        }
+    });
+    let server = builder.serve(make_service);
+    info!(error_logger, "Serving metrics and health checks on http://{}", server.local_addr());
+    if let Err(err) = server.with_graceful_shutdown(async { stop_rx.await.unwrap_or(()) }).await {
+        error!(error_logger, "HTTP endpoint failed: {}", err);
+    }
+}
+
+async fn respond(request: &Request<Body>, metrics: Option<&Metrics>, handle: &ServerHandle, logger: &Logger) -> Response<Body> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => match metrics.map(Metrics::encode) {
+            Some(Ok(body)) => Response::new(Body::from(body)),
+            Some(Err(err)) => {
+                error!(logger, "Could not encode metrics: {}", err);
+                status(StatusCode::INTERNAL_SERVER_ERROR, "Could not encode metrics")
+            }
+            None => status(StatusCode::NOT_FOUND, "Metrics are not enabled"),
+        },
+        (&Method::GET, "/health") => {
+            let health = handle.health().await;
+            if health.is_ready() {
+                status(StatusCode::OK, "OK")
+            } else if health.shutting_down {
+                status(StatusCode::SERVICE_UNAVAILABLE, "Shutting down")
+            } else {
+                status(StatusCode::SERVICE_UNAVAILABLE, "Not ready")
+            }
+        }
+        (&Method::GET, "/health/live") => {
+            if handle.is_live() {
+                status(StatusCode::OK, "OK")
+            } else {
+                status(StatusCode::SERVICE_UNAVAILABLE, "Not alive")
+            }
+        }
+        _ => status(StatusCode::NOT_FOUND, "Not found"),
+    }
+}
+
+fn status(code: StatusCode, message: &'static str) -> Response<Body> {
+    let mut response = Response::new(Body::from(message));
+    *response.status_mut() = code;
+    response
+}
@@ -1,6 +1,8 @@
 pub trait Async2Stream: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin {}
 impl Async2Stream for tokio::net::TcpStream {}
+#[cfg(feature = "ftps")]
 impl Async2Stream for tokio_tls::TlsStream<tokio::net::TcpStream> {}
+#[cfg(feature = "ftps")]
 impl Async2Stream for tokio_tls::TlsStream<Box<dyn Async2Stream>> {}
 
 pub trait AsAsyncIo {
@@ -13,6 +15,7 @@ impl AsAsyncIo for tokio::net::TcpStream {
     }
 }
 
+#[cfg(feature = "ftps")]
 impl AsAsyncIo for tokio_tls::TlsStream<Box<dyn Async2Stream>> {
     fn as_async_io(self) -> Box<dyn Async2Stream> {
         Box::new(self)
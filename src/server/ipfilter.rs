@@ -0,0 +1,136 @@
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+/// Decides whether clients are allowed to connect, based on CIDR-style allow and deny lists.
+///
+/// An address matching the deny list is always refused. If the allow list is not empty, an
+/// address needs to match it in order to be accepted.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IpFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl IpFilter {
+    pub fn allow<I, T>(&mut self, networks: I) -> Result<(), Box<dyn std::error::Error>>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        self.allow.extend(parse_networks(networks)?);
+        Ok(())
+    }
+
+    pub fn deny<I, T>(&mut self, networks: I) -> Result<(), Box<dyn std::error::Error>>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        self.deny.extend(parse_networks(networks)?);
+        Ok(())
+    }
+
+    // Replaces the deny list as a whole.
+    pub fn set_deny<I, T>(&mut self, networks: I) -> Result<(), Box<dyn std::error::Error>>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        self.deny = parse_networks(networks)?;
+        Ok(())
+    }
+
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        let ip = unmap_ipv4(ip);
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+// Accepts both networks in CIDR notation (10.0.0.0/8) and plain addresses (10.0.0.1).
+fn parse_networks<I, T>(networks: I) -> Result<Vec<IpNet>, Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+{
+    networks
+        .into_iter()
+        .map(|n| {
+            let n = n.as_ref().trim();
+            match n.parse::<IpNet>() {
+                Ok(net) => Ok(net),
+                Err(_) => Ok(IpNet::from(n.parse::<IpAddr>()?)),
+            }
+        })
+        .collect()
+}
+
+// A listener bound to an IPv6 wildcard address sees IPv4 clients as IPv4-mapped IPv6 addresses.
+pub(crate) fn unmap_ipv4(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, _, _] => IpAddr::V4(v6.to_ipv4().unwrap()),
+            _ => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IpFilter;
+
+    #[test]
+    fn empty_filter_permits_everything() {
+        let filter = IpFilter::default();
+        assert!(filter.permits("10.0.0.1".parse().unwrap()));
+        assert!(filter.permits("::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn allow_list() {
+        let mut filter = IpFilter::default();
+        filter.allow(vec!["10.0.0.0/8", "2001:db8::/32", "192.168.1.1"]).unwrap();
+        assert!(filter.permits("10.1.2.3".parse().unwrap()));
+        assert!(filter.permits("192.168.1.1".parse().unwrap()));
+        assert!(filter.permits("2001:db8::1".parse().unwrap()));
+        assert!(!filter.permits("192.168.1.2".parse().unwrap()));
+        assert!(!filter.permits("11.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn deny_takes_precedence() {
+        let mut filter = IpFilter::default();
+        filter.allow(vec!["10.0.0.0/8"]).unwrap();
+        filter.deny(vec!["10.0.0.0/24"]).unwrap();
+        assert!(!filter.permits("10.0.0.5".parse().unwrap()));
+        assert!(filter.permits("10.0.1.5".parse().unwrap()));
+    }
+
+    #[test]
+    fn set_deny_replaces_list() {
+        let mut filter = IpFilter::default();
+        filter.deny(vec!["10.0.0.0/8"]).unwrap();
+        filter.set_deny(vec!["192.168.0.0/16"]).unwrap();
+        assert!(filter.permits("10.0.0.1".parse().unwrap()));
+        assert!(!filter.permits("192.168.1.1".parse().unwrap()));
+        assert!(filter.set_deny(vec!["not an ip"]).is_err());
+        assert!(!filter.permits("192.168.1.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses() {
+        let mut filter = IpFilter::default();
+        filter.deny(vec!["127.0.0.0/8"]).unwrap();
+        assert!(!filter.permits("::ffff:127.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn invalid_network() {
+        let mut filter = IpFilter::default();
+        assert!(filter.allow(vec!["10.0.0.0/33"]).is_err());
+        assert!(filter.deny(vec!["not an ip"]).is_err());
+    }
+}
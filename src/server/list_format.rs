@@ -0,0 +1,246 @@
+//! Contains the [`ListFormatter`] trait that decides how the entries of a directory look in the
+//! output of `LIST`, `NLST` and `STAT` with a path.
+//!
+//! [`ListFormatter`]: trait.ListFormatter.html
+
+use crate::storage::Metadata;
+
+use chrono::{DateTime, Local, Utc};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// Formats the entries of directory listings. Set it with [`ServerBuilder::list_formatter`]. The
+/// server uses [`UnixListFormatter`] by default.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::storage::Metadata;
+/// use libunftp::{ListFormatter, Server};
+/// use std::path::Path;
+///
+/// // Lists the names of files with their size in parentheses.
+/// struct Sizes;
+///
+/// impl ListFormatter for Sizes {
+///     fn list_line(&self, path: &Path, metadata: &dyn Metadata) -> String {
+///         let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+///         format!("{} ({} bytes)", name, metadata.len())
+///     }
+/// }
+///
+/// let server = Server::new_with_fs_root("/tmp").list_formatter(Sizes);
+/// ```
+///
+/// [`ServerBuilder::list_formatter`]: struct.ServerBuilder.html#method.list_formatter
+/// [`UnixListFormatter`]: struct.UnixListFormatter.html
+pub trait ListFormatter: Send + Sync {
+    /// Returns the line for the entry in the output of `LIST` and `STAT`, without line ending.
+    fn list_line(&self, path: &Path, metadata: &dyn Metadata) -> String;
+
+    /// Returns the line for the entry in the output of `NLST`, without line ending. Clients
+    /// expect just the name here, which is what this returns unless overridden.
+    fn nlst_line(&self, path: &Path, _metadata: &dyn Metadata) -> String {
+        basename(path)
+    }
+}
+
+/// Formats entries like `ls -l` does, which is what most clients know how to parse.
+///
+/// ```text
+/// -rwxr-xr-x         1000         1000           1234 Oct 16 14:30 report.pdf
+/// ```
+///
+/// Month names are always in English, whatever the locale of the system, since clients parse
+/// them. Times are in UTC unless [`local_time`] is used.
+///
+/// [`local_time`]: #method.local_time
+#[derive(Clone, Debug, Default)]
+pub struct UnixListFormatter {
+    hide_owner: bool,
+    local_time: bool,
+    show_year_after: Option<Duration>,
+}
+
+impl UnixListFormatter {
+    /// Shows `ftp` as the owner and group of every entry instead of the numeric IDs from the
+    /// storage backend.
+    pub fn hide_owner(mut self) -> Self {
+        self.hide_owner = true;
+        self
+    }
+
+    /// Shows the times of entries in the local time zone of the server instead of in UTC.
+    pub fn local_time(mut self) -> Self {
+        self.local_time = true;
+        self
+    }
+
+    /// Shows the year instead of the time of day for entries that were modified longer ago than
+    /// the given age, or that were modified in the future. `ls` does this for entries older than
+    /// six months, and some clients rely on it to know the year of old entries.
+    ///
+    /// ```text
+    /// -rwxr-xr-x         1000         1000           1234 Oct 16  2019 old-report.pdf
+    /// ```
+    pub fn show_year_after(mut self, age: Duration) -> Self {
+        self.show_year_after = Some(age);
+        self
+    }
+
+    fn format_time(&self, time: SystemTime, now: SystemTime) -> String {
+        let recent = match self.show_year_after {
+            Some(age) => now.duration_since(time).map(|elapsed| elapsed <= age).unwrap_or(false),
+            None => true,
+        };
+        let format = if recent { "%b %d %H:%M" } else { "%b %d  %Y" };
+        if self.local_time {
+            DateTime::<Local>::from(time).format(format).to_string()
+        } else {
+            DateTime::<Utc>::from(time).format(format).to_string()
+        }
+    }
+}
+
+impl ListFormatter for UnixListFormatter {
+    fn list_line(&self, path: &Path, metadata: &dyn Metadata) -> String {
+        let filetype = if metadata.is_dir() {
+            "d"
+        } else if metadata.is_symlink() {
+            "l"
+        } else {
+            "-"
+        };
+        let modified = metadata
+            .modified()
+            .map(|time| self.format_time(time, SystemTime::now()))
+            .unwrap_or_else(|_| "-".to_string());
+        let (owner, group) = if self.hide_owner {
+            ("ftp".to_string(), "ftp".to_string())
+        } else {
+            (metadata.uid().to_string(), metadata.gid().to_string())
+        };
+        format!(
+            "{}rwxr-xr-x {:>12} {:>12} {:#14} {:>12} {}",
+            filetype,
+            owner,
+            group,
+            metadata.len(),
+            modified,
+            basename(path)
+        )
+    }
+}
+
+/// Formats entries the way Windows FTP servers do, for clients that only parse that style.
+///
+/// ```text
+/// 10-16-20  02:30PM       <DIR>          reports
+/// 10-16-20  02:30PM                 1234 report.pdf
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct DosListFormatter;
+
+impl ListFormatter for DosListFormatter {
+    fn list_line(&self, path: &Path, metadata: &dyn Metadata) -> String {
+        let modified = metadata
+            .modified()
+            .map(|time| DateTime::<Utc>::from(time).format("%m-%d-%y  %I:%M%p").to_string())
+            .unwrap_or_else(|_| "01-01-70  12:00AM".to_string());
+        if metadata.is_dir() {
+            format!("{}       <DIR>          {}", modified, basename(path))
+        } else {
+            format!("{} {:>20} {}", modified, metadata.len(), basename(path))
+        }
+    }
+}
+
+fn basename(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DosListFormatter, ListFormatter, UnixListFormatter};
+    use crate::storage::{self, Metadata};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    struct Entry {
+        dir: bool,
+        len: u64,
+    }
+
+    impl Metadata for Entry {
+        fn len(&self) -> u64 {
+            self.len
+        }
+
+        fn is_dir(&self) -> bool {
+            self.dir
+        }
+
+        fn is_file(&self) -> bool {
+            !self.dir
+        }
+
+        fn is_symlink(&self) -> bool {
+            false
+        }
+
+        fn modified(&self) -> storage::Result<SystemTime> {
+            // Fri Oct 16 14:30:00 UTC 2020
+            Ok(UNIX_EPOCH + Duration::from_secs(1_602_858_600))
+        }
+
+        fn gid(&self) -> u32 {
+            100
+        }
+
+        fn uid(&self) -> u32 {
+            1000
+        }
+    }
+
+    #[test]
+    fn unix() {
+        let file = Entry { dir: false, len: 1234 };
+        assert_eq!(
+            UnixListFormatter::default().list_line(Path::new("/dir/report.pdf"), &file),
+            "-rwxr-xr-x         1000          100           1234 Oct 16 14:30 report.pdf"
+        );
+        assert_eq!(
+            UnixListFormatter::default().hide_owner().list_line(Path::new("/dir/report.pdf"), &file),
+            "-rwxr-xr-x          ftp          ftp           1234 Oct 16 14:30 report.pdf"
+        );
+    }
+
+    #[test]
+    fn year_of_old_entries() {
+        let formatter = UnixListFormatter::default().show_year_after(Duration::from_secs(180 * 24 * 3600));
+        let modified = UNIX_EPOCH + Duration::from_secs(1_602_858_600);
+        assert_eq!(formatter.format_time(modified, modified + Duration::from_secs(3600)), "Oct 16 14:30");
+        assert_eq!(formatter.format_time(modified, modified + Duration::from_secs(365 * 24 * 3600)), "Oct 16  2020");
+        assert_eq!(formatter.format_time(modified, modified - Duration::from_secs(3600)), "Oct 16  2020");
+    }
+
+    #[test]
+    fn dos() {
+        let dir = Entry { dir: true, len: 0 };
+        let file = Entry { dir: false, len: 1234 };
+        assert_eq!(
+            DosListFormatter.list_line(Path::new("/reports"), &dir),
+            "10-16-20  02:30PM       <DIR>          reports"
+        );
+        assert_eq!(
+            DosListFormatter.list_line(Path::new("/report.pdf"), &file),
+            "10-16-20  02:30PM                 1234 report.pdf"
+        );
+    }
+
+    #[test]
+    fn nlst() {
+        let file = Entry { dir: false, len: 1234 };
+        assert_eq!(DosListFormatter.nlst_line(Path::new("/dir/report.pdf"), &file), "report.pdf");
+    }
+}
@@ -0,0 +1,81 @@
+//! Contains the listing of directories that `LIST`, `NLST`, `MLSD` and `STAT` with a path share.
+
+use super::glob::Glob;
+use super::hidden::HiddenPaths;
+use super::path::{self, PathMapper};
+use crate::auth::UserDetail;
+use crate::server::Session;
+use crate::storage::{self, Metadata};
+
+use futures::prelude::*;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Lists directories the way a session sees them.
+pub(crate) struct Lister<S, U>
+where
+    S: storage::StorageBackend<U>,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+    U: UserDetail,
+{
+    storage: Arc<S>,
+    user: Arc<Option<U>>,
+    path_mapper: Option<Arc<dyn PathMapper<U>>>,
+    drop_box: bool,
+    hidden_paths: Arc<HiddenPaths>,
+}
+
+impl<S, U: Send + Sync + 'static> Lister<S, U>
+where
+    S: storage::StorageBackend<U> + Send + Sync + 'static,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+    U: UserDetail,
+{
+    pub fn new(session: &Session<S, U>) -> Self {
+        Lister {
+            storage: Arc::clone(&session.storage),
+            user: session.user.clone(),
+            path_mapper: session.path_mapper.clone(),
+            drop_box: session.drop_box,
+            hidden_paths: session.hidden_paths.clone(),
+        }
+    }
+
+    // Lists the entries of the directory whose names match the pattern, if any, leaving out the
+    // hidden ones. Directories look empty in a drop box. With `file` set, a path to a file lists
+    // just that file, which scripts use to check whether it exists.
+    pub async fn list(&self, path: PathBuf, pattern: Option<Glob>, file: bool) -> storage::Result<storage::ListStream<S::Metadata>> {
+        if self.drop_box {
+            return Ok(Box::pin(stream::empty()));
+        }
+        let entries = match self.storage.list_stream(&self.user, &path).await {
+            Ok(entries) => entries,
+            Err(err) if !file => return Err(err),
+            Err(err) => match self.storage.metadata(&self.user, &path).await {
+                Ok(metadata) if !metadata.is_dir() => Box::pin(stream::once(future::ready(Ok(storage::Fileinfo { path, metadata })))),
+                _ => return Err(err),
+            },
+        };
+        if self.hidden_paths.is_empty() && pattern.is_none() {
+            return Ok(entries);
+        }
+        let (hidden_paths, path_mapper, user) = (self.hidden_paths.clone(), self.path_mapper.clone(), self.user.clone());
+        Ok(Box::pin(entries.filter(move |entry| {
+            let visible = match entry {
+                Ok(fileinfo) => {
+                    let path = path::to_client(&path_mapper, &user, &fileinfo.path);
+                    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+                    let matches = match &pattern {
+                        Some(pattern) => pattern.is_match(&name),
+                        None => true,
+                    };
+                    matches && !hidden_paths.is_hidden(&path)
+                }
+                Err(_) => true,
+            };
+            future::ready(visible)
+        })))
+    }
+}
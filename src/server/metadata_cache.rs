@@ -0,0 +1,112 @@
+//! Contains the cache of file metadata that a session keeps for `SIZE`, `MDTM` and `MLST`, so
+//! that clients that ask for the size and modification time of many files in a row don't make
+//! the storage backend look up every file more than once.
+
+use crate::storage;
+
+use slog::Logger;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// The number of paths a session keeps the metadata of. Mirroring clients stat whole directories,
+// but a session doesn't need to remember more than the files it is working on.
+const MAX_ENTRIES: usize = 1024;
+
+// The metadata of paths in terms of the storage backend, with the time it was looked up.
+pub(crate) struct MetadataCache<M> {
+    ttl: Duration,
+    entries: Mutex<HashMap<PathBuf, (Instant, Arc<M>)>>,
+}
+
+impl<M> MetadataCache<M> {
+    pub fn new(ttl: Duration) -> Self {
+        MetadataCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Returns the metadata of the path, unless it is not cached or older than the TTL.
+    pub fn get(&self, path: &Path) -> Option<Arc<M>> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(path) {
+            Some((time, metadata)) if time.elapsed() < self.ttl => Some(Arc::clone(metadata)),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&self, path: PathBuf, metadata: Arc<M>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES && !entries.contains_key(&path) {
+            let ttl = self.ttl;
+            entries.retain(|_, (time, _)| time.elapsed() < ttl);
+            if entries.len() >= MAX_ENTRIES {
+                entries.clear();
+            }
+        }
+        entries.insert(path, (Instant::now(), metadata));
+    }
+
+    // Forgets everything, for when the client changed something in storage. Commands like RNTO or
+    // RMD can affect many paths at once, so we don't try to find out which.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+// Looks up the metadata of the path, from the cache if there is one and it has the metadata,
+// otherwise from the storage backend.
+pub(crate) async fn metadata<S, U>(
+    cache: &Option<Arc<MetadataCache<S::Metadata>>>,
+    storage: &S,
+    user: &Option<U>,
+    path: &Path,
+    timeout: Option<Duration>,
+    logger: &Logger,
+) -> storage::Result<Arc<S::Metadata>>
+where
+    S: storage::StorageBackend<U>,
+    U: Send + Sync,
+{
+    if let Some(metadata) = cache.as_ref().and_then(|cache| cache.get(path)) {
+        return Ok(metadata);
+    }
+    let metadata = Arc::new(storage::with_timeout(logger, timeout, storage.metadata(user, path)).await?);
+    if let Some(cache) = cache {
+        cache.insert(path.to_path_buf(), Arc::clone(&metadata));
+    }
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MetadataCache, MAX_ENTRIES};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn entries_expire() {
+        let cache = MetadataCache::new(Duration::from_millis(50));
+        cache.insert(PathBuf::from("/a.txt"), Arc::new(1));
+        assert_eq!(cache.get(Path::new("/a.txt")).as_deref(), Some(&1));
+        assert_eq!(cache.get(Path::new("/b.txt")), None);
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.get(Path::new("/a.txt")), None);
+    }
+
+    #[test]
+    fn size_is_bounded() {
+        let cache = MetadataCache::new(Duration::from_secs(60));
+        for i in 0..=MAX_ENTRIES {
+            cache.insert(PathBuf::from(format!("/{}.txt", i)), Arc::new(i));
+        }
+        assert!(cache.entries.lock().unwrap().len() <= MAX_ENTRIES);
+        assert_eq!(cache.get(Path::new(&format!("/{}.txt", MAX_ENTRIES))).as_deref(), Some(&MAX_ENTRIES));
+
+        cache.clear();
+        assert_eq!(cache.get(Path::new(&format!("/{}.txt", MAX_ENTRIES))), None);
+    }
+}
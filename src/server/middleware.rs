@@ -0,0 +1,113 @@
+//! Contains the [`Middleware`] trait that lets users wrap the handling of the commands that clients
+//! send, for instance to audit them, to rate limit them or to rewrite them.
+//!
+//! [`Middleware`]: trait.Middleware.html
+
+use super::controlchan::command::Command;
+use super::controlchan::ControlChanError;
+use super::Reply;
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// Wraps the handling of the commands of clients. Middleware is added with
+/// [`ServerBuilder::add_middleware`] and runs in the order in which it was added, before the server
+/// checks whether the client is logged in and before the command is handled.
+///
+/// Only commands pass through middleware. Replies that are sent later, like the one at the end of
+/// a transfer, do not.
+///
+/// # Example
+///
+/// ```rust
+/// use async_trait::async_trait;
+/// use libunftp::{ControlChanError, Middleware, Next, Reply, ReplyCode, Request, Server};
+///
+/// // Refuses to delete files.
+/// struct ReadOnly;
+///
+/// #[async_trait]
+/// impl Middleware for ReadOnly {
+///     async fn handle(&self, request: Request, next: Next<'_>) -> Result<Reply, ControlChanError> {
+///         if request.name() == "DELE" {
+///             return Ok(Reply::new(ReplyCode::FileError, "Permission denied"));
+///         }
+///         next.run(request).await
+///     }
+/// }
+///
+/// let server = Server::new_with_fs_root("/tmp").add_middleware(ReadOnly);
+/// ```
+///
+/// [`ServerBuilder::add_middleware`]: struct.ServerBuilder.html#method.add_middleware
+#[async_trait]
+pub trait Middleware: Send + Sync {
+    /// Handles the command of a client. Call `next.run(request)` to pass it on to the next
+    /// middleware or to the server, or return a reply without doing so. Returning an error closes
+    /// the control connection. Middleware has no way to reply later, so a [`Reply::None`] of its
+    /// own is answered with `451` to the client.
+    ///
+    /// [`Reply::None`]: enum.Reply.html#variant.None
+    async fn handle(&self, request: Request, next: Next<'_>) -> Result<Reply, ControlChanError>;
+}
+
+/// A command from a client, on its way to be handled.
+pub struct Request {
+    /// The ID of the session, as found in the logs.
+    pub session_id: String,
+    /// The name the client gave with `USER`, if it did so already.
+    pub username: Option<String>,
+    /// The IP address of the client.
+    pub peer_ip: IpAddr,
+    pub(crate) command: Command,
+}
+
+impl Request {
+    /// The name of the command, for instance `RETR`.
+    pub fn name(&self) -> String {
+        self.command.verb()
+    }
+
+    /// Replaces the command with the one on the given line, for instance `RETR other.txt`, as if
+    /// the client had sent that instead.
+    pub fn rewrite(&mut self, line: &str) -> Result<(), ControlChanError> {
+        self.command = Command::parse(format!("{}\r\n", line.trim_end()))?;
+        Ok(())
+    }
+}
+
+// Shows the command with its arguments as parsed by the server. The secrets passed with `PASS`
+// and `ACCT` are redacted.
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.command)
+    }
+}
+
+/// The rest of the chain of middleware that a [`Request`] goes through, ending at the server.
+///
+/// [`Request`]: struct.Request.html
+pub struct Next<'a> {
+    middlewares: &'a [Arc<dyn Middleware>],
+    endpoint: &'a (dyn Fn(Command) -> BoxFuture<'a, Result<Reply, ControlChanError>> + Send + Sync),
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        middlewares: &'a [Arc<dyn Middleware>],
+        endpoint: &'a (dyn Fn(Command) -> BoxFuture<'a, Result<Reply, ControlChanError>> + Send + Sync),
+    ) -> Self {
+        Next { middlewares, endpoint }
+    }
+
+    /// Passes the request on and returns the reply to it.
+    pub async fn run(self, request: Request) -> Result<Reply, ControlChanError> {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => middleware.handle(request, Next::new(rest, self.endpoint)).await,
+            None => (self.endpoint)(request.command).await,
+        }
+    }
+}
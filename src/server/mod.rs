@@ -1,18 +1,65 @@
 //! Contains the `Server` struct that is used to configure and control a FTP server instance.
 
+mod ascii;
+mod block;
 mod chancomms;
+mod config_error;
 mod controlchan;
 mod datachan;
+mod facts;
+mod filename_policy;
 pub(crate) mod ftpserver;
+mod glob;
+#[cfg(feature = "gssapi")]
+mod gssapi;
+mod handle;
+mod health;
+mod hidden;
+#[cfg(feature = "http_endpoint")]
+mod http_endpoint;
 mod io;
+mod ipfilter;
+mod list_format;
+mod listing;
+mod metadata_cache;
+mod middleware;
+mod observer;
+mod partial_upload;
 mod password;
+mod path;
+#[cfg_attr(not(feature = "proxy_protocol"), allow(dead_code))]
 mod proxy_protocol;
+mod registry;
+mod reply_catalog;
+mod reply_filter;
 mod session;
+mod socket;
+mod spans;
+mod spool;
+mod throttle;
+#[cfg(feature = "ftps")]
 mod tls;
+mod xferlog;
 
 pub(crate) use chancomms::InternalMsg;
+pub use config_error::ConfigError;
 pub(crate) use controlchan::command::Command;
-pub(crate) use controlchan::reply::{Reply, ReplyCode};
-pub(crate) use controlchan::ControlChanErrorKind;
+pub use controlchan::handler::{CommandContext, CustomCommandHandler};
+pub use controlchan::reply::{Reply, ReplyBuilder, ReplyCode};
 pub(crate) use controlchan::Event;
+pub use controlchan::{ControlChanError, ControlChanErrorKind};
+pub use filename_policy::FilenamePolicy;
+#[cfg(feature = "gssapi")]
+pub use gssapi::{GssapiContext, GssapiResult};
+pub use handle::ServerHandle;
+pub use health::{BackendStatus, HealthCheck, HealthStatus};
+pub use list_format::{DosListFormatter, ListFormatter, UnixListFormatter};
+pub use middleware::{Middleware, Next, Request};
+pub use observer::SessionObserver;
+pub use partial_upload::PartialUploads;
+pub use path::PathMapper;
+pub use registry::{SessionInfo, TransferInfo};
+pub use reply_catalog::ReplyCatalog;
+pub use reply_filter::ReplyFilter;
 pub(self) use session::{Session, SessionState};
+pub use socket::SocketOptions;
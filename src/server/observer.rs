@@ -0,0 +1,73 @@
+//! Contains the [`SessionObserver`] trait that lets users follow sessions from connect to
+//! disconnect.
+//!
+//! [`SessionObserver`]: trait.SessionObserver.html
+
+use super::SessionInfo;
+
+/// Gets told when clients connect, log in, transfer files, log out and disconnect, so that applications can keep
+/// track of who is online, enforce policies of their own or emit their own telemetry. Set it with
+/// [`ServerBuilder::session_observer`].
+///
+/// The callbacks are called from the task of the session, so observers that need to do slow
+/// work, like network I/O, should hand it off to a task of their own. All of them do nothing by
+/// default.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::{Server, SessionInfo, SessionObserver};
+///
+/// // Allows a single session per IP address.
+/// struct OnePerAddress(std::sync::Mutex<std::collections::HashSet<std::net::IpAddr>>);
+///
+/// impl SessionObserver for OnePerAddress {
+///     fn on_connect(&self, session: &SessionInfo) -> Result<(), String> {
+///         if self.0.lock().unwrap().insert(session.peer_ip) {
+///             Ok(())
+///         } else {
+///             Err("Only one session per address".to_string())
+///         }
+///     }
+///
+///     fn on_disconnect(&self, session: &SessionInfo) {
+///         self.0.lock().unwrap().remove(&session.peer_ip);
+///     }
+/// }
+///
+/// let server = Server::new_with_fs_root("/tmp").session_observer(OnePerAddress(Default::default()));
+/// ```
+///
+/// [`ServerBuilder::session_observer`]: struct.ServerBuilder.html#method.session_observer
+pub trait SessionObserver: Send + Sync {
+    /// Called when a client connected, before it is greeted. Returning an error refuses the
+    /// session: the client gets the message in a `421` reply and is disconnected, without a call
+    /// to [`on_disconnect`].
+    ///
+    /// [`on_disconnect`]: #method.on_disconnect
+    fn on_connect(&self, _session: &SessionInfo) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called when the client logged in. The session tells the name of the user and when it
+    /// logged in.
+    fn on_login(&self, _session: &SessionInfo) {}
+
+    /// Called about every second while a file is transferred, as long as data moves. The
+    /// [`transfer`] of the session tells how far it got.
+    ///
+    /// [`transfer`]: struct.SessionInfo.html#structfield.transfer
+    fn on_transfer_progress(&self, _session: &SessionInfo) {}
+
+    /// Called when a session in which the client logged in ends, whether the client sent `QUIT`,
+    /// closed the connection, timed out or was kicked, just before [`on_disconnect`].
+    ///
+    /// [`on_disconnect`]: #method.on_disconnect
+    fn on_logout(&self, _session: &SessionInfo) {}
+
+    /// Called when the session of a client that [`on_connect`] accepted ends. The session tells
+    /// how many bytes were transferred in it.
+    ///
+    /// [`on_connect`]: #method.on_connect
+    fn on_disconnect(&self, _session: &SessionInfo) {}
+}
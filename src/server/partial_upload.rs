@@ -0,0 +1,113 @@
+//! Contains the [`PartialUploads`] policy that decides what happens to the files of uploads that
+//! didn't complete.
+//!
+//! [`PartialUploads`]: enum.PartialUploads.html
+
+use crate::auth::UserDetail;
+use crate::storage;
+
+use slog::{info, warn, Logger};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// What to do with the file of an upload that didn't complete because the client aborted it with
+/// `ABOR`, the transfer stalled, the connection to the client broke or the session was
+/// terminated. Set it with [`ServerBuilder::partial_uploads`].
+///
+/// Uploads that failed because of the storage backend are not affected. Clients that resume
+/// interrupted uploads with `REST` and `STOR` need the partial file, so they only work with the
+/// default, `Keep`.
+///
+/// [`ServerBuilder::partial_uploads`]: struct.ServerBuilder.html#method.partial_uploads
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PartialUploads {
+    /// Leave the partial file as it is. This is the default.
+    #[default]
+    Keep,
+    /// Delete the partial file. Files of uploads that were resumed with `REST` are kept, as they
+    /// held data from before the upload.
+    Delete,
+    /// Rename the partial file by adding `.part` to its name, so that it doesn't pass for a
+    /// complete one. An earlier `.part` file of the same name is replaced.
+    Rename,
+}
+
+impl fmt::Display for PartialUploads {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartialUploads::Keep => write!(f, "keep"),
+            PartialUploads::Delete => write!(f, "delete"),
+            PartialUploads::Rename => write!(f, "rename"),
+        }
+    }
+}
+
+impl FromStr for PartialUploads {
+    type Err = String;
+
+    /// Parses `keep`, `delete` or `rename`, in any case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "keep" => Ok(PartialUploads::Keep),
+            "delete" => Ok(PartialUploads::Delete),
+            "rename" => Ok(PartialUploads::Rename),
+            _ => Err("expected keep, delete or rename".to_string()),
+        }
+    }
+}
+
+// The name a partial upload is renamed to.
+fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+// Applies the policy to the file of an upload that started at `start_pos` and didn't complete.
+// Failing to do so is logged, the client has been told about the interrupted upload already.
+pub(crate) async fn clean_up<S, U>(policy: PartialUploads, storage: &S, user: &Option<U>, path: &Path, start_pos: u64, logger: &Logger)
+where
+    S: storage::StorageBackend<U>,
+    U: UserDetail,
+{
+    let result = match policy {
+        PartialUploads::Keep => return,
+        PartialUploads::Delete if start_pos > 0 => return,
+        PartialUploads::Delete => storage.del(user, path).await,
+        PartialUploads::Rename => {
+            // Renames don't replace what is at the new name, so an earlier partial file goes first.
+            match storage.del(user, part_path(path)).await {
+                Err(err) if err.kind() != storage::ErrorKind::PermanentFileNotAvailable => {
+                    warn!(logger, "Could not delete the earlier partial upload {:?}: {}", part_path(path), err);
+                    return;
+                }
+                _ => storage.rename(user, path, &part_path(path)).await,
+            }
+        }
+    };
+    match result {
+        Ok(()) => info!(logger, "Applied the {} policy to the partial upload {:?}", policy, path),
+        Err(err) if err.kind() == storage::ErrorKind::PermanentFileNotAvailable => {}
+        Err(err) => warn!(logger, "Could not {} the partial upload {:?}: {}", policy, path, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{part_path, PartialUploads};
+    use std::path::Path;
+
+    #[test]
+    fn parse() {
+        assert_eq!("Delete".parse(), Ok(PartialUploads::Delete));
+        assert_eq!(" rename".trim().parse(), Ok(PartialUploads::Rename));
+        assert_eq!(PartialUploads::Keep.to_string().parse(), Ok(PartialUploads::Keep));
+        assert!("truncate".parse::<PartialUploads>().is_err());
+    }
+
+    #[test]
+    fn renamed_path() {
+        assert_eq!(part_path(Path::new("/a/b.txt")), Path::new("/a/b.txt.part"));
+    }
+}
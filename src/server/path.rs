@@ -0,0 +1,178 @@
+//! Contains the routine that turns the paths that clients send into paths for the storage backend
+//! and the [`PathMapper`] hook that can translate them further.
+//!
+//! [`PathMapper`]: trait.PathMapper.html
+//
+// Every command that takes a path resolves it here, so that all of them agree on what a path
+// means and none of them can be used to get outside of the root of the session. Paths follow the
+// Trivial Virtual File Store (TVFS) of RFC 3659, which `FEAT` advertises: `/` separates the
+// names in a path, whatever the platform, and a path that starts with it is absolute.
+
+use super::hidden::HiddenPaths;
+use crate::storage::{Error, ErrorKind, Result};
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// Translates the paths that clients see into the paths that the storage backend gets, and back.
+/// This lets the server present a different directory tree to every user without wrapping the
+/// storage backend. Set it with [`ServerBuilder::path_mapper`].
+///
+/// The server calls [`to_storage`] with absolute paths in which `.` and `..` have been resolved
+/// already, so the paths clients send cannot be used to get around the mapping. The current
+/// working directory, and so `PWD`, stays in terms of the paths clients see.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::auth::DefaultUser;
+/// use libunftp::{PathMapper, Server};
+/// use std::path::{Path, PathBuf};
+///
+/// // Shows every user their own directory under /data as /incoming.
+/// struct Incoming;
+///
+/// impl PathMapper<DefaultUser> for Incoming {
+///     fn to_storage(&self, user: &Option<DefaultUser>, path: &Path) -> PathBuf {
+///         match (user, path.strip_prefix("/incoming")) {
+///             (Some(user), Ok(rest)) => Path::new("/data").join(user.to_string()).join("incoming").join(rest),
+///             _ => path.to_path_buf(),
+///         }
+///     }
+///
+///     fn to_client(&self, user: &Option<DefaultUser>, path: &Path) -> PathBuf {
+///         match user.as_ref().map(|user| Path::new("/data").join(user.to_string())) {
+///             Some(home) if path.starts_with(&home) => Path::new("/").join(path.strip_prefix(&home).unwrap()),
+///             _ => path.to_path_buf(),
+///         }
+///     }
+/// }
+///
+/// // The paths the mapper returns are still within the root of the storage backend, so the
+/// // files of alice end up in /srv/ftp/data/alice/incoming.
+/// let server = Server::new_with_fs_root("/srv/ftp").path_mapper(Incoming);
+/// ```
+///
+/// [`ServerBuilder::path_mapper`]: struct.ServerBuilder.html#method.path_mapper
+/// [`to_storage`]: #tymethod.to_storage
+pub trait PathMapper<U>: Send + Sync {
+    /// Returns the path for the storage backend for the given path as the client sees it.
+    fn to_storage(&self, user: &Option<U>, path: &Path) -> PathBuf;
+
+    /// Returns the path as the client sees it for the given path of the storage backend. Used for
+    /// the entries of directory listings.
+    fn to_client(&self, user: &Option<U>, path: &Path) -> PathBuf;
+}
+
+// Resolves the path the client sent, relative to the current working directory, into an absolute
+// path without `.` and `..` components. Going up from the root stays at the root, like it does on
+// a filesystem. Only `/` separates names, repeated and trailing ones are ignored. Paths containing
+// NUL or other control characters are not allowed.
+pub(crate) fn resolve<P: AsRef<Path>>(cwd: &Path, path: P) -> Result<PathBuf> {
+    let path = path.as_ref().to_string_lossy();
+    // The working directory was resolved here before, so it consists of names only.
+    let mut names: Vec<String> = if path.starts_with('/') {
+        vec![]
+    } else {
+        cwd.components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect()
+    };
+    for name in path.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => {
+                names.pop();
+            }
+            name if name.chars().any(char::is_control) => return Err(Error::from(ErrorKind::FileNameNotAllowedError)),
+            name => names.push(name.to_string()),
+        }
+    }
+    Ok(names.iter().fold(PathBuf::from("/"), |resolved, name| resolved.join(name)))
+}
+
+// Resolves the path like `resolve` does for the commands that take a path. Hidden paths are
+// reported as not found.
+pub(crate) fn resolve_visible<P: AsRef<Path>>(cwd: &Path, hidden_paths: &HiddenPaths, path: P) -> Result<PathBuf> {
+    let path = resolve(cwd, path)?;
+    if hidden_paths.is_hidden(&path) {
+        return Err(Error::from(ErrorKind::PermanentFileNotAvailable));
+    }
+    Ok(path)
+}
+
+// Translates a resolved path for the storage backend, if a path mapper is set.
+pub(crate) fn to_storage<U>(mapper: &Option<Arc<dyn PathMapper<U>>>, user: &Option<U>, path: PathBuf) -> PathBuf {
+    match mapper {
+        Some(mapper) => mapper.to_storage(user, &path),
+        None => path,
+    }
+}
+
+// Translates a path of the storage backend, like those of directory entries, back into the
+// absolute path the client sees. Backends may return paths relative to their root.
+pub(crate) fn to_client<U>(mapper: &Option<Arc<dyn PathMapper<U>>>, user: &Option<U>, path: &Path) -> PathBuf {
+    let path = Path::new("/").join(path);
+    match mapper {
+        Some(mapper) => mapper.to_client(user, &path),
+        None => path,
+    }
+}
+
+// Puts a path in double quotes the way RFC 959 wants it in `257` replies, doubling the double
+// quotes that are part of it so that clients can tell where it ends.
+pub(crate) fn quote(path: &Path) -> String {
+    format!("\"{}\"", path.to_string_lossy().replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote, resolve};
+    use crate::storage::ErrorKind;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn relative_and_absolute_paths() {
+        assert_eq!(resolve(Path::new("/"), "file.txt").unwrap(), PathBuf::from("/file.txt"));
+        assert_eq!(resolve(Path::new("/dir"), "sub/file.txt").unwrap(), PathBuf::from("/dir/sub/file.txt"));
+        assert_eq!(resolve(Path::new("/dir"), "/other/file.txt").unwrap(), PathBuf::from("/other/file.txt"));
+    }
+
+    #[test]
+    fn dots_are_resolved() {
+        assert_eq!(resolve(Path::new("/dir/sub"), "./../file.txt").unwrap(), PathBuf::from("/dir/file.txt"));
+        assert_eq!(resolve(Path::new("/dir"), "sub/./more/..").unwrap(), PathBuf::from("/dir/sub"));
+    }
+
+    #[test]
+    fn root_cannot_be_escaped() {
+        assert_eq!(resolve(Path::new("/dir"), "../../../etc/passwd").unwrap(), PathBuf::from("/etc/passwd"));
+        assert_eq!(resolve(Path::new("/"), "..").unwrap(), PathBuf::from("/"));
+    }
+
+    #[test]
+    fn only_slashes_separate_names() {
+        assert_eq!(resolve(Path::new("/dir"), "sub//file.txt").unwrap(), PathBuf::from("/dir/sub/file.txt"));
+        assert_eq!(resolve(Path::new("/dir"), "sub/").unwrap(), PathBuf::from("/dir/sub"));
+        assert_eq!(resolve(Path::new("/dir"), "//other").unwrap(), PathBuf::from("/other"));
+        assert_eq!(resolve(Path::new("/"), r"a\b.txt").unwrap(), PathBuf::from("/").join(r"a\b.txt"));
+        assert_eq!(resolve(Path::new("/dir"), "").unwrap(), PathBuf::from("/dir"));
+    }
+
+    #[test]
+    fn quotes_are_doubled() {
+        assert_eq!(quote(Path::new("/dir")), "\"/dir\"");
+        assert_eq!(quote(Path::new("/say \"hi\"")), "\"/say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn control_characters_are_rejected() {
+        for path in &["file\0.txt", "dir/line\nbreak", "bell\x07"] {
+            let err = resolve(Path::new("/"), path).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::FileNameNotAllowedError, "{:?}", path);
+        }
+    }
+}
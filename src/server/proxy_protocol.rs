@@ -1,17 +1,24 @@
 use super::session::SharedSession;
 use crate::auth::UserDetail;
+use crate::metrics::Metrics;
 use crate::storage;
 
+#[cfg(feature = "proxy_protocol")]
 use bytes::Bytes;
 use lazy_static::*;
-use log::warn;
+#[cfg(feature = "proxy_protocol")]
 use proxy_protocol::version1::ProxyAddressFamily;
+#[cfg(feature = "proxy_protocol")]
 use proxy_protocol::ProxyHeader;
 use rand::rngs::OsRng;
 use rand::RngCore;
+use slog::{info, warn, Logger};
 use std::collections::HashMap;
 use std::net::IpAddr;
 use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+#[cfg(feature = "proxy_protocol")]
 use tokio::io::AsyncReadExt;
 use tokio::sync::Mutex;
 
@@ -19,14 +26,34 @@ lazy_static! {
     static ref OS_RNG: Mutex<OsRng> = Mutex::new(OsRng);
 }
 
+#[cfg(feature = "proxy_protocol")]
 #[derive(Debug)]
 pub enum ProxyError {
     CrlfError,
     HeaderSize,
     NotProxyHdr,
     DecodeError,
-    IPv4Required,
+    UnknownAddressFamily,
     UnsupportedVersion,
+    ConnectionClosed,
+    IOError(std::io::Error),
+}
+
+#[cfg(feature = "proxy_protocol")]
+impl std::fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProxyError::IOError(err) => write!(f, "I/O error while reading the proxy header: {}", err),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+#[cfg(feature = "proxy_protocol")]
+impl From<std::io::Error> for ProxyError {
+    fn from(err: std::io::Error) -> Self {
+        ProxyError::IOError(err)
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -48,6 +75,7 @@ impl ConnectionTuple {
     }
 }
 
+#[cfg(feature = "proxy_protocol")]
 async fn read_proxy_header(tcp_stream: &mut tokio::net::TcpStream) -> Result<ProxyHeader, ProxyError> {
     let mut pbuf = vec![0; 108];
     let mut rbuf = vec![0; 108];
@@ -55,7 +83,10 @@ async fn read_proxy_header(tcp_stream: &mut tokio::net::TcpStream) -> Result<Pro
     let mut i = 0;
 
     loop {
-        let n = read_half.peek(&mut pbuf).await.unwrap();
+        let n = read_half.peek(&mut pbuf).await?;
+        if n == 0 {
+            return Err(ProxyError::ConnectionClosed);
+        }
         match pbuf.iter().position(|b| *b == b'\n') {
             Some(pos) => {
                 // invalid header size
@@ -63,7 +94,7 @@ async fn read_proxy_header(tcp_stream: &mut tokio::net::TcpStream) -> Result<Pro
                     return Err(ProxyError::HeaderSize);
                 }
 
-                read_half.read(&mut rbuf[i..=i + pos]).await.unwrap();
+                read_half.read(&mut rbuf[i..=i + pos]).await?;
 
                 // make sure the message ends with crlf or it will panic
                 if rbuf[pos - 1] != 0x0d {
@@ -83,13 +114,14 @@ async fn read_proxy_header(tcp_stream: &mut tokio::net::TcpStream) -> Result<Pro
                     return Err(ProxyError::NotProxyHdr);
                 }
 
-                read_half.read(&mut rbuf[i..i + n]).await.unwrap();
+                read_half.read(&mut rbuf[i..i + n]).await?;
                 i += n;
             }
         }
     }
 }
 
+#[cfg(feature = "proxy_protocol")]
 pub async fn get_peer_from_proxy_header(tcp_stream: &mut tokio::net::TcpStream) -> Result<ConnectionTuple, ProxyError> {
     let proxyhdr = match read_proxy_header(tcp_stream).await {
         Ok(v) => v,
@@ -106,10 +138,10 @@ pub async fn get_peer_from_proxy_header(tcp_stream: &mut tokio::net::TcpStream)
             destination_port,
             ..
         } => {
-            if family == ProxyAddressFamily::IPv4 {
+            if family == ProxyAddressFamily::IPv4 || family == ProxyAddressFamily::IPv6 {
                 Ok(ConnectionTuple::new(source, source_port, destination, destination_port))
             } else {
-                Err(ProxyError::IPv4Required)
+                Err(ProxyError::UnknownAddressFamily)
             }
         }
         _ => Err(ProxyError::UnsupportedVersion),
@@ -122,14 +154,38 @@ pub fn construct_proxy_hash_key(connection: &ConnectionTuple, port: u16) -> Stri
     format!("{}.{}", connection.from_ip, port)
 }
 
+// A passive port reserved for a session. Clients that never connect to it, for instance because
+// they abandoned the transfer, would keep it reserved forever, so it expires after a while.
+struct Reservation<S, U>
+where
+    S: storage::StorageBackend<U> + Send + Sync,
+    U: UserDetail,
+{
+    session: SharedSession<S, U>,
+    expires_at: Instant,
+}
+
+impl<S, U> Reservation<S, U>
+where
+    S: storage::StorageBackend<U> + Send + Sync,
+    U: UserDetail,
+{
+    fn is_expired(&self, now: Instant) -> bool {
+        now >= self.expires_at
+    }
+}
+
 /// Connect clients to the right data channel
 pub struct ProxyProtocolSwitchboard<S, U>
 where
     S: storage::StorageBackend<U> + Send + Sync,
     U: UserDetail,
 {
-    switchboard: HashMap<String, Option<SharedSession<S, U>>>,
+    switchboard: HashMap<String, Reservation<S, U>>,
     port_range: Range<u16>,
+    reservation_ttl: Duration,
+    logger: Logger,
+    metrics: Option<Arc<Metrics>>,
 }
 
 #[derive(Debug)]
@@ -145,25 +201,49 @@ where
     S: storage::StorageBackend<U> + Send + Sync,
     U: UserDetail + 'static,
 {
-    pub fn new(passive_ports: Range<u16>) -> Self {
+    pub fn new(logger: Logger, passive_ports: Range<u16>, reservation_ttl: Duration, metrics: Option<Arc<Metrics>>) -> Self {
         let board = HashMap::new();
         Self {
             switchboard: board,
             port_range: passive_ports,
+            reservation_ttl,
+            logger,
+            metrics,
+        }
+    }
+
+    // Called after every change to the reservations.
+    fn update_reserved_ports_metric(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.set_reserved_passive_ports(self.switchboard.len());
         }
     }
 
     fn try_and_claim(&mut self, hash: String, session_arc: SharedSession<S, U>) -> Result<(), ProxyProtocolError> {
+        let now = Instant::now();
         match self.switchboard.get(&hash) {
-            Some(_) => Err(ProxyProtocolError::EntryNotAvailable),
-            None => match self.switchboard.insert(hash, Some(session_arc)) {
-                Some(_) => {
-                    warn!("This is a data race condition. This shouldn't happen");
-                    // just return Ok anyway however
-                    Ok(())
-                }
-                None => Ok(()),
-            },
+            Some(reservation) if !reservation.is_expired(now) => Err(ProxyProtocolError::EntryNotAvailable),
+            _ => {
+                let reservation = Reservation {
+                    session: session_arc,
+                    expires_at: now + self.reservation_ttl,
+                };
+                self.switchboard.insert(hash, reservation);
+                self.update_reserved_ports_metric();
+                Ok(())
+            }
+        }
+    }
+
+    /// Removes the reservations of clients that did not connect in time, freeing their ports.
+    pub fn remove_expired(&mut self) {
+        let now = Instant::now();
+        let before = self.switchboard.len();
+        self.switchboard.retain(|_, reservation| !reservation.is_expired(now));
+        let removed = before - self.switchboard.len();
+        if removed > 0 {
+            info!(self.logger, "Released {} expired passive port reservations", removed);
+            self.update_reserved_ports_metric();
         }
     }
 
@@ -174,9 +254,9 @@ where
     pub fn unregister(&mut self, connection: &ConnectionTuple) {
         let hash = Self::get_hash_with_connection(connection);
         match self.switchboard.remove(&hash) {
-            Some(_) => (),
+            Some(_) => self.update_reserved_ports_metric(),
             None => {
-                warn!("Entry already removed?");
+                warn!(self.logger, "Entry already removed?");
             }
         }
     }
@@ -185,15 +265,15 @@ where
         let hash = Self::get_hash_with_connection(connection);
 
         match self.switchboard.get(&hash) {
-            Some(session) => session.clone(),
-            None => None,
+            Some(reservation) if !reservation.is_expired(Instant::now()) => Some(reservation.session.clone()),
+            _ => None,
         }
     }
 
-    /// based on source ip of the client, select a free entry
-    /// but initialize it to None
-    // TODO: set a TTL on the hashmap entries
+    /// based on source ip of the client, select a free entry. The reservation expires when the
+    /// client doesn't connect to the port within the reservation TTL.
     pub async fn reserve_next_free_port(&mut self, session_arc: SharedSession<S, U>) -> Result<u16, ProxyProtocolError> {
+        self.remove_expired();
         let rng_length = self.port_range.end - self.port_range.start;
 
         let mut rng = OS_RNG.lock().await;
@@ -211,7 +291,80 @@ where
             }
         }
         // out of tries
-        println!("Out of tries!");
+        if let Some(metrics) = &self.metrics {
+            metrics.inc_passive_port_reservation_failure();
+        }
         Err(ProxyProtocolError::MaxRetriesError)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionTuple, ProxyProtocolSwitchboard};
+    use crate::auth::DefaultUser;
+    #[cfg(feature = "metrics")]
+    use crate::metrics::Metrics;
+    use crate::server::Session;
+    use crate::storage::filesystem::Filesystem;
+    #[cfg(feature = "metrics")]
+    use prometheus::Registry;
+    #[cfg(feature = "metrics")]
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::runtime::Runtime;
+    use tokio::sync::Mutex;
+
+    fn session() -> Arc<Mutex<Session<Filesystem, DefaultUser>>> {
+        let mut session = Session::new(Arc::new(Filesystem::new(std::env::temp_dir())));
+        let ip = "10.0.0.2".parse().unwrap();
+        session.control_connection_info = Some(ConnectionTuple::new(ip, 40000, ip, 2121));
+        Arc::new(Mutex::new(session))
+    }
+
+    fn logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    #[test]
+    fn reservation_blocks_port_until_it_expires() {
+        Runtime::new().unwrap().block_on(async {
+            let mut switchboard = ProxyProtocolSwitchboard::new(logger(), 5000..5001, Duration::from_secs(60), None);
+            assert_eq!(switchboard.reserve_next_free_port(session()).await.unwrap(), 5000);
+            assert!(switchboard.reserve_next_free_port(session()).await.is_err());
+
+            let mut switchboard = ProxyProtocolSwitchboard::new(logger(), 5000..5001, Duration::from_secs(0), None);
+            assert_eq!(switchboard.reserve_next_free_port(session()).await.unwrap(), 5000);
+            assert_eq!(switchboard.reserve_next_free_port(session()).await.unwrap(), 5000);
+            let ip = "10.0.0.2".parse().unwrap();
+            let connection = ConnectionTuple::new(ip, 40001, ip, 5000);
+            assert!(switchboard.get_session_by_incoming_data_connection(&connection).await.is_none());
+            switchboard.remove_expired();
+            assert!(switchboard.switchboard.is_empty());
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn reservation_metrics() {
+        Runtime::new().unwrap().block_on(async {
+            let registry = Registry::new();
+            let metrics = Arc::new(Metrics::new(&registry, "", HashMap::new()).unwrap());
+            let value = |name: &str| {
+                let family = registry.gather().into_iter().find(|family| family.get_name() == name).unwrap();
+                let metric = &family.get_metric()[0];
+                (metric.get_gauge().get_value() + metric.get_counter().get_value()) as i64
+            };
+            let mut switchboard = ProxyProtocolSwitchboard::new(logger(), 5000..5001, Duration::from_secs(60), Some(metrics));
+            switchboard.reserve_next_free_port(session()).await.unwrap();
+            assert_eq!(value("ftp_passive_ports_reserved"), 1);
+
+            assert!(switchboard.reserve_next_free_port(session()).await.is_err());
+            assert_eq!(value("ftp_passive_port_reservation_failures_total"), 1);
+
+            let ip = "10.0.0.2".parse().unwrap();
+            switchboard.unregister(&ConnectionTuple::new(ip, 40001, ip, 5000));
+            assert_eq!(value("ftp_passive_ports_reserved"), 0);
+        });
+    }
+}
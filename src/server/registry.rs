@@ -0,0 +1,302 @@
+//! Keeps track of the active sessions of a server so that they can be listed and terminated through
+//! a `ServerHandle`.
+
+use super::handle::shutdown_initiated;
+use super::SessionObserver;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::watch;
+
+/// Information about an active session as returned by [`ServerHandle::sessions`].
+///
+/// [`ServerHandle::sessions`]: struct.ServerHandle.html#method.sessions
+#[derive(Clone, Debug)]
+pub struct SessionInfo {
+    /// The ID of the session, as found in the logs.
+    pub id: String,
+    /// The name the client logged in with, or `None` if it did not log in (yet).
+    pub username: Option<String>,
+    /// The IP address of the client.
+    pub peer_ip: IpAddr,
+    /// When the client connected.
+    pub connected_at: SystemTime,
+    /// When the user logged in.
+    pub logged_in_at: Option<SystemTime>,
+    /// The file transfer in progress, if any.
+    pub transfer: Option<TransferInfo>,
+    /// The number of bytes moved over data connections during the session, including those of the
+    /// transfer in progress.
+    pub bytes_transferred: u64,
+}
+
+/// A file transfer that is in progress.
+#[derive(Clone, Debug)]
+pub struct TransferInfo {
+    /// The FTP command that started the transfer, `RETR` or `STOR`.
+    pub command: &'static str,
+    /// The path of the file, relative to the storage root.
+    pub path: PathBuf,
+    /// When the transfer started.
+    pub started_at: SystemTime,
+    /// The number of bytes moved so far.
+    pub bytes: u64,
+}
+
+struct Entry {
+    info: SessionInfo,
+    // Counts the bytes of the transfer in progress. The data channel adds to it as the data flows.
+    transfer_bytes: Option<Arc<AtomicU64>>,
+    // Tells the session to terminate.
+    kick_tx: watch::Sender<bool>,
+}
+
+impl Entry {
+    fn snapshot(&self) -> SessionInfo {
+        let mut info = self.info.clone();
+        if let (Some(transfer), Some(bytes)) = (&mut info.transfer, &self.transfer_bytes) {
+            transfer.bytes = bytes.load(Ordering::Relaxed);
+            info.bytes_transferred += transfer.bytes;
+        }
+        info
+    }
+}
+
+// The sessions of a server, shared by the server and its handles.
+#[derive(Clone, Default)]
+pub(crate) struct SessionRegistry(Arc<Mutex<HashMap<String, Entry>>>);
+
+impl SessionRegistry {
+    pub fn register(&self, id: String, peer_ip: IpAddr) -> SessionTracker {
+        let info = SessionInfo {
+            id: id.clone(),
+            username: None,
+            peer_ip,
+            connected_at: SystemTime::now(),
+            logged_in_at: None,
+            transfer: None,
+            bytes_transferred: 0,
+        };
+        let (kick_tx, kick_rx) = watch::channel(false);
+        let entry = Entry {
+            info,
+            transfer_bytes: None,
+            kick_tx,
+        };
+        self.0.lock().unwrap().insert(id.clone(), entry);
+        SessionTracker {
+            id,
+            registry: self.clone(),
+            kick_rx,
+            observer: None,
+        }
+    }
+
+    // Returns the sessions ordered by the time they connected.
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> = self.0.lock().unwrap().values().map(Entry::snapshot).collect();
+        sessions.sort_by_key(|info| info.connected_at);
+        sessions
+    }
+
+    // Tells the session with the given ID to terminate. Returns false if there is no such session.
+    pub fn kick(&self, id: &str) -> bool {
+        match self.0.lock().unwrap().get(id) {
+            Some(entry) => entry.kick_tx.broadcast(true).is_ok(),
+            None => false,
+        }
+    }
+}
+
+// Updates the registry entry of a single session and tells the observer about logins and the
+// end of the session. Trackers that did not come from a registry do nothing.
+#[derive(Clone)]
+pub(crate) struct SessionTracker {
+    id: String,
+    registry: SessionRegistry,
+    kick_rx: watch::Receiver<bool>,
+    observer: Option<Arc<dyn SessionObserver>>,
+}
+
+impl Default for SessionTracker {
+    fn default() -> Self {
+        SessionTracker {
+            id: String::new(),
+            registry: SessionRegistry::default(),
+            kick_rx: watch::channel(false).1,
+            observer: None,
+        }
+    }
+}
+
+impl SessionTracker {
+    // Sets the observer once it accepted the session.
+    pub fn observer(mut self, observer: Option<Arc<dyn SessionObserver>>) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    // The session as it is now, or `None` if the tracker did not come from a registry.
+    pub fn info(&self) -> Option<SessionInfo> {
+        self.registry.0.lock().unwrap().get(&self.id).map(Entry::snapshot)
+    }
+
+    fn update<F: FnOnce(&mut Entry)>(&self, f: F) {
+        if let Some(entry) = self.registry.0.lock().unwrap().get_mut(&self.id) {
+            f(entry);
+        }
+    }
+
+    pub fn logged_in(&self, username: String) {
+        self.update(|entry| {
+            entry.info.username = Some(username);
+            entry.info.logged_in_at = Some(SystemTime::now());
+        });
+        // Observers may look at the registry through a server handle, so they are called without
+        // holding its lock.
+        if let (Some(observer), Some(info)) = (&self.observer, self.info()) {
+            observer.on_login(&info);
+        }
+    }
+
+    pub fn transfer_started(&self, command: &'static str, path: PathBuf, bytes: Arc<AtomicU64>) {
+        self.update(|entry| {
+            entry.info.transfer = Some(TransferInfo {
+                command,
+                path,
+                started_at: SystemTime::now(),
+                bytes: 0,
+            });
+            entry.transfer_bytes = Some(bytes);
+        });
+    }
+
+    // Tells the observer how far the transfer in progress got.
+    pub fn transfer_progressed(&self) {
+        if let (Some(observer), Some(info)) = (&self.observer, self.info()) {
+            if info.transfer.is_some() {
+                observer.on_transfer_progress(&info);
+            }
+        }
+    }
+
+    pub fn transfer_ended(&self) {
+        self.update(|entry| {
+            if let Some(bytes) = entry.transfer_bytes.take() {
+                entry.info.bytes_transferred += bytes.load(Ordering::Relaxed);
+            }
+            entry.info.transfer = None;
+        });
+    }
+
+    // Completes when the session got kicked through `ServerHandle::kick`.
+    pub async fn kicked(&self) {
+        let mut kick_rx = self.kick_rx.clone();
+        shutdown_initiated(&mut kick_rx).await
+    }
+
+    pub fn deregister(&self) {
+        let entry = self.registry.0.lock().unwrap().remove(&self.id);
+        if let (Some(observer), Some(entry)) = (&self.observer, entry) {
+            let info = entry.snapshot();
+            if info.logged_in_at.is_some() {
+                observer.on_logout(&info);
+            }
+            observer.on_disconnect(&info);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionRegistry;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn tracks_session_lifecycle() {
+        let registry = SessionRegistry::default();
+        let tracker = registry.register("s1".to_string(), "127.0.0.1".parse().unwrap());
+        let sessions = registry.sessions();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, "s1");
+        assert!(sessions[0].username.is_none());
+
+        tracker.logged_in("alice".to_string());
+        let bytes = Arc::new(AtomicU64::new(0));
+        tracker.transfer_started("RETR", "file.txt".into(), bytes.clone());
+        bytes.fetch_add(10, Ordering::Relaxed);
+        let info = registry.sessions().remove(0);
+        assert_eq!(info.username.as_deref(), Some("alice"));
+        assert!(info.logged_in_at.is_some());
+        assert_eq!(info.transfer.unwrap().bytes, 10);
+        assert_eq!(info.bytes_transferred, 10);
+
+        bytes.fetch_add(5, Ordering::Relaxed);
+        tracker.transfer_ended();
+        let info = registry.sessions().remove(0);
+        assert!(info.transfer.is_none());
+        assert_eq!(info.bytes_transferred, 15);
+
+        tracker.deregister();
+        assert!(registry.sessions().is_empty());
+    }
+
+    #[test]
+    fn observer() {
+        use crate::server::{SessionInfo, SessionObserver};
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Events(Mutex<Vec<String>>);
+
+        impl SessionObserver for Events {
+            fn on_login(&self, session: &SessionInfo) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("login {}", session.username.as_deref().unwrap_or_default()));
+            }
+
+            fn on_logout(&self, session: &SessionInfo) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("logout {}", session.username.as_deref().unwrap_or_default()));
+            }
+
+            fn on_disconnect(&self, session: &SessionInfo) {
+                self.0.lock().unwrap().push(format!("disconnect {}", session.id));
+            }
+        }
+
+        let registry = SessionRegistry::default();
+        let events = Arc::new(Events::default());
+        let observer: Arc<dyn SessionObserver> = events.clone();
+        let tracker = registry
+            .register("s1".to_string(), "127.0.0.1".parse().unwrap())
+            .observer(Some(observer.clone()));
+        assert_eq!(tracker.info().unwrap().id, "s1");
+        tracker.logged_in("alice".to_string());
+        tracker.deregister();
+        // Sessions that didn't log in don't log out.
+        registry
+            .register("s2".to_string(), "127.0.0.1".parse().unwrap())
+            .observer(Some(observer))
+            .deregister();
+        assert_eq!(*events.0.lock().unwrap(), vec!["login alice", "logout alice", "disconnect s1", "disconnect s2"]);
+    }
+
+    #[test]
+    fn kick() {
+        let registry = SessionRegistry::default();
+        let tracker = registry.register("s1".to_string(), "127.0.0.1".parse().unwrap());
+        assert!(!registry.kick("s2"));
+        assert!(registry.kick("s1"));
+        Runtime::new().unwrap().block_on(tracker.kicked());
+    }
+}
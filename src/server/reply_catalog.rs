@@ -0,0 +1,77 @@
+//! Contains the `ReplyCatalog` that allows changing the text of the replies sent to clients.
+
+use super::Reply;
+use std::collections::HashMap;
+
+/// A `ReplyCatalog` maps the messages that the server sends to the client onto replacement
+/// texts. This allows rebranding, localizing or sanitizing the server's messages without
+/// changing the command handlers. Reply codes are never changed.
+///
+/// Messages are identified by their default text, for example `"User logged in, proceed"` or
+/// `"File not found"`. Messages that contain dynamic content, such as the reply to `PWD` or `PASV`,
+/// and multi-line replies are passed through unchanged.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::{ReplyCatalog, Server};
+///
+/// let catalog = ReplyCatalog::new()
+///     .message("User logged in, proceed", "Gebruiker ingelogd")
+///     .message("File not found", "Bestand niet gevonden");
+/// let server = Server::new_with_fs_root("/tmp").reply_catalog(catalog);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ReplyCatalog {
+    messages: HashMap<String, String>,
+}
+
+impl ReplyCatalog {
+    /// Creates an empty catalog that leaves all messages unchanged.
+    pub fn new() -> Self {
+        ReplyCatalog::default()
+    }
+
+    /// Replaces the message with the given default text by `text`.
+    pub fn message<D: Into<String>, T: Into<String>>(mut self, default_text: D, text: T) -> Self {
+        self.messages.insert(default_text.into(), text.into());
+        self
+    }
+
+    pub(crate) fn apply(&self, reply: Reply) -> Reply {
+        match reply {
+            Reply::CodeAndMsg { code, msg } => match self.messages.get(&msg) {
+                Some(text) => Reply::CodeAndMsg { code, msg: text.clone() },
+                None => Reply::CodeAndMsg { code, msg },
+            },
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReplyCatalog;
+    use crate::server::{Reply, ReplyCode};
+
+    #[test]
+    fn replaces_known_messages() {
+        let catalog = ReplyCatalog::new().message("File not found", "No such file");
+        match catalog.apply(Reply::new(ReplyCode::FileError, "File not found")) {
+            Reply::CodeAndMsg { code, msg } => {
+                assert_eq!(u32::from(code), 550);
+                assert_eq!(msg, "No such file");
+            }
+            _ => panic!("Expected a single line reply"),
+        }
+    }
+
+    #[test]
+    fn leaves_other_messages_alone() {
+        let catalog = ReplyCatalog::new().message("File not found", "No such file");
+        match catalog.apply(Reply::new(ReplyCode::FileError, "Permission denied")) {
+            Reply::CodeAndMsg { msg, .. } => assert_eq!(msg, "Permission denied"),
+            _ => panic!("Expected a single line reply"),
+        }
+    }
+}
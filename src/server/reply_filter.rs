@@ -0,0 +1,87 @@
+//! Contains the [`ReplyFilter`] trait that lets users rewrite the replies the server sends.
+//!
+//! [`ReplyFilter`]: trait.ReplyFilter.html
+
+use super::{Reply, ReplyCatalog};
+
+use std::sync::Arc;
+
+/// Rewrites the replies that the server sends to clients, for instance to leave out the details
+/// of errors, to brand them or to translate them. Set it with [`ServerBuilder::reply_filter`].
+///
+/// Unlike [`Middleware`], which only sees the replies to commands, the filter sees every reply,
+/// including the greeting and the replies that are sent when a transfer ends. It runs last, after
+/// the [`ReplyCatalog`] has been applied, just before the reply is written to the client. Metrics
+/// and the audit trail record the reply as it was before the filter changed it.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::{Reply, ReplyCode, ReplyFilter, Server};
+///
+/// // Doesn't tell clients why a file action failed.
+/// struct Terse;
+///
+/// impl ReplyFilter for Terse {
+///     fn filter(&self, _command: Option<&str>, reply: Reply) -> Reply {
+///         match reply {
+///             Reply::CodeAndMsg { code, .. } if code == ReplyCode::FileError => Reply::new(code, "Action failed"),
+///             reply => reply,
+///         }
+///     }
+/// }
+///
+/// let server = Server::new_with_fs_root("/tmp").reply_filter(Terse);
+/// ```
+///
+/// [`ServerBuilder::reply_filter`]: struct.ServerBuilder.html#method.reply_filter
+/// [`Middleware`]: trait.Middleware.html
+/// [`ReplyCatalog`]: struct.ReplyCatalog.html
+pub trait ReplyFilter: Send + Sync {
+    /// Returns the reply to send instead of the given one. `command` is the name of the command
+    /// that the reply answers, for instance `RETR`, or `None` for replies that don't answer a
+    /// command, like the greeting or the reply at the end of a transfer.
+    fn filter(&self, command: Option<&str>, reply: Reply) -> Reply;
+}
+
+// Turns a reply into the one that is sent to the client: the text from the catalog first, then
+// the filter. There is nothing to rewrite in the absence of a reply.
+pub(crate) fn outgoing(catalog: &ReplyCatalog, filter: &Option<Arc<dyn ReplyFilter>>, command: Option<&str>, reply: Reply) -> Reply {
+    match (reply, filter) {
+        (Reply::None, _) => Reply::None,
+        (reply, Some(filter)) => filter.filter(command, catalog.apply(reply)),
+        (reply, None) => catalog.apply(reply),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{outgoing, ReplyFilter};
+    use crate::server::{Reply, ReplyCatalog, ReplyCode};
+    use std::sync::Arc;
+
+    struct Verb;
+
+    impl ReplyFilter for Verb {
+        fn filter(&self, command: Option<&str>, reply: Reply) -> Reply {
+            match reply {
+                Reply::CodeAndMsg { code, msg } => Reply::new_with_string(code, format!("{} ({})", msg, command.unwrap_or("-"))),
+                reply => reply,
+            }
+        }
+    }
+
+    #[test]
+    fn filter_runs_after_catalog() {
+        let catalog = ReplyCatalog::new().message("File not found", "No such file");
+        let filter: Option<Arc<dyn ReplyFilter>> = Some(Arc::new(Verb));
+        match outgoing(&catalog, &filter, Some("RETR"), Reply::new(ReplyCode::FileError, "File not found")) {
+            Reply::CodeAndMsg { msg, .. } => assert_eq!(msg, "No such file (RETR)"),
+            _ => panic!("Expected a single line reply"),
+        }
+        match outgoing(&catalog, &filter, None, Reply::new(ReplyCode::ClosingDataConnection, "Successfully sent")) {
+            Reply::CodeAndMsg { msg, .. } => assert_eq!(msg, "Successfully sent (-)"),
+            _ => panic!("Expected a single line reply"),
+        }
+    }
+}
@@ -3,14 +3,36 @@
 
 use super::chancomms::InternalMsg;
 use super::controlchan::command::Command;
+use super::controlchan::commands::{ModeParam, StruParam, TypeParam};
+use super::controlchan::{Reply, ReplyCode};
+use super::facts::Fact;
+use super::filename_policy::FilenamePolicy;
+use super::ftpserver::DEFAULT_STALLED_TRANSFER_TIMEOUT_SECS;
+#[cfg(feature = "gssapi")]
+use super::gssapi::GssapiSession;
+use super::hidden::HiddenPaths;
+use super::list_format::{ListFormatter, UnixListFormatter};
+use super::metadata_cache::MetadataCache;
+use super::partial_upload::PartialUploads;
+use super::path::{self, PathMapper};
 use super::proxy_protocol::ConnectionTuple;
-use crate::metrics;
+use super::registry::SessionTracker;
+use super::socket::SocketOptions;
+use super::spans::SessionSpan;
+use super::spool::UploadSpool;
+use super::throttle::{RateLimiter, TransferLimiter};
+use super::xferlog::Xferlog;
+use crate::auth::UserDetail;
+use crate::metrics::Metrics;
+use crate::notification::Notifier;
 use crate::storage;
 
 use futures::channel::mpsc::Receiver;
 use futures::channel::mpsc::Sender;
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(PartialEq)]
 pub enum SessionState {
@@ -29,6 +51,9 @@ where
     S::File: tokio::io::AsyncRead + Send,
     S::Metadata: storage::Metadata,
 {
+    // Uniquely identifies the session in logs and, if revealed, to the user.
+    pub id: String,
+    pub reveal_id: bool,
     pub user: Arc<Option<U>>,
     pub username: Option<String>,
     pub storage: Arc<S>,
@@ -39,18 +64,85 @@ where
     pub control_msg_tx: Option<Sender<InternalMsg>>,
     pub control_connection_info: Option<ConnectionTuple>,
     pub cwd: std::path::PathBuf,
-    pub rename_from: Option<PathBuf>,
+    // The path that RNFR accepted and when, until RNTO or any other command.
+    pub rename_from: Option<(PathBuf, Instant)>,
     pub state: SessionState,
     pub certs_file: Option<PathBuf>,
     pub certs_password: Option<String>,
     // True if the command channel is in secure mode
     pub cmd_tls: bool,
-    // True if the data channel is in secure mode.
+    // True if the data channel is in secure mode. Each data connection takes the mode that was
+    // set when it was opened.
     pub data_tls: bool,
-    pub collect_metrics: bool,
+    // Once the data channel is in secure mode it may not go back to plaintext.
+    pub data_downgrade_forbidden: bool,
+    // The GSSAPI state that AUTH GSSAPI and ADAT work on, if the server supports GSSAPI.
+    #[cfg(feature = "gssapi")]
+    pub gssapi: Option<GssapiSession>,
+    pub metrics: Option<Arc<Metrics>>,
     // The starting byte for a STOR or RETR command. Set by the _Restart of Interrupted Transfer (REST)_
     // command to support resume functionality.
     pub start_pos: u64,
+    // The representation type, transfer mode and file structure that the client negotiated with
+    // TYPE, MODE and STRU. Unlike RFC 959 prescribes we start in binary mode, since that is what
+    // clients of libunftp have always gotten.
+    pub transfer_type: TypeParam,
+    pub transfer_mode: ModeParam,
+    pub file_structure: StruParam,
+    // Set by `EPSV ALL` after which the client may not use any other command to set up data connections.
+    pub epsv_all: bool,
+    // Set from the preliminary reply of a transfer until it ended. Only ABOR, STAT, NOOP, TYPE and
+    // QUIT are accepted in the meantime.
+    pub transfer_in_progress: bool,
+    // The bytes that the transfer in progress moved so far, as last reported by the data channel.
+    pub transfer_bytes: u64,
+    // Limits the bandwidth of data transfers. Shared by all sessions of the server.
+    pub bandwidth_limiter: Option<Arc<RateLimiter>>,
+    // Limit the bandwidth of uploads and downloads in this session only.
+    pub upload_limiter: Option<Arc<RateLimiter>>,
+    pub download_limiter: Option<Arc<RateLimiter>>,
+    // Transfers are aborted when no data moved over the data connection for this long.
+    pub stalled_transfer_timeout: Duration,
+    // The message of the day that is shown to users after logging in.
+    pub login_message: Option<String>,
+    // Logs records about this session.
+    pub logger: slog::Logger,
+    // Keeps the information about this session that `ServerHandle::sessions` returns up to date.
+    pub tracker: SessionTracker,
+    // The span that commands and transfers of this session are traced in.
+    pub span: SessionSpan,
+    // The IP address of the client.
+    pub peer_ip: IpAddr,
+    // Records the transfers of this session, if enabled.
+    pub xferlog: Option<Xferlog>,
+    // Emits the file events of this session, if enabled.
+    pub notifier: Option<Notifier>,
+    // Where uploads are spooled before they go to the storage backend, if enabled.
+    pub upload_spool: Option<UploadSpool>,
+    // What happens to the files of uploads that were interrupted.
+    pub partial_uploads: PartialUploads,
+    // The TCP options set on the data connections of this session.
+    pub data_socket_options: SocketOptions,
+    // Formats the entries of directory listings.
+    pub list_formatter: Arc<dyn ListFormatter>,
+    // Translates the paths clients see into paths for the storage backend, if set.
+    pub path_mapper: Option<Arc<dyn PathMapper<U>>>,
+    // Directory listings are empty in a drop box.
+    pub drop_box: bool,
+    // Uploads may not overwrite existing files.
+    pub no_clobber: bool,
+    // Decides which names new files and directories may get, if set.
+    pub filename_policy: Option<Arc<dyn FilenamePolicy>>,
+    // The facts that MLSD and MLST show, as selected with OPTS MLST.
+    pub mlst_facts: Vec<Fact>,
+    // The paths that are hidden from the client.
+    pub hidden_paths: Arc<HiddenPaths>,
+    // How long calls to the storage backend may take, if limited.
+    pub storage_timeout: Option<Duration>,
+    // Keeps the metadata that SIZE, MDTM and MLST looked up for a while, if enabled.
+    pub metadata_cache: Option<Arc<MetadataCache<S::Metadata>>>,
+    // Limits the number of concurrent transfers of the server, if enabled.
+    pub transfer_limiter: Option<TransferLimiter>,
 }
 
 impl<S, U: Send + Sync + 'static> Session<S, U>
@@ -61,6 +153,8 @@ where
 {
     pub(super) fn new(storage: Arc<S>) -> Self {
         Session {
+            id: String::new(),
+            reveal_id: false,
             user: Arc::new(None),
             username: None,
             storage,
@@ -77,24 +171,234 @@ where
             certs_password: Option::None,
             cmd_tls: false,
             data_tls: false,
-            collect_metrics: false,
+            data_downgrade_forbidden: false,
+            #[cfg(feature = "gssapi")]
+            gssapi: None,
+            metrics: None,
             start_pos: 0,
+            transfer_type: TypeParam::Image,
+            transfer_mode: ModeParam::Stream,
+            file_structure: StruParam::File,
+            epsv_all: false,
+            transfer_in_progress: false,
+            transfer_bytes: 0,
+            bandwidth_limiter: None,
+            upload_limiter: None,
+            download_limiter: None,
+            stalled_transfer_timeout: Duration::from_secs(DEFAULT_STALLED_TRANSFER_TIMEOUT_SECS),
+            login_message: None,
+            logger: slog::Logger::root(slog::Discard, slog::o!()),
+            tracker: SessionTracker::default(),
+            span: SessionSpan::none(),
+            peer_ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            xferlog: None,
+            notifier: None,
+            upload_spool: None,
+            partial_uploads: PartialUploads::Keep,
+            data_socket_options: SocketOptions::default(),
+            list_formatter: Arc::new(UnixListFormatter::default()),
+            path_mapper: None,
+            drop_box: false,
+            no_clobber: false,
+            filename_policy: None,
+            mlst_facts: Fact::DEFAULT.to_vec(),
+            hidden_paths: Arc::new(HiddenPaths::default()),
+            storage_timeout: None,
+            metadata_cache: None,
+            transfer_limiter: None,
         }
     }
 
+    pub(super) fn id(mut self, id: String, reveal: bool) -> Self {
+        self.id = id;
+        self.reveal_id = reveal;
+        self
+    }
+
     pub(super) fn ftps(mut self, certs_file: Option<PathBuf>, password: Option<String>) -> Self {
         self.certs_file = certs_file;
         self.certs_password = password;
         self
     }
 
-    pub(super) fn metrics(mut self, collect_metrics: bool) -> Self {
-        if collect_metrics {
-            metrics::inc_session();
+    pub(super) fn data_downgrade_forbidden(mut self, forbidden: bool) -> Self {
+        self.data_downgrade_forbidden = forbidden;
+        self
+    }
+
+    #[cfg(feature = "gssapi")]
+    pub(super) fn gssapi(mut self, gssapi: Option<GssapiSession>) -> Self {
+        self.gssapi = gssapi;
+        self
+    }
+
+    pub(super) fn bandwidth_limiter(mut self, limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.bandwidth_limiter = limiter;
+        self
+    }
+
+    pub(super) fn session_bandwidth_limits(mut self, upload: Option<u64>, download: Option<u64>) -> Self {
+        self.upload_limiter = upload.map(|limit| Arc::new(RateLimiter::new(limit)));
+        self.download_limiter = download.map(|limit| Arc::new(RateLimiter::new(limit)));
+        self
+    }
+
+    pub(super) fn stalled_transfer_timeout(mut self, timeout: Duration) -> Self {
+        self.stalled_transfer_timeout = timeout;
+        self
+    }
+
+    pub(super) fn login_message(mut self, message: Option<String>) -> Self {
+        self.login_message = message;
+        self
+    }
+
+    pub(super) fn logger(mut self, logger: slog::Logger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    pub(super) fn tracker(mut self, tracker: SessionTracker) -> Self {
+        self.tracker = tracker;
+        self
+    }
+
+    pub(super) fn peer_ip(mut self, peer_ip: IpAddr) -> Self {
+        self.peer_ip = peer_ip;
+        self
+    }
+
+    pub(super) fn xferlog(mut self, xferlog: Option<Xferlog>) -> Self {
+        self.xferlog = xferlog;
+        self
+    }
+
+    pub(super) fn notifier(mut self, notifier: Option<Notifier>) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    pub(super) fn upload_spool(mut self, upload_spool: Option<UploadSpool>) -> Self {
+        self.upload_spool = upload_spool;
+        self
+    }
+
+    pub(super) fn partial_uploads(mut self, policy: PartialUploads) -> Self {
+        self.partial_uploads = policy;
+        self
+    }
+
+    pub(super) fn data_socket_options(mut self, options: SocketOptions) -> Self {
+        self.data_socket_options = options;
+        self
+    }
+
+    pub(super) fn list_formatter(mut self, formatter: Arc<dyn ListFormatter>) -> Self {
+        self.list_formatter = formatter;
+        self
+    }
+
+    pub(super) fn path_mapper(mut self, mapper: Option<Arc<dyn PathMapper<U>>>) -> Self {
+        self.path_mapper = mapper;
+        self
+    }
+
+    pub(super) fn drop_box(mut self, drop_box: bool) -> Self {
+        self.drop_box = drop_box;
+        self
+    }
+
+    pub(super) fn no_clobber(mut self, no_clobber: bool) -> Self {
+        self.no_clobber = no_clobber;
+        self
+    }
+
+    pub(super) fn filename_policy(mut self, policy: Option<Arc<dyn FilenamePolicy>>) -> Self {
+        self.filename_policy = policy;
+        self
+    }
+
+    pub(super) fn hidden_paths(mut self, hidden_paths: Arc<HiddenPaths>) -> Self {
+        self.hidden_paths = hidden_paths;
+        self
+    }
+
+    pub(super) fn storage_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.storage_timeout = timeout;
+        self
+    }
+
+    pub(super) fn metadata_cache(mut self, ttl: Option<Duration>) -> Self {
+        self.metadata_cache = ttl.map(|ttl| Arc::new(MetadataCache::new(ttl)));
+        self
+    }
+
+    pub(super) fn transfer_limiter(mut self, limiter: Option<TransferLimiter>) -> Self {
+        self.transfer_limiter = limiter;
+        self
+    }
+
+    pub(super) fn span(mut self, span: SessionSpan) -> Self {
+        self.span = span;
+        self
+    }
+
+    pub(super) fn metrics(mut self, metrics: Option<Arc<Metrics>>) -> Self {
+        if let Some(metrics) = &metrics {
+            metrics.inc_session();
         }
-        self.collect_metrics = collect_metrics;
+        self.metrics = metrics;
         self
     }
+
+    // Resolves the path the client sent against the current working directory. Hidden paths are
+    // reported as not found.
+    pub fn client_path<P: AsRef<std::path::Path>>(&self, path: P) -> storage::Result<PathBuf> {
+        path::resolve_visible(&self.cwd, &self.hidden_paths, path)
+    }
+
+    // Resolves the path the client sent like client_path does and translates it for the storage
+    // backend.
+    pub fn storage_path<P: AsRef<std::path::Path>>(&self, path: P) -> storage::Result<PathBuf> {
+        self.client_path(path).map(|path| path::to_storage(&self.path_mapper, &self.user, path))
+    }
+
+    // Makes the user the one that is logged in to the session, with the limits the user has.
+    pub fn log_in(&mut self, user: U)
+    where
+        U: UserDetail,
+    {
+        let username = self.username.clone().unwrap_or_default();
+        self.span.logged_in(&username);
+        self.tracker.logged_in(username);
+        if let Some(limit) = user.upload_bandwidth_limit() {
+            self.upload_limiter = Some(Arc::new(RateLimiter::new(limit)));
+        }
+        if let Some(limit) = user.download_bandwidth_limit() {
+            self.download_limiter = Some(Arc::new(RateLimiter::new(limit)));
+        }
+        if let Some(no_clobber) = user.no_clobber() {
+            self.no_clobber = no_clobber;
+        }
+        self.user = Arc::new(Some(user));
+    }
+
+    // The reply that tells the client it is logged in, preceded by the login message if there is
+    // one.
+    pub fn login_reply(&self, code: ReplyCode, text: &str) -> Reply {
+        match &self.login_message {
+            Some(message) => Reply::multiline(code, message.lines().chain(std::iter::once(text))),
+            None => Reply::new(code, text),
+        }
+    }
+
+    // Tells if the filename policy allows the name of the file or directory at the given path.
+    pub fn filename_allowed<P: AsRef<std::path::Path>>(&self, path: P) -> bool {
+        match (&self.filename_policy, path.as_ref().file_name()) {
+            (Some(policy), Some(name)) => policy.allows(&name.to_string_lossy()),
+            _ => true,
+        }
+    }
 }
 
 impl<S, U: Send + Sync> Drop for Session<S, U>
@@ -104,9 +408,10 @@ where
     S::Metadata: storage::Metadata,
 {
     fn drop(&mut self) {
-        if self.collect_metrics {
+        self.tracker.deregister();
+        if let Some(metrics) = &self.metrics {
             // Decrease the sessions metrics gauge when the session goes out of scope.
-            metrics::dec_session();
+            metrics.dec_session();
         }
     }
 }
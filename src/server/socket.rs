@@ -0,0 +1,112 @@
+//! Contains the TCP options that the server sets on the sockets of control and data connections.
+
+use slog::{warn, Logger};
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+/// TCP options for the sockets of control or data connections. Options that are not set keep the
+/// defaults of the operating system. Set them with [`ServerBuilder::control_socket_options`] and
+/// [`ServerBuilder::data_socket_options`].
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::SocketOptions;
+/// use std::time::Duration;
+///
+/// let options = SocketOptions::default()
+///     .nodelay(true)
+///     .keepalive(Duration::from_secs(60))
+///     .send_buffer_size(256 * 1024)
+///     .recv_buffer_size(256 * 1024);
+/// ```
+///
+/// [`ServerBuilder::control_socket_options`]: struct.ServerBuilder.html#method.control_socket_options
+/// [`ServerBuilder::data_socket_options`]: struct.ServerBuilder.html#method.data_socket_options
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SocketOptions {
+    nodelay: Option<bool>,
+    keepalive: Option<Duration>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+}
+
+impl SocketOptions {
+    /// Enables or disables `TCP_NODELAY`. Enabling it sends small writes, like replies on the
+    /// control connection, without waiting to combine them.
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Enables TCP keepalive, with probes sent after the connection was idle for the given time.
+    /// This keeps connections open through firewalls and NAT devices that drop idle ones.
+    pub fn keepalive(mut self, idle: Duration) -> Self {
+        self.keepalive = Some(idle);
+        self
+    }
+
+    /// Sets the size of the send buffer (`SO_SNDBUF`) in bytes.
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets the size of the receive buffer (`SO_RCVBUF`) in bytes.
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    // Sets the options on the socket. Options that cannot be set are logged and skipped, the
+    // connection is usable without them.
+    pub(crate) fn apply(&self, socket: &TcpStream, logger: &Logger) {
+        if let Some(nodelay) = self.nodelay {
+            if let Err(err) = socket.set_nodelay(nodelay) {
+                warn!(logger, "Could not set TCP_NODELAY: {}", err);
+            }
+        }
+        if let Some(idle) = self.keepalive {
+            if let Err(err) = socket.set_keepalive(Some(idle)) {
+                warn!(logger, "Could not enable TCP keepalive: {}", err);
+            }
+        }
+        if let Some(size) = self.send_buffer_size {
+            if let Err(err) = socket.set_send_buffer_size(size) {
+                warn!(logger, "Could not set the send buffer size: {}", err);
+            }
+        }
+        if let Some(size) = self.recv_buffer_size {
+            if let Err(err) = socket.set_recv_buffer_size(size) {
+                warn!(logger, "Could not set the receive buffer size: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SocketOptions;
+    use std::time::Duration;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn apply() {
+        Runtime::new().unwrap().block_on(async {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+            let (socket, _) = listener.accept().await.unwrap();
+
+            let logger = slog::Logger::root(slog::Discard, slog::o!());
+            SocketOptions::default()
+                .nodelay(true)
+                .keepalive(Duration::from_secs(30))
+                .apply(&socket, &logger);
+
+            assert!(socket.nodelay().unwrap());
+            assert_eq!(socket.keepalive().unwrap(), Some(Duration::from_secs(30)));
+            drop(client);
+        });
+    }
+}
@@ -0,0 +1,238 @@
+//! Contains the tracing spans of sessions, commands and transfers. With the `tracing` feature
+//! enabled they are created with the [`tracing`] crate, so that subscribers like
+//! `tracing-opentelemetry` can export them. The `otlp` feature comes with such an exporter, in
+//! the `otlp` module. Without the `tracing` feature they do nothing.
+//!
+//! [`tracing`]: https://docs.rs/tracing
+
+use super::Reply;
+use std::future::Future;
+use std::net::IpAddr;
+use std::path::Path;
+
+#[cfg(feature = "tracing")]
+use tracing::{field::display, field::Empty, info_span, Instrument, Span};
+
+// The span of a session. Commands and transfers become its children.
+#[derive(Clone)]
+pub(crate) struct SessionSpan {
+    #[cfg(feature = "tracing")]
+    span: Span,
+}
+
+// The span of a single command.
+pub(crate) struct CommandSpan {
+    #[cfg(feature = "tracing")]
+    span: Span,
+}
+
+// The span of a file transfer over the data connection.
+pub(crate) struct TransferSpan {
+    #[cfg(feature = "tracing")]
+    span: Span,
+}
+
+impl SessionSpan {
+    #[cfg(feature = "tracing")]
+    pub fn new(session_id: &str, peer_ip: IpAddr) -> Self {
+        SessionSpan {
+            span: info_span!("ftp.session", ftp.session_id = session_id, net.peer.ip = %peer_ip, enduser.id = Empty),
+        }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub fn new(_session_id: &str, _peer_ip: IpAddr) -> Self {
+        SessionSpan {}
+    }
+
+    // A span that is not part of any trace, for sessions that aren't created by the server.
+    #[cfg(feature = "tracing")]
+    pub fn none() -> Self {
+        SessionSpan { span: Span::none() }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub fn none() -> Self {
+        SessionSpan {}
+    }
+
+    #[allow(unused_variables)]
+    pub fn logged_in(&self, username: &str) {
+        #[cfg(feature = "tracing")]
+        self.span.record("enduser.id", username);
+    }
+
+    // Starts the span of a command, given by the name it is known by in the metrics.
+    #[allow(unused_variables)]
+    pub fn command(&self, command: &str) -> CommandSpan {
+        CommandSpan {
+            #[cfg(feature = "tracing")]
+            span: info_span!(parent: &self.span, "ftp.command", ftp.command = command, ftp.reply_code = Empty),
+        }
+    }
+
+    #[allow(unused_variables)]
+    pub fn transfer(&self, command: &'static str, path: &Path) -> TransferSpan {
+        TransferSpan {
+            #[cfg(feature = "tracing")]
+            span: info_span!(
+                parent: &self.span,
+                "ftp.transfer",
+                ftp.command = command,
+                ftp.path = %path.display(),
+                ftp.bytes = Empty,
+                ftp.result = Empty
+            ),
+        }
+    }
+}
+
+impl CommandSpan {
+    // Runs the handling of the command in the span.
+    #[cfg(feature = "tracing")]
+    pub fn instrument<F: Future>(&self, handling: F) -> impl Future<Output = F::Output> {
+        handling.instrument(self.span.clone())
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub fn instrument<F: Future>(&self, handling: F) -> impl Future<Output = F::Output> {
+        handling
+    }
+
+    #[allow(unused_variables)]
+    pub fn reply(&self, reply: &Reply) {
+        #[cfg(feature = "tracing")]
+        match reply {
+            Reply::CodeAndMsg { code, .. } | Reply::MultiLine { code, .. } => {
+                self.span.record("ftp.reply_code", u32::from(*code));
+            }
+            Reply::None => {}
+        }
+    }
+}
+
+impl TransferSpan {
+    // Runs the transfer in the span, so that spans created by the storage backend become its
+    // children.
+    #[cfg(feature = "tracing")]
+    pub fn instrument<F: Future>(&self, transfer: F) -> impl Future<Output = F::Output> {
+        transfer.instrument(self.span.clone())
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub fn instrument<F: Future>(&self, transfer: F) -> impl Future<Output = F::Output> {
+        transfer
+    }
+
+    #[allow(unused_variables)]
+    pub fn finish(self, bytes: u64, result: &str) {
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("ftp.bytes", bytes);
+            self.span.record("ftp.result", display(result));
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use super::SessionSpan;
+    use crate::server::{Reply, ReplyCode};
+    use std::fmt;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    // A span as the subscriber saw it: its name, its parent and the fields recorded in it.
+    #[derive(Debug, Default)]
+    struct Recorded {
+        name: &'static str,
+        parent: Option<u64>,
+        fields: Vec<(&'static str, String)>,
+    }
+
+    impl Recorded {
+        fn field(&self, name: &str) -> Option<&str> {
+            self.fields.iter().rev().find(|(field, _)| *field == name).map(|(_, value)| value.as_str())
+        }
+    }
+
+    impl Visit for Recorded {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.fields.push((field.name(), format!("{:?}", value)));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.fields.push((field.name(), value.to_string()));
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder {
+        spans: Arc<Mutex<Vec<Recorded>>>,
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+            let mut span = Recorded {
+                name: attributes.metadata().name(),
+                parent: attributes.parent().map(Id::into_u64),
+                ..Recorded::default()
+            };
+            attributes.record(&mut span);
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(span);
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            values.record(&mut self.spans.lock().unwrap()[span.into_u64() as usize - 1]);
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn records_sessions_commands_and_transfers() {
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let session = SessionSpan::new("0123", "127.0.0.1".parse().unwrap());
+            session.logged_in("alice");
+            session.command("stor").reply(&Reply::new(ReplyCode::FileStatusOkay, "Sending data"));
+            session.transfer("stor", Path::new("/pub/file.txt")).finish(42, "ok");
+        });
+
+        let spans = recorder.spans.lock().unwrap();
+        let names: Vec<&str> = spans.iter().map(|span| span.name).collect();
+        assert_eq!(names, vec!["ftp.session", "ftp.command", "ftp.transfer"]);
+
+        let session = &spans[0];
+        assert_eq!(session.parent, None);
+        assert_eq!(session.field("ftp.session_id"), Some("0123"));
+        assert_eq!(session.field("net.peer.ip"), Some("127.0.0.1"));
+        assert_eq!(session.field("enduser.id"), Some("alice"));
+
+        let command = &spans[1];
+        assert_eq!(command.parent, Some(1));
+        assert_eq!(command.field("ftp.command"), Some("stor"));
+        assert_eq!(command.field("ftp.reply_code"), Some("150"));
+
+        let transfer = &spans[2];
+        assert_eq!(transfer.parent, Some(1));
+        assert_eq!(transfer.field("ftp.path"), Some("/pub/file.txt"));
+        assert_eq!(transfer.field("ftp.bytes"), Some("42"));
+        assert_eq!(transfer.field("ftp.result"), Some("ok"));
+    }
+}
@@ -0,0 +1,82 @@
+//! Contains the upload spool that stores uploads in local temporary files before handing them to
+//! the storage backend, so that the backend gets them in one go and failed writes can be retried
+//! without involving the client.
+
+use crate::auth::UserDetail;
+use crate::storage::{self, Error, ErrorKind};
+
+use slog::{warn, Logger};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+
+// How long to wait before retrying a failed write to the storage backend.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// Where uploads are spooled and how often writing them to the backend is retried.
+#[derive(Clone, Debug)]
+pub(crate) struct UploadSpool {
+    dir: PathBuf,
+    retries: u32,
+}
+
+// An upload in the spool. The file is removed when this is dropped, on a thread of the blocking
+// pool.
+pub(crate) struct SpooledUpload {
+    path: PathBuf,
+}
+
+impl Drop for SpooledUpload {
+    fn drop(&mut self) {
+        let path = std::mem::take(&mut self.path);
+        tokio::task::spawn_blocking(move || {
+            let _ = std::fs::remove_file(path);
+        });
+    }
+}
+
+impl UploadSpool {
+    pub fn new(dir: PathBuf, retries: u32) -> Self {
+        UploadSpool { dir, retries }
+    }
+
+    // Copies the upload from the client into a new file in the spool.
+    pub async fn receive<R: tokio::io::AsyncRead + Unpin>(&self, mut input: R) -> storage::Result<SpooledUpload> {
+        let upload = SpooledUpload {
+            path: self.dir.join(format!("libunftp-upload-{}", Uuid::new_v4())),
+        };
+        let mut file = tokio::fs::File::create(&upload.path).await?;
+        tokio::io::copy(&mut input, &mut file).await?;
+        file.sync_all().await?;
+        Ok(upload)
+    }
+
+    // Writes the spooled upload to the storage backend, retrying when that fails for a reason
+    // that may go away.
+    pub async fn store<S, U>(&self, storage: &S, user: &Option<U>, upload: &SpooledUpload, path: &Path, start_pos: u64, logger: &Logger) -> storage::Result<u64>
+    where
+        S: storage::StorageBackend<U>,
+        U: UserDetail,
+    {
+        let mut attempt = 0;
+        loop {
+            let input = tokio::fs::File::open(&upload.path).await?;
+            match storage.put(user, input, path, start_pos).await {
+                Ok(bytes) => return Ok(bytes),
+                Err(err) if attempt < self.retries && is_transient(&err) => {
+                    attempt += 1;
+                    warn!(
+                        logger,
+                        "Could not write {:?} to the storage backend, retrying ({}/{}): {}", path, attempt, self.retries, err
+                    );
+                    tokio::time::delay_for(RETRY_DELAY).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+fn is_transient(err: &Error) -> bool {
+    matches!(err.kind(), ErrorKind::LocalError | ErrorKind::TransientFileNotAvailable)
+}
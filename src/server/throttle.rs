@@ -0,0 +1,222 @@
+//! Contains the token bucket based bandwidth throttling applied to data channel streams and the
+//! limit on the number of concurrent transfers.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Delay;
+
+// Limits how many transfers run at the same time, over all sessions that share it.
+#[derive(Clone, Debug)]
+pub(crate) struct TransferLimiter {
+    permits: Arc<Semaphore>,
+    queue_timeout: Duration,
+}
+
+impl TransferLimiter {
+    pub fn new(permits: Arc<Semaphore>, queue_timeout: Duration) -> Self {
+        TransferLimiter { permits, queue_timeout }
+    }
+
+    // Waits until the transfer may start, but no longer than the queue timeout. The transfer
+    // counts against the limit for as long as the returned permit lives.
+    pub async fn admit(&self) -> Option<OwnedSemaphorePermit> {
+        tokio::time::timeout(self.queue_timeout, self.permits.clone().acquire_owned()).await.ok()
+    }
+}
+
+/// A token bucket that hands out permission to transfer a number of bytes. The bucket holds at
+/// most one second worth of tokens and can be shared between streams to enforce a combined limit.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        let bytes_per_second = bytes_per_second.max(1);
+        RateLimiter {
+            bytes_per_second,
+            bucket: Mutex::new(Bucket {
+                tokens: bytes_per_second as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    // Takes up to `wanted` bytes worth of tokens from the bucket. If the bucket is empty, returns
+    // the time to wait before tokens become available again.
+    fn acquire(&self, wanted: usize) -> Result<usize, Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.bytes_per_second as f64).min(self.bytes_per_second as f64);
+        bucket.last_refill = now;
+
+        if bucket.tokens < 1.0 {
+            let wait = (1.0 - bucket.tokens) / self.bytes_per_second as f64;
+            return Err(Duration::from_secs_f64(wait).max(Duration::from_millis(1)));
+        }
+        let granted = (bucket.tokens as usize).min(wanted);
+        bucket.tokens -= granted as f64;
+        Ok(granted)
+    }
+
+    // Returns tokens that were acquired but not used.
+    fn release(&self, unused: usize) {
+        if unused > 0 {
+            let mut bucket = self.bucket.lock().unwrap();
+            bucket.tokens = (bucket.tokens + unused as f64).min(self.bytes_per_second as f64);
+        }
+    }
+}
+
+/// Wraps an `AsyncRead` or `AsyncWrite` so that bytes only flow through it as fast as all of the
+/// given rate limiters allow.
+pub struct Throttled<T> {
+    inner: T,
+    limiters: Vec<Arc<RateLimiter>>,
+    delay: Option<Delay>,
+}
+
+impl<T> Throttled<T> {
+    pub fn new(inner: T, limiters: Vec<Arc<RateLimiter>>) -> Self {
+        Throttled { inner, limiters, delay: None }
+    }
+
+    // Acquires permission to transfer at most `wanted` bytes from every limiter, registering a
+    // wake-up with the task context if we have to wait.
+    fn poll_acquire(&mut self, cx: &mut Context<'_>, wanted: usize) -> Poll<usize> {
+        loop {
+            if let Some(delay) = &mut self.delay {
+                futures::ready!(Pin::new(delay).poll(cx));
+                self.delay = None;
+            }
+
+            let mut granted = wanted;
+            let mut wait = None;
+            for (i, limiter) in self.limiters.iter().enumerate() {
+                match limiter.acquire(granted) {
+                    Ok(n) => {
+                        // Hand back what earlier limiters gave us in excess.
+                        for earlier in &self.limiters[..i] {
+                            earlier.release(granted - n);
+                        }
+                        granted = n;
+                    }
+                    Err(d) => {
+                        for earlier in &self.limiters[..i] {
+                            earlier.release(granted);
+                        }
+                        wait = Some(d);
+                        break;
+                    }
+                }
+            }
+
+            match wait {
+                None => return Poll::Ready(granted),
+                Some(d) => self.delay = Some(tokio::time::delay_for(d)),
+            }
+        }
+    }
+
+    fn release(&self, unused: usize) {
+        for limiter in &self.limiters {
+            limiter.release(unused);
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Throttled<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+        let granted = futures::ready!(this.poll_acquire(cx, buf.len()));
+        match Pin::new(&mut this.inner).poll_read(cx, &mut buf[..granted]) {
+            Poll::Ready(Ok(n)) => {
+                this.release(granted - n);
+                Poll::Ready(Ok(n))
+            }
+            other => {
+                this.release(granted);
+                other
+            }
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Throttled<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+        let granted = futures::ready!(this.poll_acquire(cx, buf.len()));
+        match Pin::new(&mut this.inner).poll_write(cx, &buf[..granted]) {
+            Poll::Ready(Ok(n)) => {
+                this.release(granted - n);
+                Poll::Ready(Ok(n))
+            }
+            other => {
+                this.release(granted);
+                other
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimiter, Throttled};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tokio::io::AsyncReadExt;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn acquire_caps_to_available_tokens() {
+        let limiter = RateLimiter::new(100);
+        assert_eq!(limiter.acquire(60), Ok(60));
+        assert_eq!(limiter.acquire(60), Ok(40));
+        assert!(limiter.acquire(1).is_err());
+        limiter.release(10);
+        assert_eq!(limiter.acquire(60), Ok(10));
+    }
+
+    #[test]
+    fn throttled_read_takes_time() {
+        let data = vec![0u8; 3000];
+        let limiter = Arc::new(RateLimiter::new(1000));
+        let mut reader = Throttled::new(&data[..], vec![limiter]);
+        let mut out = Vec::new();
+        let start = Instant::now();
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(reader.read_to_end(&mut out)).unwrap();
+        assert_eq!(out.len(), 3000);
+        // The first second worth of bytes is available immediately.
+        assert!(start.elapsed() >= Duration::from_millis(1900));
+    }
+}
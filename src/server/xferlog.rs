@@ -0,0 +1,159 @@
+//! Contains the transfer log that records completed transfers in the xferlog format of wu-ftpd
+//! and vsftpd.
+
+use crate::auth::anonymous::is_anonymous_user;
+
+use chrono::{DateTime, Local, TimeZone};
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// The direction of a transfer, as seen from the server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+// What the transfer log needs to know about a finished transfer.
+pub(crate) struct TransferRecord<'a> {
+    pub duration: Duration,
+    pub remote_host: IpAddr,
+    pub bytes: u64,
+    pub path: &'a Path,
+    pub direction: Direction,
+    pub username: &'a str,
+    pub anonymous: bool,
+    pub complete: bool,
+}
+
+// Writes a line per transfer to the sink it was created with. Shared by all sessions of a server.
+#[derive(Clone)]
+pub(crate) struct Xferlog(Arc<Mutex<Box<dyn Write + Send>>>);
+
+impl Xferlog {
+    pub fn new(sink: Box<dyn Write + Send>) -> Self {
+        Xferlog(Arc::new(Mutex::new(sink)))
+    }
+
+    // The sink is written to on a thread of the blocking pool, as it is usually a file.
+    pub async fn log(&self, record: &TransferRecord<'_>) -> io::Result<()> {
+        let line = format_record(Local::now(), record);
+        let sink = self.0.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut sink = sink.lock().unwrap();
+            sink.write_all(line.as_bytes())?;
+            sink.flush()
+        })
+        .await?
+    }
+
+    // Returns a transfer log that fills in the details of the session by itself.
+    pub fn for_session(&self, remote_host: IpAddr, username: String) -> SessionXferlog {
+        SessionXferlog {
+            xferlog: self.clone(),
+            remote_host,
+            anonymous: is_anonymous_user(&username),
+            username,
+        }
+    }
+}
+
+// The transfer log of a logged in session.
+pub(crate) struct SessionXferlog {
+    xferlog: Xferlog,
+    remote_host: IpAddr,
+    username: String,
+    anonymous: bool,
+}
+
+impl SessionXferlog {
+    pub async fn log(&self, path: &Path, direction: Direction, duration: Duration, bytes: u64, complete: bool) -> io::Result<()> {
+        self.xferlog
+            .log(&TransferRecord {
+                duration,
+                remote_host: self.remote_host,
+                bytes,
+                path,
+                direction,
+                username: &self.username,
+                anonymous: self.anonymous,
+                complete,
+            })
+            .await
+    }
+}
+
+// Formats the record as an xferlog line:
+//
+// current-time transfer-time remote-host file-size filename transfer-type special-action-flag
+// direction access-mode username service-name authentication-method authenticated-user-id
+// completion-status
+//
+// Whitespace in the filename and username is replaced by underscores to keep the fields apart.
+fn format_record<Tz: TimeZone>(now: DateTime<Tz>, record: &TransferRecord) -> String
+where
+    Tz::Offset: Display,
+{
+    // Transfers shorter than a second are logged as taking one, as wu-ftpd does.
+    let seconds = std::cmp::max(1, record.duration.as_secs());
+    format!(
+        "{} {} {} {} {} b _ {} {} {} ftp 0 * {}\n",
+        now.format("%a %b %e %H:%M:%S %Y"),
+        seconds,
+        record.remote_host,
+        record.bytes,
+        without_whitespace(&record.path.to_string_lossy()),
+        match record.direction {
+            Direction::Incoming => 'i',
+            Direction::Outgoing => 'o',
+        },
+        if record.anonymous { 'a' } else { 'r' },
+        without_whitespace(record.username),
+        if record.complete { 'c' } else { 'i' },
+    )
+}
+
+fn without_whitespace(s: &str) -> String {
+    s.chars().map(|c| if c.is_whitespace() { '_' } else { c }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_record, Direction, TransferRecord};
+    use chrono::{DateTime, Utc};
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[test]
+    fn xferlog_line() {
+        let now: DateTime<Utc> = "2020-05-04T13:05:09Z".parse().unwrap();
+        let mut record = TransferRecord {
+            duration: Duration::from_millis(2500),
+            remote_host: "192.168.1.2".parse().unwrap(),
+            bytes: 1234,
+            path: Path::new("/pub/my file.txt"),
+            direction: Direction::Outgoing,
+            username: "alice",
+            anonymous: false,
+            complete: true,
+        };
+        assert_eq!(
+            format_record(now, &record),
+            "Mon May  4 13:05:09 2020 2 192.168.1.2 1234 /pub/my_file.txt b _ o r alice ftp 0 * c\n"
+        );
+
+        record.duration = Duration::from_millis(10);
+        record.direction = Direction::Incoming;
+        record.username = "anonymous";
+        record.anonymous = true;
+        record.complete = false;
+        assert_eq!(
+            format_record(now, &record),
+            "Mon May  4 13:05:09 2020 1 192.168.1.2 1234 /pub/my_file.txt b _ i a anonymous ftp 0 * i\n"
+        );
+    }
+}
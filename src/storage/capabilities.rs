@@ -0,0 +1,80 @@
+use std::ops::{BitOr, BitOrAssign};
+
+/// The optional features that a storage back-end supports, as returned by
+/// [`StorageBackend::supported_features`]. Commands that need a feature the back-end lacks are
+/// answered with `502`, and the features are left out of the reply to `FEAT`.
+///
+/// Features combine with `|`:
+///
+/// ```rust
+/// use libunftp::storage::Capabilities;
+///
+/// let features = Capabilities::RESTART | Capabilities::SYMLINKS;
+/// assert!(features.contains(Capabilities::RESTART));
+/// assert!(!Capabilities::empty().contains(Capabilities::SYMLINKS));
+/// ```
+///
+/// [`StorageBackend::supported_features`]: ./trait.StorageBackend.html#method.supported_features
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// STOR and RETR can start at another offset than the start of the file, after `REST`.
+    pub const RESTART: Capabilities = Capabilities(1);
+
+    /// Symbolic links can be created with [`StorageBackend::symlink`], through `SITE SYMLINK`.
+    ///
+    /// [`StorageBackend::symlink`]: ./trait.StorageBackend.html#method.symlink
+    pub const SYMLINKS: Capabilities = Capabilities(1 << 1);
+
+    /// Files and directories can be renamed with [`StorageBackend::rename`], through `RNFR` and
+    /// `RNTO`.
+    ///
+    /// [`StorageBackend::rename`]: ./trait.StorageBackend.html#method.rename
+    pub const RENAME: Capabilities = Capabilities(1 << 2);
+
+    /// Directories can be removed with [`StorageBackend::rmd`], through `RMD`.
+    ///
+    /// [`StorageBackend::rmd`]: ./trait.StorageBackend.html#method.rmd
+    pub const RMD: Capabilities = Capabilities(1 << 3);
+
+    /// No optional features at all.
+    pub const fn empty() -> Self {
+        Capabilities(0)
+    }
+
+    /// Tells if all the features of `other` are supported.
+    pub const fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Capabilities;
+
+    fn bitor(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for Capabilities {
+    fn bitor_assign(&mut self, other: Capabilities) {
+        self.0 |= other.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Capabilities;
+
+    #[test]
+    fn contains() {
+        let mut features = Capabilities::empty();
+        assert!(features.contains(Capabilities::empty()));
+        assert!(!features.contains(Capabilities::RESTART));
+        features |= Capabilities::RESTART;
+        assert!(features.contains(Capabilities::RESTART));
+        assert!(!features.contains(Capabilities::RESTART | Capabilities::SYMLINKS));
+        assert!((features | Capabilities::SYMLINKS).contains(Capabilities::RESTART | Capabilities::SYMLINKS));
+    }
+}
@@ -6,7 +6,7 @@ mod response_body;
 mod uri;
 
 use crate::storage::cloud_storage::response_body::*;
-use crate::storage::{Error, ErrorKind, Fileinfo, Metadata, StorageBackend};
+use crate::storage::{Capabilities, Error, ErrorKind, Fileinfo, Metadata, StorageBackend};
 use async_trait::async_trait;
 use bytes::{buf::BufExt, Buf};
 use futures::prelude::*;
@@ -66,8 +66,8 @@ impl<U: Sync + Send> StorageBackend<U> for CloudStorage {
     type File = Object;
     type Metadata = ObjectMetadata;
 
-    fn supported_features(&self) -> u32 {
-        crate::storage::FEATURE_RESTART
+    fn supported_features(&self) -> Capabilities {
+        Capabilities::RESTART
     }
 
     async fn metadata<P: AsRef<Path> + Send>(&self, _user: &Option<U>, path: P) -> Result<Self::Metadata, Error> {
@@ -199,12 +199,12 @@ impl<U: Sync + Send> StorageBackend<U> for CloudStorage {
 
     async fn rename<P: AsRef<Path> + Send>(&self, _user: &Option<U>, _from: P, _to: P) -> Result<(), Error> {
         //TODO: implement this
-        unimplemented!();
+        Err(Error::from(ErrorKind::NotSupported))
     }
 
     async fn rmd<P: AsRef<Path> + Send>(&self, _user: &Option<U>, _path: P) -> Result<(), Error> {
         //TODO: implement this
-        unimplemented!();
+        Err(Error::from(ErrorKind::NotSupported))
     }
 
     async fn cwd<P: AsRef<Path> + Send>(&self, _user: &Option<U>, _path: P) -> Result<(), Error> {
@@ -10,6 +10,7 @@ pub struct ObjectMetadata {
     pub(crate) last_updated: Option<SystemTime>,
     pub(crate) is_file: bool,
     pub(crate) size: u64,
+    pub(crate) generation: Option<String>,
 }
 
 impl Metadata for ObjectMetadata {
@@ -52,4 +53,9 @@ impl Metadata for ObjectMetadata {
         //TODO: implement this
         0
     }
+
+    /// Returns the generation of the object, which changes whenever the object is replaced.
+    fn unique(&self) -> Option<String> {
+        self.generation.clone()
+    }
 }
@@ -15,6 +15,8 @@ pub(crate) struct Item {
     name: String,
     updated: DateTime<Utc>,
     size: String,
+    #[serde(default)]
+    generation: Option<String>,
 }
 
 impl ResponseBody {
@@ -40,6 +42,7 @@ impl Item {
             size,
             last_updated: Some(self.updated.into()),
             is_file: !self.name.ends_with('/'),
+            generation: self.generation.clone(),
         })
     }
 
@@ -58,6 +61,7 @@ pub(crate) fn prefix_to_file_info(prefix: &str) -> Result<Fileinfo<PathBuf, Obje
             last_updated: None,
             is_file: false,
             size: 0,
+            generation: None,
         },
     })
 }
@@ -76,12 +80,14 @@ mod test {
             name: "".into(),
             updated: date_time,
             size: "50".into(),
+            generation: Some("1602858600000000".into()),
         };
 
         let metadata: ObjectMetadata = item.to_metadata().unwrap();
         assert_eq!(metadata.size, 50);
         assert_eq!(metadata.modified().unwrap(), sys_time);
         assert_eq!(metadata.is_file, true);
+        assert_eq!(metadata.unique().as_deref(), Some("1602858600000000"));
     }
 
     #[test]
@@ -92,6 +98,7 @@ mod test {
             name: "".into(),
             updated: Utc::now(),
             size: "unparseable".into(),
+            generation: None,
         };
 
         let metadata: Result<ObjectMetadata, Error> = item.to_metadata();
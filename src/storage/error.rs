@@ -72,4 +72,13 @@ pub enum ErrorKind {
     ///     File name not allowed.
     #[fail(display = "553 File name not allowed error")]
     FileNameNotAllowedError,
+    /// 553 Requested action not taken.
+    ///     The file or directory exists already (e.g., at the new name of a rename).
+    #[fail(display = "553 File exists")]
+    FileExists,
+    /// 502 Command not implemented.
+    ///     The storage backend can't do this for the given paths (e.g., rename across
+    ///     devices).
+    #[fail(display = "502 Not supported")]
+    NotSupported,
 }
@@ -1,9 +1,10 @@
 //! StorageBackend that uses a local filesystem, like a traditional FTP server.
 
-use crate::storage::{Error, ErrorKind, Fileinfo, Metadata, Result, StorageBackend};
+use crate::storage::{Capabilities, Error, ErrorKind, Fileinfo, ListStream, Metadata, Result, StorageBackend};
 
 use async_trait::async_trait;
 use futures::prelude::*;
+use futures::stream;
 use log::warn;
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
@@ -28,6 +29,25 @@ fn canonicalize<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
     Ok(p.as_path().to_path_buf())
 }
 
+// The error of renames across file systems, which has the same number on Linux, macOS and the BSDs.
+const EXDEV: i32 = 18;
+
+// The rename of the OS replaces what is at the new name, which clients don't expect. Creating an
+// empty file or directory at the new name fails if something is there already, also when it shows
+// up after the source was looked at. The rename then replaces just that.
+fn rename_without_replacing(from: &Path, to: &Path, dir: bool) -> std::io::Result<()> {
+    if dir {
+        std::fs::create_dir(to)?;
+    } else {
+        std::fs::OpenOptions::new().write(true).create_new(true).open(to)?;
+    }
+    if let Err(err) = std::fs::rename(from, to) {
+        let _ = if dir { std::fs::remove_dir(to) } else { std::fs::remove_file(to) };
+        return Err(err);
+    }
+    Ok(())
+}
+
 impl Filesystem {
     /// Create a new Filesystem backend, with the given root. No operations can take place outside
     /// of the root. For example, when the `Filesystem` root is set to `/srv/ftp`, and a client
@@ -67,8 +87,13 @@ impl<U: Send + Sync> StorageBackend<U> for Filesystem {
     type File = tokio::fs::File;
     type Metadata = std::fs::Metadata;
 
-    fn supported_features(&self) -> u32 {
-        crate::storage::FEATURE_RESTART
+    fn supported_features(&self) -> Capabilities {
+        let features = Capabilities::RESTART | Capabilities::RENAME | Capabilities::RMD;
+        if cfg!(unix) {
+            features | Capabilities::SYMLINKS
+        } else {
+            features
+        }
     }
 
     async fn metadata<P: AsRef<Path> + Send>(&self, _user: &Option<U>, path: P) -> Result<Self::Metadata> {
@@ -79,28 +104,38 @@ impl<U: Send + Sync> StorageBackend<U> for Filesystem {
             .map_err(|_| Error::from(ErrorKind::PermanentFileNotAvailable))
     }
 
-    async fn list<P>(&self, _user: &Option<U>, path: P) -> Result<Vec<Fileinfo<std::path::PathBuf, Self::Metadata>>>
+    async fn list<P>(&self, user: &Option<U>, path: P) -> Result<Vec<Fileinfo<std::path::PathBuf, Self::Metadata>>>
     where
         P: AsRef<Path> + Send,
         <Self as StorageBackend<U>>::Metadata: Metadata,
+    {
+        self.list_stream(user, path).await?.try_collect().await
+    }
+
+    async fn list_stream<P>(&self, _user: &Option<U>, path: P) -> Result<ListStream<Self::Metadata>>
+    where
+        P: AsRef<Path> + Send,
+        Self::Metadata: 'static,
     {
         let full_path: PathBuf = self.full_path(path)?;
 
         let prefix: PathBuf = self.root.clone();
 
-        let mut rd: tokio::fs::ReadDir = tokio::fs::read_dir(full_path).await?;
+        let rd: tokio::fs::ReadDir = tokio::fs::read_dir(full_path).await?;
 
-        let mut fis: Vec<Fileinfo<std::path::PathBuf, Self::Metadata>> = vec![];
-        while let Ok(Some(dir_entry)) = rd.next_entry().await {
-            let prefix = prefix.clone();
+        let entries = stream::unfold((rd, prefix), |(mut rd, prefix)| async move {
+            // The listing ends at the first entry that cannot be read.
+            let dir_entry = rd.next_entry().await.ok()??;
             let path = dir_entry.path();
-            let relpath = path.strip_prefix(prefix).unwrap();
-            let relpath: PathBuf = std::path::PathBuf::from(relpath);
-            let meta: Self::Metadata = tokio::fs::symlink_metadata(dir_entry.path()).await?;
-            fis.push(Fileinfo { path: relpath, metadata: meta })
-        }
-
-        Ok(fis)
+            let relpath: PathBuf = path.strip_prefix(&prefix).unwrap().to_path_buf();
+            let fileinfo = tokio::fs::symlink_metadata(&path)
+                .await
+                .map(|meta| Fileinfo { path: relpath, metadata: meta })
+                .map_err(Error::from);
+            Some((fileinfo, (rd, prefix)))
+        });
+
+        Ok(Box::pin(entries))
     }
 
     async fn get<P: AsRef<Path> + Send>(&self, _user: &Option<U>, path: P, start_pos: u64) -> Result<Self::File> {
@@ -160,6 +195,15 @@ impl<U: Send + Sync> StorageBackend<U> for Filesystem {
         Ok(())
     }
 
+    // The target is resolved like any other path, so links cannot point outside of the root.
+    #[cfg(unix)]
+    async fn symlink<P: AsRef<Path> + Send>(&self, _user: &Option<U>, target: P, link: P) -> Result<()> {
+        let target = self.full_path(target)?;
+        let link = self.full_path(link)?;
+        tokio::fs::os::unix::symlink(target, link).await?;
+        Ok(())
+    }
+
     async fn rmd<P: AsRef<Path> + Send>(&self, _user: &Option<U>, path: P) -> Result<()> {
         let full_path = match self.full_path(path) {
             Ok(path) => path,
@@ -193,27 +237,21 @@ impl<U: Send + Sync> StorageBackend<U> for Filesystem {
             Err(e) => return Err(e),
         };
 
-        let from_rename = from.clone();
-
-        let r = tokio::fs::symlink_metadata(from).await;
-        match r {
-            Ok(metadata) => {
-                if metadata.is_file() {
-                    let r = tokio::fs::rename(from_rename, to).await;
-                    match r {
-                        Ok(_) => Ok(()),
-                        Err(e) => {
-                            warn!("could not rename file: {:?}", e);
-                            Err(Error::from(ErrorKind::PermanentFileNotAvailable))
-                        }
-                    }
-                } else {
-                    Err(Error::from(ErrorKind::PermanentFileNotAvailable))
-                }
-            }
+        let metadata = match tokio::fs::symlink_metadata(&from).await {
+            Ok(metadata) => metadata,
             Err(e) => {
                 warn!("could not get file metadata: {:?}", e);
-                Err(Error::from(ErrorKind::PermanentFileNotAvailable))
+                return Err(Error::from(ErrorKind::PermanentFileNotAvailable));
+            }
+        };
+        let rename = tokio::task::spawn_blocking(move || rename_without_replacing(&from, &to, metadata.is_dir()));
+        match rename.await.unwrap_or_else(|err| Err(err.into())) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Err(Error::from(ErrorKind::FileExists)),
+            Err(e) if e.raw_os_error() == Some(EXDEV) => Err(Error::from(ErrorKind::NotSupported)),
+            Err(e) => {
+                warn!("could not rename file: {:?}", e);
+                Err(Error::from(e))
             }
         }
     }
@@ -224,6 +262,12 @@ impl<U: Send + Sync> StorageBackend<U> for Filesystem {
             Err(e) => return Err(e),
         };
 
+        if let Ok(metadata) = tokio::fs::metadata(&full_path).await {
+            if !metadata.is_dir() {
+                return Err(Error::from(ErrorKind::PermanentFileNotAvailable));
+            }
+        }
+        // Reading the directory also tells whether we may enter it.
         if let Err(error) = tokio::fs::read_dir(full_path).await {
             return Err(match error.kind() {
                 std::io::ErrorKind::NotFound => Error::from(ErrorKind::PermanentFileNotAvailable),
@@ -264,6 +308,11 @@ impl Metadata for std::fs::Metadata {
     fn uid(&self) -> u32 {
         MetadataExt::uid(self)
     }
+
+    // The device and inode number identify a file on Unix.
+    fn unique(&self) -> Option<String> {
+        Some(format!("{:x}g{:x}", MetadataExt::dev(self), MetadataExt::ino(self)))
+    }
 }
 
 #[cfg(test)]
@@ -299,6 +348,8 @@ mod tests {
         assert_eq!(meta.is_symlink(), my_meta.is_symlink());
         assert_eq!(meta.len(), my_meta.len());
         assert_eq!(meta.modified().unwrap(), my_meta.modified().unwrap());
+        assert_eq!(meta.unique(), my_meta.unique());
+        assert_ne!(my_meta.unique(), rt.block_on(fs.metadata(&Some(DefaultUser {}), ".")).unwrap().unique());
     }
 
     #[test]
@@ -330,22 +381,24 @@ mod tests {
     }
 
     #[test]
-    fn fs_list_fmt() {
-        // Create a temp directory and create some files in it
+    fn fs_list_stream() {
         let root = tempfile::tempdir().unwrap();
-        let file = tempfile::NamedTempFile::new_in(&root.path()).unwrap();
-        let path = file.path();
-        let relpath = path.strip_prefix(&root.path()).unwrap();
+        for name in &["a.txt", "b.txt", "c.txt"] {
+            std::fs::write(root.path().join(name), b"").unwrap();
+        }
 
-        // Create a filesystem StorageBackend with our root dir
-        let fs = Filesystem::new(&root.path());
+        let fs = Filesystem::new(root.path());
 
         let mut rt = tokio::runtime::Builder::new().build().unwrap();
-        let my_list = rt.block_on(fs.list_fmt(&Some(DefaultUser {}), "/")).unwrap();
-
-        let my_list = std::string::String::from_utf8(my_list.into_inner()).unwrap();
-
-        assert!(my_list.contains(relpath.to_str().unwrap()));
+        let mut names: Vec<PathBuf> = rt
+            .block_on(async {
+                let entries = fs.list_stream(&Some(DefaultUser {}), "/").await.unwrap();
+                entries.map_ok(|fileinfo| fileinfo.path).try_collect::<Vec<_>>().await
+            })
+            .unwrap();
+        names.sort();
+
+        assert_eq!(names, vec![PathBuf::from("a.txt"), PathBuf::from("b.txt"), PathBuf::from("c.txt")]);
     }
 
     #[test]
@@ -383,6 +436,13 @@ mod tests {
         .unwrap();
     }
 
+    #[test]
+    fn testsuite() {
+        let root = tempfile::tempdir().unwrap();
+        let fs = Filesystem::new(root.path());
+        Runtime::new().unwrap().block_on(crate::storage::testsuite::run(&fs, &Some(DefaultUser {})));
+    }
+
     #[test]
     fn fs_put() {
         let root = std::env::temp_dir();
@@ -483,6 +543,28 @@ mod tests {
         let old_full_path = root.join(old_filename);
         std::fs::symlink_metadata(old_full_path).expect_err("Old filename should not exists anymore");
     }
+
+    #[test]
+    fn fs_rename_keeps_existing() {
+        let tempdir = tempfile::TempDir::new().unwrap();
+        let root = tempdir.path();
+        std::fs::write(root.join("old.txt"), b"old").unwrap();
+        std::fs::write(root.join("new.txt"), b"new").unwrap();
+        std::fs::create_dir(root.join("old")).unwrap();
+        std::fs::create_dir(root.join("new")).unwrap();
+
+        let mut rt = Runtime::new().unwrap();
+        let fs = Filesystem::new(root);
+        for (from, to) in &[("old.txt", "new.txt"), ("old", "new"), ("old.txt", "new"), ("old", "new.txt")] {
+            let err = rt.block_on(fs.rename(&Some(DefaultUser {}), from, to)).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::FileExists);
+        }
+
+        assert_eq!(std::fs::read(root.join("old.txt")).unwrap(), b"old");
+        assert_eq!(std::fs::read(root.join("new.txt")).unwrap(), b"new");
+        assert!(root.join("old").is_dir());
+        assert!(root.join("new").is_dir());
+    }
 }
 
 impl From<std::io::Error> for Error {
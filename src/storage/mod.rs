@@ -2,13 +2,59 @@
 
 #![deny(missing_docs)]
 
+pub(crate) mod capabilities;
+pub use capabilities::Capabilities;
+
 pub(crate) mod error;
 pub use error::{Error, ErrorKind};
 
 pub(crate) mod storage_backend;
-pub use storage_backend::{Fileinfo, Metadata, Result, StorageBackend, FEATURE_RESTART};
+pub use storage_backend::{Fileinfo, ListStream, Metadata, Result, StorageBackend};
 
 pub mod filesystem;
+pub mod testsuite;
+
+use slog::{warn, Logger};
+use std::future::Future;
+use std::time::Duration;
+
+// Runs an operation of a storage backend, failing it with a `LocalError` when it takes longer than
+// the timeout, if there is one, so that a backend that hangs doesn't hang the session with it.
+pub(crate) async fn with_timeout<F, T>(logger: &Logger, timeout: Option<Duration>, operation: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    match timeout {
+        None => operation.await,
+        Some(timeout) => match tokio::time::timeout(timeout, operation).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!(logger, "The storage backend did not finish an operation within {:?}", timeout);
+                Err(Error::from(ErrorKind::LocalError))
+            }
+        },
+    }
+}
 
 #[cfg(feature = "cloud_storage")]
 pub mod cloud_storage;
+
+#[cfg(test)]
+mod tests {
+    use super::{with_timeout, ErrorKind};
+    use std::time::Duration;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn operation_that_hangs_fails_after_timeout() {
+        Runtime::new().unwrap().block_on(async {
+            let logger = slog::Logger::root(slog::Discard, slog::o!());
+            let hangs = futures::future::pending::<super::Result<()>>();
+            let err = with_timeout(&logger, Some(Duration::from_millis(10)), hangs).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::LocalError);
+
+            assert_eq!(with_timeout(&logger, Some(Duration::from_secs(1)), async { Ok(42) }).await.unwrap(), 42);
+            assert_eq!(with_timeout(&logger, None, async { Ok(42) }).await.unwrap(), 42);
+        });
+    }
+}
@@ -1,24 +1,26 @@
 //! StorageBackend that uses a local filesystem, like a traditional FTP server.
 
-use super::error::Error;
+use super::capabilities::Capabilities;
+use super::error::{Error, ErrorKind};
+use crate::server::{ListFormatter, UnixListFormatter};
 
 use async_trait::async_trait;
-use chrono::prelude::{DateTime, Utc};
-use itertools::Itertools;
-use log::warn;
+use futures::stream::{self, Stream};
 use std::fmt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::result;
 use std::time::SystemTime;
 use tokio::io::AsyncRead;
 
-/// Tells if STOR/RETR restarts are supported by the storage back-end
-/// i.e. starting from a different byte offset.
-pub const FEATURE_RESTART: u32 = 0b0000_0001;
-
 /// Result type used by traits in this module
 pub type Result<T> = result::Result<T, Error>;
 
+/// The entries of a directory, as returned by [`StorageBackend::list_stream`].
+///
+/// [`StorageBackend::list_stream`]: ./trait.StorageBackend.html#method.list_stream
+pub type ListStream<M> = Pin<Box<dyn Stream<Item = Result<Fileinfo<PathBuf, M>>> + Send>>;
+
 /// Represents the metadata of a _FTP File_
 pub trait Metadata {
     /// Returns the length (size) of the file in bytes.
@@ -46,6 +48,14 @@ pub trait Metadata {
 
     /// Returns the `uid` of the file.
     fn uid(&self) -> u32;
+
+    /// Returns an identifier that is the same for all paths that lead to the same file and
+    /// differs between files, if the storage backend has one. `MLSD` and `MLST` show it as the
+    /// `unique` fact, which mirroring clients use to notice that they visit a directory again
+    /// through a link, instead of mirroring it forever. The default implementation returns `None`.
+    fn unique(&self) -> Option<String> {
+        None
+    }
 }
 
 /// Fileinfo contains the path and `Metadata` of a file.
@@ -68,40 +78,9 @@ where
     P: AsRef<Path>,
     M: Metadata,
 {
+    // The same line as in the listings of the server by default.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let modified: String = self
-            .metadata
-            .modified()
-            .map(|x| DateTime::<Utc>::from(x).format("%b %d %H:%M").to_string())
-            .unwrap_or_else(|_| "-".to_string());
-        let basename = self.path.as_ref().components().last();
-        let path = match basename {
-            Some(v) => v.as_os_str().to_string_lossy(),
-            None => {
-                warn!("error parsing path components");
-                return Err(std::fmt::Error);
-            }
-        };
-        #[allow(clippy::write_literal)]
-        write!(
-            f,
-            "{filetype}{permissions} {owner:>12} {group:>12} {size:#14} {modified:>12} {path}",
-            filetype = if self.metadata.is_dir() {
-                "d"
-            } else if self.metadata.is_symlink() {
-                "l"
-            } else {
-                "-"
-            },
-            // TODO: Don't hardcode permissions ;)
-            permissions = "rwxr-xr-x",
-            // TODO: Consider showing canonical names here
-            owner = self.metadata.uid(),
-            group = self.metadata.gid(),
-            size = self.metadata.len(),
-            modified = modified,
-            path = path,
-        )
+        f.write_str(&UnixListFormatter::default().list_line(self.path.as_ref(), &self.metadata))
     }
 }
 
@@ -117,10 +96,15 @@ pub trait StorageBackend<U: Sync + Send> {
     /// The concrete type of the _metadata_ used by this storage backend.
     type Metadata: Metadata + Sync + Send;
 
-    /// Tells which optional features are supported by the storage back-end
-    /// Return a value with bits set according to the FEATURE_* constants.
-    fn supported_features(&self) -> u32 {
-        0
+    /// Tells which optional features are supported by the storage back-end. By default those
+    /// that every back-end is expected to have: [`Capabilities::RENAME`] and
+    /// [`Capabilities::RMD`]. Back-ends that can't rename files or remove directories should
+    /// leave them out, so that the server answers the commands for them with `502`.
+    ///
+    /// [`Capabilities::RENAME`]: ./struct.Capabilities.html#associatedconstant.RENAME
+    /// [`Capabilities::RMD`]: ./struct.Capabilities.html#associatedconstant.RMD
+    fn supported_features(&self) -> Capabilities {
+        Capabilities::RENAME | Capabilities::RMD
     }
 
     /// Returns the `Metadata` for the given file.
@@ -133,42 +117,24 @@ pub trait StorageBackend<U: Sync + Send> {
     where
         <Self as StorageBackend<U>>::Metadata: Metadata;
 
-    /// Returns some bytes that make up a directory listing that can immediately be sent to the client.
-    async fn list_fmt<P>(&self, user: &Option<U>, path: P) -> std::result::Result<std::io::Cursor<Vec<u8>>, std::io::Error>
-    where
-        P: AsRef<Path> + Send,
-        Self::Metadata: Metadata + 'static,
-    {
-        let list = self.list(user, path).await.map_err(|_| std::io::Error::from(std::io::ErrorKind::Other))?;
-
-        let file_infos: Vec<u8> = list.iter().map(|fi| format!("{}\r\n", fi).into_bytes()).concat();
-
-        Ok(std::io::Cursor::new(file_infos))
-    }
-
-    /// Returns some bytes that make up a NLST directory listing (only the basename) that can
-    /// immediately be sent to the client.
-    async fn nlst<P>(&self, user: &Option<U>, path: P) -> std::result::Result<std::io::Cursor<Vec<u8>>, std::io::Error>
+    /// Returns the entries of the given directory one at a time, so that the listing of a large
+    /// directory can be sent to the client while it is being read. The server lists directories
+    /// with this method. The default implementation gets all entries at once with `list`.
+    async fn list_stream<P>(&self, user: &Option<U>, path: P) -> Result<ListStream<Self::Metadata>>
     where
         P: AsRef<Path> + Send,
-        Self::Metadata: Metadata + 'static,
+        Self::Metadata: 'static,
     {
-        let list = self.list(user, path).await.map_err(|_| std::io::Error::from(std::io::ErrorKind::Other))?;
-
-        let bytes = list
-            .iter()
-            .map(|file| {
-                let info = file.path.file_name().unwrap_or_else(|| std::ffi::OsStr::new("")).to_str().unwrap_or("");
-                format!("{}\r\n", info).into_bytes()
-            })
-            .concat();
-        Ok(std::io::Cursor::new(bytes))
+        let list = self.list(user, path).await?;
+        Ok(Box::pin(stream::iter(list.into_iter().map(Ok))))
     }
 
     /// Returns the content of the given file from offset start_pos.
     /// The starting position can only be greater than zero if the storage back-end implementation
     /// advertises to support partial reads through the supported_features method i.e. the result
-    /// from supported_features yield 1 if a logical and operation is applied with FEATURE_RESTART.
+    /// from supported_features contains [`Capabilities::RESTART`].
+    ///
+    /// [`Capabilities::RESTART`]: ./struct.Capabilities.html#associatedconstant.RESTART
     async fn get<P: AsRef<Path> + Send>(&self, user: &Option<U>, path: P, start_pos: u64) -> Result<Self::File>;
 
     /// Writes bytes from the given reader to the specified path starting at offset start_pos in the file
@@ -186,12 +152,42 @@ pub trait StorageBackend<U: Sync + Send> {
     /// Creates the given directory.
     async fn mkd<P: AsRef<Path> + Send>(&self, user: &Option<U>, path: P) -> Result<()>;
 
-    /// Renames the given file to the given new filename.
+    /// Renames the given file or directory to the given new name, which may be in another
+    /// directory. Implementations should fail with [`ErrorKind::PermanentFileNotAvailable`] if
+    /// `from` doesn't exist, with [`ErrorKind::FileExists`] if `to` does, rather than replace it,
+    /// and with [`ErrorKind::NotSupported`] if they can't move between the two places, for
+    /// instance between devices. The server tells the client which it was.
+    ///
+    /// [`ErrorKind::PermanentFileNotAvailable`]: ./enum.ErrorKind.html#variant.PermanentFileNotAvailable
+    /// [`ErrorKind::FileExists`]: ./enum.ErrorKind.html#variant.FileExists
+    /// [`ErrorKind::NotSupported`]: ./enum.ErrorKind.html#variant.NotSupported
     async fn rename<P: AsRef<Path> + Send>(&self, user: &Option<U>, from: P, to: P) -> Result<()>;
 
     /// Deletes the given directory.
     async fn rmd<P: AsRef<Path> + Send>(&self, user: &Option<U>, path: P) -> Result<()>;
 
-    /// Changes the working directory to the given path.
-    async fn cwd<P: AsRef<Path> + Send>(&self, user: &Option<U>, path: P) -> Result<()>;
+    /// Checks that the working directory can be changed to the given path. The server refuses
+    /// `CWD` with the error returned, so that clients don't end up in a directory that doesn't
+    /// exist. Paths that don't exist or aren't directories should fail with
+    /// [`ErrorKind::PermanentFileNotAvailable`]. The default implementation checks this with
+    /// `metadata`.
+    ///
+    /// [`ErrorKind::PermanentFileNotAvailable`]: ./enum.ErrorKind.html#variant.PermanentFileNotAvailable
+    async fn cwd<P: AsRef<Path> + Send>(&self, user: &Option<U>, path: P) -> Result<()> {
+        if self.metadata(user, path).await?.is_dir() {
+            Ok(())
+        } else {
+            Err(Error::from(ErrorKind::PermanentFileNotAvailable))
+        }
+    }
+
+    /// Creates a symbolic link at `link` that points to `target`. Backends that implement this
+    /// should also return [`Capabilities::SYMLINKS`] from `supported_features`, and must not
+    /// create links that point outside of what the user can access. This default implementation
+    /// refuses.
+    ///
+    /// [`Capabilities::SYMLINKS`]: ./struct.Capabilities.html#associatedconstant.SYMLINKS
+    async fn symlink<P: AsRef<Path> + Send>(&self, _user: &Option<U>, _target: P, _link: P) -> Result<()> {
+        Err(Error::from(ErrorKind::PermissionDenied))
+    }
 }
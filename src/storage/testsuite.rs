@@ -0,0 +1,298 @@
+//! Contains tests that every [`StorageBackend`] should pass, for the authors of storage backends
+//! to run against their own implementation.
+//!
+//! Each test panics, like a failing assertion does, when the backend doesn't behave the way the
+//! server expects. Every test works in a directory of its own at the root of the backend, named
+//! after the test like `/testsuite-rename`, that it creates and removes again when it passes.
+//! [`run`] runs all of them. Backends that don't support some operation can run the other tests one
+//! by one instead.
+//!
+//! # Example
+//!
+//! ```rust
+//! use libunftp::auth::DefaultUser;
+//! use libunftp::storage::{filesystem::Filesystem, testsuite};
+//!
+//! let root = tempfile::tempdir().unwrap();
+//! let backend = Filesystem::new(root.path());
+//! let mut rt = tokio::runtime::Runtime::new().unwrap();
+//! rt.block_on(testsuite::run(&backend, &Some(DefaultUser)));
+//! ```
+//!
+//! [`StorageBackend`]: ../trait.StorageBackend.html
+//! [`run`]: fn.run.html
+
+use super::{Capabilities, ErrorKind, Metadata, StorageBackend};
+
+use futures::TryStreamExt;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncReadExt;
+
+const CONTENT: &[u8] = b"Hello, libunftp!";
+
+/// Runs all tests of the suite.
+pub async fn run<S, U>(backend: &S, user: &Option<U>)
+where
+    S: StorageBackend<U> + Sync,
+    S::Metadata: 'static,
+    U: Send + Sync,
+{
+    put_and_get(backend, user).await;
+    restart(backend, user).await;
+    list(backend, user).await;
+    rename(backend, user).await;
+    delete(backend, user).await;
+    directories(backend, user).await;
+    missing_files(backend, user).await;
+}
+
+/// Checks that a file that is stored can be read back, and that its metadata tells its size.
+pub async fn put_and_get<S, U>(backend: &S, user: &Option<U>)
+where
+    S: StorageBackend<U>,
+    U: Send + Sync,
+{
+    let dir = setup(backend, user, "put_and_get").await;
+    let path = dir.join("file.txt");
+    let written = check(backend.put(user, CONTENT, &path, 0).await, "put a file");
+    assert_eq!(written, CONTENT.len() as u64, "put should return the number of bytes written");
+    assert_eq!(read(backend, user, &path, 0).await, CONTENT, "get should return what was put");
+
+    let metadata = check(backend.metadata(user, &path).await, "get the metadata of a file");
+    assert!(metadata.is_file() && !metadata.is_dir(), "the metadata of a file should say it is one");
+    assert_eq!(metadata.len(), CONTENT.len() as u64, "the metadata should have the size of the file");
+    check(metadata.modified(), "get the modification time of a file");
+
+    // Storing a file again replaces its content.
+    check(backend.put(user, &b"Bye"[..], &path, 0).await, "overwrite a file");
+    assert_eq!(read(backend, user, &path, 0).await, b"Bye", "put should replace the content of a file");
+    teardown(backend, user, &dir, &["file.txt"]).await;
+}
+
+/// Checks reading from and writing at an offset, when the backend supports
+/// [`Capabilities::RESTART`].
+///
+/// [`Capabilities::RESTART`]: ../struct.Capabilities.html#associatedconstant.RESTART
+pub async fn restart<S, U>(backend: &S, user: &Option<U>)
+where
+    S: StorageBackend<U>,
+    U: Send + Sync,
+{
+    if !backend.supported_features().contains(Capabilities::RESTART) {
+        return;
+    }
+    let dir = setup(backend, user, "restart").await;
+    let path = dir.join("file.txt");
+    check(backend.put(user, CONTENT, &path, 0).await, "put a file");
+    assert_eq!(read(backend, user, &path, 7).await, &CONTENT[7..], "get should start at the offset");
+    assert_eq!(
+        read(backend, user, &path, CONTENT.len() as u64).await,
+        b"",
+        "get at the end should return nothing"
+    );
+
+    check(backend.put(user, &b"FTP!"[..], &path, 7).await, "put a file at an offset");
+    assert_eq!(read(backend, user, &path, 0).await, b"Hello, FTP!", "put should write from the offset on");
+    teardown(backend, user, &dir, &["file.txt"]).await;
+}
+
+/// Checks that listing a directory returns its files and directories, and only those, with
+/// `list` as well as `list_stream`.
+pub async fn list<S, U>(backend: &S, user: &Option<U>)
+where
+    S: StorageBackend<U> + Sync,
+    S::Metadata: 'static,
+    U: Send + Sync,
+{
+    let dir = setup(backend, user, "list").await;
+    check(backend.put(user, CONTENT, dir.join("file.txt"), 0).await, "put a file");
+    check(backend.mkd(user, dir.join("sub")).await, "create a directory");
+    check(backend.put(user, CONTENT, dir.join("sub/nested.txt"), 0).await, "put a file in a subdirectory");
+
+    let listed = check(backend.list(user, &dir).await, "list a directory");
+    let mut entries: Vec<(String, bool)> = listed.iter().map(|entry| (name(&entry.path), entry.metadata.is_dir())).collect();
+    entries.sort();
+    assert_eq!(
+        entries,
+        vec![("file.txt".to_string(), false), ("sub".to_string(), true)],
+        "list should return the entries of the directory and nothing else"
+    );
+
+    let streamed = check(backend.list_stream(user, &dir).await, "list a directory as a stream");
+    let streamed = check(streamed.try_collect::<Vec<_>>().await, "read the entries of a listing");
+    let mut names: Vec<String> = streamed.iter().map(|entry| name(&entry.path)).collect();
+    names.sort();
+    assert_eq!(names, vec!["file.txt", "sub"], "list_stream should return the same entries as list");
+
+    check(backend.del(user, dir.join("sub/nested.txt")).await, "delete a file");
+    check(backend.rmd(user, dir.join("sub")).await, "remove a directory");
+    teardown(backend, user, &dir, &["file.txt"]).await;
+}
+
+/// Checks that a renamed file is found under its new name only, also when that is in another
+/// directory, that a rename doesn't replace what is at the new name and that renaming a missing
+/// file fails with [`ErrorKind::PermanentFileNotAvailable`], when the backend supports
+/// [`Capabilities::RENAME`].
+///
+/// [`ErrorKind::PermanentFileNotAvailable`]: ../enum.ErrorKind.html#variant.PermanentFileNotAvailable
+/// [`Capabilities::RENAME`]: ../struct.Capabilities.html#associatedconstant.RENAME
+pub async fn rename<S, U>(backend: &S, user: &Option<U>)
+where
+    S: StorageBackend<U>,
+    U: Send + Sync,
+{
+    if !backend.supported_features().contains(Capabilities::RENAME) {
+        return;
+    }
+    let dir = setup(backend, user, "rename").await;
+    check(backend.put(user, CONTENT, dir.join("old.txt"), 0).await, "put a file");
+    check(backend.rename(user, dir.join("old.txt"), dir.join("new.txt")).await, "rename a file");
+    assert_eq!(
+        read(backend, user, &dir.join("new.txt"), 0).await,
+        CONTENT,
+        "a renamed file should keep its content"
+    );
+    expect_missing(backend.metadata(user, dir.join("old.txt")).await, "the old name of a renamed file");
+
+    check(backend.put(user, &b"other"[..], dir.join("other.txt"), 0).await, "put a file");
+    match backend.rename(user, dir.join("other.txt"), dir.join("new.txt")).await {
+        Ok(_) => panic!("Expected a rename to an existing name to fail"),
+        Err(err) => assert_eq!(err.kind(), ErrorKind::FileExists, "Wrong error for a rename to an existing name"),
+    }
+    assert_eq!(
+        read(backend, user, &dir.join("new.txt"), 0).await,
+        CONTENT,
+        "a failed rename should leave files alone"
+    );
+    expect_missing(
+        backend.rename(user, dir.join("missing.txt"), dir.join("found.txt")).await,
+        "rename of a missing file",
+    );
+
+    check(backend.mkd(user, dir.join("sub")).await, "create a directory");
+    check(
+        backend.rename(user, dir.join("new.txt"), dir.join("sub/moved.txt")).await,
+        "move a file to another directory",
+    );
+    assert_eq!(
+        read(backend, user, &dir.join("sub/moved.txt"), 0).await,
+        CONTENT,
+        "a moved file should keep its content"
+    );
+    check(backend.del(user, dir.join("sub/moved.txt")).await, "delete a file");
+    check(backend.rmd(user, dir.join("sub")).await, "remove a directory");
+    teardown(backend, user, &dir, &["other.txt"]).await;
+}
+
+/// Checks that a deleted file is gone and that deleting it again fails.
+pub async fn delete<S, U>(backend: &S, user: &Option<U>)
+where
+    S: StorageBackend<U>,
+    U: Send + Sync,
+{
+    let dir = setup(backend, user, "delete").await;
+    let path = dir.join("file.txt");
+    check(backend.put(user, CONTENT, &path, 0).await, "put a file");
+    check(backend.del(user, &path).await, "delete a file");
+    expect_missing(backend.metadata(user, &path).await, "a deleted file");
+    expect_missing(backend.del(user, &path).await, "deleting a deleted file");
+    teardown(backend, user, &dir, &[]).await;
+}
+
+/// Checks creating, entering and removing directories, and that a directory with files in it
+/// cannot be removed.
+pub async fn directories<S, U>(backend: &S, user: &Option<U>)
+where
+    S: StorageBackend<U> + Sync,
+    U: Send + Sync,
+{
+    let dir = setup(backend, user, "directories").await;
+    let sub = dir.join("sub");
+    check(backend.mkd(user, &sub).await, "create a directory");
+    let metadata = check(backend.metadata(user, &sub).await, "get the metadata of a directory");
+    assert!(metadata.is_dir() && !metadata.is_file(), "the metadata of a directory should say it is one");
+    check(backend.cwd(user, &sub).await, "change into a directory");
+    assert!(backend.mkd(user, &sub).await.is_err(), "creating a directory that exists should fail");
+
+    check(backend.put(user, CONTENT, sub.join("file.txt"), 0).await, "put a file in a directory");
+    assert!(backend.rmd(user, &sub).await.is_err(), "removing a directory that isn't empty should fail");
+    assert!(backend.cwd(user, sub.join("file.txt")).await.is_err(), "changing into a file should fail");
+
+    check(backend.del(user, sub.join("file.txt")).await, "delete a file");
+    check(backend.rmd(user, &sub).await, "remove an empty directory");
+    expect_missing(backend.metadata(user, &sub).await, "a removed directory");
+    teardown(backend, user, &dir, &[]).await;
+}
+
+/// Checks that operations on files and directories that don't exist fail with
+/// [`ErrorKind::PermanentFileNotAvailable`], which the server replies to with `550`.
+///
+/// [`ErrorKind::PermanentFileNotAvailable`]: ../enum.ErrorKind.html#variant.PermanentFileNotAvailable
+pub async fn missing_files<S, U>(backend: &S, user: &Option<U>)
+where
+    S: StorageBackend<U> + Sync,
+    U: Send + Sync,
+{
+    let dir = setup(backend, user, "missing_files").await;
+    let missing = dir.join("missing");
+    expect_missing(backend.metadata(user, &missing).await, "metadata of a missing file");
+    expect_missing(backend.get(user, &missing, 0).await, "get of a missing file");
+    expect_missing(backend.list(user, &missing).await, "list of a missing directory");
+    expect_missing(backend.del(user, &missing).await, "del of a missing file");
+    expect_missing(backend.rmd(user, &missing).await, "rmd of a missing directory");
+    expect_missing(backend.cwd(user, &missing).await, "cwd to a missing directory");
+    teardown(backend, user, &dir, &[]).await;
+}
+
+// Creates the directory the test works in.
+async fn setup<S, U>(backend: &S, user: &Option<U>, test: &str) -> PathBuf
+where
+    S: StorageBackend<U>,
+    U: Send + Sync,
+{
+    let dir = PathBuf::from(format!("/testsuite-{}", test));
+    check(backend.mkd(user, &dir).await, "create the directory of the test");
+    dir
+}
+
+// Removes the directory of the test, with the files that the test left in it.
+async fn teardown<S, U>(backend: &S, user: &Option<U>, dir: &Path, files: &[&str])
+where
+    S: StorageBackend<U>,
+    U: Send + Sync,
+{
+    for file in files {
+        check(backend.del(user, dir.join(file)).await, "delete a file");
+    }
+    check(backend.rmd(user, dir).await, "remove the directory of the test");
+}
+
+async fn read<S, U>(backend: &S, user: &Option<U>, path: &Path, start_pos: u64) -> Vec<u8>
+where
+    S: StorageBackend<U>,
+    U: Send + Sync,
+{
+    let mut file = check(backend.get(user, path, start_pos).await, "get a file");
+    let mut content = Vec::new();
+    check(file.read_to_end(&mut content).await, "read a file");
+    content
+}
+
+fn name(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+fn check<T, E: Debug>(result: Result<T, E>, action: &str) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => panic!("Failed to {}: {:?}", action, err),
+    }
+}
+
+fn expect_missing<T>(result: super::Result<T>, what: &str) {
+    match result {
+        Ok(_) => panic!("Expected {} to fail", what),
+        Err(err) => assert_eq!(err.kind(), ErrorKind::PermanentFileNotAvailable, "Wrong error for {}", what),
+    }
+}
@@ -1,21 +1,75 @@
 use failure::_core::time::Duration;
 use ftp::types::Result;
 use ftp::FtpStream;
+use libunftp::auth::{DefaultUser, UserDetail};
+use libunftp::storage;
+use libunftp::ServerHandle;
 use pretty_assertions::assert_eq;
 use regex::Regex;
+use slog::Drain;
 use std::fmt::Debug;
 use std::fs;
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::path::PathBuf;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::str;
+use std::time::Instant;
 use tokio::runtime::Runtime;
 
 fn test_with(addr: &'static str, path: impl Into<PathBuf> + Send, test: impl FnOnce() -> ()) {
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(path.into()), |_| test());
+}
+
+// Runs `test` once the server that `builder` builds accepts connections on `addr`. The test gets a
+// handle to the server.
+fn test_with_builder<S, U>(addr: &'static str, builder: libunftp::ServerBuilder<S, U>, test: impl FnOnce(ServerHandle))
+where
+    S: 'static + storage::StorageBackend<U> + Sync + Send,
+    S::File: tokio::io::AsyncRead + Send,
+    S::Metadata: storage::Metadata,
+    U: UserDetail + 'static,
+{
     let rt = Runtime::new().unwrap();
-    let server = libunftp::Server::new_with_fs_root(path.into());
+    let server = builder.build().unwrap();
+    let handle = server.handle();
     let _thread = rt.spawn(server.listen(addr));
-    std::thread::sleep(Duration::new(1, 0));
-    test();
+    wait_until_ready(addr, &handle);
+    test(handle);
+}
+
+// Waits until the server accepts connections on `addr`. The connection it takes to find out is
+// gone again by the time this returns, so tests start out without sessions.
+fn wait_until_ready(addr: &str, handle: &ServerHandle) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let stream = loop {
+        match std::net::TcpStream::connect(addr) {
+            Ok(stream) => break stream,
+            Err(err) if Instant::now() > deadline => panic!("The server at {} did not come up: {}", addr, err),
+            Err(_) => std::thread::sleep(Duration::from_millis(10)),
+        }
+    };
+    // The session is registered before the greeting goes out. Servers that refuse the connection
+    // close it after their reply, the others once the session has quit.
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    BufReader::new(&stream).read_line(&mut String::new()).unwrap();
+    let _ = (&stream).write_all(b"QUIT\r\n");
+    let _ = (&stream).read_to_end(&mut Vec::new());
+    while !handle.sessions().is_empty() {
+        assert!(Instant::now() < deadline, "The session of the readiness check at {} did not end", addr);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Reads a reply from the control channel, including all the lines of multi-line replies.
+fn read_reply(reader: &mut impl BufRead) -> String {
+    let mut reply = String::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        reply.push_str(&line);
+        if line.len() > 3 && line.as_bytes()[3] == b' ' && line[..3].bytes().all(|b| b.is_ascii_digit()) {
+            return reply;
+        }
+    }
 }
 
 fn ensure_login_required<T: Debug>(r: Result<T>) {
@@ -204,6 +258,42 @@ fn cwd() {
     });
 }
 
+#[test]
+fn cwd_resolves_dots_within_root() {
+    let addr = "127.0.0.1:1280";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(root.path().join("dir")).unwrap();
+
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        ftp_stream.cwd("./dir/../dir/.").unwrap();
+        assert_eq!(ftp_stream.pwd().unwrap(), "/dir");
+        ftp_stream.cwd("../../..").unwrap();
+        assert_eq!(ftp_stream.pwd().unwrap(), "/");
+    });
+}
+
+#[test]
+fn cwd_checks_the_directory() {
+    let addr = "127.0.0.1:1310";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(root.path().join("dir")).unwrap();
+    std::fs::write(root.path().join("file.txt"), b"Not a directory").unwrap();
+
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        for path in &["missing", "file.txt"] {
+            let err = ftp_stream.cwd(path).unwrap_err().to_string();
+            assert!(err.contains("550"), "unexpected error for {}: {}", path, err);
+            assert_eq!(ftp_stream.pwd().unwrap(), "/");
+        }
+        ftp_stream.cwd("dir").unwrap();
+        assert_eq!(ftp_stream.pwd().unwrap(), "/dir");
+    });
+}
+
 #[test]
 fn cdup() {
     let addr = "127.0.0.1:1242";
@@ -365,3 +455,2479 @@ fn size() {
         assert_eq!(size3, fs::metadata(&file_in_root).unwrap().len() as usize, "Wrong size returned.");
     });
 }
+
+#[test]
+fn listen_all() {
+    let addrs = ["127.0.0.1:1249", "127.0.0.1:1250"];
+    let rt = Runtime::new().unwrap();
+    let server = libunftp::Server::new_with_fs_root(std::env::temp_dir()).build().unwrap();
+    let handle = server.handle();
+    let _thread = rt.spawn(server.listen_all(addrs.to_vec()));
+    for addr in addrs.iter() {
+        wait_until_ready(addr, &handle);
+    }
+
+    for addr in addrs.iter() {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        ftp_stream.pwd().unwrap();
+    }
+}
+
+#[test]
+fn max_connections() {
+    let addr = "127.0.0.1:1251";
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(std::env::temp_dir()).max_connections(1), |_| {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        let err = FtpStream::connect(addr).err().unwrap().to_string();
+        assert!(err.contains("421 Too many connections"), "Expected a 421 reply, got: {}", err);
+
+        // Once the first connection is gone, there's room for a new one.
+        ftp_stream.quit().unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        FtpStream::connect(addr).unwrap();
+    });
+}
+
+#[test]
+fn deny_ips() {
+    let addr = "127.0.0.1:1252";
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(std::env::temp_dir()).deny_ips(vec!["127.0.0.0/8"]).unwrap(),
+        |_| {
+            let err = FtpStream::connect(addr).err().unwrap().to_string();
+            assert!(err.contains("421 Access denied"), "Expected a 421 reply, got: {}", err);
+        },
+    );
+}
+
+#[test]
+fn download_bandwidth_limit() {
+    let addr = "127.0.0.1:1253";
+    let root = tempfile::TempDir::new().unwrap();
+    let data = vec![42u8; 2500];
+    fs::write(root.path().join("limited.txt"), &data).unwrap();
+
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf()).download_bandwidth_limit(1000),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            let start = std::time::Instant::now();
+            let remote_data = ftp_stream.simple_retr("limited.txt").unwrap().into_inner();
+            assert_eq!(remote_data, data);
+            // One second worth of data goes out right away, the rest is throttled.
+            assert!(start.elapsed() >= Duration::from_millis(1400), "Download wasn't throttled");
+        },
+    );
+}
+
+#[test]
+fn stalled_transfer_timeout() {
+    let addr = "127.0.0.1:1254";
+    let root = tempfile::TempDir::new().unwrap();
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf()).stalled_transfer_timeout(1),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            let mut tcps = ftp_stream.get_ref();
+            let mut reader = BufReader::new(tcps);
+            let mut line = String::new();
+
+            tcps.write_all(b"PASV\r\n").unwrap();
+            reader.read_line(&mut line).unwrap();
+            let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+            let caps = re.captures(&line).expect("Invalid PASV reply");
+            let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+            let _data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+            // Start an upload but never send any data
+            tcps.write_all(b"STOR stalled.txt\r\n").unwrap();
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert!(line.starts_with("150"), "Unexpected reply: {}", line);
+
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert!(line.starts_with("426"), "Expected the stalled transfer to be aborted, got: {}", line);
+        },
+    );
+}
+
+#[test]
+fn stalled_transfer_timeout_disabled() {
+    let addr = "127.0.0.1:1329";
+    let root = tempfile::TempDir::new().unwrap();
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf()).stalled_transfer_timeout(0),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            ftp_stream.put("quick.txt", &mut std::io::Cursor::new(b"quick")).unwrap();
+            assert_eq!(ftp_stream.simple_retr("quick.txt").unwrap().into_inner(), b"quick");
+
+            let mut tcps = ftp_stream.get_ref();
+            let mut reader = BufReader::new(tcps);
+            let mut line = String::new();
+            tcps.write_all(b"PASV\r\n").unwrap();
+            reader.read_line(&mut line).unwrap();
+            let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+            let caps = re.captures(&line).expect("Invalid PASV reply");
+            let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+            let mut data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+            tcps.write_all(b"STOR paused.txt\r\n").unwrap();
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert!(line.starts_with("150"), "Unexpected reply: {}", line);
+
+            // Without a timeout, an upload that pauses is not aborted.
+            std::thread::sleep(Duration::from_millis(1500));
+            data_stream.write_all(b"paused").unwrap();
+            drop(data_stream);
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert!(line.starts_with("226"), "Expected the upload to complete, got: {}", line);
+            assert_eq!(fs::read(root.path().join("paused.txt")).unwrap(), b"paused");
+        },
+    );
+}
+
+#[test]
+fn ipv6_extended_passive_mode() {
+    let addr = "[::1]:1259";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::write(root.path().join("hello.txt"), b"hello").unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+        let mut line = String::new();
+
+        // PASV can't represent an IPv6 address
+        tcps.write_all(b"PASV\r\n").unwrap();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("522"), "Unexpected reply: {}", line);
+
+        line.clear();
+        tcps.write_all(b"EPSV 1\r\n").unwrap();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "522 Network protocol not supported, use (2)\r\n");
+
+        line.clear();
+        tcps.write_all(b"EPSV\r\n").unwrap();
+        reader.read_line(&mut line).unwrap();
+        let re = Regex::new(r"^229 .*\(\|\|\|(\d+)\|\)").unwrap();
+        let caps = re.captures(&line).expect("Invalid EPSV reply");
+        let port = caps[1].parse::<u16>().unwrap();
+        let mut data_stream = std::net::TcpStream::connect(("::1", port)).unwrap();
+
+        tcps.write_all(b"NLST\r\n").unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("150"), "Unexpected reply: {}", line);
+        let mut listing = String::new();
+        data_stream.read_to_string(&mut listing).unwrap();
+        assert_eq!(listing.trim(), "hello.txt");
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("226"), "Unexpected reply: {}", line);
+    });
+}
+
+#[test]
+fn epsv_all() {
+    let addr = "127.0.0.1:1324";
+    test_with(addr, std::env::temp_dir(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+
+        tcps.write_all(b"EPSV ALL\r\n").unwrap();
+        assert_eq!(read_reply(&mut reader), "200 EPSV ALL ok\r\n");
+        for command in &["PASV", "LPSV", "PORT 127,0,0,1,4,1", "LPRT 4,4,127,0,0,1,2,4,1"] {
+            tcps.write_all(format!("{}\r\n", command).as_bytes()).unwrap();
+            assert_eq!(
+                read_reply(&mut reader),
+                "503 Only EPSV is allowed after EPSV ALL\r\n",
+                "Unexpected reply to {}",
+                command
+            );
+        }
+        tcps.write_all(b"EPSV\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("229 "));
+    });
+}
+
+#[cfg(feature = "ftps")]
+#[test]
+fn ftps_data_downgrade() {
+    // A self-signed identity for localhost, in a PKCS #12 archive protected with the password
+    // "libunftp".
+    let identity = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/resources/identity.pfx");
+    for (addr, forbidden) in &[("127.0.0.1:1325", false), ("127.0.0.1:1326", true)] {
+        let mut builder = libunftp::Server::new_with_fs_root(std::env::temp_dir()).ftps(identity.clone(), "libunftp");
+        if *forbidden {
+            builder = builder.ftps_forbid_data_downgrade();
+        }
+        test_with_builder(addr, builder, |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            let mut tcps = ftp_stream.get_ref();
+            let mut reader = BufReader::new(tcps);
+
+            tcps.write_all(b"PBSZ 0\r\n").unwrap();
+            assert!(read_reply(&mut reader).starts_with("200 "));
+            tcps.write_all(b"PROT P\r\n").unwrap();
+            assert_eq!(read_reply(&mut reader), "200 PROT OK. Securing data channel\r\n");
+            tcps.write_all(b"PROT C\r\n").unwrap();
+            if *forbidden {
+                assert_eq!(read_reply(&mut reader), "534 PROT C denied, the data channel must stay private\r\n");
+            } else {
+                assert_eq!(read_reply(&mut reader), "200 PROT OK. Switching data channel to plaintext\r\n");
+            }
+        });
+    }
+}
+
+#[cfg(feature = "ftps")]
+#[test]
+fn ftps_implicit() {
+    let identity = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/resources/identity.pfx");
+    let addr = "127.0.0.1:1330";
+    let implicit_addr = "127.0.0.1:1331";
+    let builder = libunftp::Server::new_with_fs_root(std::env::temp_dir())
+        .ftps(identity, "libunftp")
+        .ftps_implicit(implicit_addr);
+    test_with_builder(addr, builder, |_| {
+        let connector = native_tls::TlsConnector::builder().danger_accept_invalid_certs(true).build().unwrap();
+        let tcps = std::net::TcpStream::connect(implicit_addr).unwrap();
+        tcps.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        // The handshake comes first, the greeting is only sent over TLS.
+        let mut tlss = connector.connect("localhost", tcps).unwrap();
+        assert!(read_reply(&mut BufReader::new(&mut tlss)).starts_with("220 "));
+
+        tlss.write_all(b"USER hoi\r\n").unwrap();
+        assert!(read_reply(&mut BufReader::new(&mut tlss)).starts_with("331 "));
+        tlss.write_all(b"PASS jij\r\n").unwrap();
+        assert!(read_reply(&mut BufReader::new(&mut tlss)).starts_with("230 "));
+        tlss.write_all(b"AUTH TLS\r\n").unwrap();
+        assert!(read_reply(&mut BufReader::new(&mut tlss)).starts_with("503 "));
+        tlss.write_all(b"PWD\r\n").unwrap();
+        assert!(read_reply(&mut BufReader::new(&mut tlss)).starts_with("257 "));
+    });
+}
+
+#[cfg(feature = "ftps")]
+#[test]
+fn ftps_implicit_needs_a_certificate() {
+    let result = libunftp::Server::new_with_fs_root(std::env::temp_dir()).ftps_implicit("127.0.0.1:1332").build();
+    assert!(matches!(result, Err(libunftp::ConfigError::ImplicitFtpsWithoutCertificate)));
+}
+
+// Collects the messages of the log records together with the value of their `peer` key.
+struct CollectingDrain(std::sync::Arc<std::sync::Mutex<Vec<(String, String)>>>);
+
+impl slog::Drain for CollectingDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &slog::Record, values: &slog::OwnedKVList) -> std::result::Result<(), slog::Never> {
+        struct Peer(String);
+        impl slog::Serializer for Peer {
+            fn emit_arguments(&mut self, key: slog::Key, val: &std::fmt::Arguments) -> slog::Result {
+                if key == "peer" {
+                    self.0 = val.to_string();
+                }
+                Ok(())
+            }
+        }
+        let mut peer = Peer(String::new());
+        slog::KV::serialize(values, record, &mut peer).unwrap();
+        self.0.lock().unwrap().push((record.msg().to_string(), peer.0));
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_logger() {
+    let addr = "127.0.0.1:1260";
+    let records = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let logger = slog::Logger::root(std::sync::Mutex::new(CollectingDrain(records.clone())).fuse(), slog::o!());
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(std::env::temp_dir()).logger(logger), |_| {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let local_addr = ftp_stream.get_ref().local_addr().unwrap().to_string();
+        ftp_stream.quit().unwrap();
+
+        let records = records.lock().unwrap();
+        assert!(
+            records.iter().any(|(msg, peer)| msg == "User DefaultUser logged in" && *peer == local_addr),
+            "No login record for {} in {:?}",
+            local_addr,
+            records
+        );
+    });
+}
+
+#[test]
+fn reveal_session_id() {
+    let addr = "127.0.0.1:1261";
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(std::env::temp_dir()).reveal_session_id(), |_| {
+        let session_id = || {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            let mut tcps = ftp_stream.get_ref();
+            tcps.write_all(b"STAT\r\n").unwrap();
+            let mut reader = BufReader::new(tcps);
+            let mut line = String::new();
+            loop {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                if let Some(id) = line.trim_end().strip_prefix("Session ID: ") {
+                    return id.to_string();
+                }
+                assert!(!line.starts_with("211 "), "No session ID in STAT reply");
+            }
+        };
+        let first = session_id();
+        let second = session_id();
+        assert!(!first.is_empty());
+        assert_ne!(first, second);
+    });
+}
+
+#[test]
+fn reload_settings_through_handle() {
+    let addr = "127.0.0.1:1262";
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(std::env::temp_dir()), |handle| {
+        let first_line = || {
+            let stream = std::net::TcpStream::connect(addr).unwrap();
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            line
+        };
+        handle.set_greeting("Reloaded greeting");
+        assert_eq!(first_line(), "220 Reloaded greeting\r\n");
+
+        assert!(handle.set_deny_ips(vec!["not an ip"]).is_err());
+        handle.set_deny_ips(vec!["127.0.0.1"]).unwrap();
+        assert!(first_line().starts_with("421 "));
+        handle.set_deny_ips(Vec::<&str>::new()).unwrap();
+        assert_eq!(first_line(), "220 Reloaded greeting\r\n");
+    });
+}
+
+#[test]
+fn list_sessions_through_handle() {
+    use std::io::Cursor;
+
+    let addr = "127.0.0.1:1263";
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(std::env::temp_dir()), |handle| {
+        assert!(handle.sessions().is_empty());
+
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let content = b"Who's online?\n";
+        ftp_stream.put("sessions.txt", &mut Cursor::new(content)).unwrap();
+
+        let sessions = handle.sessions();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].username.as_deref(), Some("hoi"));
+        assert_eq!(sessions[0].peer_ip.to_string(), "127.0.0.1");
+        assert!(sessions[0].logged_in_at.is_some());
+        assert!(sessions[0].transfer.is_none());
+        assert_eq!(sessions[0].bytes_transferred, content.len() as u64);
+
+        ftp_stream.quit().unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(handle.sessions().is_empty());
+    });
+}
+
+#[test]
+fn kick_session_through_handle() {
+    let addr = "127.0.0.1:1264";
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(std::env::temp_dir()), |handle| {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        assert!(!handle.kick("no such session"));
+        let session_id = handle.sessions().remove(0).id;
+        assert!(handle.kick(&session_id));
+
+        let mut line = String::new();
+        BufReader::new(ftp_stream.get_ref()).read_line(&mut line).unwrap();
+        assert!(line.starts_with("421 "), "unexpected reply: {}", line);
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(handle.sessions().is_empty());
+    });
+}
+
+#[cfg(all(feature = "http_endpoint", feature = "metrics"))]
+#[test]
+fn http_endpoint() {
+    let addr = "127.0.0.1:1265";
+    let http_addr = "127.0.0.1:1266";
+    let registry = prometheus::Registry::new();
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(std::env::temp_dir())
+            .metrics_registry(&registry, "endpoint_test", Default::default())
+            .unwrap()
+            .http_endpoint(http_addr),
+        |handle| {
+            let get = |path: &str| {
+                let mut stream = std::net::TcpStream::connect(http_addr).unwrap();
+                write!(stream, "GET {} HTTP/1.0\r\n\r\n", path).unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                response
+            };
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            assert!(get("/metrics").contains("endpoint_test_ftp_sessions_total 1"));
+            assert!(get("/health").starts_with("HTTP/1.0 200"));
+            assert!(get("/health/live").starts_with("HTTP/1.0 200"));
+            assert!(get("/nothing").starts_with("HTTP/1.0 404"));
+
+            handle.shutdown();
+            std::thread::sleep(Duration::from_millis(100));
+            assert!(get("/health").starts_with("HTTP/1.0 503"));
+        },
+    );
+}
+
+#[cfg(feature = "http_endpoint")]
+#[test]
+fn http_endpoint_errors() {
+    let result = libunftp::Server::new_with_fs_root(std::env::temp_dir()).http_endpoint("localhost").build();
+    assert!(matches!(result, Err(libunftp::ConfigError::InvalidHttpEndpoint(..))));
+
+    // The server doesn't start when the endpoint can't bind to its address.
+    let taken = std::net::TcpListener::bind("127.0.0.1:1322").unwrap();
+    let server = libunftp::Server::new_with_fs_root(std::env::temp_dir())
+        .http_endpoint(taken.local_addr().unwrap().to_string())
+        .build()
+        .unwrap();
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(server.listen("127.0.0.1:1323"));
+    assert!(std::net::TcpStream::connect("127.0.0.1:1323").is_err());
+}
+
+#[test]
+fn health() {
+    use async_trait::async_trait;
+    use libunftp::{BackendStatus, HealthCheck};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    struct Toggle(Arc<AtomicBool>);
+
+    #[async_trait]
+    impl HealthCheck for Toggle {
+        async fn check(&self) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            if self.0.load(Ordering::SeqCst) {
+                Ok(())
+            } else {
+                Err("backend down".into())
+            }
+        }
+    }
+
+    let addr = "127.0.0.1:1272";
+    let reachable = Arc::new(AtomicBool::new(true));
+    let mut rt = Runtime::new().unwrap();
+    let server = libunftp::Server::new_with_fs_root(std::env::temp_dir())
+        .health_check(Toggle(reachable.clone()))
+        .build()
+        .unwrap();
+    let handle = server.handle();
+    assert!(!rt.block_on(handle.health()).is_live());
+
+    let _thread = rt.spawn(server.listen(addr));
+    wait_until_ready(addr, &handle);
+    let health = rt.block_on(handle.health());
+    assert!(health.is_live());
+    assert!(health.is_ready());
+    assert_eq!(health.backend, BackendStatus::Reachable);
+
+    reachable.store(false, Ordering::SeqCst);
+    let health = rt.block_on(handle.health());
+    assert!(health.is_live());
+    assert!(!health.is_ready());
+    assert_eq!(health.backend, BackendStatus::Unreachable("backend down".to_string()));
+
+    reachable.store(true, Ordering::SeqCst);
+    handle.shutdown();
+    std::thread::sleep(Duration::from_millis(100));
+    let health = rt.block_on(handle.health());
+    assert!(health.shutting_down);
+    assert!(!health.is_ready());
+    assert!(!health.is_live());
+}
+
+#[cfg(feature = "proxy_protocol")]
+#[test]
+fn proxy_protocol_trusted_proxies() {
+    let rt = Runtime::new().unwrap();
+    let greeting = |addr: &str| {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        write!(stream, "PROXY TCP4 192.0.2.1 127.0.0.1 40000 2121\r\n").unwrap();
+        // Untrusted connections are closed, possibly with a reset because the header was not read.
+        let mut line = String::new();
+        let _ = BufReader::new(stream).read_line(&mut line);
+        line
+    };
+
+    let trusted = "127.0.0.1:1273";
+    let server = libunftp::Server::new_with_fs_root(std::env::temp_dir())
+        .proxy_protocol_mode("127.0.0.1", 2121)
+        .unwrap()
+        .proxy_protocol_trusted_proxies(vec!["127.0.0.0/8"])
+        .unwrap()
+        .build()
+        .unwrap();
+    let _thread = rt.spawn(server.listen(trusted));
+
+    let untrusted = "127.0.0.1:1274";
+    let server = libunftp::Server::new_with_fs_root(std::env::temp_dir())
+        .proxy_protocol_mode("127.0.0.1", 2121)
+        .unwrap()
+        .proxy_protocol_trusted_proxies(vec!["10.0.0.1"])
+        .unwrap()
+        .build()
+        .unwrap();
+    let _thread = rt.spawn(server.listen(untrusted));
+    // The readiness check doesn't send the header that these servers expect.
+    std::thread::sleep(Duration::new(1, 0));
+
+    assert!(greeting(trusted).starts_with("220 "));
+    assert_eq!(greeting(untrusted), "");
+}
+
+#[test]
+fn xferlog() {
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let addr = "127.0.0.1:1267";
+    let buffer = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(std::env::temp_dir()).xferlog(buffer.clone()), |_| {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let content = b"Logged transfer\n";
+        ftp_stream.put("xferlog.txt", &mut Cursor::new(content)).unwrap();
+        ftp_stream.simple_retr("xferlog.txt").unwrap();
+
+        let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2, "unexpected log: {}", log);
+        assert!(
+            lines[0].ends_with(" 127.0.0.1 16 /xferlog.txt b _ i r hoi ftp 0 * c"),
+            "unexpected line: {}",
+            lines[0]
+        );
+        assert!(
+            lines[1].ends_with(" 127.0.0.1 16 /xferlog.txt b _ o r hoi ftp 0 * c"),
+            "unexpected line: {}",
+            lines[1]
+        );
+    });
+}
+
+#[test]
+fn audit_log() {
+    use libunftp::audit::{AuditEvent, AuditSink};
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Events(Arc<Mutex<Vec<AuditEvent>>>);
+
+    impl AuditSink for Events {
+        fn record(&self, event: &AuditEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    let addr = "127.0.0.1:1268";
+    let events = Events::default();
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(std::env::temp_dir()).audit_sink(events.clone()),
+        |_| {
+            // Leave out the connection of the readiness check.
+            events.0.lock().unwrap().clear();
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            ftp_stream.put("audit.txt", &mut Cursor::new(b"Audited\n")).unwrap();
+            ftp_stream.quit().unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+
+            let events = events.0.lock().unwrap();
+            let summary: Vec<(&str, Option<&str>, Option<u32>)> = events
+                .iter()
+                .map(|event| (event.command.as_str(), event.username.as_deref(), event.reply_code))
+                .collect();
+            assert_eq!(
+                summary,
+                vec![
+                    ("USER", Some("hoi"), Some(331)),
+                    ("PASS", Some("hoi"), Some(230)),
+                    ("PASV", Some("hoi"), Some(227)),
+                    ("STOR", Some("hoi"), Some(150)),
+                    ("QUIT", Some("hoi"), Some(221)),
+                ]
+            );
+            assert!(!events[1].arguments.contains("jij"), "password not redacted: {}", events[1].arguments);
+            assert!(events[3].arguments.contains("audit.txt"));
+            assert!(events.iter().all(|event| event.peer_ip.to_string() == "127.0.0.1"));
+        },
+    );
+}
+
+#[test]
+fn file_event_notifications() {
+    use libunftp::notification::{FileEvent, FileEventKind};
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    let addr = "127.0.0.1:1269";
+    let root = std::env::temp_dir().join("file_event_notifications");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir(&root).unwrap();
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<FileEvent>();
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(root.clone()).notify(tx), |_| {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        ftp_stream.mkdir("dir").unwrap();
+        ftp_stream.put("dir/a.txt", &mut Cursor::new(b"Notified\n")).unwrap();
+        ftp_stream.simple_retr("dir/a.txt").unwrap();
+        ftp_stream.cwd("dir").unwrap();
+        ftp_stream.rename("a.txt", "b.txt").unwrap();
+        ftp_stream.rm("b.txt").unwrap();
+        ftp_stream.cdup().unwrap();
+        ftp_stream.rmdir("dir").unwrap();
+        ftp_stream.quit().unwrap();
+
+        // The events may arrive after the replies, so wait for as many as are expected.
+        let mut rt = Runtime::new().unwrap();
+        let events: Vec<FileEvent> = (0..6)
+            .map(|_| {
+                rt.block_on(async { tokio::time::timeout(Duration::from_secs(5), rx.recv()).await })
+                    .unwrap()
+                    .unwrap()
+            })
+            .collect();
+        assert!(rx.try_recv().is_err());
+        let summary: Vec<(FileEventKind, PathBuf)> = events.iter().map(|event| (event.kind.clone(), event.path.clone())).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (FileEventKind::DirectoryCreated, "/dir".into()),
+                (FileEventKind::Uploaded { bytes: 9 }, "/dir/a.txt".into()),
+                (FileEventKind::Downloaded { bytes: 9 }, "/dir/a.txt".into()),
+                (FileEventKind::Renamed { from: "/dir/a.txt".into() }, "/dir/b.txt".into()),
+                (FileEventKind::Deleted, "/dir/b.txt".into()),
+                (FileEventKind::DirectoryRemoved, "/dir".into()),
+            ]
+        );
+        assert!(events.iter().all(|event| event.username.as_deref() == Some("hoi")));
+    });
+}
+
+#[cfg(feature = "webhook")]
+#[test]
+fn webhook_notifications() {
+    use libunftp::notification::webhook::WebhookNotifier;
+    use std::sync::mpsc;
+
+    let addr = "127.0.0.1:1270";
+    let webhook_addr = "127.0.0.1:1271";
+
+    // A webhook that fails the first delivery and accepts the retry.
+    let listener = std::net::TcpListener::bind(webhook_addr).unwrap();
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for (attempt, stream) in listener.incoming().enumerate() {
+            let mut reader = BufReader::new(stream.unwrap());
+            let mut headers = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                headers.push(line.trim_end().to_lowercase());
+            }
+            let length: usize = headers
+                .iter()
+                .find_map(|header| header.strip_prefix("content-length: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let status = if attempt == 0 { "500 Internal Server Error" } else { "200 OK" };
+            write!(reader.get_mut(), "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+            tx.send((headers, String::from_utf8(body).unwrap())).unwrap();
+        }
+    });
+
+    let webhook = WebhookNotifier::new(&format!("http://{}/events", webhook_addr))
+        .unwrap()
+        .secret("s3cr3t")
+        .retry_delay(Duration::from_millis(10))
+        .build();
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(std::env::temp_dir()).notify(webhook), |_| {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        ftp_stream.put("webhook.txt", &mut std::io::Cursor::new(b"Hooked\n")).unwrap();
+
+        let (_, first) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        let (headers, retried) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(first, retried);
+        assert!(retried.contains(r#""event":"uploaded""#), "unexpected payload: {}", retried);
+        assert!(retried.contains(r#""path":"/webhook.txt""#), "unexpected payload: {}", retried);
+        assert!(retried.contains(r#""bytes":7"#), "unexpected payload: {}", retried);
+        assert!(headers.iter().any(|header| header.starts_with("x-libunftp-signature: sha256=")));
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+    });
+}
+
+#[test]
+fn greeting_fn() {
+    let addr = "127.0.0.1:1255";
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(std::env::temp_dir()).greeting_fn(|peer, local| format!("Hello {} on port {}", peer.ip(), local.port())),
+        |_| {
+            let stream = std::net::TcpStream::connect(addr).unwrap();
+            let mut greeting = String::new();
+            BufReader::new(stream).read_line(&mut greeting).unwrap();
+            assert_eq!(greeting, "220 Hello 127.0.0.1 on port 1255\r\n");
+        },
+    );
+}
+
+#[test]
+fn multiline_greeting_and_login_message() {
+    let addr = "127.0.0.1:1256";
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(std::env::temp_dir())
+            .greeting("Welcome to\nthe test server")
+            .login_message("Message of the day:\n\nBe nice"),
+        |_| {
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            assert_eq!(read_reply(&mut reader), "220-Welcome to\r\n220 the test server\r\n");
+            stream.write_all(b"USER hoi\r\n").unwrap();
+            read_reply(&mut reader);
+            stream.write_all(b"PASS jij\r\n").unwrap();
+            assert_eq!(
+                read_reply(&mut reader),
+                "230-Message of the day:\r\n\r\nBe nice\r\n230 User logged in, proceed\r\n"
+            );
+        },
+    );
+}
+
+#[test]
+fn reply_catalog() {
+    let addr = "127.0.0.1:1257";
+    let catalog = libunftp::ReplyCatalog::new().message("Please authenticate", "Log in first, please");
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(std::env::temp_dir()).reply_catalog(catalog), |_| {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        let err = ftp_stream.pwd().unwrap_err().to_string();
+        assert!(err.contains("530 Log in first, please"), "Unexpected reply: {}", err);
+    });
+}
+
+#[test]
+fn graceful_shutdown() {
+    let addr = "127.0.0.1:1258";
+    let mut rt = Runtime::new().unwrap();
+    let server = libunftp::Server::new_with_fs_root(std::env::temp_dir()).build().unwrap();
+    let handle = server.handle();
+    let listening = rt.spawn(server.listen(addr));
+    wait_until_ready(addr, &handle);
+
+    let mut ftp_stream = FtpStream::connect(addr).unwrap();
+    ftp_stream.login("hoi", "jij").unwrap();
+
+    handle.shutdown();
+    std::thread::sleep(Duration::from_millis(100));
+
+    // The active session is told to go away at its next command
+    let err = ftp_stream.pwd().unwrap_err().to_string();
+    assert!(err.contains("421 Service closing control connection"), "Unexpected reply: {}", err);
+
+    // No new connections are accepted and the server finishes once the sessions are gone
+    assert!(FtpStream::connect(addr).is_err());
+    rt.block_on(listening).unwrap();
+}
+
+#[test]
+fn spooled_upload() {
+    use std::io::Cursor;
+
+    let addr = "127.0.0.1:1275";
+    let root = tempfile::tempdir().unwrap();
+    let spool = tempfile::tempdir().unwrap();
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf()).spool_uploads(spool.path()),
+        |_| {
+            let content = b"Spooled before it was stored\n";
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            ftp_stream.put("spooled.txt", &mut Cursor::new(content)).unwrap();
+
+            assert_eq!(std::fs::read(root.path().join("spooled.txt")).unwrap(), content);
+            // The spooled file is gone once the upload is stored.
+            assert_eq!(std::fs::read_dir(spool.path()).unwrap().count(), 0);
+        },
+    );
+}
+
+// Linux answers on all of 127.0.0.0/8 out of the box, macOS for one only on 127.0.0.1.
+#[cfg(target_os = "linux")]
+#[test]
+fn data_bind_address() {
+    use std::io::Cursor;
+
+    let addr = "127.0.0.1:1276";
+    let root = tempfile::tempdir().unwrap();
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf()).data_bind_address("127.0.0.2".parse().unwrap()),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+
+            // The client connects the data connection to the address in the PASV reply.
+            ftp_stream.put("bound.txt", &mut Cursor::new(b"Through the other interface\n")).unwrap();
+            assert_eq!(std::fs::read(root.path().join("bound.txt")).unwrap(), b"Through the other interface\n");
+
+            let mut tcps = ftp_stream.get_ref();
+            tcps.write_all(b"PASV\r\n").unwrap();
+            let mut reply = String::new();
+            BufReader::new(tcps).read_line(&mut reply).unwrap();
+            assert!(reply.starts_with("227 Entering Passive Mode (127,0,0,2,"), "{}", reply);
+        },
+    );
+}
+
+#[test]
+fn max_concurrent_transfers() {
+    use std::io::Cursor;
+
+    let addr = "127.0.0.1:1277";
+    let root = tempfile::TempDir::new().unwrap();
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf())
+            .max_concurrent_transfers(1)
+            .transfer_queue_timeout(0),
+        |_| {
+            // Start an upload that takes up the only transfer slot by never sending any data.
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            let mut tcps = ftp_stream.get_ref();
+            let mut reader = BufReader::new(tcps);
+            let mut line = String::new();
+            tcps.write_all(b"PASV\r\n").unwrap();
+            reader.read_line(&mut line).unwrap();
+            let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+            let caps = re.captures(&line).expect("Invalid PASV reply");
+            let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+            let _data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+            tcps.write_all(b"STOR slow.txt\r\n").unwrap();
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert!(line.starts_with("150"), "Unexpected reply: {}", line);
+
+            let mut other_stream = FtpStream::connect(addr).unwrap();
+            other_stream.login("hoi", "jij").unwrap();
+            let err = other_stream.put("refused.txt", &mut Cursor::new(b"Not now")).unwrap_err();
+            assert!(err.to_string().contains("450 Too many transfers"), "{}", err);
+        },
+    );
+}
+
+#[test]
+fn max_concurrent_transfers_of_zero() {
+    let result = libunftp::Server::new_with_fs_root(std::env::temp_dir()).max_concurrent_transfers(0).build();
+    assert!(matches!(result, Err(libunftp::ConfigError::NoConcurrentTransfers)));
+}
+
+#[test]
+fn transfer_replies_come_before_replies_to_later_commands() {
+    let addr = "127.0.0.1:1278";
+    let root = tempfile::TempDir::new().unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+        let mut line = String::new();
+        tcps.write_all(b"PASV\r\n").unwrap();
+        reader.read_line(&mut line).unwrap();
+        let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+        let caps = re.captures(&line).expect("Invalid PASV reply");
+        let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+        let _data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+        // Send both commands at once, the reply to RETR must still come first.
+        tcps.write_all(b"RETR missing.txt\r\nNOOP\r\n").unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("550"), "Unexpected reply: {}", line);
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("200"), "Unexpected reply: {}", line);
+    });
+}
+
+#[test]
+fn abort_transfer_with_telnet_synch() {
+    let addr = "127.0.0.1:1279";
+    let root = tempfile::TempDir::new().unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        // Start an upload that never sends any data.
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+        let mut line = String::new();
+        tcps.write_all(b"PASV\r\n").unwrap();
+        reader.read_line(&mut line).unwrap();
+        let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+        let caps = re.captures(&line).expect("Invalid PASV reply");
+        let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+        let _data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        tcps.write_all(b"STOR slow.txt\r\n").unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("150"), "Unexpected reply: {}", line);
+
+        // Abort it the way clients do: Interrupt Process and Data Mark, followed by ABOR.
+        tcps.write_all(b"\xff\xf4\xff\xf2ABOR\r\n").unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("426"), "Unexpected reply: {}", line);
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("226"), "Unexpected reply: {}", line);
+    });
+}
+
+#[test]
+fn abort_listing() {
+    let addr = "127.0.0.1:1334";
+    let root = tempfile::TempDir::new().unwrap();
+    for i in 0..20 {
+        std::fs::write(root.path().join(format!("file{:02}.txt", i)), b"").unwrap();
+    }
+    // At 10 bytes per second the listing takes half a minute.
+    let builder = libunftp::Server::new_with_fs_root(root.path().to_path_buf()).download_bandwidth_limit(10);
+    test_with_builder(addr, builder, |_| {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+        tcps.write_all(b"PASV\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+        let caps = re.captures(&reply).expect("Invalid PASV reply");
+        let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+        let _data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        tcps.write_all(b"NLST\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("150"));
+
+        let started = Instant::now();
+        tcps.write_all(b"\xff\xf4\xff\xf2ABOR\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("426"));
+        assert!(read_reply(&mut reader).starts_with("226"));
+        assert!(started.elapsed() < Duration::from_secs(10), "The listing ran to its end");
+        tcps.write_all(b"NOOP\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("200"));
+    });
+}
+
+#[test]
+fn pipelined_commands() {
+    let addr = "127.0.0.1:1281";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::write(root.path().join("greeting.txt"), b"Hello there").unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        assert!(read_reply(&mut reader).starts_with("220"));
+
+        // Send the whole session at once, like some clients do.
+        stream.write_all(b"USER hoi\r\nPASS jij\r\nPASV\r\nRETR greeting.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("331"));
+        assert!(read_reply(&mut reader).starts_with("230"));
+        let reply = read_reply(&mut reader);
+        let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+        let caps = re.captures(&reply).expect("Invalid PASV reply");
+        let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+        let mut data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let reply = read_reply(&mut reader);
+        assert!(reply.starts_with("150"), "Unexpected reply: {}", reply);
+        let mut data = String::new();
+        data_stream.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "Hello there");
+        let reply = read_reply(&mut reader);
+        assert!(reply.starts_with("226"), "Unexpected reply: {}", reply);
+    });
+}
+
+#[test]
+fn commands_during_transfer() {
+    let addr = "127.0.0.1:1282";
+    let root = tempfile::TempDir::new().unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+        tcps.write_all(b"PASV\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+        let caps = re.captures(&reply).expect("Invalid PASV reply");
+        let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+        let _data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        tcps.write_all(b"STOR slow.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("150"));
+
+        tcps.write_all(b"PWD\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        assert!(reply.starts_with("503"), "Unexpected reply: {}", reply);
+        tcps.write_all(b"STAT\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        assert!(reply.starts_with("211"), "Unexpected reply: {}", reply);
+
+        tcps.write_all(b"ABOR\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("426"));
+        assert!(read_reply(&mut reader).starts_with("226"));
+        tcps.write_all(b"NOOP\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        assert!(reply.starts_with("200"), "Unexpected reply: {}", reply);
+    });
+}
+
+#[test]
+fn ascii_transfer_type() {
+    let addr = "127.0.0.1:1283";
+    let root = tempfile::TempDir::new().unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+        let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+        let data_connection = |reply: String| {
+            let caps = re.captures(&reply).expect("Invalid PASV reply");
+            let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+            std::net::TcpStream::connect(("127.0.0.1", port)).unwrap()
+        };
+
+        tcps.write_all(b"TYPE E\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("504"));
+        tcps.write_all(b"MODE C\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("504"));
+        tcps.write_all(b"TYPE A\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("200"));
+
+        tcps.write_all(b"PASV\r\n").unwrap();
+        let mut data_stream = data_connection(read_reply(&mut reader));
+        tcps.write_all(b"STOR ascii.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("150"));
+        data_stream.write_all(b"one\r\ntwo\r\n").unwrap();
+        drop(data_stream);
+        assert!(read_reply(&mut reader).starts_with("226"));
+        assert_eq!(fs::read(root.path().join("ascii.txt")).unwrap(), b"one\ntwo\n".to_vec());
+
+        tcps.write_all(b"PASV\r\n").unwrap();
+        let mut data_stream = data_connection(read_reply(&mut reader));
+        tcps.write_all(b"RETR ascii.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("150"));
+        let mut data = Vec::new();
+        data_stream.read_to_end(&mut data).unwrap();
+        assert!(read_reply(&mut reader).starts_with("226"));
+        assert_eq!(data, b"one\r\ntwo\r\n".to_vec());
+    });
+}
+
+#[test]
+fn command_too_long() {
+    let addr = "127.0.0.1:1284";
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(std::env::temp_dir()).max_command_length(64), |_| {
+        let mut tcps = std::net::TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(tcps.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("220"));
+
+        tcps.write_all(&[b'A'; 100]).unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("500"), "Unexpected reply: {}", line);
+        line.clear();
+        assert_eq!(reader.read_line(&mut line).unwrap(), 0);
+    });
+}
+
+#[test]
+fn middleware() {
+    use async_trait::async_trait;
+    use libunftp::{ControlChanError, Middleware, Next, Reply, ReplyCode, Request};
+    use std::sync::{Arc, Mutex};
+
+    struct Rules {
+        seen: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Middleware for Rules {
+        async fn handle(&self, mut request: Request, next: Next<'_>) -> std::result::Result<Reply, ControlChanError> {
+            self.seen.lock().unwrap().push(request.name());
+            match request.name().as_str() {
+                "DELE" => Ok(Reply::new(ReplyCode::FileError, "Permission denied")),
+                "NOOP" => Ok(Reply::none()),
+                "CWD" => {
+                    request.rewrite("CWD new")?;
+                    next.run(request).await
+                }
+                _ => next.run(request).await,
+            }
+        }
+    }
+
+    let addr = "127.0.0.1:1285";
+    let root = tempfile::TempDir::new().unwrap();
+    fs::write(root.path().join("keep.txt"), b"Keep me").unwrap();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf()).add_middleware(Rules { seen: seen.clone() }),
+        |_| {
+            // Leave out the connection of the readiness check.
+            seen.lock().unwrap().clear();
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            assert!(ftp_stream.rm("keep.txt").is_err());
+            assert!(root.path().join("keep.txt").exists());
+            ftp_stream.mkdir("new").unwrap();
+            ftp_stream.cwd("old").unwrap();
+            assert_eq!(ftp_stream.pwd().unwrap(), "/new");
+            // Middleware that gives no reply doesn't stall the session, transfers that reply later do.
+            assert!(ftp_stream.noop().unwrap_err().to_string().contains("451"));
+            assert!(ftp_stream.list(None).unwrap().is_empty());
+            ftp_stream.quit().unwrap();
+
+            assert_eq!(
+                *seen.lock().unwrap(),
+                vec!["USER", "PASS", "DELE", "MKD", "CWD", "PWD", "NOOP", "PASV", "LIST", "QUIT"]
+            );
+        },
+    );
+}
+
+#[test]
+fn custom_commands() {
+    use async_trait::async_trait;
+    use libunftp::auth::DefaultUser;
+    use libunftp::storage::filesystem::Filesystem;
+    use libunftp::{CommandContext, ControlChanError, CustomCommandHandler, Reply, ReplyCode};
+
+    struct Hello;
+
+    #[async_trait]
+    impl CustomCommandHandler<Filesystem, DefaultUser> for Hello {
+        async fn handle(&self, verb: &str, argument: &str, context: CommandContext<Filesystem, DefaultUser>) -> std::result::Result<Reply, ControlChanError> {
+            if argument.is_empty() {
+                return Ok(Reply::none());
+            }
+            let username = context.username().await.unwrap_or_default();
+            Ok(Reply::new_with_string(
+                ReplyCode::CommandOkay,
+                format!("{} {} from {}", verb, argument, username),
+            ))
+        }
+    }
+
+    let addr = "127.0.0.1:1286";
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(std::env::temp_dir()).custom_command("xhello", Hello),
+        |_| {
+            let mut tcps = std::net::TcpStream::connect(addr).unwrap();
+            let mut reader = BufReader::new(tcps.try_clone().unwrap());
+            assert!(read_reply(&mut reader).starts_with("220"));
+            tcps.write_all(b"XHELLO world\r\n").unwrap();
+            assert!(read_reply(&mut reader).starts_with("530"));
+            tcps.write_all(b"USER hoi\r\nPASS jij\r\n").unwrap();
+            assert!(read_reply(&mut reader).starts_with("331"));
+            assert!(read_reply(&mut reader).starts_with("230"));
+            tcps.write_all(b"xhello big world\r\n").unwrap();
+            assert_eq!(read_reply(&mut reader), "200 XHELLO big world from hoi\r\n");
+            // A handler that gives no reply doesn't stall the session.
+            tcps.write_all(b"XHELLO\r\n").unwrap();
+            assert!(read_reply(&mut reader).starts_with("451"));
+            tcps.write_all(b"XBYE\r\n").unwrap();
+            assert!(read_reply(&mut reader).starts_with("500"));
+        },
+    );
+}
+
+#[test]
+fn dos_list_format() {
+    let addr = "127.0.0.1:1287";
+    let root = tempfile::TempDir::new().unwrap();
+    fs::create_dir(root.path().join("reports")).unwrap();
+    fs::write(root.path().join("report.pdf"), b"1234").unwrap();
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf()).list_formatter(libunftp::DosListFormatter),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            let list = ftp_stream.list(None).unwrap();
+            let line = Regex::new(r"^\d\d-\d\d-\d\d  \d\d:\d\d[AP]M +(<DIR>|\d+) +(\S+)$").unwrap();
+            let mut entries: Vec<(String, String)> = list
+                .iter()
+                .map(|entry| {
+                    let captures = line.captures(entry).unwrap_or_else(|| panic!("Unexpected entry: {}", entry));
+                    (captures[1].to_string(), captures[2].to_string())
+                })
+                .collect();
+            entries.sort_by(|a, b| a.1.cmp(&b.1));
+            assert_eq!(
+                entries,
+                vec![("4".to_string(), "report.pdf".to_string()), ("<DIR>".to_string(), "reports".to_string())]
+            );
+        },
+    );
+}
+
+#[test]
+fn path_mapper() {
+    use libunftp::auth::DefaultUser;
+    use std::io::Cursor;
+    use std::path::Path;
+
+    // Keeps what users put in /incoming under /data/<user>/incoming.
+    struct Incoming;
+
+    impl libunftp::PathMapper<DefaultUser> for Incoming {
+        fn to_storage(&self, user: &Option<DefaultUser>, path: &Path) -> PathBuf {
+            match (user, path.strip_prefix("/incoming")) {
+                (Some(user), Ok(rest)) => Path::new("/data").join(user.to_string()).join("incoming").join(rest),
+                _ => path.to_path_buf(),
+            }
+        }
+
+        fn to_client(&self, user: &Option<DefaultUser>, path: &Path) -> PathBuf {
+            let home = Path::new("/data").join(user.as_ref().unwrap().to_string());
+            match path.strip_prefix(&home) {
+                Ok(rest) => Path::new("/").join(rest),
+                Err(_) => path.to_path_buf(),
+            }
+        }
+    }
+
+    let addr = "127.0.0.1:1288";
+    let root = tempfile::TempDir::new().unwrap();
+    fs::create_dir_all(root.path().join("data/DefaultUser/incoming")).unwrap();
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf()).path_mapper(Incoming),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            ftp_stream.cwd("/incoming").unwrap();
+            assert_eq!(ftp_stream.pwd().unwrap(), "/incoming");
+            ftp_stream.put("report.txt", &mut Cursor::new(b"mapped")).unwrap();
+            assert_eq!(fs::read(root.path().join("data/DefaultUser/incoming/report.txt")).unwrap(), b"mapped");
+            assert_eq!(ftp_stream.nlst(None).unwrap(), vec!["report.txt".to_string()]);
+            assert_eq!(ftp_stream.simple_retr("/incoming/report.txt").unwrap().into_inner(), b"mapped");
+
+            // STOU names the file in the directory the client sees and stores it where the mapper says.
+            let mut tcps = ftp_stream.get_ref();
+            let mut reader = BufReader::new(tcps);
+            tcps.write_all(b"EPSV\r\n").unwrap();
+            let reply = read_reply(&mut reader);
+            let port = Regex::new(r"\(\|\|\|(\d+)\|\)").unwrap().captures(&reply).expect("Invalid EPSV reply")[1]
+                .parse::<u16>()
+                .unwrap();
+            let mut data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+            tcps.write_all(b"STOU\r\n").unwrap();
+            let reply = read_reply(&mut reader);
+            let caps = Regex::new(r#"^150 FILE: "/incoming/([0-9a-f-]+)"\r\n$"#)
+                .unwrap()
+                .captures(&reply)
+                .expect("Invalid STOU reply");
+            assert!(read_reply(&mut reader).starts_with("150 Ready to receive data"));
+            data_stream.write_all(b"unique").unwrap();
+            drop(data_stream);
+            assert!(read_reply(&mut reader).starts_with("226"));
+            assert_eq!(fs::read(root.path().join("data/DefaultUser/incoming").join(&caps[1])).unwrap(), b"unique");
+        },
+    );
+}
+
+#[test]
+fn disable_commands() {
+    let addr = "127.0.0.1:1289";
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(std::env::temp_dir()).disable_commands(["dele", "SIZE"]),
+        |_| {
+            let mut tcps = std::net::TcpStream::connect(addr).unwrap();
+            let mut reader = BufReader::new(tcps.try_clone().unwrap());
+            assert!(read_reply(&mut reader).starts_with("220"));
+            tcps.write_all(b"USER hoi\r\nPASS jij\r\n").unwrap();
+            assert!(read_reply(&mut reader).starts_with("331"));
+            assert!(read_reply(&mut reader).starts_with("230"));
+            tcps.write_all(b"DELE some.txt\r\n").unwrap();
+            assert_eq!(read_reply(&mut reader), "502 Command disabled\r\n");
+            tcps.write_all(b"FEAT\r\n").unwrap();
+            let feat = read_reply(&mut reader);
+            assert!(feat.contains(" MDTM\r\n") && !feat.contains("SIZE"), "Unexpected reply: {}", feat);
+            tcps.write_all(b"HELP\r\n").unwrap();
+            let help = read_reply(&mut reader);
+            assert!(
+                help.contains("RETR") && !help.contains("DELE") && !help.contains("SIZE") && !help.contains("PORT") && !help.contains("LPRT"),
+                "Unexpected reply: {}",
+                help
+            );
+        },
+    );
+}
+
+#[test]
+fn drop_box() {
+    use std::io::Cursor;
+
+    let addr = "127.0.0.1:1290";
+    let root = tempfile::TempDir::new().unwrap();
+    fs::write(root.path().join("earlier.txt"), b"secret").unwrap();
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(root.path().to_path_buf()).drop_box(), |_| {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        ftp_stream.put("delivery.txt", &mut Cursor::new(b"delivered")).unwrap();
+        ftp_stream.mkdir("batch").unwrap();
+        assert_eq!(fs::read(root.path().join("delivery.txt")).unwrap(), b"delivered");
+        assert!(root.path().join("batch").is_dir());
+        let err = ftp_stream.put("earlier.txt", &mut Cursor::new(b"replaced")).unwrap_err().to_string();
+        assert!(err.contains("553 File exists"), "Unexpected reply: {}", err);
+        for command in ["SITE MKDIR -p batch/inner\r\n", "SITE SYMLINK earlier.txt link.txt\r\n"] {
+            ftp_stream.get_ref().write_all(command.as_bytes()).unwrap();
+            let mut line = String::new();
+            BufReader::new(ftp_stream.get_ref()).read_line(&mut line).unwrap();
+            assert_eq!(line, "550 Permission denied\r\n");
+        }
+        assert!(!root.path().join("batch/inner").exists());
+
+        assert!(ftp_stream.list(None).unwrap().is_empty());
+        assert!(ftp_stream.nlst(None).unwrap().is_empty());
+        for err in [
+            ftp_stream.simple_retr("earlier.txt").map(|_| ()),
+            ftp_stream.size("earlier.txt").map(|_| ()),
+            ftp_stream.rm("earlier.txt"),
+            ftp_stream.rename("earlier.txt", "mine.txt"),
+        ] {
+            let err = err.unwrap_err().to_string();
+            assert!(err.contains("550 Permission denied"), "Unexpected reply: {}", err);
+        }
+        assert_eq!(fs::read(root.path().join("earlier.txt")).unwrap(), b"secret");
+    });
+}
+
+#[test]
+fn no_clobber() {
+    use std::io::Cursor;
+
+    let addr = "127.0.0.1:1291";
+    let root = tempfile::TempDir::new().unwrap();
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(root.path().to_path_buf()).no_clobber(), |_| {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        ftp_stream.put("report.txt", &mut Cursor::new(b"first")).unwrap();
+        let err = ftp_stream.put("report.txt", &mut Cursor::new(b"second")).unwrap_err().to_string();
+        assert!(err.contains("553 File exists"), "Unexpected reply: {}", err);
+        assert_eq!(fs::read(root.path().join("report.txt")).unwrap(), b"first");
+
+        ftp_stream.rm("report.txt").unwrap();
+        ftp_stream.put("report.txt", &mut Cursor::new(b"second")).unwrap();
+        assert_eq!(fs::read(root.path().join("report.txt")).unwrap(), b"second");
+
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+        tcps.write_all(b"REST 6\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("350"));
+        tcps.write_all(b"PASV\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("227"));
+        tcps.write_all(b"STOR report.txt\r\n").unwrap();
+        assert_eq!(read_reply(&mut reader), "550 Resuming uploads is not allowed\r\n");
+        assert_eq!(fs::read(root.path().join("report.txt")).unwrap(), b"second");
+    });
+}
+
+#[test]
+fn filename_policy() {
+    use std::io::Cursor;
+
+    let addr = "127.0.0.1:1292";
+    let root = tempfile::TempDir::new().unwrap();
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf()).filename_policy(|name: &str| !name.starts_with('.')),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            ftp_stream.put("report.txt", &mut Cursor::new(b"report")).unwrap();
+            ftp_stream.mkdir("archive").unwrap();
+            for err in [
+                ftp_stream.put(".hidden", &mut Cursor::new(b"hidden")),
+                ftp_stream.mkdir("archive/.trash"),
+                ftp_stream.rename("report.txt", ".report.txt"),
+            ] {
+                let err = err.unwrap_err().to_string();
+                assert!(err.contains("553 File name not allowed"), "Unexpected reply: {}", err);
+            }
+            assert!(!root.path().join(".hidden").exists());
+            assert!(!root.path().join("archive/.trash").exists());
+            assert!(root.path().join("report.txt").exists());
+        },
+    );
+}
+
+#[test]
+fn hidden_paths() {
+    let addr = "127.0.0.1:1293";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(root.path().join(".trash")).unwrap();
+    std::fs::write(root.path().join(".trash/old.txt"), b"old").unwrap();
+    std::fs::write(root.path().join("upload.partial"), b"partial").unwrap();
+    std::fs::write(root.path().join("report.txt"), b"report").unwrap();
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf())
+            .hide_dotfiles()
+            .hide_paths(["*.partial"]),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            assert_eq!(ftp_stream.nlst(None).unwrap(), vec!["report.txt".to_string()]);
+            let listing = ftp_stream.list(None).unwrap();
+            assert_eq!(listing.len(), 1);
+            assert!(listing[0].ends_with("report.txt"), "Unexpected listing: {:?}", listing);
+            for err in [
+                ftp_stream.cwd(".trash"),
+                ftp_stream.rm(".trash/old.txt"),
+                ftp_stream.size("upload.partial").map(|_| ()),
+                ftp_stream.simple_retr("upload.partial").map(|_| ()),
+            ] {
+                let err = err.unwrap_err().to_string();
+                assert!(err.contains("550"), "Unexpected reply: {}", err);
+            }
+            assert!(root.path().join(".trash/old.txt").exists());
+        },
+    );
+}
+
+#[test]
+fn mlsd_and_mlst() {
+    let addr = "127.0.0.1:1294";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::write(root.path().join("report.txt"), b"report").unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+
+        tcps.write_all(b"FEAT\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        assert!(
+            reply.contains(" MLST type*;size*;modify*;unique*;UNIX.uid;UNIX.gid;\r\n"),
+            "Unexpected reply: {}",
+            reply
+        );
+
+        tcps.write_all(b"MLST report.txt\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        assert!(reply.starts_with("250-"), "Unexpected reply: {}", reply);
+        assert!(reply.contains("\r\n type=file;size=6;modify="), "Unexpected reply: {}", reply);
+        assert!(reply.contains(";unique="), "Unexpected reply: {}", reply);
+        assert!(reply.contains("; /report.txt\r\n"), "Unexpected reply: {}", reply);
+
+        tcps.write_all(b"OPTS MLST Size;color;\r\n").unwrap();
+        assert_eq!(read_reply(&mut reader), "200 MLST OPTS size;\r\n");
+
+        tcps.write_all(b"PASV\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+        let caps = re.captures(&reply).expect("Invalid PASV reply");
+        let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+        let mut data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        tcps.write_all(b"MLSD\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("150"));
+        let mut listing = String::new();
+        data_stream.read_to_string(&mut listing).unwrap();
+        assert_eq!(listing, "size=6; report.txt\r\n");
+        assert!(read_reply(&mut reader).starts_with("226"));
+    });
+}
+
+#[test]
+fn list_with_wildcards() {
+    let addr = "127.0.0.1:1295";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(root.path().join("logs")).unwrap();
+    for name in &["data-2024-01.log", "data-2023-12.log", "notes.txt", "[draft].txt"] {
+        std::fs::write(root.path().join("logs").join(name), b"").unwrap();
+    }
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        assert_eq!(ftp_stream.nlst(Some("logs/data-2024*")).unwrap(), vec!["data-2024-01.log".to_string()]);
+        let mut names = ftp_stream.nlst(Some("logs/*.log")).unwrap();
+        names.sort();
+        assert_eq!(names, vec!["data-2023-12.log".to_string(), "data-2024-01.log".to_string()]);
+        ftp_stream.cwd("logs").unwrap();
+        assert_eq!(ftp_stream.list(Some("\\[draft\\]*")).unwrap().len(), 1);
+        assert!(ftp_stream.nlst(Some("*.pdf")).unwrap().is_empty());
+    });
+}
+
+#[test]
+fn list_single_file() {
+    let addr = "127.0.0.1:1296";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(root.path().join("logs")).unwrap();
+    std::fs::write(root.path().join("logs/server.log"), b"log").unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let listing = ftp_stream.list(Some("logs/server.log")).unwrap();
+        assert_eq!(listing.len(), 1);
+        assert!(listing[0].ends_with(" server.log"), "Unexpected listing: {:?}", listing);
+        assert_eq!(ftp_stream.nlst(Some("logs/server.log")).unwrap(), vec!["server.log".to_string()]);
+        assert!(ftp_stream.list(Some("logs/missing.log")).is_err());
+    });
+}
+
+#[test]
+fn site_commands() {
+    use async_trait::async_trait;
+    use libunftp::auth::DefaultUser;
+    use libunftp::storage::filesystem::Filesystem;
+    use libunftp::{CommandContext, ControlChanError, CustomCommandHandler, Reply, ReplyCode};
+
+    struct Who;
+
+    #[async_trait]
+    impl CustomCommandHandler<Filesystem, DefaultUser> for Who {
+        async fn handle(&self, verb: &str, _argument: &str, context: CommandContext<Filesystem, DefaultUser>) -> std::result::Result<Reply, ControlChanError> {
+            let username = context.username().await.unwrap_or_default();
+            Ok(Reply::new_with_string(ReplyCode::CommandOkay, format!("{} {}", verb, username)))
+        }
+    }
+
+    let addr = "127.0.0.1:1297";
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(std::env::temp_dir()).site_command("who", Who), |_| {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+
+        tcps.write_all(b"SITE HELP\r\n").unwrap();
+        assert_eq!(
+            read_reply(&mut reader),
+            "214-The following SITE commands are recognized:\r\n214 HELP LINK MKDIR RMDIR SYMLINK WHO\r\n"
+        );
+        tcps.write_all(b"site who\r\n").unwrap();
+        assert_eq!(read_reply(&mut reader), "200 WHO hoi\r\n");
+        tcps.write_all(b"SITE NOPE\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("500"));
+    });
+}
+
+#[test]
+fn site_symlink() {
+    let addr = "127.0.0.1:1298";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::write(root.path().join("report.txt"), b"report").unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+
+        tcps.write_all(b"SITE SYMLINK report.txt latest.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("250"));
+        assert_eq!(std::fs::read(root.path().join("latest.txt")).unwrap(), b"report");
+
+        // Going up from the root stays at the root, so the link points inside it.
+        tcps.write_all(b"SITE LINK ../../etc/passwd passwd\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("250"));
+        let target = std::fs::read_link(root.path().join("passwd")).unwrap();
+        assert!(target.starts_with(root.path().canonicalize().unwrap()), "Link points to {:?}", target);
+
+        tcps.write_all(b"SITE SYMLINK report.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("501"));
+    });
+}
+
+#[test]
+fn site_symlink_with_hidden_paths() {
+    let addr = "127.0.0.1:1333";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(root.path().join("internal")).unwrap();
+    std::fs::write(root.path().join("internal/state"), b"state").unwrap();
+    std::fs::write(root.path().join("report.txt"), b"report").unwrap();
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf()).hide_paths(["/internal"]),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            {
+                let mut tcps = ftp_stream.get_ref();
+                let mut reader = BufReader::new(tcps);
+                tcps.write_all(b"SITE SYMLINK report.txt latest.txt\r\n").unwrap();
+                assert!(read_reply(&mut reader).starts_with("250"));
+
+                // A link to the root would make /alias/internal/state reachable.
+                tcps.write_all(b"SITE SYMLINK / alias\r\n").unwrap();
+                assert_eq!(read_reply(&mut reader), "550 Links can only point to files\r\n");
+                assert!(!root.path().join("alias").exists());
+            }
+            assert!(ftp_stream.simple_retr("/alias/internal/state").is_err());
+            assert_eq!(ftp_stream.simple_retr("/latest.txt").unwrap().into_inner(), b"report");
+        },
+    );
+}
+
+#[test]
+fn site_mkdir() {
+    let addr = "127.0.0.1:1299";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(root.path().join("mirror")).unwrap();
+    std::fs::write(root.path().join("file.txt"), b"file").unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+
+        tcps.write_all(b"SITE MKDIR -p mirror/a/b/c\r\n").unwrap();
+        assert_eq!(read_reply(&mut reader), "257 \"/mirror/a/b/c\" created\r\n");
+        assert!(root.path().join("mirror/a/b/c").is_dir());
+        tcps.write_all(b"SITE MKDIR mirror/a/b/c\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("257"));
+        tcps.write_all(b"SITE MKDIR file.txt/sub\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("553"));
+    });
+}
+
+#[test]
+fn site_rmdir() {
+    let addr = "127.0.0.1:1300";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(root.path().join("tree/a/b")).unwrap();
+    std::fs::write(root.path().join("tree/one.txt"), b"one").unwrap();
+    std::fs::write(root.path().join("tree/a/b/two.txt"), b"two").unwrap();
+    std::fs::create_dir_all(root.path().join("secret/.git")).unwrap();
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(root.path().to_path_buf()).hide_dotfiles(), |_| {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+
+        tcps.write_all(b"SITE RMDIR tree\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("250"));
+        assert!(!root.path().join("tree").exists());
+
+        // Hidden entries cannot be removed, so nothing is.
+        tcps.write_all(b"SITE RMDIR secret\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("550"));
+        assert!(root.path().join("secret/.git").exists());
+
+        tcps.write_all(b"SITE RMDIR /\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("550"));
+    });
+}
+
+#[test]
+fn system_type_and_identification() {
+    let addr = "127.0.0.1:1301";
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(std::env::temp_dir())
+            .system_type("Generic")
+            .identification(""),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            let mut tcps = ftp_stream.get_ref();
+            let mut reader = BufReader::new(tcps);
+
+            tcps.write_all(b"SYST\r\n").unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "215 Generic\r\n");
+
+            tcps.write_all(b"STAT\r\n").unwrap();
+            let mut reply = String::new();
+            while !reply.lines().last().map(|line| line.starts_with("211 ")).unwrap_or(false) {
+                reader.read_line(&mut reply).unwrap();
+            }
+            assert!(!reply.contains("libunftp"), "Unexpected reply: {}", reply);
+        },
+    );
+}
+
+#[test]
+fn reply_filter() {
+    struct Tagged;
+
+    impl libunftp::ReplyFilter for Tagged {
+        fn filter(&self, command: Option<&str>, reply: libunftp::Reply) -> libunftp::Reply {
+            match reply {
+                libunftp::Reply::CodeAndMsg { code, msg } => libunftp::Reply::new_with_string(code, format!("{} [{}]", msg, command.unwrap_or("none"))),
+                reply => reply,
+            }
+        }
+    }
+
+    let addr = "127.0.0.1:1302";
+    let catalog = libunftp::ReplyCatalog::new().message("Please authenticate", "Log in first, please");
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(std::env::temp_dir())
+            .reply_catalog(catalog)
+            .reply_filter(Tagged),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            let err = ftp_stream.pwd().unwrap_err().to_string();
+            assert!(err.contains("530 Log in first, please [PWD]"), "Unexpected reply: {}", err);
+        },
+    );
+}
+
+#[test]
+fn idle_timeout_waits_for_transfers() {
+    let addr = "127.0.0.1:1303";
+    let root = tempfile::TempDir::new().unwrap();
+    let data = vec![42u8; 3000];
+    fs::write(root.path().join("slow.txt"), &data).unwrap();
+
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf())
+            .idle_session_timeout(1)
+            .download_bandwidth_limit(1000),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            // The download takes about two seconds, longer than the session may be idle.
+            let remote_data = ftp_stream.simple_retr("slow.txt").unwrap().into_inner();
+            assert_eq!(remote_data, data);
+            ftp_stream.noop().unwrap();
+
+            // Once the transfer is done, the session times out as usual.
+            std::thread::sleep(Duration::from_millis(1500));
+            assert!(ftp_stream.noop().is_err());
+        },
+    );
+}
+
+#[test]
+fn keepalive_during_transfer() {
+    let addr = "127.0.0.1:1304";
+    let root = tempfile::TempDir::new().unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+        tcps.write_all(b"TYPE I\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("200"));
+        tcps.write_all(b"PASV\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+        let caps = re.captures(&reply).expect("Invalid PASV reply");
+        let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+        let mut data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        tcps.write_all(b"STOR kept.bin\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("150"));
+
+        data_stream.write_all(b"one\r\n").unwrap();
+        tcps.write_all(b"NOOP\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        assert!(reply.starts_with("200"), "Unexpected reply: {}", reply);
+        // The type changes for the next transfer only, this one stays binary.
+        tcps.write_all(b"TYPE A\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        assert!(reply.starts_with("200"), "Unexpected reply: {}", reply);
+        data_stream.write_all(b"two\r\n").unwrap();
+        tcps.write_all(b"NOOP\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("200"));
+        drop(data_stream);
+
+        let reply = read_reply(&mut reader);
+        assert!(reply.starts_with("226"), "Unexpected reply: {}", reply);
+        assert_eq!(fs::read(root.path().join("kept.bin")).unwrap(), b"one\r\ntwo\r\n");
+        tcps.write_all(b"STAT\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        assert!(reply.contains("TYPE: ASCII"), "Unexpected reply: {}", reply);
+    });
+}
+
+#[test]
+fn transfer_progress() {
+    // Sends the bytes of the transfer in progress whenever the observer hears about them.
+    struct Progress(std::sync::Mutex<std::sync::mpsc::Sender<u64>>);
+
+    impl libunftp::SessionObserver for Progress {
+        fn on_transfer_progress(&self, session: &libunftp::SessionInfo) {
+            let _ = self.0.lock().unwrap().send(session.transfer.as_ref().unwrap().bytes);
+        }
+    }
+
+    let addr = "127.0.0.1:1305";
+    let root = tempfile::TempDir::new().unwrap();
+    // Takes about four seconds at the limit, long enough to be asked about in the meantime.
+    fs::write(root.path().join("slow.txt"), vec![42u8; 40_000]).unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let builder = libunftp::Server::new_with_fs_root(root.path().to_path_buf())
+        .download_bandwidth_limit(10_000)
+        .session_observer(Progress(std::sync::Mutex::new(tx)));
+    #[cfg(feature = "metrics")]
+    let registry = prometheus::Registry::new();
+    #[cfg(feature = "metrics")]
+    let builder = builder.metrics_registry(&registry, "progress_test", Default::default()).unwrap();
+    test_with_builder(addr, builder, |_| {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+        tcps.write_all(b"PASV\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+        let caps = re.captures(&reply).expect("Invalid PASV reply");
+        let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+        let mut data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        tcps.write_all(b"RETR slow.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("150"));
+
+        // What STAT shows is updated before the observer is told.
+        let observed = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(observed > 0 && observed < 40_000, "Unexpected progress: {}", observed);
+        tcps.write_all(b"STAT\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        let re = Regex::new(r"Transfer in progress, (\d+) bytes moved so far").unwrap();
+        let bytes: u64 = re.captures(&reply).expect("No progress in STAT")[1].parse().unwrap();
+        assert!(bytes > 0 && bytes < 40_000, "Unexpected progress: {}", reply);
+
+        let mut data = Vec::new();
+        data_stream.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 40_000);
+        assert!(read_reply(&mut reader).starts_with("226"));
+
+        // The rest of the bytes are reported when the transfer ended, before the reply.
+        #[cfg(feature = "metrics")]
+        {
+            let counted: i64 = registry
+                .gather()
+                .iter()
+                .filter(|family| family.get_name() == "progress_test_ftp_transfer_progress_bytes")
+                .flat_map(|family| family.get_metric())
+                .map(|metric| metric.get_counter().get_value() as i64)
+                .sum();
+            assert_eq!(counted, 40_000);
+        }
+    });
+}
+
+#[test]
+fn passive_port_in_use() {
+    let addr = "127.0.0.1:1308";
+    test_with_builder(addr, libunftp::Server::new_with_fs_root(std::env::temp_dir()).passive_ports(1306..1308), |_| {
+        let _taken = std::net::TcpListener::bind("127.0.0.1:1306").unwrap();
+
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+
+        // Whatever port is tried first, the free one is found.
+        tcps.write_all(b"PASV\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        assert!(reply.contains(",5,27)"), "Unexpected reply: {}", reply);
+
+        // The first PASV still listens on the other port, so the range is exhausted now.
+        tcps.write_all(b"PASV\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        assert!(reply.starts_with("425"), "Unexpected reply: {}", reply);
+    });
+}
+
+#[test]
+fn client_closes_data_connection() {
+    let addr = "127.0.0.1:1309";
+    let root = tempfile::TempDir::new().unwrap();
+    fs::write(root.path().join("big.bin"), vec![42u8; 16 * 1024 * 1024]).unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+        tcps.write_all(b"PASV\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+        let caps = re.captures(&reply).expect("Invalid PASV reply");
+        let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+        let mut data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        tcps.write_all(b"RETR big.bin\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("150"));
+
+        // Closing the connection with unread data in it resets it.
+        let mut buf = [0u8; 1024];
+        data_stream.read_exact(&mut buf).unwrap();
+        drop(data_stream);
+        let reply = read_reply(&mut reader);
+        assert!(reply.starts_with("426 Data connection closed by the client"), "unexpected reply: {}", reply);
+    });
+}
+
+#[test]
+fn pathnames_are_quoted() {
+    let addr = "127.0.0.1:1311";
+    let root = tempfile::TempDir::new().unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+
+        tcps.write_all(b"MKD say \"hi\"\r\n").unwrap();
+        assert_eq!(read_reply(&mut reader), "257 \"/say \"\"hi\"\"\" created\r\n");
+        tcps.write_all(b"CWD say \"hi\"\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("250"));
+        tcps.write_all(b"PWD\r\n").unwrap();
+        assert_eq!(read_reply(&mut reader), "257 \"/say \"\"hi\"\"\" is the current directory\r\n");
+    });
+}
+
+#[test]
+fn metadata_cache() {
+    use std::io::Cursor;
+
+    let addr = "127.0.0.1:1312";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::write(root.path().join("file.txt"), b"1234").unwrap();
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf()).metadata_cache_ttl(60),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            assert_eq!(ftp_stream.size("file.txt").unwrap(), Some(4));
+
+            // Changes that others make show up only once the metadata expired.
+            std::fs::write(root.path().join("file.txt"), b"123456").unwrap();
+            assert_eq!(ftp_stream.size("file.txt").unwrap(), Some(4));
+
+            // Changes that the client makes show up right away.
+            ftp_stream.put("file.txt", &mut Cursor::new(b"12345678")).unwrap();
+            assert_eq!(ftp_stream.size("file.txt").unwrap(), Some(8));
+        },
+    );
+}
+
+#[test]
+fn session_observer() {
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl libunftp::SessionObserver for Recorder {
+        fn on_connect(&self, _session: &libunftp::SessionInfo) -> std::result::Result<(), String> {
+            let mut events = self.0.lock().unwrap();
+            events.push("connect".to_string());
+            if events.iter().filter(|event| *event == "connect").count() > 1 {
+                return Err("No more sessions today".to_string());
+            }
+            Ok(())
+        }
+
+        fn on_login(&self, session: &libunftp::SessionInfo) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("login {}", session.username.as_deref().unwrap_or_default()));
+        }
+
+        fn on_logout(&self, session: &libunftp::SessionInfo) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("logout {}", session.username.as_deref().unwrap_or_default()));
+        }
+
+        fn on_disconnect(&self, _session: &libunftp::SessionInfo) {
+            self.0.lock().unwrap().push("disconnect".to_string());
+        }
+    }
+
+    let addr = "127.0.0.1:1313";
+    let events = Arc::new(Mutex::new(vec![]));
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(std::env::temp_dir()).session_observer(Recorder(events.clone())),
+        |_| {
+            // Leave out the connection of the readiness check.
+            events.lock().unwrap().clear();
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            ftp_stream.quit().unwrap();
+            std::thread::sleep(Duration::from_millis(200));
+
+            let err = FtpStream::connect(addr).unwrap_err().to_string();
+            assert!(err.contains("421 No more sessions today"), "Unexpected reply: {}", err);
+            std::thread::sleep(Duration::from_millis(200));
+            assert_eq!(*events.lock().unwrap(), vec!["connect", "login hoi", "logout hoi", "disconnect", "connect"]);
+        },
+    );
+}
+
+#[test]
+fn partial_uploads() {
+    let addr = "127.0.0.1:1314";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::write(root.path().join("upload.txt.part"), b"an earlier upload").unwrap();
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf()).partial_uploads(libunftp::PartialUploads::Rename),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            let mut tcps = ftp_stream.get_ref();
+            let mut reader = BufReader::new(tcps);
+            tcps.write_all(b"PASV\r\n").unwrap();
+            let reply = read_reply(&mut reader);
+            let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+            let caps = re.captures(&reply).expect("Invalid PASV reply");
+            let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+            let mut data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+            tcps.write_all(b"STOR upload.txt\r\n").unwrap();
+            assert!(read_reply(&mut reader).starts_with("150"));
+            data_stream.write_all(b"the first half").unwrap();
+            std::thread::sleep(Duration::from_millis(200));
+
+            tcps.write_all(b"ABOR\r\n").unwrap();
+            assert!(read_reply(&mut reader).starts_with("426"));
+            assert!(read_reply(&mut reader).starts_with("226"));
+            assert!(!root.path().join("upload.txt").exists());
+            assert_eq!(std::fs::read(root.path().join("upload.txt.part")).unwrap(), b"the first half");
+        },
+    );
+}
+
+#[test]
+fn partial_uploads_resumed() {
+    let addr = "127.0.0.1:1327";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::write(root.path().join("upload.txt"), b"the first half").unwrap();
+    test_with_builder(
+        addr,
+        libunftp::Server::new_with_fs_root(root.path().to_path_buf()).partial_uploads(libunftp::PartialUploads::Delete),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            let mut tcps = ftp_stream.get_ref();
+            let mut reader = BufReader::new(tcps);
+            tcps.write_all(b"REST 14\r\n").unwrap();
+            assert!(read_reply(&mut reader).starts_with("350"));
+            tcps.write_all(b"PASV\r\n").unwrap();
+            let reply = read_reply(&mut reader);
+            let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+            let caps = re.captures(&reply).expect("Invalid PASV reply");
+            let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+            let mut data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+            tcps.write_all(b"STOR upload.txt\r\n").unwrap();
+            assert!(read_reply(&mut reader).starts_with("150"));
+            data_stream.write_all(b", and a bit").unwrap();
+            std::thread::sleep(Duration::from_millis(200));
+
+            // The file held data before the upload, so it stays.
+            tcps.write_all(b"ABOR\r\n").unwrap();
+            assert!(read_reply(&mut reader).starts_with("426"));
+            assert!(read_reply(&mut reader).starts_with("226"));
+            assert_eq!(std::fs::read(root.path().join("upload.txt")).unwrap(), b"the first half, and a bit");
+        },
+    );
+}
+
+#[test]
+fn stat_with_wildcards() {
+    let addr = "127.0.0.1:1315";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::write(root.path().join("a.txt"), b"a").unwrap();
+    std::fs::write(root.path().join("b.txt"), b"b").unwrap();
+    std::fs::write(root.path().join("c.log"), b"c").unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+
+        tcps.write_all(b"STAT *.txt\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        assert!(reply.starts_with("213-Status of *.txt:"), "Unexpected reply: {}", reply);
+        assert!(reply.contains(" a.txt\r\n") && reply.contains(" b.txt\r\n"), "Unexpected reply: {}", reply);
+        assert!(!reply.contains("c.log"), "Unexpected reply: {}", reply);
+
+        tcps.write_all(b"STAT c.log\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        assert!(reply.contains(" c.log\r\n") && !reply.contains("a.txt"), "Unexpected reply: {}", reply);
+        assert!(reply.ends_with("213 End of status\r\n"), "Unexpected reply: {}", reply);
+
+        tcps.write_all(b"STAT missing.txt\r\n").unwrap();
+        assert_eq!(read_reply(&mut reader), "550 File not found\r\n");
+    });
+}
+
+#[test]
+fn tvfs_paths() {
+    use std::io::Cursor;
+
+    let addr = "127.0.0.1:1316";
+    let root = tempfile::TempDir::new().unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        ensure_feat_support(&mut ftp_stream, "TVFS");
+
+        ftp_stream.mkdir("dir").unwrap();
+        ftp_stream.cwd("//dir/").unwrap();
+        assert_eq!(ftp_stream.pwd().unwrap(), "/dir");
+        ftp_stream.put("./sub/../file.txt", &mut Cursor::new(b"1234")).unwrap();
+        assert!(root.path().join("dir/file.txt").exists());
+        assert_eq!(ftp_stream.size("/dir//file.txt").unwrap(), Some(4));
+        assert_eq!(ftp_stream.size("../dir/file.txt").unwrap(), Some(4));
+        ftp_stream.cwd("/").unwrap();
+        assert_eq!(ftp_stream.size("dir/file.txt").unwrap(), Some(4));
+    });
+}
+
+#[test]
+fn long_passive_mode() {
+    let addr = "127.0.0.1:1317";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::write(root.path().join("file.txt"), b"1234").unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+        tcps.write_all(b"LPSV\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        let re = Regex::new(r"^228 Entering Long Passive Mode \(4,4,127,0,0,1,2,(\d+),(\d+)\)").unwrap();
+        let caps = re.captures(&reply).expect("Invalid LPSV reply");
+        let port = caps[1].parse::<u16>().unwrap() * 256 + caps[2].parse::<u16>().unwrap();
+        let mut data_stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        tcps.write_all(b"NLST\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("150"));
+        let mut listing = String::new();
+        data_stream.read_to_string(&mut listing).unwrap();
+        assert_eq!(listing, "file.txt\r\n");
+        assert!(read_reply(&mut reader).starts_with("226"));
+
+        tcps.write_all(b"LPRT 4,4,127,0,0,1,2,4,1\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("502"));
+    });
+}
+
+#[test]
+fn block_mode() {
+    let addr = "127.0.0.1:1318";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::write(root.path().join("file.txt"), b"1234").unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+        let re = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+        let data_connection = |reply: String| {
+            let caps = re.captures(&reply).expect("Invalid PASV reply");
+            let port = caps[5].parse::<u16>().unwrap() * 256 + caps[6].parse::<u16>().unwrap();
+            std::net::TcpStream::connect(("127.0.0.1", port)).unwrap()
+        };
+
+        tcps.write_all(b"MODE B\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("200"));
+
+        tcps.write_all(b"PASV\r\n").unwrap();
+        let mut data_stream = data_connection(read_reply(&mut reader));
+        tcps.write_all(b"RETR file.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("150"));
+        let mut data = Vec::new();
+        data_stream.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"\x00\x00\x041234\x40\x00\x00".to_vec());
+        assert!(read_reply(&mut reader).starts_with("226"));
+
+        // The client restarts the upload at the marker the server replied with.
+        tcps.write_all(b"PASV\r\n").unwrap();
+        let mut data_stream = data_connection(read_reply(&mut reader));
+        tcps.write_all(b"STOR upload.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("150"));
+        data_stream.write_all(b"\x00\x00\x03abc\x10\x00\x02m1").unwrap();
+        assert_eq!(read_reply(&mut reader), "110 MARK m1 = 3\r\n");
+        drop(data_stream);
+        assert!(read_reply(&mut reader).starts_with("426"));
+        tcps.write_all(b"REST 3\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("350"));
+        tcps.write_all(b"PASV\r\n").unwrap();
+        let mut data_stream = data_connection(read_reply(&mut reader));
+        tcps.write_all(b"STOR upload.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("150"));
+        data_stream.write_all(b"\x00\x00\x02de\x40\x00\x00").unwrap();
+        assert!(read_reply(&mut reader).starts_with("226"));
+        assert_eq!(std::fs::read(root.path().join("upload.txt")).unwrap(), b"abcde".to_vec());
+    });
+}
+
+#[test]
+fn rename_errors() {
+    let addr = "127.0.0.1:1319";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::write(root.path().join("a.txt"), b"a").unwrap();
+    std::fs::write(root.path().join("b.txt"), b"b").unwrap();
+    std::fs::create_dir(root.path().join("dir")).unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+
+        tcps.write_all(b"RNFR missing.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("550"));
+
+        tcps.write_all(b"RNFR a.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("350"));
+        tcps.write_all(b"RNTO b.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("553"));
+        assert_eq!(std::fs::read(root.path().join("b.txt")).unwrap(), b"b".to_vec());
+
+        tcps.write_all(b"RNFR a.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("350"));
+        tcps.write_all(b"RNTO dir/a.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("250"));
+        assert_eq!(std::fs::read(root.path().join("dir/a.txt")).unwrap(), b"a".to_vec());
+
+        tcps.write_all(b"RNFR dir\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("350"));
+        tcps.write_all(b"RNTO renamed\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("250"));
+        assert!(root.path().join("renamed/a.txt").exists());
+    });
+}
+
+#[test]
+fn rename_sequence() {
+    let addr = "127.0.0.1:1320";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::write(root.path().join("a.txt"), b"a").unwrap();
+    test_with(addr, root.path().to_path_buf(), || {
+        let mut ftp_stream = FtpStream::connect(addr).unwrap();
+        ftp_stream.login("hoi", "jij").unwrap();
+        let mut tcps = ftp_stream.get_ref();
+        let mut reader = BufReader::new(tcps);
+
+        tcps.write_all(b"RNTO b.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("503"));
+
+        tcps.write_all(b"RNFR a.txt\r\n").unwrap();
+        assert_eq!(read_reply(&mut reader), "350 \"/a.txt\" exists, ready for the new name\r\n");
+        tcps.write_all(b"NOOP\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("200"));
+        tcps.write_all(b"RNTO b.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("503"));
+
+        tcps.write_all(b"RNFR a.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("350"));
+        tcps.write_all(b"RNTO b.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("250"));
+        tcps.write_all(b"RNTO c.txt\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("503"));
+        assert!(root.path().join("b.txt").exists());
+    });
+}
+
+// A filesystem that can't remove directories, like some object stores.
+struct NoRmd(libunftp::storage::filesystem::Filesystem);
+
+#[async_trait::async_trait]
+impl libunftp::storage::StorageBackend<libunftp::auth::DefaultUser> for NoRmd {
+    type File = tokio::fs::File;
+    type Metadata = std::fs::Metadata;
+
+    fn supported_features(&self) -> libunftp::storage::Capabilities {
+        libunftp::storage::Capabilities::RESTART | libunftp::storage::Capabilities::RENAME
+    }
+
+    async fn metadata<P: AsRef<Path> + Send>(&self, user: &Option<DefaultUser>, path: P) -> storage::Result<Self::Metadata> {
+        self.0.metadata(user, path).await
+    }
+
+    async fn list<P: AsRef<Path> + Send>(&self, user: &Option<DefaultUser>, path: P) -> storage::Result<Vec<storage::Fileinfo<PathBuf, Self::Metadata>>> {
+        self.0.list(user, path).await
+    }
+
+    async fn get<P: AsRef<Path> + Send>(&self, user: &Option<DefaultUser>, path: P, start_pos: u64) -> storage::Result<Self::File> {
+        self.0.get(user, path, start_pos).await
+    }
+
+    async fn put<P: AsRef<Path> + Send, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &Option<DefaultUser>,
+        input: R,
+        path: P,
+        start_pos: u64,
+    ) -> storage::Result<u64> {
+        self.0.put(user, input, path, start_pos).await
+    }
+
+    async fn del<P: AsRef<Path> + Send>(&self, user: &Option<DefaultUser>, path: P) -> storage::Result<()> {
+        self.0.del(user, path).await
+    }
+
+    async fn mkd<P: AsRef<Path> + Send>(&self, user: &Option<DefaultUser>, path: P) -> storage::Result<()> {
+        self.0.mkd(user, path).await
+    }
+
+    async fn rename<P: AsRef<Path> + Send>(&self, user: &Option<DefaultUser>, from: P, to: P) -> storage::Result<()> {
+        self.0.rename(user, from, to).await
+    }
+
+    async fn rmd<P: AsRef<Path> + Send>(&self, _user: &Option<DefaultUser>, _path: P) -> storage::Result<()> {
+        Err(storage::Error::from(storage::ErrorKind::NotSupported))
+    }
+}
+
+#[test]
+fn backend_without_rmd() {
+    let addr = "127.0.0.1:1321";
+    let root = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(root.path().join("dir/sub")).unwrap();
+    std::fs::write(root.path().join("dir/a.txt"), b"a").unwrap();
+    let fs_root = root.path().to_path_buf();
+    test_with_builder(
+        addr,
+        libunftp::Server::new(Box::new(move || NoRmd(libunftp::storage::filesystem::Filesystem::new(fs_root.clone())))),
+        |_| {
+            let mut ftp_stream = FtpStream::connect(addr).unwrap();
+            ftp_stream.login("hoi", "jij").unwrap();
+            let mut tcps = ftp_stream.get_ref();
+            let mut reader = BufReader::new(tcps);
+
+            tcps.write_all(b"RMD dir/sub\r\n").unwrap();
+            assert_eq!(read_reply(&mut reader), "502 Not supported by the selected storage back-end.\r\n");
+            tcps.write_all(b"SITE RMDIR dir\r\n").unwrap();
+            assert_eq!(read_reply(&mut reader), "502 Not supported by the selected storage back-end.\r\n");
+            tcps.write_all(b"HELP\r\n").unwrap();
+            let help = read_reply(&mut reader);
+            let verbs: Vec<&str> = help.split_whitespace().collect();
+            assert!(verbs.contains(&"RNFR") && !verbs.contains(&"RMD"), "Unexpected reply: {}", help);
+            assert!(root.path().join("dir/sub").exists());
+            assert!(root.path().join("dir/a.txt").exists());
+        },
+    );
+}
+
+#[cfg(feature = "gssapi")]
+#[test]
+fn gssapi_login_without_password() {
+    use libunftp::{GssapiContext, GssapiResult};
+
+    // Authenticates every client as alice on the first token and doesn't protect anything.
+    struct Kerberos {
+        complete: bool,
+    }
+
+    impl GssapiContext for Kerberos {
+        fn step(&mut self, _token: &[u8]) -> GssapiResult<Option<Vec<u8>>> {
+            self.complete = true;
+            Ok(None)
+        }
+        fn is_complete(&self) -> bool {
+            self.complete
+        }
+        fn principal(&self) -> Option<String> {
+            Some("alice@EXAMPLE.COM".to_string())
+        }
+        fn unwrap(&mut self, message: &[u8]) -> GssapiResult<Vec<u8>> {
+            Ok(message.to_vec())
+        }
+        fn wrap(&mut self, _encrypt: bool, message: &[u8]) -> GssapiResult<Vec<u8>> {
+            Ok(message.to_vec())
+        }
+    }
+
+    let addr = "127.0.0.1:1328";
+    let builder = libunftp::Server::new_with_fs_root(std::env::temp_dir()).gssapi(|| Box::new(Kerberos { complete: false }));
+    test_with_builder(addr, builder, |_| {
+        let tcps = std::net::TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(&tcps);
+        let mut tcps = &tcps;
+        assert!(read_reply(&mut reader).starts_with("220"));
+
+        tcps.write_all(b"AUTH GSSAPI\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("334"));
+        tcps.write_all(b"ADAT dG9rZW4=\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("235"));
+
+        // Only the principal may log in.
+        tcps.write_all(b"USER bob\r\n").unwrap();
+        assert!(read_reply(&mut reader).starts_with("530"));
+
+        tcps.write_all(b"USER alice\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        assert!(reply.starts_with("232"), "Expected a login without password, got: {}", reply);
+        tcps.write_all(b"PWD\r\n").unwrap();
+        let reply = read_reply(&mut reader);
+        assert!(reply.starts_with("257"), "Expected to be logged in, got: {}", reply);
+    });
+}